@@ -178,6 +178,7 @@ fn run() -> Result<(), SavePcapError> {
         rollover_time_seconds,
         rollover_packet_count,
         rollover_file_size_mb,
+        ..Default::default()
     };
 
     // 创建捕获器并开始捕获
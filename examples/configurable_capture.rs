@@ -1,6 +1,6 @@
 use anyhow::{Context, Result};
 use clap::Parser;
-use save_pcap::{FileFormat, PcapCaptureOptions, PcapCapturer};
+use save_pcap::{FileFormat, PcapCaptureOptions, PcapCapturer, Pipeline, Scenario};
 use serde::{Deserialize, Serialize};
 use std::fs;
 
@@ -12,6 +12,7 @@ struct CaptureConfig {
     file_path: String,
     file_format: String,
     packet_limit: Option<usize>,
+    byte_limit: Option<u64>,
     snaplen: i32,
     timeout_ms: i32,
     // 滚动保存相关配置
@@ -19,6 +20,13 @@ struct CaptureConfig {
     rollover_time_seconds: Option<u64>,
     rollover_packet_count: Option<usize>,
     rollover_file_size_mb: Option<u64>,
+    // 无线抓包相关配置
+    rfmon: Option<bool>,
+    linktype: Option<i32>,
+    // 排障场景预设
+    scenario: Option<String>,
+    // 处理流程描述文件，覆盖数据来源、过滤、分流和输出设置
+    pipeline: Option<String>,
 }
 
 // 命令行参数定义
@@ -45,6 +53,10 @@ struct Args {
     #[arg(short = 'l', long)]
     packet_limit: Option<usize>,
 
+    /// 写入的数据包字节数限制
+    #[arg(long)]
+    byte_limit: Option<u64>,
+
     /// 捕获的数据包大小限制
     #[arg(short = 's', long, default_value_t = 65535)]
     snaplen: i32,
@@ -72,6 +84,22 @@ struct Args {
     /// 每个文件最大大小(MB)
     #[arg(long)]
     rollover_file_size_mb: Option<u64>,
+
+    /// 启用监听模式(rfmon)，用于802.11无线抓包
+    #[arg(long, default_value_t = false)]
+    rfmon: bool,
+
+    /// 链路层类型(DLT)，例如127表示802.11 radiotap
+    #[arg(long)]
+    linktype: Option<i32>,
+
+    /// 排障场景预设(slow-web-app、voip-quality、dhcp-issues)，覆盖过滤、快照长度、滚动等设置
+    #[arg(long)]
+    scenario: Option<String>,
+
+    /// 处理流程描述文件(数据来源、变换、分流、输出)，覆盖对应的设置
+    #[arg(long)]
+    pipeline: Option<String>,
 }
 
 // 将字符串转换为FileFormat枚举
@@ -98,9 +126,13 @@ fn main() -> Result<()> {
     let args = Args::parse();
 
     // 构建PcapCaptureOptions
-    let options = if let Some(config_file) = &args.config_file {
+    let mut scenario = args.scenario.clone();
+    let mut pipeline = args.pipeline.clone();
+    let mut options = if let Some(config_file) = &args.config_file {
         // 从配置文件加载配置
         let config = load_config_from_file(config_file)?;
+        scenario = scenario.or(config.scenario);
+        pipeline = pipeline.or(config.pipeline);
 
         // 创建选项，命令行参数优先级高于配置文件
         PcapCaptureOptions {
@@ -111,6 +143,7 @@ fn main() -> Result<()> {
             file_path: args.file_path.unwrap_or(config.file_path),
             file_format: str_to_file_format(&args.file_format.unwrap_or(config.file_format))?,
             packet_limit: args.packet_limit.or(config.packet_limit),
+            byte_limit: args.byte_limit.or(config.byte_limit),
             snaplen: args.snaplen,
             timeout_ms: args.timeout_ms,
             continuous_capture: if args.continuous_capture {
@@ -121,34 +154,69 @@ fn main() -> Result<()> {
             rollover_time_seconds: args.rollover_time_seconds.or(config.rollover_time_seconds),
             rollover_packet_count: args.rollover_packet_count.or(config.rollover_packet_count),
             rollover_file_size_mb: args.rollover_file_size_mb.or(config.rollover_file_size_mb),
+            rfmon: args.rfmon || config.rfmon.unwrap_or(false),
+            linktype: args.linktype.or(config.linktype),
+            ..Default::default()
         }
     } else {
         // 仅使用命令行参数
         PcapCaptureOptions {
-            packet_source: save_pcap::PacketSource::NetworkDevice(args.device_name.ok_or_else(
-                || anyhow::anyhow!("必须提供网络设备名称，请使用--device-name参数或配置文件"),
-            )?),
-            file_prefix: args.file_prefix.ok_or_else(|| {
-                anyhow::anyhow!("必须提供文件前缀，请使用--file-prefix参数或配置文件")
-            })?,
+            // 数据来源可以由处理流程描述文件提供
+            packet_source: match (args.device_name, &pipeline) {
+                (Some(name), _) => save_pcap::PacketSource::NetworkDevice(name),
+                (None, Some(_)) => save_pcap::PacketSource::UserProvided,
+                (None, None) => {
+                    return Err(anyhow::anyhow!(
+                        "必须提供网络设备名称，请使用--device-name参数或配置文件"
+                    ));
+                }
+            },
+            file_prefix: match (args.file_prefix, &pipeline) {
+                (Some(prefix), _) => prefix,
+                (None, Some(_)) => "capture".to_string(),
+                (None, None) => {
+                    return Err(anyhow::anyhow!(
+                        "必须提供文件前缀，请使用--file-prefix参数或配置文件"
+                    ));
+                }
+            },
             file_path: args.file_path.unwrap_or("./".to_string()),
             file_format: str_to_file_format(&args.file_format.unwrap_or("pcap".to_string()))?,
             packet_limit: args.packet_limit,
+            byte_limit: args.byte_limit,
             snaplen: args.snaplen,
             timeout_ms: args.timeout_ms,
             continuous_capture: args.continuous_capture,
             rollover_time_seconds: args.rollover_time_seconds,
             rollover_packet_count: args.rollover_packet_count,
             rollover_file_size_mb: args.rollover_file_size_mb,
+            rfmon: args.rfmon,
+            linktype: args.linktype,
+            ..Default::default()
         }
     };
 
+    // 加载时校验处理流程，出错时不开始捕获
+    if let Some(path) = pipeline {
+        let pipeline = Pipeline::load(&path)?;
+        pipeline.apply(&mut options);
+        println!("处理流程: {}", path);
+    }
+
+    if let Some(name) = scenario {
+        let scenario: Scenario = name.parse()?;
+        scenario.apply(&mut options);
+        println!("排障场景: {} ({})", scenario, scenario.description());
+    }
+
     // 打印配置信息
     println!("开始捕获数据包...");
     println!(
         "设备名称: {:?}",
         match &options.packet_source {
-            save_pcap::PacketSource::NetworkDevice(name) => name.as_str(),
+            save_pcap::PacketSource::NetworkDevice(name)
+            | save_pcap::PacketSource::BluetoothHci(name)
+            | save_pcap::PacketSource::CanInterface(name) => name.as_str(),
             _ => "用户提供的数据包",
         }
     );
@@ -158,6 +226,9 @@ fn main() -> Result<()> {
     if let Some(limit) = options.packet_limit {
         println!("数据包限制: {}", limit);
     }
+    if let Some(limit) = options.byte_limit {
+        println!("字节数限制: {}", limit);
+    }
     println!("快照长度: {}", options.snaplen);
     println!("超时时间: {}ms", options.timeout_ms);
     println!(
@@ -177,6 +248,12 @@ fn main() -> Result<()> {
     if let Some(size) = options.rollover_file_size_mb {
         println!("文件大小滚动阈值: {}MB", size);
     }
+    if options.rfmon {
+        println!("监听模式: 已启用");
+    }
+    if let Some(linktype) = options.linktype {
+        println!("链路层类型: {}", linktype);
+    }
 
     // 创建捕获器并开始捕获
     let capturer = PcapCapturer::new(options);
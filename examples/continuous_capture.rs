@@ -57,13 +57,16 @@ fn main() {
         rollover_time_seconds: config.default_rollover_time_seconds,
         rollover_packet_count: config.default_rollover_packet_count,
         rollover_file_size_mb: config.default_rollover_file_size_mb,
+        ..Default::default()
     };
 
     println!("启动持续数据包捕获和滚动保存...");
     println!(
         "设备: {:?}",
         match &options.packet_source {
-            save_pcap::PacketSource::NetworkDevice(name) => name.as_str(),
+            save_pcap::PacketSource::NetworkDevice(name)
+            | save_pcap::PacketSource::BluetoothHci(name)
+            | save_pcap::PacketSource::CanInterface(name) => name.as_str(),
             _ => "用户提供的数据包",
         }
     );
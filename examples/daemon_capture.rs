@@ -0,0 +1,56 @@
+// 以守护进程方式在后台持续捕获，使用 kill <pid> 停止
+// cargo run --example daemon_capture --features daemon -- <device> [output_dir]
+
+#[cfg(unix)]
+fn main() {
+    use save_pcap::daemon::{DaemonOptions, daemonize, stop_on_signals};
+    use save_pcap::{PacketSource, PcapCaptureOptions, PcapCapturer};
+    use std::path::PathBuf;
+
+    let mut args = std::env::args().skip(1);
+    let Some(device_name) = args.next() else {
+        eprintln!("用法: daemon_capture <device> [output_dir]");
+        std::process::exit(1);
+    };
+    let output_dir = std::path::absolute(args.next().unwrap_or_else(|| ".".to_string()))
+        .expect("无效的输出目录");
+
+    let daemon_options = DaemonOptions {
+        pid_file: output_dir.join("save_pcap.pid"),
+        log_file: Some(output_dir.join("save_pcap.log")),
+        working_dir: PathBuf::from("/"),
+    };
+
+    // 必须在创建线程和初始化日志之前转为守护进程
+    let _pid_file = match daemonize(&daemon_options) {
+        Ok(pid_file) => pid_file,
+        Err(e) => {
+            eprintln!("无法启动守护进程: {}", e);
+            std::process::exit(1);
+        }
+    };
+    env_logger::init();
+
+    let options = PcapCaptureOptions {
+        packet_source: PacketSource::NetworkDevice(device_name),
+        file_path: output_dir.to_string_lossy().into_owned(),
+        continuous_capture: true,
+        rollover_time_seconds: Some(3600),
+        ..Default::default()
+    };
+
+    let capturer = PcapCapturer::new(options);
+    if let Err(e) = stop_on_signals(capturer.handle()) {
+        eprintln!("无法注册信号处理: {}", e);
+        std::process::exit(1);
+    }
+
+    if let Err(e) = capturer.capture() {
+        eprintln!("捕获失败：{}", e);
+    }
+}
+
+#[cfg(not(unix))]
+fn main() {
+    eprintln!("守护进程模式仅支持Unix系统");
+}
@@ -1,16 +1,16 @@
-use save_pcap::get_available_devices;
+use save_pcap::get_available_devices_detailed;
 
 fn main() {
     env_logger::init();
     
-    match get_available_devices() {
+    match get_available_devices_detailed() {
         Ok(devices) => {
             println!("可用的网络设备列表:");
             for device in devices {
-                println!("- {}", device);
+                println!("- {}: {}", device.name, device.display_name());
             }
             println!();
-            println!("请在运行示例程序时使用上述设备名称之一。");
+            println!("请在运行示例程序时使用上述设备名称或友好名称之一。");
         }
         Err(e) => eprintln!("获取设备列表失败: {}", e),
     }
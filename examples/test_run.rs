@@ -16,6 +16,7 @@ fn main() {
         rollover_time_seconds: None, // 无时间滚动
         rollover_packet_count: None, // 无数据包数量滚动
         rollover_file_size_mb: None, // 无文件大小滚动
+        ..Default::default()
     };
 
     // 创建捕获器并开始捕获
@@ -1,7 +1,8 @@
 // 这个示例展示如何使用用户提供的数据包功能
 // 你可以通过这个示例学习如何创建自己的数据包并发送给save_pcap库保存
 
-use save_pcap::{FileFormat, PcapCaptureOptions, PcapCapturer, UserPacket};
+use save_pcap::{EthernetFrame, FileFormat, PcapCaptureOptions, PcapCapturer};
+use std::net::Ipv4Addr;
 use std::thread;
 use std::time::Duration;
 
@@ -21,6 +22,7 @@ fn main() {
         rollover_packet_count: Some(20), // 每20个数据包创建一个新文件
         rollover_file_size_mb: None,     // 不按文件大小滚动
         rollover_time_seconds: None,     // 不按时间滚动
+        ..Default::default()
     };
 
     // 创建捕获器
@@ -39,23 +41,20 @@ fn main() {
     let sender_thread = thread::spawn(move || {
         // 发送一些示例数据包
         for i in 0..100 {
-            // 创建一个简单的以太网数据包
-            let mut packet_data = vec![0u8; 64];
-
-            // 设置目标MAC地址
-            packet_data[0..6].copy_from_slice(&[0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
-            // 设置源MAC地址
-            packet_data[6..12].copy_from_slice(&[0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]);
-            // 设置以太网类型为IPv4
-            packet_data[12..14].copy_from_slice(&[0x08, 0x00]);
-            // 在数据包中添加一些标识信息
-            packet_data[14] = (i & 0xFF) as u8;
-            packet_data[15] = ((i >> 8) & 0xFF) as u8;
-
-            // 创建用户数据包
-            let user_packet = UserPacket {
-                data: packet_data,
-                timestamp: None, // 使用当前时间作为时间戳
+            // 构造一个UDP数据包，长度字段和校验和自动填写，负载中带上序号
+            let user_packet = match EthernetFrame::new()
+                .source_mac([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF])
+                .destination_mac([0x11, 0x22, 0x33, 0x44, 0x55, 0x66])
+                .ipv4(Ipv4Addr::new(192, 168, 1, 10), Ipv4Addr::new(192, 168, 1, 20))
+                .udp(40000, 9000)
+                .payload((i as u16).to_be_bytes())
+                .build() // 未设置时间戳，使用当前时间
+            {
+                Ok(packet) => packet,
+                Err(err) => {
+                    eprintln!("Failed to build packet {}: {:?}", i, err);
+                    break;
+                }
             };
 
             // 发送数据包
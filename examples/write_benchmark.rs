@@ -0,0 +1,93 @@
+// 在当前硬件上测试写入吞吐量、文件滚动耗时和丢包率
+// cargo run --release --example write_benchmark -- --rate 200000 --duration 30
+
+use clap::Parser;
+use save_pcap::PcapCaptureOptions;
+use save_pcap::bench::{BenchOptions, run_benchmark};
+use std::time::Duration;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "写入性能自测")]
+struct Args {
+    /// 测试文件的输出目录
+    #[arg(long, default_value = ".")]
+    file_path: String,
+
+    /// 每个数据包的字节数
+    #[arg(long, default_value_t = 512)]
+    packet_size: usize,
+
+    /// 目标速率（包/秒），不指定时尽可能快地生成
+    #[arg(long)]
+    rate: Option<u64>,
+
+    /// 测试时长（秒）
+    #[arg(long, default_value_t = 10)]
+    duration: u64,
+
+    /// 按文件大小滚动（MB）
+    #[arg(long, default_value_t = 100)]
+    rollover_file_size_mb: u64,
+
+    /// 启用独立写入线程并指定写入队列容量
+    #[arg(long)]
+    writer_queue_capacity: Option<usize>,
+
+    /// 保留测试生成的文件
+    #[arg(long)]
+    keep_files: bool,
+}
+
+fn main() {
+    env_logger::init();
+    let args = Args::parse();
+
+    let options = BenchOptions {
+        capture_options: PcapCaptureOptions {
+            file_prefix: "bench".to_string(),
+            file_path: args.file_path,
+            continuous_capture: true,
+            rollover_file_size_mb: Some(args.rollover_file_size_mb),
+            writer_queue_capacity: args.writer_queue_capacity,
+            ..Default::default()
+        },
+        packet_size: args.packet_size,
+        packet_rate: args.rate,
+        duration: Duration::from_secs(args.duration),
+        keep_files: args.keep_files,
+        ..Default::default()
+    };
+
+    match run_benchmark(options) {
+        Ok(report) => {
+            println!("测试时长:       {:.2?}", report.elapsed);
+            println!(
+                "写入数据包:     {} ({:.0} 包/秒)",
+                report.packets_written,
+                report.packets_per_second()
+            );
+            println!(
+                "写入数据量:     {} 字节 ({:.1} MB/秒)",
+                report.bytes_written,
+                report.megabytes_per_second()
+            );
+            println!(
+                "丢包:           {} / {} ({:.3}%)",
+                report.packets_dropped,
+                report.packets_generated,
+                report.drop_rate() * 100.0
+            );
+            println!(
+                "文件滚动:       {} 个文件，平均 {:.2?}，最长 {:.2?}",
+                report.files_created, report.rotation_time_avg, report.rotation_time_max
+            );
+            if args.writer_queue_capacity.is_some() {
+                println!("写入队列峰值:   {}", report.writer_queue_high_watermark);
+            }
+        }
+        Err(e) => {
+            eprintln!("测试失败：{}", e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use crate::stats::LiveStats;
+use log::warn;
+use std::fmt;
+use std::mem;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// 告警回调，在写入线程中调用，不应长时间阻塞
+pub type AlertCallback = Arc<dyn Fn(&Alert) + Send + Sync>;
+
+/// 告警阈值。每秒检查一次，指标从正常变为超过阈值时记录警告日志并调用回调；
+/// 持续超过阈值期间不重复告警，恢复正常后再次超过时重新告警
+#[derive(Clone, Default)]
+pub struct AlertOptions {
+    /// 内核缓冲区或网卡出现新的丢包时告警
+    pub kernel_drops: bool,
+    /// 写入的数据包速率超过该值时告警
+    pub max_packets_per_second: Option<f64>,
+    /// 写入的流量（按数据包原始长度）超过该值（bit/s）时告警
+    pub max_bits_per_second: Option<f64>,
+    /// 捕获文件的增长速度超过该值（MB/分钟）时告警
+    pub max_file_growth_mb_per_minute: Option<f64>,
+    /// None表示只记录警告日志
+    pub callback: Option<AlertCallback>,
+}
+
+/// 指标超过阈值时产生的告警
+#[derive(Debug, Clone, PartialEq)]
+pub enum Alert {
+    /// 最近一秒内新增的丢包数和累计丢包数
+    KernelDrops { dropped: u64, total: u64 },
+    PacketRate {
+        packets_per_second: f64,
+        threshold: f64,
+    },
+    BitRate {
+        bits_per_second: f64,
+        threshold: f64,
+    },
+    FileGrowth {
+        mb_per_minute: f64,
+        threshold: f64,
+        file: Option<PathBuf>,
+    },
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Alert::KernelDrops { dropped, total } => {
+                write!(
+                    f,
+                    "{} packets dropped by the kernel ({} total)",
+                    dropped, total
+                )
+            }
+            Alert::PacketRate {
+                packets_per_second,
+                threshold,
+            } => write!(
+                f,
+                "packet rate {:.0} pps exceeds {:.0} pps",
+                packets_per_second, threshold
+            ),
+            Alert::BitRate {
+                bits_per_second,
+                threshold,
+            } => write!(
+                f,
+                "bit rate {:.0} bit/s exceeds {:.0} bit/s",
+                bits_per_second, threshold
+            ),
+            Alert::FileGrowth {
+                mb_per_minute,
+                threshold,
+                file,
+            } => write!(
+                f,
+                "file growth {:.1} MB/min exceeds {:.1} MB/min ({})",
+                mb_per_minute,
+                threshold,
+                file.as_ref()
+                    .map(|file| file.display().to_string())
+                    .unwrap_or_default()
+            ),
+        }
+    }
+}
+
+// 写入线程中的告警检查，记录上次检查时的累计值和每个阈值当前是否处于告警状态
+pub(crate) struct AlertMonitor {
+    options: AlertOptions,
+    last_check: Instant,
+    last_dropped: u64,
+    last_bytes_written: u64,
+    // 丢包、包速率、比特率、文件增长
+    active: [bool; 4],
+}
+
+impl AlertMonitor {
+    pub fn new(options: &AlertOptions) -> Self {
+        Self {
+            options: options.clone(),
+            last_check: Instant::now(),
+            last_dropped: 0,
+            last_bytes_written: 0,
+            active: [false; 4],
+        }
+    }
+
+    pub fn check(&mut self, live: &LiveStats) {
+        let minutes = self.last_check.elapsed().as_secs_f64() / 60.0;
+        self.last_check = Instant::now();
+        let dropped = live.kernel_dropped.saturating_sub(self.last_dropped);
+        self.last_dropped = live.kernel_dropped;
+        let grown = live.bytes_written.saturating_sub(self.last_bytes_written);
+        self.last_bytes_written = live.bytes_written;
+
+        let drops = (self.options.kernel_drops && dropped > 0).then_some(Alert::KernelDrops {
+            dropped,
+            total: live.kernel_dropped,
+        });
+        let packet_rate = self
+            .options
+            .max_packets_per_second
+            .filter(|&threshold| live.packets_per_second > threshold)
+            .map(|threshold| Alert::PacketRate {
+                packets_per_second: live.packets_per_second,
+                threshold,
+            });
+        let bit_rate = self
+            .options
+            .max_bits_per_second
+            .filter(|&threshold| live.bits_per_second > threshold)
+            .map(|threshold| Alert::BitRate {
+                bits_per_second: live.bits_per_second,
+                threshold,
+            });
+        let mb_per_minute = if minutes > 0.0 {
+            grown as f64 / (1024.0 * 1024.0) / minutes
+        } else {
+            0.0
+        };
+        let file_growth = self
+            .options
+            .max_file_growth_mb_per_minute
+            .filter(|&threshold| mb_per_minute > threshold)
+            .map(|threshold| Alert::FileGrowth {
+                mb_per_minute,
+                threshold,
+                file: live.current_file.clone(),
+            });
+
+        for (i, alert) in [drops, packet_rate, bit_rate, file_growth]
+            .into_iter()
+            .enumerate()
+        {
+            let was_active = mem::replace(&mut self.active[i], alert.is_some());
+            if let Some(alert) = alert
+                && !was_active
+            {
+                self.raise(&alert);
+            }
+        }
+    }
+
+    fn raise(&self, alert: &Alert) {
+        warn!("Capture alert: {}", alert);
+        if let Some(callback) = &self.options.callback {
+            callback(alert);
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use crate::metadata::{self, json_string};
+use crate::source::SourcePacket;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// 传给`PacketAnnotator`的数据包
+#[derive(Debug, Clone, Copy)]
+pub struct AnnotatedPacket<'a> {
+    pub timestamp: Duration,
+    pub orig_len: u32,
+    pub data: &'a [u8],
+    /// 用户提供的数据包在发送顺序中的序号（与`RejectedPacket::index`相同），
+    /// 便于按序号查找应用中的上下文；其他来源为None
+    pub user_index: Option<u64>,
+}
+
+/// 数据包注释回调，返回要附加到数据包上的键值对（例如`("request-id", "…")`），
+/// 每个数据包在写入前调用一次。在写入线程中调用，不应长时间阻塞
+pub type PacketAnnotator = Arc<dyn Fn(&AnnotatedPacket) -> Vec<(String, String)> + Send + Sync>;
+
+// 写入pcapng注释时的格式，与合法性检查的标记使用相同的分隔符
+pub(crate) fn comment(annotations: &[(String, String)]) -> Option<String> {
+    if annotations.is_empty() {
+        return None;
+    }
+    let pairs: Vec<String> = annotations
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect();
+    Some(pairs.join("; "))
+}
+
+// 每个捕获文件对应一个`.annotations.jsonl`，每行一个带注释的数据包
+pub(crate) struct AnnotationLog {
+    entries: String,
+}
+
+impl AnnotationLog {
+    pub fn new() -> Self {
+        Self {
+            entries: String::new(),
+        }
+    }
+
+    /// offset为数据包记录在捕获文件中的起始偏移，可以与经典pcap文件配合使用
+    pub fn record(&mut self, packet: &SourcePacket, offset: u64, annotations: &[(String, String)]) {
+        if annotations.is_empty() {
+            return;
+        }
+        let fields: Vec<String> = annotations
+            .iter()
+            .map(|(key, value)| format!("{}: {}", json_string(key), json_string(value)))
+            .collect();
+        let _ = writeln!(
+            self.entries,
+            "{{\"timestamp\": {}, \"offset\": {}, \"annotations\": {{{}}}}}",
+            json_string(&metadata::rfc3339(packet.timestamp)),
+            offset,
+            fields.join(", ")
+        );
+    }
+
+    pub fn discard(&mut self) {
+        self.entries.clear();
+    }
+
+    /// 没有带注释的数据包时不创建文件
+    pub fn write_sidecar(&mut self, capture_path: &Path) -> io::Result<()> {
+        if self.entries.is_empty() {
+            return Ok(());
+        }
+        let path = metadata::sidecar_path_with(capture_path, metadata::ANNOTATIONS_SUFFIX);
+        let result = fs::write(path, &self.entries);
+        self.entries.clear();
+        result
+    }
+}
@@ -0,0 +1,80 @@
+use chrono::{DateTime, Local, SecondsFormat};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+// 数据包时间戳跳跃过大（例如系统时钟被调整）时不再逐个补写空的时间段
+const MAX_EMPTY_INTERVALS: u64 = 86400;
+
+// 按数据包时间戳把流量划分到与整秒对齐的固定时间段，每个时间段结束后写一行CSV。
+// 没有数据包的时间段写0，便于直接画图
+pub(crate) struct BandwidthLog {
+    interval_secs: u64,
+    writer: BufWriter<File>,
+    // 当前时间段的编号（开始时间/时间段长度）、数据包数和字节数
+    current: Option<(u64, u64, u64)>,
+}
+
+impl BandwidthLog {
+    pub fn create(path: &Path, interval_secs: u64) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(
+            writer,
+            "interval_start,packets,bytes,packets_per_second,bits_per_second"
+        )?;
+        Ok(Self {
+            interval_secs: interval_secs.max(1),
+            writer,
+            current: None,
+        })
+    }
+
+    pub fn record(&mut self, timestamp: Duration, bytes: u64) -> io::Result<()> {
+        let interval = timestamp.as_secs() / self.interval_secs;
+        match self.current {
+            // 乱序到达的早期数据包计入当前时间段
+            Some((current, ref mut packets, ref mut total)) if current >= interval => {
+                *packets += 1;
+                *total += bytes;
+            }
+            Some((current, packets, total)) => {
+                self.write_row(current, packets, total)?;
+                if interval - current - 1 <= MAX_EMPTY_INTERVALS {
+                    for empty in current + 1..interval {
+                        self.write_row(empty, 0, 0)?;
+                    }
+                }
+                self.current = Some((interval, 1, bytes));
+            }
+            None => self.current = Some((interval, 1, bytes)),
+        }
+        Ok(())
+    }
+
+    /// 写出最后一个（可能不完整的）时间段
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some((current, packets, total)) = self.current.take() {
+            self.write_row(current, packets, total)?;
+        }
+        self.writer.flush()
+    }
+
+    fn write_row(&mut self, interval: u64, packets: u64, bytes: u64) -> io::Result<()> {
+        let start = DateTime::<Local>::from(
+            UNIX_EPOCH + Duration::from_secs(interval * self.interval_secs),
+        );
+        let seconds = self.interval_secs as f64;
+        writeln!(
+            self.writer,
+            "{},{},{},{:.1},{:.0}",
+            start.to_rfc3339_opts(SecondsFormat::Secs, false),
+            packets,
+            bytes,
+            packets as f64 / seconds,
+            bytes as f64 * 8.0 / seconds
+        )?;
+        // 每个时间段结束时写出，外部程序可以随时读取已完成的时间段
+        self.writer.flush()
+    }
+}
@@ -0,0 +1,220 @@
+//! 写入性能自测：按指定速率生成合成数据包，走与真实捕获相同的写入和滚动流程，
+//! 测量当前硬件上可达到的写入吞吐量、文件滚动耗时和丢包率，用于上线前评估部署规模。
+
+use crate::pool::BufferPool;
+use crate::source::{NextPacket, PacketStream, SourcePacket};
+use crate::{PcapCaptureOptions, PcapCapturer, SavePcapError};
+use chrono::Local;
+use log::{info, warn};
+use pcap_file::DataLink;
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// 本地实验用的以太网类型，避免合成数据包被误认为真实协议
+const BENCH_ETHERTYPE: [u8; 2] = [0x88, 0xB5];
+// 以太网头部之后写入8字节序号
+const MIN_PACKET_SIZE: usize = 22;
+
+pub struct BenchOptions {
+    /// 写入配置（输出目录、文件格式、滚动条件、写入队列等），与真实捕获使用的配置相同
+    pub capture_options: PcapCaptureOptions,
+    /// 每个合成数据包的字节数
+    pub packet_size: usize,
+    /// 目标速率（包/秒），None表示尽可能快地生成
+    pub packet_rate: Option<u64>,
+    /// 测试时长
+    pub duration: Duration,
+    /// 模拟的内核捕获缓冲区可容纳的数据包数量，写入落后超过该数量的部分计为丢包
+    pub kernel_buffer_packets: u64,
+    /// 保留测试生成的文件，默认测试结束后删除
+    pub keep_files: bool,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        Self {
+            capture_options: PcapCaptureOptions {
+                file_prefix: "bench".to_string(),
+                continuous_capture: true,
+                rollover_file_size_mb: Some(100),
+                ..Default::default()
+            },
+            packet_size: 512,
+            packet_rate: None,
+            duration: Duration::from_secs(10),
+            kernel_buffer_packets: 4096,
+            keep_files: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BenchReport {
+    pub elapsed: Duration,
+    /// 生成的数据包数量（包括丢弃的）
+    pub packets_generated: u64,
+    pub packets_written: u64,
+    pub packets_dropped: u64,
+    /// 写入的总字节数（包括每个数据包的记录头）
+    pub bytes_written: u64,
+    pub files_created: u64,
+    pub rotation_time_avg: Duration,
+    pub rotation_time_max: Duration,
+    /// 写入队列出现过的最大占用量（仅在启用独立写入线程时有效）
+    pub writer_queue_high_watermark: usize,
+}
+
+impl BenchReport {
+    pub fn packets_per_second(&self) -> f64 {
+        self.packets_written as f64 / self.elapsed.as_secs_f64()
+    }
+
+    pub fn megabytes_per_second(&self) -> f64 {
+        self.bytes_written as f64 / (1024.0 * 1024.0) / self.elapsed.as_secs_f64()
+    }
+
+    pub fn drop_rate(&self) -> f64 {
+        if self.packets_generated == 0 {
+            0.0
+        } else {
+            self.packets_dropped as f64 / self.packets_generated as f64
+        }
+    }
+}
+
+/// 运行写入性能测试。测试文件写入`capture_options.file_path`下的独立子目录。
+pub fn run_benchmark(options: BenchOptions) -> Result<BenchReport, SavePcapError> {
+    let run_dir = Path::new(&options.capture_options.file_path).join(format!(
+        "save_pcap_bench_{}",
+        Local::now().format("%Y%m%d_%H%M%S")
+    ));
+    fs::create_dir_all(&run_dir)?;
+
+    let mut capture_options = options.capture_options;
+    capture_options.file_path = run_dir.to_string_lossy().into_owned();
+    capture_options.packet_limit = None;
+    let capturer = PcapCapturer::new(capture_options);
+
+    let mut stream = SyntheticStream::new(
+        options.packet_size,
+        options.packet_rate,
+        options.duration,
+        options.kernel_buffer_packets,
+    );
+    info!(
+        "Starting write benchmark: {} byte packets at {}, output in {:?}",
+        options.packet_size,
+        options
+            .packet_rate
+            .map(|rate| format!("{} packets/s", rate))
+            .unwrap_or_else(|| "maximum rate".to_string()),
+        run_dir
+    );
+
+    let started = Instant::now();
+    let result = capturer.run_capture(&mut stream);
+    let elapsed = started.elapsed();
+
+    if !options.keep_files
+        && let Err(e) = fs::remove_dir_all(&run_dir)
+    {
+        warn!("Failed to remove benchmark files in {:?}: {}", run_dir, e);
+    }
+    result?;
+
+    let stats = capturer.handle().stats();
+    Ok(BenchReport {
+        elapsed,
+        packets_generated: stream.generated,
+        packets_written: stats.packets_written,
+        packets_dropped: stream.dropped,
+        bytes_written: stats.bytes_written,
+        files_created: stats.rotations + 1,
+        rotation_time_avg: stats
+            .rotation_time_total
+            .checked_div(stats.rotations as u32)
+            .unwrap_or_default(),
+        rotation_time_max: stats.rotation_time_max,
+        writer_queue_high_watermark: stats.writer_queue_high_watermark,
+    })
+}
+
+// 按目标速率生成数据包；写入跟不上时，超出模拟内核缓冲区的部分计为丢包
+struct SyntheticStream {
+    template: Vec<u8>,
+    packet_rate: Option<u64>,
+    duration: Duration,
+    kernel_buffer_packets: u64,
+    started: Instant,
+    generated: u64,
+    dropped: u64,
+}
+
+impl SyntheticStream {
+    fn new(
+        packet_size: usize,
+        packet_rate: Option<u64>,
+        duration: Duration,
+        kernel_buffer_packets: u64,
+    ) -> Self {
+        // 广播目的地址、全零源地址的以太网帧，其余字节填充固定内容
+        let mut template = vec![0xA5; packet_size.max(MIN_PACKET_SIZE)];
+        template[..6].fill(0xFF);
+        template[6..12].fill(0x00);
+        template[12..14].copy_from_slice(&BENCH_ETHERTYPE);
+
+        Self {
+            template,
+            packet_rate,
+            duration,
+            kernel_buffer_packets,
+            started: Instant::now(),
+            generated: 0,
+            dropped: 0,
+        }
+    }
+}
+
+impl PacketStream for SyntheticStream {
+    fn datalink(&self) -> DataLink {
+        DataLink::ETHERNET
+    }
+
+    fn next_packet(&mut self, pool: &mut BufferPool) -> Result<NextPacket, SavePcapError> {
+        let elapsed = self.started.elapsed();
+        if elapsed >= self.duration {
+            return Ok(NextPacket::End);
+        }
+
+        if let Some(rate) = self.packet_rate {
+            let due = (elapsed.as_secs_f64() * rate as f64) as u64;
+            if self.generated >= due {
+                thread::sleep(Duration::from_micros(50));
+                return Ok(NextPacket::Idle);
+            }
+
+            let backlog = due - self.generated;
+            if backlog > self.kernel_buffer_packets {
+                let lost = backlog - self.kernel_buffer_packets;
+                self.dropped += lost;
+                self.generated += lost;
+            }
+        }
+
+        let mut data = pool.take();
+        data.extend_from_slice(&self.template);
+        data[14..22].copy_from_slice(&self.generated.to_be_bytes());
+        self.generated += 1;
+
+        Ok(NextPacket::Packet(SourcePacket {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default(),
+            orig_len: data.len() as u32,
+            data,
+            user_index: None,
+        }))
+    }
+}
@@ -0,0 +1,52 @@
+use chrono::{DateTime, Local, TimeZone, Timelike};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// 按整点对齐的时间段（本地时间），用于滚动和命名文件
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeBucket {
+    /// 每小时一个文件，例如`capture_2024-06-01_14.pcap`
+    Hour,
+    /// 每天一个文件，例如`capture_2024-06-01.pcap`
+    Day,
+}
+
+/// 时间戳早于当前时间段的数据包（例如网卡或发送方延迟送达的数据包）的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LatePacketPolicy {
+    /// 写入当前时间段的文件
+    #[default]
+    Current,
+    /// 丢弃，并通过`rejected_packets`报告
+    Drop,
+}
+
+impl TimeBucket {
+    // 时间戳所在时间段的开始时间
+    pub(crate) fn start(self, timestamp: Duration) -> Duration {
+        let time = DateTime::<Local>::from(UNIX_EPOCH + timestamp);
+        let start = match self {
+            TimeBucket::Hour => time
+                .with_minute(0)
+                .and_then(|time| time.with_second(0))
+                .and_then(|time| time.with_nanosecond(0)),
+            // 夏令时切换可能使午夜不存在，取当天最早的时间
+            TimeBucket::Day => time
+                .date_naive()
+                .and_hms_opt(0, 0, 0)
+                .and_then(|midnight| Local.from_local_datetime(&midnight).earliest()),
+        };
+        start
+            .and_then(|start| u64::try_from(start.timestamp()).ok())
+            .map(Duration::from_secs)
+            .unwrap_or(timestamp)
+    }
+
+    // 文件名中的时间部分
+    pub(crate) fn label(self, start: Duration) -> String {
+        let time = DateTime::<Local>::from(UNIX_EPOCH + start);
+        match self {
+            TimeBucket::Hour => time.format("%Y-%m-%d_%H").to_string(),
+            TimeBucket::Day => time.format("%Y-%m-%d").to_string(),
+        }
+    }
+}
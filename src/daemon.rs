@@ -0,0 +1,199 @@
+//! Unix守护进程辅助函数：脱离终端在后台运行持续捕获，写入pid文件，
+//! 并通过信号（SIGTERM/SIGINT）控制捕获的停止。
+
+use crate::{CaptureHandle, SavePcapError};
+use log::{info, warn};
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, Write};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+pub struct DaemonOptions {
+    /// pid文件路径
+    pub pid_file: PathBuf,
+    /// 日志输出文件，守护进程的stdout/stderr会被重定向到该文件；None表示丢弃
+    pub log_file: Option<PathBuf>,
+    /// 守护进程的工作目录
+    pub working_dir: PathBuf,
+}
+
+impl Default for DaemonOptions {
+    fn default() -> Self {
+        Self {
+            pid_file: PathBuf::from("save_pcap.pid"),
+            log_file: None,
+            working_dir: PathBuf::from("/"),
+        }
+    }
+}
+
+/// pid文件守卫，进程正常退出时删除pid文件。守护进程运行期间持有pid文件的排他锁，
+/// 锁由操作系统在进程退出（包括崩溃）时释放
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+    // 持有pid文件锁，守卫释放时先删除文件再关闭
+    _lock: File,
+}
+
+impl PidFile {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("Failed to remove pid file {:?}: {}", self.path, e);
+        }
+    }
+}
+
+/// 将当前进程转为守护进程（两次fork + setsid），重定向标准输入输出并写入pid文件。
+///
+/// 必须在创建任何线程之前调用，返回的`PidFile`需要保持到进程退出。
+pub fn daemonize(options: &DaemonOptions) -> Result<PidFile, SavePcapError> {
+    // 切换工作目录前先把相对路径转换为绝对路径
+    let pid_file = std::path::absolute(&options.pid_file)?;
+    let log_file = match &options.log_file {
+        Some(path) => Some(std::path::absolute(path)?),
+        None => None,
+    };
+
+    // fork之前加锁，锁随文件描述符由守护进程继承
+    let mut file = lock_pid_file(&pid_file)?;
+
+    fork_and_exit_parent()?;
+    if unsafe { libc::setsid() } == -1 {
+        return Err(io::Error::last_os_error().into());
+    }
+    // 第二次fork确保守护进程不会重新获得控制终端
+    fork_and_exit_parent()?;
+
+    unsafe {
+        libc::umask(0o027);
+    }
+    std::env::set_current_dir(&options.working_dir)?;
+    redirect_stdio(log_file.as_deref())?;
+
+    file.set_len(0)?;
+    writeln!(file, "{}", std::process::id())?;
+    info!("Daemon started with pid {}", std::process::id());
+
+    Ok(PidFile {
+        path: pid_file,
+        _lock: file,
+    })
+}
+
+/// 收到SIGTERM或SIGINT时通过控制句柄结束捕获，使当前文件正常关闭
+pub fn stop_on_signals(handle: CaptureHandle) -> Result<(), SavePcapError> {
+    for signal in [libc::SIGTERM, libc::SIGINT] {
+        let previous = unsafe {
+            libc::signal(
+                signal,
+                handle_stop_signal as extern "C" fn(libc::c_int) as libc::sighandler_t,
+            )
+        };
+        if previous == libc::SIG_ERR {
+            return Err(io::Error::last_os_error().into());
+        }
+    }
+
+    // 信号处理函数中只能做异步信号安全的操作，这里由后台线程转发停止请求
+    thread::spawn(move || {
+        while !STOP_REQUESTED.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(200));
+        }
+        info!("Termination signal received, stopping capture");
+        handle.stop();
+    });
+
+    Ok(())
+}
+
+extern "C" fn handle_stop_signal(_signal: libc::c_int) {
+    STOP_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+fn fork_and_exit_parent() -> Result<(), SavePcapError> {
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error().into()),
+        0 => Ok(()),
+        _ => unsafe { libc::_exit(0) },
+    }
+}
+
+fn redirect_stdio(log_file: Option<&Path>) -> io::Result<()> {
+    let null = File::open("/dev/null")?;
+    let output = match log_file {
+        Some(path) => OpenOptions::new().create(true).append(true).open(path)?,
+        None => OpenOptions::new().write(true).open("/dev/null")?,
+    };
+
+    for (source, target) in [
+        (null.as_raw_fd(), libc::STDIN_FILENO),
+        (output.as_raw_fd(), libc::STDOUT_FILENO),
+        (output.as_raw_fd(), libc::STDERR_FILENO),
+    ] {
+        if unsafe { libc::dup2(source, target) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+// 创建并锁住pid文件。文件已存在时（例如上次异常退出留下的）由锁判断持有它的进程是否仍在运行，
+// 同时启动的两个进程只有一个能拿到锁
+fn lock_pid_file(path: &Path) -> Result<File, SavePcapError> {
+    loop {
+        let file = match OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path)
+        {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                match OpenOptions::new().read(true).write(true).open(path) {
+                    Ok(file) => file,
+                    // 持有者刚好退出并删除了文件，重新创建
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            Err(e) => return Err(e.into()),
+        };
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let holder = fs::read_to_string(path).unwrap_or_default();
+                let holder = holder.trim();
+                return Err(SavePcapError::AlreadyRunning(if holder.is_empty() {
+                    format!("another process holds pid file {}", path.display())
+                } else {
+                    format!("process {} holds pid file {}", holder, path.display())
+                }));
+            }
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+        // 加锁前持有者退出时锁住的是已被删除的文件，需要重新创建
+        let locked = file.metadata()?;
+        match fs::metadata(path) {
+            Ok(current) if current.dev() == locked.dev() && current.ino() == locked.ino() => {
+                return Ok(file);
+            }
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use crate::SavePcapError;
+use crate::diagnose;
+use pcap::Device;
+use std::collections::HashMap;
+
+/// 网卡的系统标识和便于识别的名称
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    /// 系统中的设备标识，例如"eth0"、"en0"或"\\Device\\NPF_{...}"
+    pub name: String,
+    /// 系统显示的名称：Windows为连接名（"Ethernet 2"），macOS为硬件端口名（"Wi-Fi"），
+    /// Linux为网卡别名（ifalias）；没有时与name相同
+    pub friendly_name: String,
+    /// 驱动提供的描述，通常为网卡型号
+    pub description: Option<String>,
+}
+
+impl DeviceInfo {
+    /// 名称加描述，例如"Wi-Fi (Intel(R) Wi-Fi 6E AX210 160MHz)"，适合在选择列表中显示
+    pub fn display_name(&self) -> String {
+        match &self.description {
+            Some(description) if *description != self.friendly_name => {
+                format!("{} ({})", self.friendly_name, description)
+            }
+            _ => self.friendly_name.clone(),
+        }
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        self.friendly_name.eq_ignore_ascii_case(name)
+            || self.display_name().eq_ignore_ascii_case(name)
+    }
+}
+
+/// 与`get_available_devices`相同，同时返回每个网卡的友好名称和描述。
+/// `PacketSource::NetworkDevice`也接受友好名称或`display_name()`（不区分大小写）
+pub fn get_available_devices_detailed() -> Result<Vec<DeviceInfo>, SavePcapError> {
+    Ok(describe(Device::list()?))
+}
+
+// 把设备名或友好名称解析为系统设备标识，找不到时返回与打开网卡相同的错误
+pub(crate) fn resolve_device(name: &str) -> Result<String, SavePcapError> {
+    let devices = Device::list().map_err(|e| diagnose::device_error(name, e))?;
+    if devices.iter().any(|d| d.name == name) {
+        return Ok(name.to_string());
+    }
+    describe(devices)
+        .into_iter()
+        .find(|device| device.matches(name))
+        .map(|device| device.name)
+        .ok_or_else(|| diagnose::missing_device_error(name))
+}
+
+fn describe(devices: Vec<Device>) -> Vec<DeviceInfo> {
+    let mut friendly_names = friendly_names(&devices);
+    devices
+        .into_iter()
+        .map(|device| DeviceInfo {
+            friendly_name: friendly_names
+                .remove(&device.name)
+                .unwrap_or_else(|| device.name.clone()),
+            description: device.desc.filter(|desc| !desc.is_empty()),
+            name: device.name,
+        })
+        .collect()
+}
+
+// 连接名保存在注册表中，键名为设备标识中的GUID
+#[cfg(windows)]
+fn friendly_names(devices: &[Device]) -> HashMap<String, String> {
+    use std::process::Command;
+
+    const NETWORK_KEY: &str =
+        r"HKLM\SYSTEM\CurrentControlSet\Control\Network\{4D36E972-E325-11CE-BFC1-08002BE10318}";
+    let mut names = HashMap::new();
+    for device in devices {
+        let Some(guid) = device.name.find('{').map(|start| &device.name[start..]) else {
+            continue;
+        };
+        let key = format!(r"{}\{}\Connection", NETWORK_KEY, guid);
+        let Ok(output) = Command::new("reg")
+            .args(["query", &key, "/v", "Name"])
+            .output()
+        else {
+            continue;
+        };
+        // 输出形如"    Name    REG_SZ    Ethernet 2"
+        let name = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .find_map(|line| {
+                line.split_once("REG_SZ")
+                    .map(|(_, name)| name.trim().to_string())
+            });
+        if let Some(name) = name.filter(|name| !name.is_empty()) {
+            names.insert(device.name.clone(), name);
+        }
+    }
+    names
+}
+
+// networksetup的输出中每个硬件端口为"Hardware Port: Wi-Fi"后跟"Device: en0"
+#[cfg(target_os = "macos")]
+fn friendly_names(_devices: &[Device]) -> HashMap<String, String> {
+    use std::process::Command;
+
+    let mut names = HashMap::new();
+    let Ok(output) = Command::new("networksetup")
+        .arg("-listallhardwareports")
+        .output()
+    else {
+        return names;
+    };
+    let mut port = None;
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        if let Some(name) = line.strip_prefix("Hardware Port: ") {
+            port = Some(name.trim().to_string());
+        } else if let Some(device) = line.strip_prefix("Device: ")
+            && let Some(port) = port.take()
+        {
+            names.insert(device.trim().to_string(), port);
+        }
+    }
+    names
+}
+
+// 网卡名本身即可识别，只使用管理员通过`ip link set <dev> alias`设置的别名
+#[cfg(target_os = "linux")]
+fn friendly_names(devices: &[Device]) -> HashMap<String, String> {
+    devices
+        .iter()
+        .filter_map(|device| {
+            let path = format!("/sys/class/net/{}/ifalias", device.name);
+            let alias = std::fs::read_to_string(path).ok()?;
+            let alias = alias.trim();
+            (!alias.is_empty()).then(|| (device.name.clone(), alias.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(not(any(windows, target_os = "macos", target_os = "linux")))]
+fn friendly_names(_devices: &[Device]) -> HashMap<String, String> {
+    HashMap::new()
+}
@@ -0,0 +1,104 @@
+use crate::SavePcapError;
+use crate::health::device_state;
+use pcap::Error as PcapError;
+use std::fmt;
+
+/// 无法打开网卡进行捕获的常见原因，Display包含处理建议
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceErrorCause {
+    /// 未安装Npcap（或其驱动未运行，仅Windows）
+    DriverMissing,
+    /// 没有捕获权限，例如Linux下缺少CAP_NET_RAW
+    PermissionDenied,
+    /// 网卡存在但未启用
+    InterfaceDown,
+}
+
+impl fmt::Display for DeviceErrorCause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceErrorCause::DriverMissing => write!(
+                f,
+                "Npcap is not installed or its driver is not running; install it from https://npcap.com with \"WinPcap API-compatible mode\" enabled, or start the npcap service"
+            ),
+            DeviceErrorCause::PermissionDenied => write!(f, "{}", PERMISSION_HINT),
+            DeviceErrorCause::InterfaceDown => write!(
+                f,
+                "the interface is down; bring it up (for example `ip link set <device> up`) and check the cable or wireless connection"
+            ),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+const PERMISSION_HINT: &str = "permission denied; run as root or grant the capture capabilities with `sudo setcap cap_net_raw,cap_net_admin=eip <program>`";
+#[cfg(target_os = "macos")]
+const PERMISSION_HINT: &str = "permission denied; run as root or give your user read access to /dev/bpf* (for example with Wireshark's ChmodBPF)";
+#[cfg(windows)]
+const PERMISSION_HINT: &str = "permission denied; run as Administrator, or reinstall Npcap without \"Restrict Npcap driver's access to Administrators only\"";
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+const PERMISSION_HINT: &str = "permission denied; run as root";
+
+// 打开网卡失败时识别常见原因，无法识别时保留libpcap的原始错误
+pub(crate) fn device_error(device: &str, error: PcapError) -> SavePcapError {
+    let detail = match &error {
+        PcapError::PcapError(message) => message.clone(),
+        error => error.to_string(),
+    };
+    match cause(device, &detail) {
+        Some(cause) => SavePcapError::DeviceUnavailable {
+            device: device.to_string(),
+            cause,
+            detail,
+        },
+        None => SavePcapError::PcapError(error),
+    }
+}
+
+// 网卡列表中没有该设备时调用：Windows下没有Npcap时列表总是为空
+pub(crate) fn missing_device_error(device: &str) -> SavePcapError {
+    if driver_missing() {
+        SavePcapError::DeviceUnavailable {
+            device: device.to_string(),
+            cause: DeviceErrorCause::DriverMissing,
+            detail: "no capture devices found".to_string(),
+        }
+    } else {
+        SavePcapError::InvalidDevice(device.to_string())
+    }
+}
+
+fn cause(device: &str, message: &str) -> Option<DeviceErrorCause> {
+    let message = message.to_lowercase();
+    let contains_any = |patterns: &[&str]| patterns.iter().any(|p| message.contains(p));
+
+    // 各平台libpcap/Npcap的错误信息，例如
+    // "eth0: You don't have permission to perform this capture on that device (socket: Operation not permitted)"
+    if driver_missing()
+        || (cfg!(windows) && contains_any(&["npf", "npcap", "wpcap", "packetgetadapternames"]))
+    {
+        Some(DeviceErrorCause::DriverMissing)
+    } else if contains_any(&["permission", "not permitted", "access is denied"]) {
+        Some(DeviceErrorCause::PermissionDenied)
+    } else if contains_any(&["not up", "network is down"]) {
+        Some(DeviceErrorCause::InterfaceDown)
+    } else {
+        // 错误信息不明确时再看网卡状态；网卡不存在时不属于这几种原因
+        match device_state(device) {
+            Some(state) if state != "missing" => Some(DeviceErrorCause::InterfaceDown),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(windows)]
+fn driver_missing() -> bool {
+    let root = std::env::var("SystemRoot").unwrap_or_else(|_| r"C:\Windows".to_string());
+    let system32 = std::path::Path::new(&root).join("System32");
+    !system32.join("Npcap").join("wpcap.dll").exists() && !system32.join("wpcap.dll").exists()
+}
+
+#[cfg(not(windows))]
+fn driver_missing() -> bool {
+    false
+}
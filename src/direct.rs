@@ -0,0 +1,131 @@
+use log::error;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Seek, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+// O_DIRECT要求缓冲区地址、写入长度和文件偏移都按逻辑块大小对齐，4096可覆盖常见设备
+const ALIGNMENT: usize = 4096;
+// 每次攒满1MB再写出
+const BUFFER_BLOCKS: usize = 256;
+
+#[repr(C, align(4096))]
+#[derive(Clone, Copy)]
+struct Block([u8; ALIGNMENT]);
+
+// 以O_DIRECT打开文件并只写出完整的对齐块，持续捕获时不占用页缓存，
+// 不会把宿主应用的工作集挤出内存。文件末尾不足一块的数据在关闭时写出。
+pub(crate) struct DirectWriter {
+    file: File,
+    blocks: Vec<Block>,
+    len: usize,
+}
+
+impl DirectWriter {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .custom_flags(libc::O_DIRECT)
+            .open(path)?;
+
+        Ok(Self {
+            file,
+            blocks: vec![Block([0; ALIGNMENT]); BUFFER_BLOCKS],
+            len: 0,
+        })
+    }
+
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// 当前写入位置，包括缓冲区中尚未写出的数据
+    pub fn position(&self) -> io::Result<u64> {
+        let mut file = &self.file;
+        Ok(file.stream_position()? + self.len as u64)
+    }
+
+    /// 写出包括末尾不足一块在内的全部数据，之后不应再写入
+    pub fn close(&mut self) -> io::Result<()> {
+        self.write_tail()
+    }
+
+    /// 丢弃缓冲区中尚未写出的数据
+    pub fn discard(&mut self) {
+        self.len = 0;
+    }
+
+    fn capacity(&self) -> usize {
+        self.blocks.len() * ALIGNMENT
+    }
+
+    // 写出缓冲区中所有完整的块，剩余不足一块的数据移到缓冲区开头
+    fn write_full_blocks(&mut self) -> io::Result<()> {
+        let full = self.len / ALIGNMENT * ALIGNMENT;
+        if full == 0 {
+            return Ok(());
+        }
+
+        let buffer = as_bytes_mut(&mut self.blocks);
+        self.file.write_all(&buffer[..full])?;
+        buffer.copy_within(full..self.len, 0);
+        self.len -= full;
+        Ok(())
+    }
+
+    fn write_tail(&mut self) -> io::Result<()> {
+        self.write_full_blocks()?;
+        if self.len == 0 {
+            return Ok(());
+        }
+
+        // 文件末尾不足一块的数据无法以O_DIRECT写入，先清除该标志
+        let fd = self.file.as_raw_fd();
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+        if flags == -1 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_DIRECT) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let len = self.len;
+        self.file
+            .write_all(&as_bytes_mut(&mut self.blocks)[..len])?;
+        self.len = 0;
+        Ok(())
+    }
+}
+
+impl Write for DirectWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let count = buf.len().min(self.capacity() - self.len);
+        let start = self.len;
+        as_bytes_mut(&mut self.blocks)[start..start + count].copy_from_slice(&buf[..count]);
+        self.len += count;
+
+        if self.len == self.capacity() {
+            self.write_full_blocks()?;
+        }
+        Ok(count)
+    }
+
+    // 只能写出完整的块，不足一块的数据保留到后续写入或关闭文件时
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_full_blocks()
+    }
+}
+
+impl Drop for DirectWriter {
+    fn drop(&mut self) {
+        if let Err(e) = self.write_tail() {
+            error!("Failed to write the end of an O_DIRECT file: {}", e);
+        }
+    }
+}
+
+fn as_bytes_mut(blocks: &mut [Block]) -> &mut [u8] {
+    unsafe {
+        std::slice::from_raw_parts_mut(blocks.as_mut_ptr().cast::<u8>(), blocks.len() * ALIGNMENT)
+    }
+}
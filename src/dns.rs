@@ -0,0 +1,269 @@
+use crate::metadata::{self, json_string};
+use crate::parse::{self, IPPROTO_UDP, Transport};
+use crate::source::SourcePacket;
+use pcap_file::DataLink;
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+
+// DNS和mDNS的UDP端口
+const DNS_PORTS: [u16; 2] = [53, 5353];
+const HEADER_LEN: usize = 12;
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+// 域名压缩指针的最大跳转次数，防止构造的报文形成循环
+const MAX_POINTER_JUMPS: usize = 16;
+const MAX_NAME_LEN: usize = 255;
+
+/// 一条DNS查询或响应中用到的字段
+pub(crate) struct DnsMessage {
+    pub id: u16,
+    pub response: bool,
+    pub rcode: u8,
+    pub qname: Option<String>,
+    pub qtype: Option<u16>,
+    /// 响应中A/AAAA记录的所有者名称和地址
+    pub addresses: Vec<(String, IpAddr)>,
+}
+
+/// 解码UDP 53/5353端口上的DNS报文；基于TCP的DNS不解析
+pub(crate) fn decode(datalink: DataLink, data: &[u8]) -> Option<(Transport<'_>, DnsMessage)> {
+    let transport = parse::transport(datalink, data)?;
+    if transport.protocol != IPPROTO_UDP
+        || !(DNS_PORTS.contains(&transport.source_port)
+            || DNS_PORTS.contains(&transport.destination_port))
+    {
+        return None;
+    }
+    let message = decode_message(transport.payload)?;
+    Some((transport, message))
+}
+
+fn decode_message(payload: &[u8]) -> Option<DnsMessage> {
+    let header = payload.get(..HEADER_LEN)?;
+    let id = u16::from_be_bytes([header[0], header[1]]);
+    let response = header[2] & 0x80 != 0;
+    let rcode = header[3] & 0x0F;
+    let question_count = u16::from_be_bytes([header[4], header[5]]);
+    let answer_count = u16::from_be_bytes([header[6], header[7]]);
+
+    let mut message = DnsMessage {
+        id,
+        response,
+        rcode,
+        qname: None,
+        qtype: None,
+        addresses: Vec::new(),
+    };
+
+    let mut offset = HEADER_LEN;
+    for i in 0..question_count {
+        let (name, end) = read_name(payload, offset)?;
+        let qtype = u16::from_be_bytes([*payload.get(end)?, *payload.get(end + 1)?]);
+        // 实际使用中每个报文只有一个问题，只记录第一个
+        if i == 0 {
+            message.qname = Some(name);
+            message.qtype = Some(qtype);
+        }
+        offset = end + 4;
+    }
+
+    // 应答部分被快照长度截断时保留已解析的部分
+    for _ in 0..answer_count {
+        let Some((name, end)) = read_name(payload, offset) else {
+            break;
+        };
+        let Some(fixed) = payload.get(end..end + 10) else {
+            break;
+        };
+        let record_type = u16::from_be_bytes([fixed[0], fixed[1]]);
+        let data_len = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+        let Some(data) = payload.get(end + 10..end + 10 + data_len) else {
+            break;
+        };
+        let address = match (record_type, data.len()) {
+            (TYPE_A, 4) => Some(IpAddr::from(Ipv4Addr::new(
+                data[0], data[1], data[2], data[3],
+            ))),
+            (TYPE_AAAA, 16) => {
+                let octets: [u8; 16] = data.try_into().ok()?;
+                Some(IpAddr::from(Ipv6Addr::from(octets)))
+            }
+            _ => None,
+        };
+        if let Some(address) = address {
+            message.addresses.push((name, address));
+        }
+        offset = end + 10 + data_len;
+    }
+
+    Some(message)
+}
+
+// 读取从offset开始的域名（支持压缩指针），返回域名和域名之后的偏移
+fn read_name(payload: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut name = String::new();
+    let mut end = None;
+    let mut jumps = 0;
+    loop {
+        let len = *payload.get(offset)? as usize;
+        if len == 0 {
+            break;
+        }
+        if len & 0xC0 == 0xC0 {
+            let pointer = ((len & 0x3F) << 8) | *payload.get(offset + 1)? as usize;
+            end.get_or_insert(offset + 2);
+            jumps += 1;
+            if jumps > MAX_POINTER_JUMPS {
+                return None;
+            }
+            offset = pointer;
+            continue;
+        }
+
+        let label = payload.get(offset + 1..offset + 1 + len)?;
+        if !name.is_empty() {
+            name.push('.');
+        }
+        name.push_str(&String::from_utf8_lossy(label));
+        if name.len() > MAX_NAME_LEN {
+            return None;
+        }
+        offset += 1 + len;
+    }
+    Some((name, end.unwrap_or(offset + 1)))
+}
+
+fn type_name(qtype: u16) -> Cow<'static, str> {
+    match qtype {
+        1 => "A".into(),
+        2 => "NS".into(),
+        5 => "CNAME".into(),
+        6 => "SOA".into(),
+        12 => "PTR".into(),
+        15 => "MX".into(),
+        16 => "TXT".into(),
+        28 => "AAAA".into(),
+        33 => "SRV".into(),
+        64 => "SVCB".into(),
+        65 => "HTTPS".into(),
+        255 => "ANY".into(),
+        other => format!("TYPE{}", other).into(),
+    }
+}
+
+fn rcode_name(rcode: u8) -> Cow<'static, str> {
+    match rcode {
+        0 => "NOERROR".into(),
+        1 => "FORMERR".into(),
+        2 => "SERVFAIL".into(),
+        3 => "NXDOMAIN".into(),
+        4 => "NOTIMP".into(),
+        5 => "REFUSED".into(),
+        other => format!("RCODE{}", other).into(),
+    }
+}
+
+// 每个捕获文件对应一个`.dns.jsonl`查询日志，每行一个查询或响应
+pub(crate) struct DnsLog {
+    entries: String,
+}
+
+impl DnsLog {
+    pub fn new() -> Self {
+        Self {
+            entries: String::new(),
+        }
+    }
+
+    pub fn record(&mut self, datalink: DataLink, packet: &SourcePacket) {
+        let Some((transport, message)) = decode(datalink, &packet.data) else {
+            return;
+        };
+
+        let addresses: Vec<_> = message
+            .addresses
+            .iter()
+            .map(|(_, address)| json_string(&address.to_string()))
+            .collect();
+        let _ = writeln!(
+            self.entries,
+            "{{\"timestamp\": {}, \"source\": {}, \"destination\": {}, \"id\": {}, \"response\": {}, \"qname\": {}, \"qtype\": {}, \"rcode\": {}, \"addresses\": [{}]}}",
+            json_string(&metadata::rfc3339(packet.timestamp)),
+            json_string(&SocketAddr::new(transport.source, transport.source_port).to_string()),
+            json_string(
+                &SocketAddr::new(transport.destination, transport.destination_port).to_string()
+            ),
+            message.id,
+            message.response,
+            message
+                .qname
+                .as_deref()
+                .map(json_string)
+                .unwrap_or_else(|| "null".to_string()),
+            message
+                .qtype
+                .map(|qtype| json_string(&type_name(qtype)))
+                .unwrap_or_else(|| "null".to_string()),
+            if message.response {
+                json_string(&rcode_name(message.rcode))
+            } else {
+                "null".to_string()
+            },
+            addresses.join(", "),
+        );
+    }
+
+    pub fn discard(&mut self) {
+        self.entries.clear();
+    }
+
+    /// 没有任何DNS报文时不创建文件
+    pub fn write_sidecar(&mut self, capture_path: &Path) -> io::Result<()> {
+        if self.entries.is_empty() {
+            return Ok(());
+        }
+        let path = metadata::sidecar_path_with(capture_path, metadata::DNS_SUFFIX);
+        let result = fs::write(path, &self.entries);
+        self.entries.clear();
+        result
+    }
+}
+
+// 记录当前文件中已写入名称解析块的地址和名称，同一对只写一次
+pub(crate) struct NameResolution {
+    written: HashSet<(IpAddr, String)>,
+}
+
+impl NameResolution {
+    pub fn new() -> Self {
+        Self {
+            written: HashSet::new(),
+        }
+    }
+
+    /// 返回DNS响应中当前文件尚未记录过的地址和名称
+    pub fn new_records(&mut self, datalink: DataLink, data: &[u8]) -> Vec<(IpAddr, String)> {
+        let Some((_, message)) = decode(datalink, data) else {
+            return Vec::new();
+        };
+        if !message.response {
+            return Vec::new();
+        }
+        message
+            .addresses
+            .into_iter()
+            .filter(|(name, address)| self.written.insert((*address, name.clone())))
+            .map(|(name, address)| (address, name))
+            .collect()
+    }
+
+    /// 换到新文件时调用，新文件需要重新记录
+    pub fn reset(&mut self) {
+        self.written.clear();
+    }
+}
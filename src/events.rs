@@ -0,0 +1,231 @@
+use crate::metadata::{json_string, labels_json, rfc3339};
+use crate::observer::{ClosedFile, Observer};
+use crate::stats::{CaptureStats, PcapStats, StatsCounters};
+use crate::{PcapCaptureOptions, WriteErrorPolicy};
+use log::{info, warn};
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// 结构化事件的输出位置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JsonEventTarget {
+    /// 以`save_pcap::events`为target的info级别日志，每条消息为一行JSON
+    Log,
+    /// 追加写入该文件，每行一个JSON对象（JSON Lines）
+    File(String),
+}
+
+/// 把捕获事件（开始、滚动、暂停、恢复、出错、结束）输出为JSON，字段名和取值都是固定的英文，
+/// 便于日志系统解析
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonEventOptions {
+    /// 写入每个事件的`session`字段，用于区分同一日志中的多个捕获会话
+    pub session_id: String,
+    pub target: JsonEventTarget,
+}
+
+impl JsonEventOptions {
+    pub fn new(session_id: impl Into<String>) -> Self {
+        Self {
+            session_id: session_id.into(),
+            target: JsonEventTarget::Log,
+        }
+    }
+}
+
+// 作为观察者注册到会话状态，事件中的计数来自捕获统计
+pub(crate) struct JsonEventLog {
+    session_id: String,
+    // 文件在第一个事件时打开，打开失败后改为输出到日志
+    file: Mutex<Option<File>>,
+    path: Option<String>,
+    stats: Arc<StatsCounters>,
+    // 文件名实际使用的前缀和设备名（已处理掉不能用于文件名的字符），在start事件中输出
+    file_prefix: String,
+    file_device: Option<String>,
+    labels: Vec<(String, String)>,
+}
+
+impl JsonEventLog {
+    pub fn new(
+        options: &JsonEventOptions,
+        capture: &PcapCaptureOptions,
+        stats: Arc<StatsCounters>,
+    ) -> Self {
+        let path = match &options.target {
+            JsonEventTarget::Log => None,
+            JsonEventTarget::File(path) => Some(path.clone()),
+        };
+        Self {
+            session_id: options.session_id.clone(),
+            file: Mutex::new(None),
+            path,
+            stats,
+            file_prefix: capture.file_prefix.clone(),
+            file_device: capture.file_name_device(),
+            labels: capture.labels.clone(),
+        }
+    }
+
+    // fields为事件特有的字段，已按JSON格式转义
+    fn emit(&self, event: &str, fields: &[(&str, String)], stats: &CaptureStats) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut json = format!(
+            "{{\"time\": {}, \"session\": {}, \"event\": {}",
+            json_string(&rfc3339(now)),
+            json_string(&self.session_id),
+            json_string(event)
+        );
+        if let Some(file) = self.stats.live_snapshot().current_file {
+            let _ = write!(
+                json,
+                ", \"file\": {}",
+                json_string(&file.display().to_string())
+            );
+        }
+        for (name, value) in fields {
+            let _ = write!(json, ", {}: {}", json_string(name), value);
+        }
+        let _ = write!(
+            json,
+            ", \"packets_written\": {}, \"bytes_written\": {}, \"rotations\": {}, \"invalid_packets\": {}, \"kernel_dropped\": {}}}",
+            stats.packets_written,
+            stats.bytes_written,
+            stats.rotations,
+            stats.invalid_packets,
+            stats.kernel_dropped
+        );
+
+        if self.path.is_none() || !self.write_line(&json) {
+            info!(target: "save_pcap::events", "{}", json);
+        }
+    }
+
+    // 返回是否写入了文件
+    fn write_line(&self, json: &str) -> bool {
+        let (Some(path), Ok(mut file)) = (&self.path, self.file.lock()) else {
+            return false;
+        };
+        if file.is_none() {
+            match OpenOptions::new().create(true).append(true).open(path) {
+                Ok(opened) => *file = Some(opened),
+                Err(e) => {
+                    warn!("Failed to open event log {}: {}", path, e);
+                    return false;
+                }
+            }
+        }
+        let Some(opened) = file.as_mut() else {
+            return false;
+        };
+        match writeln!(opened, "{}", json) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("Failed to write event log {}: {}", path, e);
+                *file = None;
+                false
+            }
+        }
+    }
+}
+
+impl Observer for JsonEventLog {
+    fn on_start(&self) {
+        let file_device = match &self.file_device {
+            Some(device) => json_string(device),
+            None => "null".to_string(),
+        };
+        let fields = [
+            ("file_prefix", json_string(&self.file_prefix)),
+            ("file_device", file_device),
+            ("labels", labels_json(&self.labels)),
+        ];
+        self.emit("start", &fields, &self.stats.snapshot());
+    }
+
+    fn on_rotate(&self, closed: &ClosedFile, opened: &Path) {
+        let time = |timestamp: Option<_>| match timestamp {
+            Some(timestamp) => json_string(&rfc3339(timestamp)),
+            None => "null".to_string(),
+        };
+        let pcap = |value: fn(PcapStats) -> u64| match closed.pcap_stats {
+            Some(stats) => value(stats).to_string(),
+            None => "null".to_string(),
+        };
+        let fields = [
+            (
+                "closed_file",
+                json_string(&closed.path.display().to_string()),
+            ),
+            ("opened_file", json_string(&opened.display().to_string())),
+            ("file_first_packet", time(closed.first_packet)),
+            ("file_last_packet", time(closed.last_packet)),
+            ("file_packets", closed.packets.to_string()),
+            ("file_bytes", closed.bytes.to_string()),
+            ("file_dropped", closed.dropped.to_string()),
+            ("file_pcap_received", pcap(|stats| stats.received)),
+            ("file_pcap_dropped", pcap(|stats| stats.dropped)),
+            ("file_pcap_if_dropped", pcap(|stats| stats.if_dropped)),
+            ("file_redactions", closed.redactions.to_string()),
+        ];
+        self.emit("rotate", &fields, &self.stats.snapshot());
+    }
+
+    fn on_pause(&self) {
+        self.emit("pause", &[], &self.stats.snapshot());
+    }
+
+    fn on_resume(&self) {
+        self.emit("resume", &[], &self.stats.snapshot());
+    }
+
+    fn on_write_error(&self, error: &str, policy: WriteErrorPolicy) {
+        let action = match policy {
+            WriteErrorPolicy::Abort => "abort",
+            WriteErrorPolicy::Skip => "skip",
+            WriteErrorPolicy::Rotate => "rotate",
+        };
+        self.emit(
+            "write_error",
+            &[
+                ("error", json_string(error)),
+                ("action", json_string(action)),
+            ],
+            &self.stats.snapshot(),
+        );
+    }
+
+    fn on_output_unavailable(&self, error: &str) {
+        self.emit(
+            "output_unavailable",
+            &[("error", json_string(error))],
+            &self.stats.snapshot(),
+        );
+    }
+
+    fn on_output_restored(&self, backlog: u64) {
+        self.emit(
+            "output_restored",
+            &[("backlog_packets", backlog.to_string())],
+            &self.stats.snapshot(),
+        );
+    }
+
+    fn on_error(&self, error: &str) {
+        self.emit(
+            "error",
+            &[("error", json_string(error))],
+            &self.stats.snapshot(),
+        );
+    }
+
+    fn on_stop(&self, stats: &CaptureStats) {
+        self.emit("stop", &[], stats);
+    }
+}
@@ -0,0 +1,318 @@
+use crate::SavePcapError;
+use crate::layout::{self, OutputLayout};
+use crate::merge::{self, PacketIter};
+use crate::packet_index::{self, IndexedCaptureReader, PacketIndex};
+use crate::reader::CaptureReader;
+use crate::source::SourcePacket;
+use chrono::{Local, NaiveDate, NaiveDateTime, TimeZone};
+use log::{debug, info};
+use pcap_file::DataLink;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// 文件名中的时间部分，D表示数字
+const TIME_RANGE_PATTERN: &[u8] = b"DDDDDDDDTDDDDDD-DDDDDDDDTDDDDDD";
+const START_TIME_PATTERN: &[u8] = b"DDDDDDDD_DDDDDD";
+// `time_buckets`的小时和自然日时间段
+const HOUR_BUCKET_PATTERN: &[u8] = b"DDDD-DD-DD_DD";
+const DAY_BUCKET_PATTERN: &[u8] = b"DDDD-DD-DD";
+
+// 可能包含所需数据包的文件，end为不包含的结束时间，None表示未知
+pub(crate) struct Candidate {
+    pub path: PathBuf,
+    series: String,
+    pub start: Duration,
+    pub end: Option<Duration>,
+    indexed: bool,
+}
+
+/// 从捕获目录中提取时间范围`[start, end)`内的数据包，按时间排序合并为一个pcap文件，
+/// 返回写入的数据包数量。
+///
+/// 根据文件名中的时间（创建时间或`time_range_file_names`的首尾时间）和`.pktidx`数据包索引
+/// 选出与时间范围重叠的文件，有索引的文件直接定位到起始时间，不读取之前的数据包。
+/// 只处理本库生成的文件名，转存异常数据包的文件不包含在内
+pub fn extract<P: AsRef<Path>, Q: AsRef<Path>>(
+    dir: P,
+    start: SystemTime,
+    end: SystemTime,
+    output: Q,
+) -> Result<usize, SavePcapError> {
+    extract_in(dir.as_ref(), None, start, end, output.as_ref())
+}
+
+/// 与`extract`相同，layout为捕获目录使用的`output_layout`，同时查找布局生成的子目录
+pub(crate) fn extract_in(
+    dir: &Path,
+    layout: Option<&OutputLayout>,
+    start: SystemTime,
+    end: SystemTime,
+    output: &Path,
+) -> Result<usize, SavePcapError> {
+    let (start, end) = (since_epoch(start), since_epoch(end));
+    if end <= start {
+        return Err(SavePcapError::InvalidTimeWindow(
+            "extract end time must be after the start time".to_string(),
+        ));
+    }
+
+    let files = files_in_range(dir, layout, output, start, end)?;
+    if files.is_empty() {
+        return Err(SavePcapError::PcapFileError(format!(
+            "No capture files in {} overlap the requested time range",
+            dir.display()
+        )));
+    }
+
+    let mut datalink = None;
+    let mut inputs = Vec::with_capacity(files.len());
+    for file in &files {
+        debug!("Extracting from {:?}", file.path);
+        let (file_datalink, packets) = open_range(file, start, end)?;
+        check_datalink(&mut datalink, file_datalink, file)?;
+        inputs.push(packets);
+    }
+
+    let datalink = datalink.unwrap_or(DataLink::ETHERNET);
+    let packet_count = merge::write_merged(inputs, datalink, output)?;
+    info!(
+        "Extracted {} packets from {} files into {}",
+        packet_count,
+        files.len(),
+        output.display()
+    );
+    Ok(packet_count)
+}
+
+pub(crate) fn since_epoch(time: SystemTime) -> Duration {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+/// 目录中与时间范围`[start, end)`重叠的捕获文件，按开始时间排序，不包括output
+pub(crate) fn files_in_range(
+    dir: &Path,
+    layout: Option<&OutputLayout>,
+    output: &Path,
+    start: Duration,
+    end: Duration,
+) -> Result<Vec<Candidate>, SavePcapError> {
+    Ok(candidates(dir, layout, output)?
+        .into_iter()
+        .filter(|file| file.start < end && file.end.is_none_or(|file_end| file_end > start))
+        .collect())
+}
+
+/// 目录中所有可以确定时间范围的捕获文件，按开始时间排序
+#[cfg(feature = "http-server")]
+pub(crate) fn catalog(
+    dir: &Path,
+    layout: Option<&OutputLayout>,
+) -> Result<Vec<Candidate>, SavePcapError> {
+    candidates(dir, layout, Path::new(""))
+}
+
+/// 打开文件中时间范围`[start, end)`内的数据包，同时返回文件的链路层类型
+pub(crate) fn open_range(
+    file: &Candidate,
+    start: Duration,
+    end: Duration,
+) -> Result<(DataLink, PacketIter<'static>), SavePcapError> {
+    let mut reader = CaptureReader::open(&file.path)?;
+    let datalink = reader.datalink();
+
+    // 读取错误保留下来，由合并时返回
+    let in_range = move |packet: &Result<SourcePacket, SavePcapError>| match packet {
+        Ok(packet) => packet.timestamp >= start && packet.timestamp < end,
+        Err(_) => true,
+    };
+    let packets: PacketIter<'static> = if file.indexed {
+        let mut indexed = IndexedCaptureReader::open(&file.path)?;
+        indexed.seek_time(start);
+        let packets = indexed.map(|packet| {
+            packet.map(|packet| SourcePacket {
+                timestamp: packet.timestamp,
+                orig_len: packet.orig_len,
+                data: packet.data,
+                user_index: None,
+            })
+        });
+        Box::new(packets.take_while(in_range))
+    } else {
+        let packets = std::iter::from_fn(move || reader.read_packet());
+        Box::new(packets.filter(in_range))
+    };
+    Ok((datalink, packets))
+}
+
+/// 合并的文件必须使用相同的链路层类型，expected为第一个文件的类型
+pub(crate) fn check_datalink(
+    expected: &mut Option<DataLink>,
+    datalink: DataLink,
+    file: &Candidate,
+) -> Result<(), SavePcapError> {
+    if *expected.get_or_insert(datalink) != datalink {
+        return Err(SavePcapError::PcapFileError(format!(
+            "Link type of {} differs from the other files",
+            file.path.display()
+        )));
+    }
+    Ok(())
+}
+
+// 目录（包括按layout生成的子目录）中所有可以确定时间范围的捕获文件，按开始时间排序
+fn candidates(
+    dir: &Path,
+    layout: Option<&OutputLayout>,
+    output: &Path,
+) -> Result<Vec<Candidate>, SavePcapError> {
+    let output = output.canonicalize().ok();
+    let mut files = Vec::new();
+    let mut entries = Vec::new();
+    for dir in layout::capture_dirs(layout, dir) {
+        entries.extend(fs::read_dir(dir)?);
+    }
+    for entry in entries {
+        let path = entry?.path();
+        let Some(extension) = path.extension().and_then(|ext| ext.to_str()) else {
+            continue;
+        };
+        if !matches!(extension, "pcap" | "pcapng")
+            || (output.is_some() && path.canonicalize().ok() == output)
+        {
+            continue;
+        }
+        let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        let name_times = parse_name(stem, extension);
+
+        // 索引记录了准确的首尾时间；空索引表示文件中没有数据包
+        if packet_index::packet_index_path(&path).exists() {
+            let index = PacketIndex::open(&path)?;
+            let last = index
+                .len()
+                .checked_sub(1)
+                .and_then(|number| index.get(number));
+            let (Some(first), Some(last)) = (index.get(0), last) else {
+                continue;
+            };
+            files.push(Candidate {
+                series: name_times.map(|(series, _, _)| series).unwrap_or_default(),
+                path,
+                start: first.timestamp,
+                end: Some(last.timestamp + Duration::from_nanos(1)),
+                indexed: true,
+            });
+        } else if let Some((series, start, end)) = name_times {
+            files.push(Candidate {
+                path,
+                series,
+                start,
+                end,
+                indexed: false,
+            });
+        }
+    }
+    files.sort_by(|a, b| a.start.cmp(&b.start).then_with(|| a.path.cmp(&b.path)));
+
+    // 只有创建时间的文件到同一组文件中的下一个文件创建时结束，不同子目录中的文件不属于同一组
+    let mut next_start: HashMap<(PathBuf, String), Duration> = HashMap::new();
+    for file in files.iter_mut().rev() {
+        let series = (
+            file.path
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_default(),
+            file.series.clone(),
+        );
+        if file.end.is_none() {
+            // 文件名精确到秒，下一个文件可能在同一秒内创建
+            file.end = next_start
+                .get(&series)
+                .map(|next| *next + Duration::from_secs(1));
+        }
+        next_start.insert(series, file.start);
+    }
+    Ok(files)
+}
+
+// 从文件名中解析所属的一组文件（前缀、工作线程后缀和格式相同）、开始时间和结束时间
+fn parse_name(stem: &str, extension: &str) -> Option<(String, Duration, Option<Duration>)> {
+    let (position, pattern_len, start, end) = if let Some(position) =
+        find_pattern(stem, TIME_RANGE_PATTERN)
+    {
+        let first = local_time(&stem[position..position + 15], "%Y%m%dT%H%M%S")?;
+        let last = local_time(&stem[position + 16..position + 31], "%Y%m%dT%H%M%S")?;
+        let end = last + Duration::from_secs(1);
+        (position, TIME_RANGE_PATTERN.len(), first, Some(end))
+    } else if let Some(position) = find_pattern(stem, START_TIME_PATTERN) {
+        let start = local_time(&stem[position..position + 15], "%Y%m%d_%H%M%S")?;
+        (position, START_TIME_PATTERN.len(), start, None)
+    } else if let Some(position) = find_pattern(stem, HOUR_BUCKET_PATTERN) {
+        let hour = format!("{}:00:00", &stem[position..position + 13]);
+        let start = local_time(&hour, "%Y-%m-%d_%H:%M:%S")?;
+        let end = start + Duration::from_secs(3600);
+        (position, HOUR_BUCKET_PATTERN.len(), start, Some(end))
+    } else {
+        let position = find_pattern(stem, DAY_BUCKET_PATTERN)?;
+        let date = NaiveDate::parse_from_str(&stem[position..position + 10], "%Y-%m-%d").ok()?;
+        let start = local_midnight(date)?;
+        let end = local_midnight(date.succ_opt()?)?;
+        (position, DAY_BUCKET_PATTERN.len(), start, Some(end))
+    };
+
+    let mut prefix = &stem[..position];
+    if prefix.ends_with("errors_") {
+        return None;
+    }
+    // 去掉文件序号，同一组文件的序号各不相同
+    if let Some(rest) = prefix.strip_suffix('_') {
+        let without_sequence = rest.trim_end_matches(|c: char| c.is_ascii_digit());
+        if without_sequence.len() < rest.len() && without_sequence.ends_with('_') {
+            prefix = without_sequence;
+        }
+    }
+    // 去掉避免文件名冲突时加在时间后的"_N"
+    let mut rest = &stem[position + pattern_len..];
+    if let Some(after) = rest.strip_prefix('_') {
+        let without_suffix = after.trim_start_matches(|c: char| c.is_ascii_digit());
+        if without_suffix.len() < after.len()
+            && (without_suffix.is_empty() || without_suffix.starts_with('_'))
+        {
+            rest = without_suffix;
+        }
+    }
+    let series = format!("{}|{}|{}", prefix, rest, extension);
+    Some((series, start, end))
+}
+
+// 最后一处匹配的位置，前缀中可能也有数字
+fn find_pattern(stem: &str, pattern: &[u8]) -> Option<usize> {
+    stem.as_bytes().windows(pattern.len()).rposition(|window| {
+        window
+            .iter()
+            .zip(pattern)
+            .all(|(byte, expected)| match expected {
+                b'D' => byte.is_ascii_digit(),
+                _ => byte == expected,
+            })
+    })
+}
+
+// 夏令时切换可能使午夜不存在，取当天最早的时间
+fn local_midnight(date: NaiveDate) -> Option<Duration> {
+    let seconds = Local
+        .from_local_datetime(&date.and_hms_opt(0, 0, 0)?)
+        .earliest()?
+        .timestamp();
+    Some(Duration::from_secs(u64::try_from(seconds).ok()?))
+}
+
+// 文件名中的时间为本地时间
+fn local_time(text: &str, format: &str) -> Option<Duration> {
+    let naive = NaiveDateTime::parse_from_str(text, format).ok()?;
+    let seconds = Local.from_local_datetime(&naive).earliest()?.timestamp();
+    Some(Duration::from_secs(u64::try_from(seconds).ok()?))
+}
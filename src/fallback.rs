@@ -0,0 +1,88 @@
+use crate::source::SourcePacket;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::time::Instant;
+
+// 输出位置不可用期间缓存的数据包，连同写入时需要的注释和附加信息
+pub(crate) struct BufferedPacket {
+    pub packet: SourcePacket,
+    pub tag: Option<String>,
+    pub annotations: Vec<(String, String)>,
+}
+
+impl BufferedPacket {
+    pub fn new(
+        packet: &SourcePacket,
+        tag: Option<String>,
+        annotations: &[(String, String)],
+    ) -> Self {
+        Self {
+            packet: SourcePacket {
+                timestamp: packet.timestamp,
+                orig_len: packet.orig_len,
+                data: packet.data.clone(),
+                user_index: packet.user_index,
+            },
+            tag,
+            annotations: annotations.to_vec(),
+        }
+    }
+
+    fn size(&self) -> u64 {
+        self.packet.data.len() as u64
+    }
+}
+
+/// 输出位置不可用时缓存数据包的内存环，见`PcapCaptureOptions::memory_fallback_bytes`。
+/// 超过容量时丢弃最早的数据包，恢复后写出的是最近的一段流量
+pub(crate) struct MemoryRing {
+    packets: VecDeque<BufferedPacket>,
+    bytes: u64,
+    max_bytes: u64,
+    /// 输出位置不可用时正在写入的文件，恢复后截断到最后一个完整的数据包
+    pub abandoned: Option<PathBuf>,
+    /// 上次检查输出位置是否恢复的时间
+    pub checked: Instant,
+}
+
+impl MemoryRing {
+    pub fn new(max_bytes: u64) -> Self {
+        Self {
+            packets: VecDeque::new(),
+            bytes: 0,
+            max_bytes,
+            abandoned: None,
+            checked: Instant::now(),
+        }
+    }
+
+    /// 缓存数据包，返回因超过容量丢弃的数据包数。刚加入的数据包总是保留
+    pub fn push(&mut self, packet: BufferedPacket) -> u64 {
+        self.bytes += packet.size();
+        self.packets.push_back(packet);
+        let mut dropped = 0;
+        while self.bytes > self.max_bytes && self.packets.len() > 1 {
+            if let Some(oldest) = self.packets.pop_front() {
+                self.bytes -= oldest.size();
+                dropped += 1;
+            }
+        }
+        dropped
+    }
+
+    pub fn pop(&mut self) -> Option<BufferedPacket> {
+        let packet = self.packets.pop_front()?;
+        self.bytes -= packet.size();
+        Some(packet)
+    }
+
+    /// 放回写出失败的数据包，下次恢复时最先写出
+    pub fn push_front(&mut self, packet: BufferedPacket) {
+        self.bytes += packet.size();
+        self.packets.push_front(packet);
+    }
+
+    pub fn len(&self) -> usize {
+        self.packets.len()
+    }
+}
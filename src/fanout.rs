@@ -0,0 +1,586 @@
+use crate::paths;
+use crate::query::compile;
+use crate::rules::ClassificationRules;
+use crate::session::SessionStatus;
+use crate::source::SourcePacket;
+use crate::stats::StatsCounters;
+#[cfg(feature = "validate")]
+use crate::validate::ValidationAction;
+#[cfg(feature = "websocket")]
+use crate::websocket::LiveStream;
+use crate::writer::RotatingWriter;
+use crate::{AnnotatedPacket, FileFormat, InvalidPacketAction, PcapCaptureOptions, SavePcapError};
+use log::{info, warn};
+use pcap::BpfProgram;
+use pcap_file::DataLink;
+use std::path::Path;
+
+/// 额外的输出：同一次捕获的数据包同时写入另一组文件，例如同时保存pcap和pcapng
+#[derive(Debug, Clone)]
+pub struct OutputOptions {
+    pub file_format: FileFormat,
+    /// None表示与主输出相同
+    pub file_path: Option<String>,
+    /// None表示与主输出相同
+    pub file_prefix: Option<String>,
+    /// 写入该输出时把数据包截断到的长度（保留原始长度），例如只保存96字节的头部；
+    /// 相当于该输出的`slice_bytes`，不影响捕获和其他输出。None表示沿用主配置的`slice_bytes`
+    pub snaplen: Option<usize>,
+    /// 只写入每N个数据包中的第一个，例如主输出保存全部数据包、短期保留，
+    /// 抽样输出用于长期归档。None表示写入所有数据包
+    pub sample_every: Option<u64>,
+    /// 只写入匹配该BPF过滤器的数据包，例如把"port 53"写入前缀为"dns"的输出。
+    /// 过滤器在用户态对已捕获的数据包求值，网卡只打开一次，不影响捕获过滤器和其他输出
+    pub filter: Option<String>,
+    /// 只写入不匹配任何其他输出`filter`的数据包，用于保存分流后剩余的流量
+    pub unmatched: bool,
+}
+
+impl OutputOptions {
+    /// 与主输出使用同一目录和文件前缀、只有格式不同的输出
+    pub fn format(file_format: FileFormat) -> Self {
+        Self {
+            file_format,
+            file_path: None,
+            file_prefix: None,
+            snaplen: None,
+            sample_every: None,
+            filter: None,
+            unmatched: false,
+        }
+    }
+}
+
+// 额外输出各自的写入配置和计数器，需要比Fanout活得更久
+pub(crate) struct Output {
+    options: PcapCaptureOptions,
+    sample_every: Option<u64>,
+    filter: Option<String>,
+    unmatched: bool,
+    // 分类规则输出在`ClassificationRules::outputs()`中的序号
+    route: Option<usize>,
+    stats: StatsCounters,
+    status: SessionStatus,
+}
+
+/// 按主配置生成每个额外输出（包括每个分类规则输出）的写入配置，并创建输出目录
+pub(crate) fn prepare(options: &PcapCaptureOptions) -> Result<Vec<Output>, SavePcapError> {
+    let mut outputs: Vec<Output> = Vec::new();
+    for output in &options.outputs {
+        if output.sample_every == Some(0) {
+            return Err(SavePcapError::InvalidOutput(
+                "sample_every must be at least 1".to_string(),
+            ));
+        }
+        if output.unmatched && output.filter.is_some() {
+            return Err(SavePcapError::InvalidOutput(
+                "an output cannot have both filter and unmatched".to_string(),
+            ));
+        }
+        add_output(options, output, None, &mut outputs)?;
+    }
+    // 规则输出与主输出使用同一目录和格式，文件前缀为输出名
+    if let Some(rules) = &options.rules {
+        for (route, name) in rules.outputs().into_iter().enumerate() {
+            let output = OutputOptions {
+                file_prefix: Some(name.to_string()),
+                ..OutputOptions::format(options.file_format)
+            };
+            add_output(options, &output, Some(route), &mut outputs)?;
+        }
+    }
+    Ok(outputs)
+}
+
+fn add_output(
+    options: &PcapCaptureOptions,
+    output: &OutputOptions,
+    route: Option<usize>,
+    outputs: &mut Vec<Output>,
+) -> Result<(), SavePcapError> {
+    let derived = derive_options(options, output);
+    // 各输出共用暂存目录，前缀和格式相同的输出即使目录不同也会在暂存目录中冲突
+    let conflicts = |other: &PcapCaptureOptions| {
+        other.file_format == derived.file_format
+            && other.file_prefix == derived.file_prefix
+            && (derived.staging_dir.is_some()
+                || Path::new(&other.file_path) == Path::new(&derived.file_path))
+    };
+    if conflicts(options) || outputs.iter().any(|other| conflicts(&other.options)) {
+        return Err(SavePcapError::InvalidOutput(format!(
+            "{:?} output in {} with prefix {} would overwrite another output",
+            derived.file_format, derived.file_path, derived.file_prefix
+        )));
+    }
+
+    paths::ensure_writable(Path::new(&derived.file_path))?;
+    outputs.push(Output {
+        options: derived,
+        sample_every: output.sample_every,
+        filter: output.filter.clone(),
+        unmatched: output.unmatched,
+        route,
+        stats: StatsCounters::default(),
+        status: SessionStatus::default(),
+    });
+    Ok(())
+}
+
+// 额外输出沿用主配置的滚动和元数据设置。合法性检查在分发前进行一次，被拒绝的数据包
+// 也只由主输出报告；整个捕获只有一份的统计文件、带宽记录、直方图和告警也只由主输出负责
+fn derive_options(options: &PcapCaptureOptions, output: &OutputOptions) -> PcapCaptureOptions {
+    let mut derived = options.clone();
+    derived.file_format = output.file_format;
+    if let Some(file_path) = &output.file_path {
+        derived.file_path = file_path.clone();
+    }
+    if let Some(file_prefix) = &output.file_prefix {
+        derived.file_prefix = file_prefix.clone();
+    }
+    derived.outputs = Vec::new();
+    derived.rules = None;
+    derived.sanity_check = None;
+    derived.rejected_packets = None;
+    #[cfg(feature = "validate")]
+    {
+        derived.packet_validation = None;
+    }
+    derived.stats_file = None;
+    derived.bandwidth_interval_seconds = None;
+    derived.histograms = None;
+    derived.alerts = None;
+    derived.protocol_stats = false;
+    // 名称解析块只能写入pcapng文件
+    derived.dns_name_resolution &= output.file_format == FileFormat::PcapNg;
+    if let Some(snaplen) = output.snaplen {
+        derived.slice_bytes = Some(derived.slice_bytes.map_or(snaplen, |s| s.min(snaplen)));
+    }
+    derived
+}
+
+#[cfg(feature = "validate")]
+enum Validated {
+    Valid,
+    Rejected,
+    Tagged(String),
+    Fixed(SourcePacket),
+}
+
+enum Checked {
+    Valid,
+    Rejected,
+    Tagged(String),
+}
+
+// 额外输出的写入器，截断由写入器按`slice_bytes`完成，这里只负责过滤和抽样
+struct Sink<'a> {
+    writer: RotatingWriter<'a>,
+    filter: Option<BpfProgram>,
+    unmatched: bool,
+    route: Option<usize>,
+    sample_every: Option<u64>,
+    // 分发给该输出的数据包数，用于抽样
+    offered: u64,
+    // 最后一次写入的序号，用于选择最久未写入的输出暂时关闭
+    last_used: u64,
+}
+
+impl Sink<'_> {
+    fn write(
+        &mut self,
+        packet: &SourcePacket,
+        tag: Option<String>,
+        annotations: &[(String, String)],
+        redactions: u64,
+    ) -> Result<(), SavePcapError> {
+        let index = self.offered;
+        self.offered += 1;
+        if let Some(every) = self.sample_every
+            && !index.is_multiple_of(every)
+        {
+            return Ok(());
+        }
+        if self.writer.write(packet, tag, annotations)? {
+            self.writer.record_redactions(redactions);
+        }
+        Ok(())
+    }
+}
+
+// 把通过合法性检查的每个数据包分别写入主输出和所有额外输出，
+// 某个输出跳过、缓存数据包或写入失败不影响其他输出
+pub(crate) struct Fanout<'a> {
+    primary: RotatingWriter<'a>,
+    outputs: Vec<Sink<'a>>,
+    // 分类规则和每条规则对应的输出序号
+    rules: Option<(&'a ClassificationRules, Vec<usize>)>,
+    // 协议校验、遮盖、合法性检查和注释在分发前进行，修正、遮盖后的数据包和注释写入所有输出
+    options: &'a PcapCaptureOptions,
+    stats: &'a StatsCounters,
+    datalink: DataLink,
+    max_open_outputs: Option<usize>,
+    // O_DIRECT写入的文件不能暂时关闭
+    suspendable: bool,
+    // 写入额外输出的次数
+    writes: u64,
+    #[cfg(feature = "websocket")]
+    live: Option<LiveStream>,
+}
+
+impl<'a> Fanout<'a> {
+    pub fn new(
+        options: &'a PcapCaptureOptions,
+        stats: &'a StatsCounters,
+        status: &'a SessionStatus,
+        datalink: DataLink,
+        outputs: &'a [Output],
+    ) -> Result<Self, SavePcapError> {
+        if options.direct_io && options.max_open_outputs.is_some() {
+            return Err(SavePcapError::UnsupportedSource(
+                "Limiting open outputs is not supported with O_DIRECT writing".to_string(),
+            ));
+        }
+        // 在创建文件之前编译所有过滤器，过滤器有误时不留下空文件
+        let mut filters = Vec::new();
+        for output in outputs {
+            let filter = match &output.filter {
+                Some(filter) => Some(compile(filter, datalink).map_err(|e| {
+                    SavePcapError::InvalidOutput(format!("invalid filter {:?}: {}", filter, e))
+                })?),
+                None => None,
+            };
+            filters.push(filter);
+        }
+        #[cfg(feature = "websocket")]
+        let live = match &options.live_stream {
+            Some(live) => Some(LiveStream::start(options, live, datalink)?),
+            None => None,
+        };
+
+        let mut fanout = Self {
+            primary: RotatingWriter::new(options, stats, status, datalink)?,
+            outputs: Vec::new(),
+            rules: options.rules.as_ref().map(|rules| (rules, rules.routes())),
+            options,
+            stats,
+            datalink,
+            max_open_outputs: options.max_open_outputs.map(|max| max.max(1)),
+            suspendable: !options.direct_io,
+            writes: 0,
+            #[cfg(feature = "websocket")]
+            live,
+        };
+        for (output, filter) in outputs.iter().zip(filters) {
+            info!(
+                "Also writing {:?} files to {}",
+                output.options.file_format, output.options.file_path
+            );
+            fanout.make_room(None);
+            let writer = fanout.retry_on_fd_exhaustion(None, |_| {
+                RotatingWriter::new(&output.options, &output.stats, &output.status, datalink)
+            });
+            match writer {
+                Ok(writer) => fanout.outputs.push(Sink {
+                    writer,
+                    filter,
+                    unmatched: output.unmatched,
+                    route: output.route,
+                    sample_every: output.sample_every,
+                    offered: 0,
+                    last_used: 0,
+                }),
+                Err(e) => {
+                    // 关闭已经创建的文件
+                    let _ = fanout.finish();
+                    return Err(e);
+                }
+            }
+        }
+        Ok(fanout)
+    }
+
+    pub fn tick(&mut self) -> Result<(), SavePcapError> {
+        self.primary.tick()?;
+        for output in &mut self.outputs {
+            output.writer.tick()?;
+        }
+        Ok(())
+    }
+
+    pub fn write(&mut self, packet: &SourcePacket) -> Result<(), SavePcapError> {
+        #[cfg(feature = "validate")]
+        let fixed;
+        #[cfg(feature = "validate")]
+        let (packet, tag) = match self.validate(packet) {
+            Validated::Valid => (packet, None),
+            Validated::Rejected => return Ok(()),
+            Validated::Tagged(reason) => (packet, Some(reason)),
+            Validated::Fixed(packet) => {
+                fixed = packet;
+                (&fixed, None)
+            }
+        };
+        #[cfg(not(feature = "validate"))]
+        let tag: Option<String> = None;
+        let redacted;
+        let (packet, redactions) = match self.redact(packet) {
+            Some((packet, redactions)) => {
+                redacted = packet;
+                (&redacted, redactions)
+            }
+            None => (packet, 0),
+        };
+        let tag = match self.sanity_check(packet)? {
+            Checked::Valid => tag,
+            Checked::Rejected => return Ok(()),
+            Checked::Tagged(reason) => Some(match tag {
+                Some(tag) => format!("{}; {}", tag, reason),
+                None => reason,
+            }),
+        };
+        let annotations = self.annotate(packet);
+
+        let primary = self.primary.write(packet, tag.clone(), &annotations);
+        if let Ok(true) = primary {
+            self.primary.record_redactions(redactions);
+            #[cfg(feature = "websocket")]
+            if let Some(live) = &self.live {
+                live.publish(packet);
+            }
+        }
+        let route = self.rules.as_ref().and_then(|(rules, routes)| {
+            rules
+                .classify(self.datalink, &packet.data)
+                .map(|rule| routes[rule])
+        });
+        let mut matched = false;
+        let mut targets = Vec::new();
+        for (index, output) in self.outputs.iter().enumerate() {
+            if output.route.is_some() {
+                if output.route == route {
+                    targets.push(index);
+                }
+                continue;
+            }
+            if let Some(filter) = &output.filter {
+                if !filter.filter(&packet.data) {
+                    continue;
+                }
+                matched = true;
+            }
+            if !output.unmatched {
+                targets.push(index);
+            }
+        }
+        if !matched {
+            targets.extend((0..self.outputs.len()).filter(|index| self.outputs[*index].unmatched));
+        }
+        for index in targets {
+            self.write_output(index, packet, tag.clone(), &annotations, redactions)?;
+        }
+        primary.map(|_| ())
+    }
+
+    // `sanity_check`：丢弃或转存到主输出的异常数据包文件的数据包不写入任何输出
+    fn sanity_check(&mut self, packet: &SourcePacket) -> Result<Checked, SavePcapError> {
+        let Some(sanity_check) = &self.options.sanity_check else {
+            return Ok(Checked::Valid);
+        };
+        let Some(reason) = sanity_check.check(self.datalink, packet) else {
+            return Ok(Checked::Valid);
+        };
+        self.stats.record_invalid();
+        match sanity_check.action {
+            InvalidPacketAction::Drop => {
+                self.options.report_rejected(packet, &reason);
+                Ok(Checked::Rejected)
+            }
+            InvalidPacketAction::Tag => Ok(Checked::Tagged(reason)),
+            InvalidPacketAction::Divert => {
+                self.options
+                    .report_rejected(packet, &format!("diverted: {}", reason));
+                self.primary.divert(packet, &reason)?;
+                Ok(Checked::Rejected)
+            }
+        }
+    }
+
+    fn write_output(
+        &mut self,
+        index: usize,
+        packet: &SourcePacket,
+        tag: Option<String>,
+        annotations: &[(String, String)],
+        redactions: u64,
+    ) -> Result<(), SavePcapError> {
+        self.writes += 1;
+        self.outputs[index].last_used = self.writes;
+        if self.outputs[index].writer.is_suspended() {
+            self.make_room(Some(index));
+            self.retry_on_fd_exhaustion(Some(index), |outputs| outputs[index].writer.resume())?;
+        }
+        self.outputs[index].write(packet, tag, annotations, redactions)
+    }
+
+    // 有内容被遮盖时返回遮盖后的副本和遮盖处数
+    fn redact(&self, packet: &SourcePacket) -> Option<(SourcePacket, u64)> {
+        let redaction = self.options.redaction.as_ref()?;
+        let (data, redactions) = redaction.redact(self.datalink, &packet.data)?;
+        let packet = SourcePacket {
+            timestamp: packet.timestamp,
+            orig_len: packet.orig_len,
+            data,
+            user_index: packet.user_index,
+        };
+        Some((packet, redactions))
+    }
+
+    // 每个数据包只调用一次注释回调，所有输出使用相同的注释
+    fn annotate(&self, packet: &SourcePacket) -> Vec<(String, String)> {
+        let Some(annotator) = &self.options.annotator else {
+            return Vec::new();
+        };
+        annotator(&AnnotatedPacket {
+            timestamp: packet.timestamp,
+            orig_len: packet.orig_len,
+            data: &packet.data,
+            user_index: packet.user_index,
+        })
+    }
+
+    // 打开的输出达到上限时暂时关闭最久未写入的输出，为即将打开的输出（except）腾出位置
+    fn make_room(&mut self, except: Option<usize>) {
+        let Some(max) = self.max_open_outputs else {
+            return;
+        };
+        let open = self
+            .outputs
+            .iter()
+            .filter(|output| !output.writer.is_suspended())
+            .count();
+        for _ in max..=open {
+            if !self.suspend_least_recent(except) {
+                break;
+            }
+        }
+    }
+
+    // 返回是否关闭了一个输出
+    fn suspend_least_recent(&mut self, except: Option<usize>) -> bool {
+        if !self.suspendable {
+            return false;
+        }
+        let least_recent = self
+            .outputs
+            .iter_mut()
+            .enumerate()
+            .filter(|(index, output)| Some(*index) != except && !output.writer.is_suspended())
+            .min_by_key(|(_, output)| output.last_used);
+        match least_recent {
+            Some((_, output)) => match output.writer.suspend() {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!("Failed to suspend output: {}", e);
+                    false
+                }
+            },
+            None => false,
+        }
+    }
+
+    // 文件描述符耗尽时关闭最久未写入的输出后重试，直到成功或没有可关闭的输出
+    fn retry_on_fd_exhaustion<T>(
+        &mut self,
+        except: Option<usize>,
+        mut open: impl FnMut(&mut [Sink<'a>]) -> Result<T, SavePcapError>,
+    ) -> Result<T, SavePcapError> {
+        loop {
+            match open(&mut self.outputs) {
+                Err(e) if is_fd_exhausted(&e) => {
+                    warn!("Too many open files, closing the least recently used output");
+                    if !self.suspend_least_recent(except) {
+                        return Err(e);
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+
+    #[cfg(feature = "validate")]
+    fn validate(&self, packet: &SourcePacket) -> Validated {
+        let Some(validation) = &self.options.packet_validation else {
+            return Validated::Valid;
+        };
+        // 被截断的数据包无法校验长度和校验和
+        if packet.data.len() < packet.orig_len as usize {
+            return Validated::Valid;
+        }
+        let Some(reason) = validation.check(self.datalink, &packet.data) else {
+            return Validated::Valid;
+        };
+        self.stats.record_invalid();
+        match validation.action {
+            ValidationAction::Reject => {
+                self.options.report_rejected(packet, &reason);
+                Validated::Rejected
+            }
+            ValidationAction::Tag => Validated::Tagged(reason),
+            ValidationAction::Fix => {
+                let mut data = packet.data.clone();
+                match validation.fix(self.datalink, &mut data) {
+                    Ok(()) => Validated::Fixed(SourcePacket {
+                        timestamp: packet.timestamp,
+                        orig_len: packet.orig_len,
+                        data,
+                        user_index: packet.user_index,
+                    }),
+                    Err(reason) => {
+                        self.options.report_rejected(packet, &reason);
+                        Validated::Rejected
+                    }
+                }
+            }
+        }
+    }
+
+    /// 所有输出立即滚动到新文件
+    pub fn rotate_now(&mut self) -> Result<(), SavePcapError> {
+        self.primary.rotate_now()?;
+        for output in &mut self.outputs {
+            output.writer.rotate_now()?;
+        }
+        Ok(())
+    }
+
+    /// 写出所有输出缓冲区中的数据并同步到磁盘
+    pub fn flush(&mut self) -> Result<(), SavePcapError> {
+        self.primary.flush()?;
+        for output in &mut self.outputs {
+            output.writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// 关闭所有输出的当前文件，返回第一个错误
+    pub fn finish(self) -> Result<(), SavePcapError> {
+        let mut result = self.primary.finish();
+        for output in self.outputs {
+            result = result.and(output.writer.finish());
+        }
+        result
+    }
+}
+
+fn is_fd_exhausted(e: &SavePcapError) -> bool {
+    let SavePcapError::IoError(e) = e else {
+        return false;
+    };
+    #[cfg(unix)]
+    return matches!(e.raw_os_error(), Some(libc::EMFILE | libc::ENFILE));
+    // ERROR_TOO_MANY_OPEN_FILES
+    #[cfg(windows)]
+    return e.raw_os_error() == Some(4);
+    #[cfg(not(any(unix, windows)))]
+    return false;
+}
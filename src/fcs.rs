@@ -0,0 +1,85 @@
+use crate::SavePcapError;
+use log::{info, warn};
+use std::process::Command;
+
+/// 以太网帧校验序列(FCS)的字节数
+pub(crate) const ETHERNET_FCS_LEN: u8 = 4;
+
+// 通过ethtool让网卡保留每个帧末尾的FCS，并尽量同时接收FCS错误的帧。
+// 只改动原本关闭的设置，捕获结束时恢复
+pub(crate) struct FcsGuard {
+    device_name: String,
+    restore: Vec<&'static str>,
+}
+
+impl FcsGuard {
+    pub fn enable(device_name: &str) -> Result<Self, SavePcapError> {
+        let mut guard = Self {
+            device_name: device_name.to_string(),
+            restore: Vec::new(),
+        };
+
+        // 没有rx-fcs就无法保留校验和，直接报错；rx-all只影响是否收到错误帧，失败时仅警告
+        if !feature_enabled(device_name, "rx-fcs")? {
+            run_ethtool(&["-K", device_name, "rx-fcs", "on"])?;
+            guard.restore.push("rx-fcs");
+        }
+        match feature_enabled(device_name, "rx-all") {
+            Ok(true) => {}
+            Ok(false) => match run_ethtool(&["-K", device_name, "rx-all", "on"]) {
+                Ok(()) => guard.restore.push("rx-all"),
+                Err(e) => warn!("Frames with a bad FCS will not be captured: {}", e),
+            },
+            Err(e) => warn!("Frames with a bad FCS will not be captured: {}", e),
+        }
+
+        info!("Capturing frames with FCS on {}", device_name);
+        Ok(guard)
+    }
+}
+
+impl Drop for FcsGuard {
+    fn drop(&mut self) {
+        for feature in &self.restore {
+            if let Err(e) = run_ethtool(&["-K", &self.device_name, feature, "off"]) {
+                warn!(
+                    "Failed to turn {} back off on {}: {}",
+                    feature, self.device_name, e
+                );
+            }
+        }
+    }
+}
+
+// `ethtool -k`的输出中每行形如"rx-fcs: off [fixed]"
+fn feature_enabled(device_name: &str, feature: &str) -> Result<bool, SavePcapError> {
+    let output = ethtool(&["-k", device_name])?;
+    let prefix = format!("{}:", feature);
+    output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix(&prefix))
+        .map(|state| state.trim_start().starts_with("on"))
+        .ok_or_else(|| {
+            SavePcapError::UnsupportedSource(format!(
+                "{} does not support {}",
+                device_name, feature
+            ))
+        })
+}
+
+fn run_ethtool(args: &[&str]) -> Result<(), SavePcapError> {
+    ethtool(args).map(|_| ())
+}
+
+fn ethtool(args: &[&str]) -> Result<String, SavePcapError> {
+    let output = Command::new("ethtool").args(args).output()?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(SavePcapError::UnsupportedSource(format!(
+            "ethtool {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
@@ -0,0 +1,434 @@
+use crate::OutputLayout;
+use crate::SavePcapError;
+use crate::extract;
+use crate::metadata::{self, json_string};
+use chrono::DateTime;
+use log::{debug, info, warn};
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// 检查是否已停止的间隔
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+// 请求行和请求头的长度上限，只接受简单的GET请求
+const MAX_REQUEST_LEN: usize = 8192;
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 内置HTTP文件服务的设置（需要启用`http-server` feature）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileServerOptions {
+    /// 提供下载的捕获目录，通常与`PcapCaptureOptions::file_path`相同
+    pub dir: String,
+    /// 监听地址，例如"0.0.0.0:8080"；端口为0时由系统分配，通过`FileServer::local_addr`获取
+    pub listen: String,
+    /// 捕获时使用的`PcapCaptureOptions::output_layout`，设置后文件列表、下载和提取同时包括
+    /// 布局生成的子目录，文件名为相对`dir`的路径，例如`team-a/2024-01-01/uplink/capture.pcap`；
+    /// None表示只提供`dir`中直接包含的文件
+    pub layout: Option<OutputLayout>,
+}
+
+/// 通过HTTP提供捕获目录中的文件，分析人员可以从远程探针上只取回需要的部分：
+///
+/// - `GET /`：JSON格式的文件列表，包括每个文件的大小和时间范围（与`extract`判断时间范围的方式相同）
+/// - `GET /files/<name>`：下载一个捕获文件
+/// - `GET /extract?start=<time>&end=<time>`：按时间范围`[start, end)`即时提取并下载一个pcap文件，
+///   时间为RFC 3339格式或Unix时间戳（秒）
+///
+/// 只提供明文HTTP，需要HTTPS时放在反向代理之后。服务在后台线程中运行，
+/// 调用`stop`或丢弃`FileServer`时停止
+pub struct FileServer {
+    address: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl FileServer {
+    pub fn start(options: FileServerOptions) -> Result<Self, SavePcapError> {
+        let dir = PathBuf::from(&options.dir);
+        if !dir.is_dir() {
+            return Err(SavePcapError::InvalidOutput(format!(
+                "{} is not a directory",
+                dir.display()
+            )));
+        }
+        let listener = TcpListener::bind(&options.listen)?;
+        listener.set_nonblocking(true)?;
+        let address = listener.local_addr()?;
+        info!("Serving {} on http://{}", dir.display(), address);
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = stop.clone();
+            let layout = options.layout;
+            thread::Builder::new()
+                .name("save-pcap-file-server".to_string())
+                .spawn(move || accept(&listener, &dir, layout.as_ref(), &stop))?
+        };
+        Ok(Self {
+            address,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// 实际监听的地址
+    pub fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// 停止接受新的连接，正在进行的下载继续完成
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for FileServer {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+fn accept(listener: &TcpListener, dir: &Path, layout: Option<&OutputLayout>, stop: &AtomicBool) {
+    while !stop.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, peer)) => {
+                let dir = dir.to_path_buf();
+                let layout = layout.cloned();
+                let spawned = thread::Builder::new()
+                    .name("save-pcap-file-request".to_string())
+                    .spawn(move || {
+                        if let Err(e) = serve(stream, &dir, layout.as_ref()) {
+                            debug!("HTTP request from {} failed: {}", peer, e);
+                        }
+                    });
+                if let Err(e) = spawned {
+                    warn!("Failed to handle HTTP request from {}: {}", peer, e);
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(ACCEPT_POLL_INTERVAL);
+            }
+            Err(e) => {
+                warn!("HTTP file server accept failed: {}", e);
+                thread::sleep(ACCEPT_POLL_INTERVAL);
+            }
+        }
+    }
+}
+
+// 一个响应：状态码、内容类型和内容
+enum Response {
+    Json(String),
+    File {
+        path: PathBuf,
+        name: String,
+        // 即时提取的临时文件，发送后删除
+        temporary: bool,
+    },
+    Error(u16, String),
+}
+
+fn serve(stream: TcpStream, dir: &Path, layout: Option<&OutputLayout>) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let request_line = read_request(&mut reader)?;
+
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (
+        parts.next().unwrap_or_default(),
+        parts.next().unwrap_or("/"),
+    );
+    let response = if method != "GET" && method != "HEAD" {
+        Response::Error(405, "only GET is supported".to_string())
+    } else {
+        route(dir, layout, target)
+    };
+    respond(stream, response, method == "HEAD")
+}
+
+// 读取请求行，跳过请求头
+fn read_request(reader: &mut impl BufRead) -> io::Result<String> {
+    let mut request_line = String::new();
+    let mut total = 0;
+    loop {
+        let mut line = String::new();
+        let read = reader.read_line(&mut line)?;
+        total += read;
+        if total > MAX_REQUEST_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "request too long",
+            ));
+        }
+        if read == 0 || line.trim_end().is_empty() {
+            return Ok(request_line);
+        }
+        if request_line.is_empty() {
+            request_line = line.trim_end().to_string();
+        }
+    }
+}
+
+fn route(dir: &Path, layout: Option<&OutputLayout>, target: &str) -> Response {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let path = percent_decode(path);
+    if path == "/" {
+        return match index(dir, layout) {
+            Ok(json) => Response::Json(json),
+            Err(e) => Response::Error(500, e.to_string()),
+        };
+    }
+    if let Some(name) = path.strip_prefix("/files/") {
+        return file(dir, layout, name);
+    }
+    if path == "/extract" {
+        return extract_window(dir, layout, query);
+    }
+    Response::Error(404, format!("no such resource: {}", path))
+}
+
+// 文件列表与`extract`使用同一份时间范围，按开始时间排序。文件名为相对dir的路径，以`/`分隔
+fn index(dir: &Path, layout: Option<&OutputLayout>) -> Result<String, SavePcapError> {
+    let mut json = String::from("{\"files\": [");
+    for (number, file) in extract::catalog(dir, layout)?.iter().enumerate() {
+        let Some(name) = relative_name(dir, &file.path) else {
+            continue;
+        };
+        let size = fs::metadata(&file.path)
+            .map(|m| m.len())
+            .unwrap_or_default();
+        let end = file
+            .end
+            .map(|end| json_string(&metadata::rfc3339(end)))
+            .unwrap_or_else(|| "null".to_string());
+        let _ = write!(
+            json,
+            "{}\n  {{\"name\": {}, \"size\": {}, \"start\": {}, \"end\": {}}}",
+            if number == 0 { "" } else { "," },
+            json_string(&name),
+            size,
+            json_string(&metadata::rfc3339(file.start)),
+            end
+        );
+    }
+    json.push_str("\n]}\n");
+    Ok(json)
+}
+
+fn relative_name(dir: &Path, path: &Path) -> Option<String> {
+    let parts: Option<Vec<&str>> = path
+        .strip_prefix(dir)
+        .ok()?
+        .components()
+        .map(|component| component.as_os_str().to_str())
+        .collect();
+    Some(parts?.join("/"))
+}
+
+// 只提供目录中直接包含的捕获文件，设置了布局时还包括布局层数以内的子目录中的文件；
+// 每一级都不能为空或以`.`开头，不允许访问其他路径
+fn file(dir: &Path, layout: Option<&OutputLayout>, name: &str) -> Response {
+    let not_found = || Response::Error(404, format!("no such file: {}", name));
+    let is_capture = name.ends_with(".pcap") || name.ends_with(".pcapng");
+    let parts: Vec<&str> = name.split('/').collect();
+    let max_parts = layout.map_or(0, OutputLayout::depth) + 1;
+    let valid = parts
+        .iter()
+        .all(|part| !part.is_empty() && !part.starts_with('.') && !part.contains('\\'));
+    if !is_capture || !valid || parts.len() > max_parts {
+        return not_found();
+    }
+    let path = parts
+        .iter()
+        .fold(dir.to_path_buf(), |path, part| path.join(part));
+    if !path.is_file() {
+        return not_found();
+    }
+    Response::File {
+        path,
+        name: parts[parts.len() - 1].to_string(),
+        temporary: false,
+    }
+}
+
+fn extract_window(dir: &Path, layout: Option<&OutputLayout>, query: &str) -> Response {
+    let mut start = None;
+    let mut end = None;
+    for pair in query.split('&') {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        match key {
+            "start" => start = parse_time(&percent_decode(value)),
+            "end" => end = parse_time(&percent_decode(value)),
+            _ => {}
+        }
+    }
+    let (Some(start), Some(end)) = (start, end) else {
+        return Response::Error(
+            400,
+            "start and end are required as RFC 3339 times or Unix seconds".to_string(),
+        );
+    };
+
+    // 临时文件放在系统临时目录中，不会出现在捕获目录的文件列表里
+    static NEXT_EXTRACT: AtomicU64 = AtomicU64::new(0);
+    let path = std::env::temp_dir().join(format!(
+        "save_pcap_extract_{}_{}.pcap",
+        std::process::id(),
+        NEXT_EXTRACT.fetch_add(1, Ordering::Relaxed)
+    ));
+    match extract::extract_in(dir, layout, start, end, &path) {
+        Ok(_) => Response::File {
+            name: format!(
+                "extract_{}-{}.pcap",
+                extract::since_epoch(start).as_secs(),
+                extract::since_epoch(end).as_secs()
+            ),
+            path,
+            temporary: true,
+        },
+        Err(e) => {
+            let _ = fs::remove_file(&path);
+            let status = match e {
+                SavePcapError::InvalidTimeWindow(_) => 400,
+                _ => 404,
+            };
+            Response::Error(status, e.to_string())
+        }
+    }
+}
+
+fn parse_time(value: &str) -> Option<SystemTime> {
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(UNIX_EPOCH + Duration::from_secs(seconds));
+    }
+    let time = DateTime::parse_from_rfc3339(value).ok()?;
+    Some(SystemTime::from(time))
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn respond(mut stream: TcpStream, response: Response, head_only: bool) -> io::Result<()> {
+    match response {
+        Response::Json(json) => {
+            write_head(
+                &mut stream,
+                200,
+                "application/json",
+                json.len() as u64,
+                None,
+            )?;
+            if !head_only {
+                stream.write_all(json.as_bytes())?;
+            }
+        }
+        Response::File {
+            path,
+            name,
+            temporary,
+        } => {
+            let result = send_file(&mut stream, &path, &name, head_only);
+            if temporary {
+                let _ = fs::remove_file(&path);
+            }
+            result?;
+        }
+        Response::Error(status, message) => {
+            let body = format!("{}\n", message);
+            write_head(
+                &mut stream,
+                status,
+                "text/plain; charset=utf-8",
+                body.len() as u64,
+                None,
+            )?;
+            if !head_only {
+                stream.write_all(body.as_bytes())?;
+            }
+        }
+    }
+    stream.flush()
+}
+
+fn send_file(stream: &mut TcpStream, path: &Path, name: &str, head_only: bool) -> io::Result<()> {
+    let mut file = File::open(path)?;
+    let content_type = if name.ends_with(".pcapng") {
+        "application/x-pcapng"
+    } else {
+        "application/vnd.tcpdump.pcap"
+    };
+    write_head(
+        stream,
+        200,
+        content_type,
+        file.metadata()?.len(),
+        Some(name),
+    )?;
+    if !head_only {
+        io::copy(&mut file, stream)?;
+    }
+    Ok(())
+}
+
+fn write_head(
+    stream: &mut TcpStream,
+    status: u16,
+    content_type: &str,
+    length: u64,
+    file_name: Option<&str>,
+) -> io::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    };
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        status, reason, content_type, length
+    );
+    if let Some(name) = file_name {
+        let _ = write!(
+            head,
+            "Content-Disposition: attachment; filename=\"{}\"\r\n",
+            name
+        );
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())
+}
@@ -0,0 +1,116 @@
+use crate::SavePcapError;
+use crate::parse::{self, IPPROTO_TCP};
+use crate::query;
+use log::info;
+use pcap::BpfProgram;
+use pcap_file::DataLink;
+use std::net::SocketAddr;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+const TCP_ACK: u8 = 0x10;
+
+/// 只保存一条TCP连接，见`PcapCaptureOptions::follow_tcp`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FollowTcp {
+    /// 开始跟踪的数据包需要匹配的BPF过滤器（在用户态求值），例如`"dst host 10.0.0.5 and dst port 443"`；
+    /// None表示任意TCP数据包
+    pub trigger: Option<String>,
+    /// 只从SYN（不带ACK）开始跟踪，文件中包含完整的握手；为false时连接中的任意数据包都可以开始跟踪，
+    /// 用于跟踪已经建立的连接
+    pub syn_only: bool,
+}
+
+impl Default for FollowTcp {
+    fn default() -> Self {
+        Self {
+            trigger: None,
+            syn_only: true,
+        }
+    }
+}
+
+pub(crate) enum Followed {
+    /// 不属于跟踪的连接，或者还没有开始跟踪
+    Other,
+    Connection,
+    /// 连接的最后一个数据包（RST，或双方都发送FIN后的确认）
+    Closed,
+}
+
+// 第一个匹配触发条件的数据包确定连接的两个端点，之后只接受这两个端点之间的数据包
+pub(crate) struct TcpFollower {
+    trigger: Option<BpfProgram>,
+    syn_only: bool,
+    datalink: DataLink,
+    // 发起方和接收方，开始跟踪前为None
+    connection: Option<(SocketAddr, SocketAddr)>,
+    // 发起方和接收方是否已发送FIN
+    fin: [bool; 2],
+}
+
+impl TcpFollower {
+    /// 在创建文件之前调用，触发过滤器有误时不留下空文件
+    pub fn new(options: &FollowTcp, datalink: DataLink) -> Result<Self, SavePcapError> {
+        let trigger = match &options.trigger {
+            Some(trigger) => Some(query::compile(trigger, datalink)?),
+            None => None,
+        };
+        Ok(Self {
+            trigger,
+            syn_only: options.syn_only,
+            datalink,
+            connection: None,
+            fin: [false; 2],
+        })
+    }
+
+    pub fn check(&mut self, data: &[u8]) -> Followed {
+        let Some(transport) =
+            parse::transport(self.datalink, data).filter(|t| t.protocol == IPPROTO_TCP)
+        else {
+            return Followed::Other;
+        };
+        let source = SocketAddr::new(transport.source, transport.source_port);
+        let destination = SocketAddr::new(transport.destination, transport.destination_port);
+        let flags = transport.tcp_flags;
+
+        let Some((client, server)) = self.connection else {
+            if self.syn_only && flags & (TCP_SYN | TCP_ACK) != TCP_SYN {
+                return Followed::Other;
+            }
+            if let Some(trigger) = &self.trigger
+                && !trigger.filter(data)
+            {
+                return Followed::Other;
+            }
+            info!("Following TCP connection {} -> {}", source, destination);
+            self.connection = Some((source, destination));
+            return self.update(0, flags);
+        };
+        let direction = if (source, destination) == (client, server) {
+            0
+        } else if (source, destination) == (server, client) {
+            1
+        } else {
+            return Followed::Other;
+        };
+        self.update(direction, flags)
+    }
+
+    fn update(&mut self, direction: usize, flags: u8) -> Followed {
+        if flags & TCP_RST != 0 {
+            return Followed::Closed;
+        }
+        if flags & TCP_FIN != 0 {
+            self.fin[direction] = true;
+            return Followed::Connection;
+        }
+        // 双方都发送FIN后，对最后一个FIN的确认结束连接
+        if self.fin == [true, true] && flags & TCP_ACK != 0 {
+            return Followed::Closed;
+        }
+        Followed::Connection
+    }
+}
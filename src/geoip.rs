@@ -0,0 +1,135 @@
+//! 按MaxMind数据库查询文件中出现的IP地址所属的国家和自治系统，
+//! 写入每个文件的`.geoip.jsonl`元数据文件，分诊时无需再单独做一遍富化
+
+use crate::SavePcapError;
+use crate::metadata::{self, json_string};
+use crate::parse;
+use log::debug;
+use maxminddb::{MaxMindDBError, Reader, geoip2};
+use pcap_file::DataLink;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+/// GeoIP数据库路径，至少指定一个
+#[derive(Debug, Clone, Default)]
+pub struct GeoIpOptions {
+    /// GeoLite2-Country或GeoLite2-City数据库，用于查询国家代码
+    pub country_database: Option<PathBuf>,
+    /// GeoLite2-ASN数据库，用于查询自治系统编号和名称
+    pub asn_database: Option<PathBuf>,
+}
+
+#[derive(Default)]
+struct HostCounters {
+    packets: u64,
+    bytes: u64,
+}
+
+// 写入时只统计每个地址的流量，文件关闭时每个地址查询一次数据库
+pub(crate) struct GeoIpEnricher {
+    country: Option<Reader<Vec<u8>>>,
+    asn: Option<Reader<Vec<u8>>>,
+    hosts: HashMap<IpAddr, HostCounters>,
+}
+
+impl GeoIpEnricher {
+    pub fn open(options: &GeoIpOptions) -> Result<Self, SavePcapError> {
+        let open = |path: &Option<PathBuf>| {
+            path.as_ref()
+                .map(|path| {
+                    Reader::open_readfile(path).map_err(|e| {
+                        SavePcapError::GeoIpDatabase(format!("{}: {}", path.display(), e))
+                    })
+                })
+                .transpose()
+        };
+        let enricher = Self {
+            country: open(&options.country_database)?,
+            asn: open(&options.asn_database)?,
+            hosts: HashMap::new(),
+        };
+        if enricher.country.is_none() && enricher.asn.is_none() {
+            return Err(SavePcapError::GeoIpDatabase(
+                "no database configured".to_string(),
+            ));
+        }
+        Ok(enricher)
+    }
+
+    pub fn record(&mut self, datalink: DataLink, data: &[u8], len: u32) {
+        if let Some((source, destination)) = parse::ip_addresses(datalink, data) {
+            for address in [source, destination] {
+                let counters = self.hosts.entry(address).or_default();
+                counters.packets += 1;
+                counters.bytes += len as u64;
+            }
+        }
+    }
+
+    pub fn discard(&mut self) {
+        self.hosts.clear();
+    }
+
+    /// 每行一个地址，按流量从大到小排列；查不到的字段为null
+    pub fn write_sidecar(&mut self, capture_path: &Path) -> io::Result<()> {
+        if self.hosts.is_empty() {
+            return Ok(());
+        }
+        let mut hosts: Vec<_> = self.hosts.drain().collect();
+        hosts.sort_by_key(|(_, counters)| Reverse(counters.bytes));
+
+        let mut lines = String::new();
+        for (address, counters) in hosts {
+            let country = self.country.as_ref().and_then(|reader| {
+                found(address, reader.lookup::<geoip2::Country>(address))?
+                    .country?
+                    .iso_code
+                    .map(json_string)
+            });
+            let asn = self
+                .asn
+                .as_ref()
+                .and_then(|reader| found(address, reader.lookup::<geoip2::Asn>(address)));
+            let _ = writeln!(
+                lines,
+                "{{\"ip\": {}, \"packets\": {}, \"bytes\": {}, \"country\": {}, \"asn\": {}, \"as_org\": {}}}",
+                json_string(&address.to_string()),
+                counters.packets,
+                counters.bytes,
+                country.as_deref().unwrap_or("null"),
+                asn.as_ref()
+                    .and_then(|asn| asn.autonomous_system_number)
+                    .map(|number| number.to_string())
+                    .as_deref()
+                    .unwrap_or("null"),
+                asn.as_ref()
+                    .and_then(|asn| asn.autonomous_system_organization)
+                    .map(json_string)
+                    .as_deref()
+                    .unwrap_or("null"),
+            );
+        }
+
+        fs::write(
+            metadata::sidecar_path_with(capture_path, metadata::GEOIP_SUFFIX),
+            lines,
+        )
+    }
+}
+
+// 私有地址等不在数据库中的地址返回None
+fn found<T>(address: IpAddr, result: Result<T, MaxMindDBError>) -> Option<T> {
+    match result {
+        Ok(record) => Some(record),
+        Err(MaxMindDBError::AddressNotFoundError(_)) => None,
+        Err(e) => {
+            debug!("GeoIP lookup failed for {}: {}", address, e);
+            None
+        }
+    }
+}
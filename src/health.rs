@@ -0,0 +1,236 @@
+use crate::session::SessionState;
+use crate::stats::CaptureStats;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// 健康检查的阈值，用于`CaptureHandle::health()`
+#[derive(Debug, Clone)]
+pub struct HealthOptions {
+    /// 输出目录所在磁盘的可用空间低于该值（MB）时为Degraded，0表示不检查（仅Unix）
+    pub min_free_disk_mb: u64,
+    /// 写入队列占用达到容量的该比例时为Degraded（仅在启用独立写入线程时有效）
+    pub queue_saturation: f64,
+    /// 超过该时间没有写入数据包时为Degraded，None表示不检查；暂停期间不检查
+    pub max_idle: Option<Duration>,
+}
+
+impl Default for HealthOptions {
+    fn default() -> Self {
+        Self {
+            min_free_disk_mb: 1024,
+            queue_saturation: 0.9,
+            max_idle: None,
+        }
+    }
+}
+
+/// 健康状态，依次变差
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HealthStatus {
+    /// 正在捕获，没有发现问题
+    Ready,
+    /// 仍在捕获（或尚未开始），但需要关注，例如可作为就绪探针失败、存活探针成功的依据
+    Degraded,
+    /// 捕获已结束或无法继续
+    Failed,
+}
+
+/// 导致Degraded或Failed的原因
+#[derive(Debug, Clone, PartialEq)]
+pub enum HealthIssue {
+    /// 尚未调用`capture()`或尚未打开第一个文件
+    NotStarted,
+    /// 捕获已正常结束
+    Stopped,
+    /// 捕获因错误结束
+    CaptureFailed(String),
+    /// 网卡不存在或未启用（仅Linux）
+    DeviceDown {
+        device: String,
+        state: String,
+    },
+    LowDiskSpace {
+        free_mb: u64,
+        threshold_mb: u64,
+    },
+    QueueSaturated {
+        len: usize,
+        capacity: usize,
+    },
+    NoPackets {
+        idle: Duration,
+    },
+}
+
+impl HealthIssue {
+    fn status(&self) -> HealthStatus {
+        match self {
+            HealthIssue::Stopped
+            | HealthIssue::CaptureFailed(_)
+            | HealthIssue::DeviceDown { .. } => HealthStatus::Failed,
+            _ => HealthStatus::Degraded,
+        }
+    }
+}
+
+impl fmt::Display for HealthIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HealthIssue::NotStarted => write!(f, "capture not started"),
+            HealthIssue::Stopped => write!(f, "capture stopped"),
+            HealthIssue::CaptureFailed(error) => write!(f, "capture failed: {}", error),
+            HealthIssue::DeviceDown { device, state } => {
+                write!(f, "device {} is {}", device, state)
+            }
+            HealthIssue::LowDiskSpace {
+                free_mb,
+                threshold_mb,
+            } => write!(
+                f,
+                "{} MB free disk space is below {} MB",
+                free_mb, threshold_mb
+            ),
+            HealthIssue::QueueSaturated { len, capacity } => {
+                write!(f, "writer queue {}/{} full", len, capacity)
+            }
+            HealthIssue::NoPackets { idle } => {
+                write!(f, "no packets written for {}s", idle.as_secs())
+            }
+        }
+    }
+}
+
+/// 健康检查结果，status为所有原因中最差的状态
+#[derive(Debug, Clone, PartialEq)]
+pub struct Health {
+    pub status: HealthStatus,
+    pub issues: Vec<HealthIssue>,
+}
+
+// 捕获器创建时确定的检查对象，由控制句柄持有
+#[derive(Debug, Default)]
+pub(crate) struct HealthContext {
+    pub options: HealthOptions,
+    pub output_dir: PathBuf,
+    pub device: Option<String>,
+}
+
+impl HealthContext {
+    // idle为距上次写入数据包（或开始捕获）的时间
+    pub fn check(
+        &self,
+        state: SessionState,
+        error: Option<String>,
+        stats: &CaptureStats,
+        idle: Duration,
+    ) -> Health {
+        let mut issues = Vec::new();
+        match state {
+            SessionState::Created => issues.push(HealthIssue::NotStarted),
+            SessionState::Stopped => issues.push(HealthIssue::Stopped),
+            SessionState::Failed => {
+                issues.push(HealthIssue::CaptureFailed(error.unwrap_or_default()))
+            }
+            SessionState::Running | SessionState::Paused | SessionState::Rotating => {
+                self.check_running(state, stats, idle, &mut issues)
+            }
+        }
+
+        let status = issues
+            .iter()
+            .map(HealthIssue::status)
+            .max()
+            .unwrap_or(HealthStatus::Ready);
+        Health { status, issues }
+    }
+
+    fn check_running(
+        &self,
+        state: SessionState,
+        stats: &CaptureStats,
+        idle: Duration,
+        issues: &mut Vec<HealthIssue>,
+    ) {
+        if let Some(device) = &self.device
+            && let Some(device_state) = device_state(device)
+        {
+            issues.push(HealthIssue::DeviceDown {
+                device: device.clone(),
+                state: device_state,
+            });
+        }
+
+        let threshold_mb = self.options.min_free_disk_mb;
+        if threshold_mb > 0
+            && let Some(free_mb) = free_disk_mb(&self.output_dir)
+            && free_mb < threshold_mb
+        {
+            issues.push(HealthIssue::LowDiskSpace {
+                free_mb,
+                threshold_mb,
+            });
+        }
+
+        let capacity = stats.writer_queue_capacity;
+        if capacity > 0
+            && stats.writer_queue_len as f64 >= capacity as f64 * self.options.queue_saturation
+        {
+            issues.push(HealthIssue::QueueSaturated {
+                len: stats.writer_queue_len,
+                capacity,
+            });
+        }
+
+        if let Some(max_idle) = self.options.max_idle
+            && state != SessionState::Paused
+            && idle > max_idle
+        {
+            issues.push(HealthIssue::NoPackets { idle });
+        }
+    }
+}
+
+// 网卡未启用时返回其状态，网卡正常或无法判断时返回None
+#[cfg(target_os = "linux")]
+pub(crate) fn device_state(device: &str) -> Option<String> {
+    let net = Path::new("/sys/class/net");
+    if !net.exists() {
+        return None;
+    }
+    // 只检查普通网卡名，"any"等伪设备没有对应的目录
+    if device == "any" || device.contains(['/', ':']) {
+        return None;
+    }
+    match std::fs::read_to_string(net.join(device).join("operstate")) {
+        // 部分虚拟网卡（例如lo、tun）的状态为unknown
+        Ok(state) => match state.trim() {
+            "up" | "unknown" => None,
+            state => Some(state.to_string()),
+        },
+        Err(_) => Some("missing".to_string()),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn device_state(_device: &str) -> Option<String> {
+    None
+}
+
+#[cfg(unix)]
+fn free_disk_mb(path: &Path) -> Option<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64 / (1024 * 1024))
+}
+
+#[cfg(not(unix))]
+fn free_disk_mb(_path: &Path) -> Option<u64> {
+    None
+}
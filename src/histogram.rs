@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+/// 直方图的分桶配置。每个边界是一个桶的上限（包含），超过最后一个边界的值计入溢出桶
+#[derive(Debug, Clone)]
+pub struct HistogramOptions {
+    /// 相邻数据包时间戳之差的分桶边界，用于观察突发和微突发
+    pub inter_arrival_bounds: Vec<Duration>,
+    /// 数据包原始长度（字节）的分桶边界
+    pub size_bounds: Vec<u64>,
+}
+
+impl Default for HistogramOptions {
+    fn default() -> Self {
+        Self {
+            inter_arrival_bounds: [1, 10, 100, 1_000, 10_000, 100_000, 1_000_000]
+                .into_iter()
+                .map(Duration::from_micros)
+                .collect(),
+            size_bounds: vec![64, 128, 256, 512, 1024, 1518, 9000],
+        }
+    }
+}
+
+/// 固定分桶的直方图。`counts`比`upper_bounds`多一项，最后一项为超过所有边界的值
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Histogram {
+    /// 每个桶的上限（包含），时间间隔以纳秒为单位
+    pub upper_bounds: Vec<u64>,
+    pub counts: Vec<u64>,
+    pub min: Option<u64>,
+    pub max: Option<u64>,
+}
+
+impl Histogram {
+    pub fn new(mut upper_bounds: Vec<u64>) -> Self {
+        upper_bounds.sort_unstable();
+        upper_bounds.dedup();
+        let counts = vec![0; upper_bounds.len() + 1];
+        Self {
+            upper_bounds,
+            counts,
+            min: None,
+            max: None,
+        }
+    }
+
+    pub fn record(&mut self, value: u64) {
+        let bucket = self.upper_bounds.partition_point(|&bound| bound < value);
+        self.counts[bucket] += 1;
+        self.min = Some(self.min.map_or(value, |min| min.min(value)));
+        self.max = Some(self.max.map_or(value, |max| max.max(value)));
+    }
+
+    /// 已记录的值的数量
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+}
+
+// 写入线程维护的两个直方图
+#[derive(Debug, Clone)]
+pub(crate) struct PacketHistograms {
+    pub inter_arrival: Histogram,
+    pub sizes: Histogram,
+    last_timestamp: Option<Duration>,
+}
+
+impl PacketHistograms {
+    pub fn new(options: &HistogramOptions) -> Self {
+        Self {
+            inter_arrival: Histogram::new(
+                options
+                    .inter_arrival_bounds
+                    .iter()
+                    .map(|bound| bound.as_nanos() as u64)
+                    .collect(),
+            ),
+            sizes: Histogram::new(options.size_bounds.clone()),
+            last_timestamp: None,
+        }
+    }
+
+    // 时间戳倒退（乱序）的数据包间隔记为0
+    pub fn record(&mut self, timestamp: Duration, orig_len: u32) {
+        if let Some(last) = self.last_timestamp {
+            let gap = timestamp.saturating_sub(last);
+            self.inter_arrival.record(gap.as_nanos() as u64);
+        }
+        self.last_timestamp = Some(
+            self.last_timestamp
+                .map_or(timestamp, |last| last.max(timestamp)),
+        );
+        self.sizes.record(orig_len as u64);
+    }
+}
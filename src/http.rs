@@ -0,0 +1,46 @@
+// 只识别常见的请求方法，避免把任意以大写单词开头的TCP负载当作HTTP请求
+const METHODS: [&str; 9] = [
+    "GET", "POST", "PUT", "DELETE", "HEAD", "OPTIONS", "PATCH", "CONNECT", "TRACE",
+];
+
+/// 明文HTTP/1.x请求的请求行和Host头
+pub(crate) struct HttpRequest<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    pub host: Option<&'a str>,
+}
+
+/// 从TCP负载中解析HTTP请求。只处理请求头位于单个数据包开头的情况；
+/// 请求头跨越多个数据包时只能得到请求行，Host可能缺失
+pub(crate) fn request(payload: &[u8]) -> Option<HttpRequest<'_>> {
+    let method_len = payload.iter().take(8).position(|&b| b == b' ')?;
+    let method = std::str::from_utf8(&payload[..method_len]).ok()?;
+    if !METHODS.contains(&method) {
+        return None;
+    }
+
+    // 头部之后的请求体可能是二进制数据，只对头部做UTF-8解码；
+    // 被快照长度截断的头部只取截断前完整的部分
+    let header_end = payload
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .unwrap_or(payload.len());
+    let header = match std::str::from_utf8(&payload[..header_end]) {
+        Ok(header) => header,
+        Err(e) => std::str::from_utf8(&payload[..e.valid_up_to()]).ok()?,
+    };
+    let mut lines = header.split("\r\n");
+
+    let mut request_line = lines.next()?.split(' ');
+    request_line.next()?;
+    let path = request_line.next().filter(|path| !path.is_empty())?;
+    if !request_line.next()?.starts_with("HTTP/1.") {
+        return None;
+    }
+
+    let host = lines.find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.eq_ignore_ascii_case("host").then(|| value.trim())
+    });
+    Some(HttpRequest { method, path, host })
+}
@@ -0,0 +1,103 @@
+use crate::PcapCaptureOptions;
+use crate::http;
+use crate::metadata::{self, json_string};
+use crate::parse::{self, IPPROTO_TCP, Transport};
+use crate::source::SourcePacket;
+use crate::tls;
+use pcap_file::DataLink;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+
+// 会话索引：每个捕获文件对应一个`.index.jsonl`文件，每行记录一个可检索的会话特征
+// （TLS SNI、HTTP请求行）及其数据包在文件中的偏移，不打开捕获文件就能找到
+// 包含某个会话的文件和位置
+pub(crate) struct SessionIndex {
+    tls_sni: bool,
+    http_requests: bool,
+    entries: String,
+}
+
+impl SessionIndex {
+    pub fn new(options: &PcapCaptureOptions) -> Option<Self> {
+        (options.tls_sni_index || options.http_request_index).then(|| Self {
+            tls_sni: options.tls_sni_index,
+            http_requests: options.http_request_index,
+            entries: String::new(),
+        })
+    }
+
+    /// offset为数据包记录在捕获文件中的起始偏移
+    pub fn record(&mut self, datalink: DataLink, packet: &SourcePacket, offset: u64) {
+        let Some(transport) = parse::transport(datalink, &packet.data) else {
+            return;
+        };
+        if transport.protocol != IPPROTO_TCP || transport.payload.is_empty() {
+            return;
+        }
+
+        if self.tls_sni
+            && let Some(sni) = tls::client_hello_sni(transport.payload)
+        {
+            self.push(packet, offset, &transport, "tls", &[("sni", Some(sni))]);
+        } else if self.http_requests
+            && let Some(request) = http::request(transport.payload)
+        {
+            self.push(
+                packet,
+                offset,
+                &transport,
+                "http",
+                &[
+                    ("method", Some(request.method)),
+                    ("host", request.host),
+                    ("path", Some(request.path)),
+                ],
+            );
+        }
+    }
+
+    // 每行的公共字段之后追加该类型特有的字段
+    fn push(
+        &mut self,
+        packet: &SourcePacket,
+        offset: u64,
+        transport: &Transport,
+        kind: &str,
+        fields: &[(&str, Option<&str>)],
+    ) {
+        let _ = write!(
+            self.entries,
+            "{{\"timestamp\": {}, \"offset\": {}, \"type\": \"{}\", \"source\": {}, \"destination\": {}",
+            json_string(&metadata::rfc3339(packet.timestamp)),
+            offset,
+            kind,
+            json_string(&SocketAddr::new(transport.source, transport.source_port).to_string()),
+            json_string(
+                &SocketAddr::new(transport.destination, transport.destination_port).to_string()
+            ),
+        );
+        for (key, value) in fields {
+            let value = value.map(json_string).unwrap_or_else(|| "null".to_string());
+            let _ = write!(self.entries, ", \"{}\": {}", key, value);
+        }
+        self.entries.push_str("}\n");
+    }
+
+    pub fn discard(&mut self) {
+        self.entries.clear();
+    }
+
+    /// 没有任何条目时不创建文件
+    pub fn write_sidecar(&mut self, capture_path: &Path) -> io::Result<()> {
+        if self.entries.is_empty() {
+            return Ok(());
+        }
+        let path = metadata::sidecar_path_with(capture_path, metadata::INDEX_SUFFIX);
+        let result = fs::write(path, &self.entries);
+        self.entries.clear();
+        result
+    }
+}
@@ -0,0 +1,154 @@
+use crate::paths;
+use chrono::{DateTime, Local};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// 多租户的输出布局：捕获文件按模板放入`file_path`下的子目录，例如
+/// `/data/team-a/2024-01-01/uplink/capture_20240101_120000.pcap`，见`PcapCaptureOptions::output_layout`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputLayout {
+    /// 相对`file_path`的子目录，以`/`分隔。可用的占位符：`{tenant}`、`{session}`、
+    /// `{date}`（创建文件时的本地日期，`2024-01-01`）、`{hour}`（`00`~`23`）和`{label:键}`（`labels`中的值）
+    pub template: String,
+    pub tenant: String,
+    pub session: String,
+    /// 新建目录的权限（仅Unix），例如`0o750`，不受umask影响；None表示按umask
+    pub dir_mode: Option<u32>,
+    /// 新建捕获文件的权限（仅Unix），例如`0o640`；None表示按umask
+    pub file_mode: Option<u32>,
+}
+
+impl OutputLayout {
+    /// `{tenant}/{date}/{session}`布局，目录只允许所有者和同组用户访问
+    pub fn new(tenant: impl Into<String>, session: impl Into<String>) -> Self {
+        Self {
+            template: "{tenant}/{date}/{session}".to_string(),
+            tenant: tenant.into(),
+            session: session.into(),
+            dir_mode: Some(0o750),
+            file_mode: Some(0o640),
+        }
+    }
+
+    /// 按模板生成相对路径。每一级目录名按`paths::sanitize_file_name_part`处理，租户和标签的值
+    /// 中的`/`、`..`等不会改变目录结构
+    pub(crate) fn render(
+        &self,
+        labels: &[(String, String)],
+        now: DateTime<Local>,
+    ) -> Result<PathBuf, String> {
+        let mut relative = PathBuf::new();
+        for segment in self.template.split(['/', '\\']) {
+            if segment.is_empty() {
+                continue;
+            }
+            let rendered = self.render_segment(segment, labels, now)?;
+            let sanitized = paths::sanitize_file_name_part(&rendered).ok_or_else(|| {
+                format!("layout segment `{}` is empty after substitution", segment)
+            })?;
+            relative.push(sanitized);
+        }
+        Ok(relative)
+    }
+
+    fn render_segment(
+        &self,
+        segment: &str,
+        labels: &[(String, String)],
+        now: DateTime<Local>,
+    ) -> Result<String, String> {
+        let mut rendered = String::new();
+        let mut rest = segment;
+        while let Some(start) = rest.find('{') {
+            rendered.push_str(&rest[..start]);
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("unclosed placeholder in layout `{}`", self.template))?;
+            let name = &rest[start + 1..start + end];
+            let value = match name {
+                "tenant" => self.tenant.clone(),
+                "session" => self.session.clone(),
+                "date" => now.format("%Y-%m-%d").to_string(),
+                "hour" => now.format("%H").to_string(),
+                _ => match name.strip_prefix("label:") {
+                    Some(key) => labels
+                        .iter()
+                        .find(|(label, _)| label == key)
+                        .map(|(_, value)| value.clone())
+                        .ok_or_else(|| format!("label `{}` used in the layout is not set", key))?,
+                    None => return Err(format!("unknown layout placeholder `{{{}}}`", name)),
+                },
+            };
+            rendered.push_str(&value);
+            rest = &rest[start + end + 1..];
+        }
+        rendered.push_str(rest);
+        Ok(rendered)
+    }
+
+    /// 按需逐级创建base下的目录，新建的目录设置`dir_mode`
+    pub(crate) fn create_dirs(&self, base: &Path, relative: &Path) -> io::Result<()> {
+        let mut dir = base.to_path_buf();
+        for component in relative.components() {
+            dir.push(component);
+            match fs::create_dir(&dir) {
+                Ok(()) => set_mode(&dir, self.dir_mode)?,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists && dir.is_dir() => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// 新建的捕获文件设置`file_mode`
+    pub(crate) fn set_file_mode(&self, path: &Path) -> io::Result<()> {
+        set_mode(path, self.file_mode)
+    }
+
+    // 模板的目录层数，修复、清理和提取时在这么多层子目录中查找捕获文件
+    pub(crate) fn depth(&self) -> usize {
+        self.template
+            .split(['/', '\\'])
+            .filter(|segment| !segment.is_empty())
+            .count()
+    }
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: Option<u32>) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    match mode {
+        Some(mode) => fs::set_permissions(path, fs::Permissions::from_mode(mode)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: Option<u32>) -> io::Result<()> {
+    Ok(())
+}
+
+/// 可能存放捕获文件的目录：base本身，启用布局时还包括按模板层数展开的所有子目录
+pub(crate) fn capture_dirs(layout: Option<&OutputLayout>, base: &Path) -> Vec<PathBuf> {
+    let mut dirs = vec![base.to_path_buf()];
+    let depth = layout.map_or(0, OutputLayout::depth);
+    let mut level = vec![base.to_path_buf()];
+    for _ in 0..depth {
+        let mut next = Vec::new();
+        for dir in &level {
+            let Ok(entries) = fs::read_dir(dir) else {
+                continue;
+            };
+            next.extend(
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_dir()))
+                    .map(|entry| entry.path()),
+            );
+        }
+        dirs.extend(next.iter().cloned());
+        level = next;
+    }
+    dirs
+}
@@ -1,14 +1,161 @@
+mod alert;
+mod annotation;
+mod bandwidth;
+pub mod bench;
+mod bucket;
+#[cfg(all(unix, feature = "daemon"))]
+pub mod daemon;
+mod devices;
+mod diagnose;
+#[cfg(all(target_os = "linux", feature = "direct-io"))]
+mod direct;
+mod dns;
+mod events;
+mod extract;
+mod fallback;
+mod fanout;
+mod fcs;
+#[cfg(feature = "http-server")]
+mod file_server;
+mod follow;
+#[cfg(feature = "geoip")]
+mod geoip;
+mod health;
+mod histogram;
+mod http;
+mod index;
+mod layout;
+mod manager;
+mod merge;
+mod metadata;
+mod observer;
+#[cfg(feature = "otel")]
+mod otel;
+mod output_lock;
+mod packet_builder;
+mod packet_index;
+mod parse;
+mod paths;
+mod pipeline;
+mod pktmon;
+mod pool;
+mod query;
+mod reader;
+mod redact;
+mod reorder;
+mod repair;
+mod replay;
+mod rules;
+mod sampling;
+mod sanity;
+mod scenario;
+mod sender;
+#[cfg(all(windows, feature = "windows-service"))]
+pub mod service;
+mod session;
+mod share;
+mod shared;
+mod sidecar;
+mod source;
+mod spsc;
+mod state_file;
+mod stats;
+#[cfg(feature = "syslog")]
+mod syslog;
+mod talkers;
+pub mod testgen;
+mod time_shift;
+mod time_window;
+mod tls;
+#[cfg(feature = "validate")]
+mod validate;
+mod verify;
+#[cfg(feature = "websocket")]
+mod websocket;
+mod writer;
+
+pub use alert::{Alert, AlertCallback, AlertOptions};
+pub use annotation::{AnnotatedPacket, PacketAnnotator};
+pub use bucket::{LatePacketPolicy, TimeBucket};
 use chrono::{DateTime, Local};
-use log::{debug, error, info};
-use pcap::{Active, Capture, Device, Error as PcapError};
-use pcap_file::pcap::{PcapPacket, PcapWriter};
-use std::borrow::Cow;
-use std::fs::{self, File};
-use std::io::{BufWriter, Write};
-use std::path::Path;
+pub use devices::{DeviceInfo, get_available_devices_detailed};
+pub use diagnose::DeviceErrorCause;
+use events::JsonEventLog;
+pub use events::{JsonEventOptions, JsonEventTarget};
+pub use extract::extract;
+use fanout::Fanout;
+pub use fanout::OutputOptions;
+use fcs::FcsGuard;
+#[cfg(feature = "http-server")]
+pub use file_server::{FileServer, FileServerOptions};
+pub use follow::FollowTcp;
+use follow::{Followed, TcpFollower};
+#[cfg(feature = "geoip")]
+pub use geoip::GeoIpOptions;
+use health::HealthContext;
+pub use health::{Health, HealthIssue, HealthOptions, HealthStatus};
+pub use histogram::{Histogram, HistogramOptions};
+pub use layout::OutputLayout;
+use log::{debug, info, warn};
+pub use manager::{CaptureManager, SessionInfo};
+pub use merge::merge_capture_files;
+pub use observer::{ClosedFile, Observer};
+#[cfg(feature = "otel")]
+use otel::OtelExporter;
+#[cfg(feature = "otel")]
+pub use otel::OtelOptions;
+use output_lock::OutputLock;
+pub use packet_builder::{EthernetFrame, TcpFlags};
+pub use packet_index::{IndexedCaptureReader, IndexedPacket, PacketIndex, PacketIndexEntry};
+use pcap::{Active, Capture, Device, Error as PcapError, Linktype};
+use pcap_file::DataLink;
+pub use pipeline::{Pipeline, Rotation, Transform};
+use pktmon::PktmonStream;
+use pool::BufferPool;
+pub use query::{
+    QueryMatcher, QueryOptions, QueryPacket, QueryProgress, QueryProgressCallback, query,
+};
+use reader::{CaptureReader, FilteredStream};
+pub use redact::{RedactionOptions, RedactionPattern};
+pub use reorder::TimestampPolicy;
+pub use repair::{RepairReport, repair};
+pub use replay::{ReplayOptions, ReplayReport, ReplayTiming, replay, replay_with};
+pub use rules::{ClassificationRules, Rule, RuleMatch};
+use sampling::{FlowSampler, Sampler};
+pub use sanity::{InvalidPacketAction, SanityCheck};
+pub use scenario::Scenario;
+pub use sender::PacketSender;
+use session::SessionStatus;
+pub use session::{SessionState, StateChange};
+pub use share::ShareRetry;
+use shared::SharedStream;
+use source::{NextPacket, PacketStream, SourcePacket, UserPacketStream};
+use stats::StatsCounters;
+pub use stats::{
+    CaptureReport, CaptureStats, LiveStats, PcapStats, ProtocolStats, SenderStats, TrafficCounter,
+};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read};
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{Receiver, Sender, channel};
-use std::time::Duration;
+use std::thread;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+#[cfg(feature = "syslog")]
+use syslog::SyslogNotifier;
+#[cfg(feature = "syslog")]
+pub use syslog::{SyslogFacility, SyslogOptions};
 use thiserror::Error;
+pub use time_shift::TimeShift;
+use time_shift::TimeShifter;
+pub use time_window::TimeWindow;
+#[cfg(feature = "validate")]
+pub use validate::{PacketValidation, ValidationAction};
+pub use verify::{VerificationIssue, VerificationReport, verify};
+#[cfg(feature = "websocket")]
+pub use websocket::LiveStreamOptions;
 
 #[derive(Error, Debug)]
 pub enum SavePcapError {
@@ -18,38 +165,373 @@ pub enum SavePcapError {
     IoError(#[from] std::io::Error),
     #[error("Invalid device name: {0}")]
     InvalidDevice(String),
+    /// 打开网卡失败且识别出了原因，detail为libpcap的原始错误信息
+    #[error("Cannot capture on {device}: {cause} ({detail})")]
+    DeviceUnavailable {
+        device: String,
+        cause: DeviceErrorCause,
+        detail: String,
+    },
     #[error("Directory creation failed: {0}")]
     DirectoryCreationFailed(String),
+    #[error("Directory is not writable: {0}")]
+    DirectoryNotWritable(String),
     #[error("Capture interrupted")]
     CaptureInterrupted,
     #[error("Pcap file error: {0}")]
     PcapFileError(String),
+    #[error("Unsupported packet source: {0}")]
+    UnsupportedSource(String),
+    #[error("Already running: {0}")]
+    AlreadyRunning(String),
+    #[error("Disk full while writing: {0}")]
+    DiskFull(String),
+    #[error("Invalid time window: {0}")]
+    InvalidTimeWindow(String),
+    #[error("Capture session not found: {0}")]
+    SessionNotFound(String),
+    #[error("Invalid session state file: {0}")]
+    InvalidStateFile(String),
+    #[error("Unknown scenario: {0}")]
+    UnknownScenario(String),
+    #[error("Invalid output: {0}")]
+    InvalidOutput(String),
+    #[error("Invalid packet index: {0}")]
+    InvalidPacketIndex(String),
+    #[error("Invalid replay options: {0}")]
+    InvalidReplayOptions(String),
+    #[error("Invalid traffic generator options: {0}")]
+    InvalidTrafficOptions(String),
+    #[error("Invalid packet: {0}")]
+    InvalidPacket(String),
+    #[error("Invalid classification rules: {0}")]
+    InvalidRules(String),
+    #[error("Invalid pipeline: {0}")]
+    InvalidPipeline(String),
+    #[error("Invalid sampling: {0}")]
+    InvalidSampling(String),
+    #[error("Output file already exists: {0}")]
+    FileExists(String),
+    #[error("Output is in use by another capture: {0}")]
+    OutputLocked(String),
+    #[error("Memory limit exceeded: {0}")]
+    MemoryLimit(String),
+    #[error("Output unavailable: {0}")]
+    OutputUnavailable(String),
+    #[cfg(feature = "geoip")]
+    #[error("GeoIP database error: {0}")]
+    GeoIpDatabase(String),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FileFormat {
     Pcap,
     PcapNg,
 }
 
-#[derive(Debug)]
+/// 写入时磁盘已满的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DiskFullPolicy {
+    /// 将当前文件截断到最后一个完整的数据包，停止捕获并返回`SavePcapError::DiskFull`
+    #[default]
+    Stop,
+    /// 删除输出目录中最旧的同前缀捕获文件，在新文件中继续捕获；没有可删除的文件时同Stop
+    DeleteOldest,
+}
+
+/// 写入数据包失败（磁盘已满除外，见`DiskFullPolicy`）时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WriteErrorPolicy {
+    /// 结束捕获并返回`SavePcapError::PcapFileError`
+    #[default]
+    Abort,
+    /// 丢弃该数据包，继续写入当前文件。适合偶发的、不会留下半个数据包的错误
+    Skip,
+    /// 丢弃该数据包，把当前文件截断到最后一个完整的数据包，在新文件中继续捕获
+    Rotate,
+}
+
+/// 缓存的数据包超过`memory_limit_bytes`时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MemoryLimitPolicy {
+    /// 捕获线程等待写入线程腾出内存，把背压留给内核缓冲区
+    #[default]
+    Block,
+    /// 丢弃新的数据包，计入`CaptureStats::memory_dropped`
+    Drop,
+    /// 结束捕获并返回`SavePcapError::MemoryLimit`
+    Abort,
+}
+
+/// 新文件的文件名与已有文件相同时的处理方式。滚动产生的文件总是使用新的文件名（同`AppendSuffix`），
+/// 该设置只影响捕获开始时创建的第一个文件
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictPolicy {
+    /// 返回`SavePcapError::FileExists`，不修改已有文件
+    Error,
+    /// 截断并覆盖已有文件
+    Overwrite,
+    /// 在文件名的时间后加上`_1`、`_2`……直到文件名未被使用
+    #[default]
+    AppendSuffix,
+}
+
+#[derive(Debug, Clone)]
 pub enum PacketSource {
     NetworkDevice(String),
     UserProvided,
+    /// Linux蓝牙HCI设备，例如"bluetooth0"
+    BluetoothHci(String),
+    /// Linux SocketCAN接口，例如"can0"或"vcan0"
+    CanInterface(String),
+    /// Linux netfilter日志组，对应iptables/nftables中`NFLOG --nflog-group`的组号
+    Nflog(u16),
+    /// Windows自带的Pktmon(ETW)抓包，适用于不允许安装Npcap的服务器
+    Pktmon,
+    /// 从已有的pcap/pcapng文件读取数据包，按当前配置（过滤、滚动等）重新保存
+    File(String),
+    /// 从标准输入读取pcap/pcapng流，例如`tcpdump -w - | mytool`，流结束时捕获结束。
+    /// 其他`Read`数据流使用`PcapCapturer::capture_from_reader`
+    Stdin,
+    /// 与同一进程中使用同一网卡的其他捕获共用一个打开的网卡，网卡只读取一次。
+    /// `filter`在用户态求值，各捕获可以使用不同的过滤器和输出；网卡按第一个捕获的设置打开，
+    /// 在最后一个捕获结束时关闭
+    SharedDevice(String),
 }
 
+#[derive(Clone)]
 pub struct PcapCaptureOptions {
     pub packet_source: PacketSource,
     pub file_prefix: String,
     pub file_path: String,
+    /// 相对路径的`file_path`（以及额外输出的目录）相对于该目录解析，None表示当前工作目录。
+    /// 路径在创建`PcapCapturer`时解析为绝对路径，之后切换工作目录（例如以守护进程运行）不受影响
+    pub base_dir: Option<String>,
+    /// 文件先写入该目录（通常在本地磁盘），结束或滚动时再移动到`file_path`，适合输出目录是
+    /// NFS/SMB挂载或UNC路径（如`\\server\share\captures`）的情况：共享不可用时写入本地的文件
+    /// 不受影响，重试后仍移动失败的文件留在暂存目录，启用`repair_on_startup`时下次启动再移动。
+    /// 移动在写文件的线程中同步进行，滚动要等移动（跨文件系统时包括复制和落盘，以及`share_retry`
+    /// 的等待）完成后才继续，这期间到达的数据包积压在写入队列（见`writer_queue_capacity`）和
+    /// 内核缓冲区中，内核缓冲区满时会丢包；None表示直接写入`file_path`
+    pub staging_dir: Option<String>,
+    /// 创建、重命名和移动文件遇到网络共享的临时错误时等待后重试。写入过程中的错误不重试，
+    /// 可以配合`write_error_policy`的`Rotate`换到新文件继续；None表示不重试
+    pub share_retry: Option<ShareRetry>,
+    /// 按模板把捕获文件放入`file_path`下的子目录，例如`{tenant}/{date}/{session}`，适合多个团队
+    /// 共用一台捕获设备的情况。子目录在创建文件时按需创建，日期变化后的新文件自动进入新的目录；
+    /// 设置`staging_dir`时暂存目录中使用相同的子目录。None表示文件直接放在`file_path`中
+    pub output_layout: Option<OutputLayout>,
     pub file_format: FileFormat,
     pub packet_limit: Option<usize>,
+    /// 写入的数据包字节数（按`slice_bytes`截断后的长度，不含文件头和记录头）达到该值后停止捕获，
+    /// 比数据包数量更适合按存储预算限制捕获；超过上限的那个数据包仍完整写入。
+    /// 与`packet_limit`同时设置时任一条件满足即停止，None表示不限制
+    pub byte_limit: Option<u64>,
+    /// 连续这么多秒没有需要保存的数据包（通过过滤、长度和抽样等条件）时结束捕获，计时从开始捕获
+    /// 算起，暂停期间不计时。用于"捕获这次探测的响应"之类的脚本，不必等待可能永远达不到的
+    /// `packet_limit`；None表示不自动结束
+    pub stop_after_quiet_seconds: Option<u64>,
     pub snaplen: i32,
+    /// 写入文件时只保存每个数据包的前N字节（记录中保留原始长度）。与`snaplen`不同，
+    /// 捕获和BPF过滤仍使用完整的帧，会话索引、DNS日志等元数据也从完整的帧中提取；None表示不截断
+    pub slice_bytes: Option<usize>,
     pub timeout_ms: i32,
     pub continuous_capture: bool,
     pub rollover_time_seconds: Option<u64>,
     pub rollover_packet_count: Option<usize>,
     pub rollover_file_size_mb: Option<u64>,
+    /// 按文件大小滚动时，在写入会使文件超过`rollover_file_size_mb`的数据包之前滚动，文件不会
+    /// 超过上限（单个数据包超过上限或pcapng文件末尾的统计块除外）；默认在超过上限之后滚动
+    pub strict_size_rollover: bool,
+    /// 持续捕获时超过该秒数没有数据包则在最后一个数据包处结束当前文件，流量恢复时再创建
+    /// 下一个文件，每个文件对应一段连续的流量；None表示不按空闲时间滚动
+    pub rollover_idle_seconds: Option<u64>,
+    /// 连续这么多秒没有写入数据包时向当前文件写入一条心跳记录，此后每隔这么多秒再写一条，
+    /// 读取稀疏捕获的程序据此区分"没有流量"和"捕获已停止"。pcapng为带`save_pcap heartbeat`
+    /// 注释的接口统计块，pcap为长度为0的数据包记录；None表示不写心跳
+    pub heartbeat_interval_seconds: Option<u64>,
+    /// 按数据包时间戳（本地时间）所在的整点小时或自然日滚动文件，文件以时间段命名，
+    /// 例如`capture_2024-06-01_14.pcap`；没有数据包时按系统时间在时间段结束时关闭文件。
+    /// 需要开启持续捕获，可与其他滚动条件同时使用；None表示不按时间段
+    pub time_buckets: Option<TimeBucket>,
+    /// 时间戳早于当前时间段的数据包的处理方式，仅在设置`time_buckets`时生效
+    pub late_packet_policy: LatePacketPolicy,
+    /// 启用监听模式(rfmon)，用于捕获802.11无线帧
+    pub rfmon: bool,
+    /// 指定捕获使用的链路层类型(DLT)，None表示使用设备默认值
+    pub linktype: Option<i32>,
+    /// 工作线程编号，设置后文件名追加`_w{编号}`后缀，每个工作线程写入独立的轮转文件
+    pub worker_id: Option<usize>,
+    /// 文件名前缀后加上捕获的设备名，例如`capture_eth0_20240101_120000.pcap`，多个会话写入
+    /// 同一目录时便于区分；设备名中文件名不允许的字符替换为`_`，不是从设备捕获时不加
+    pub device_in_file_name: bool,
+    /// 会话标签（工单号、客户、测试批次等），记录在元数据文件、pcapng节头块的注释、JSON事件和
+    /// `CaptureManager::list()`中，文件归档后仍可按标签检索；同名的标签只保留最后一个
+    pub labels: Vec<(String, String)>,
+    /// 文件名中设备名之后加上各标签的值，例如`capture_INC-1234_acme_20240101_120000.pcap`，
+    /// 值中文件名不允许的字符替换为`_`
+    pub labels_in_file_name: bool,
+    /// 写入队列容量，设置后由独立线程写文件，捕获线程通过无锁队列传递数据包；
+    /// None表示在捕获线程中直接写入
+    pub writer_queue_capacity: Option<usize>,
+    /// 写入队列和排序缓冲区（`TimestampPolicy::Reorder`）中缓存的数据包最多占用的内存字节数，
+    /// 按缓冲区容量计算，当前用量见`CaptureStats::memory_used`。排序缓冲区超过上限时提前写出
+    /// 最早的数据包，写入队列超过上限时按`memory_limit_policy`处理；None表示不限制
+    pub memory_limit_bytes: Option<u64>,
+    pub memory_limit_policy: MemoryLimitPolicy,
+    /// 输出位置（设置`staging_dir`时为暂存目录）不可用时（例如共享被卸载）放弃当前文件，
+    /// 之后的数据包缓存在内存中，最多占用这么多字节，超过时丢弃最早的数据包。每秒检查一次，
+    /// 输出位置恢复可写后截断放弃的文件，在新文件中先写出缓存的数据包再继续捕获，两次转换都会
+    /// 通知观察者；None表示不回退，按`write_error_policy`处理
+    pub memory_fallback_bytes: Option<u64>,
+    /// 以O_DIRECT方式写入文件，不占用页缓存（仅Linux，需要启用`direct-io` feature）
+    pub direct_io: bool,
+    /// 按文件大小滚动时，创建文件即预分配`rollover_file_size_mb`大小的磁盘空间；
+    /// 对不适合预分配的文件系统（例如写时复制或需要精简配置的存储）可以关闭
+    pub preallocate: bool,
+    /// 写入时磁盘已满的处理方式
+    pub disk_full_policy: DiskFullPolicy,
+    /// 写入数据包失败时的处理方式，继续捕获时计入`CaptureStats::write_errors`
+    /// 并通知`Observer::on_write_error`
+    pub write_error_policy: WriteErrorPolicy,
+    /// 第一个文件的文件名已被使用时的处理方式
+    pub conflict_policy: ConflictPolicy,
+    /// 只保存原始长度（线路上的帧长，不受快照长度影响）不小于该值的数据包
+    pub min_packet_len: Option<usize>,
+    /// 只保存原始长度不大于该值的数据包
+    pub max_packet_len: Option<usize>,
+    /// 只保存时间戳（本地时间）落在这些每日时间段内的数据包，为空时不限制
+    pub time_of_day_windows: Vec<TimeWindow>,
+    /// 丢弃第一个数据包之后这么多秒内（按数据包时间戳）的数据包，用于排除开始捕获时的突发流量，
+    /// 例如ARP风暴或用来启动捕获的SSH会话；None表示不丢弃
+    pub discard_first_seconds: Option<u64>,
+    /// 丢弃最先读取的N个数据包；与`discard_first_seconds`同时设置时，满足任一条件的数据包都丢弃。
+    /// 丢弃的数据包不计入`packet_limit`和`byte_limit`
+    pub discard_first_packets: Option<u64>,
+    /// 按概率随机抽样：每个数据包独立地以该概率（0.0~1.0）保存，未抽中的数据包不计入
+    /// `packet_limit`和`byte_limit`。与额外输出的`sample_every`不同，作用于整个捕获；None表示不抽样
+    pub sample_probability: Option<f64>,
+    /// 按流抽样：TCP和UDP按五元组（不分方向）、其他IP协议按地址和协议计算哈希，只保存哈希
+    /// 除以N余0的流，约为1/N的流，每条保留的流都是完整的会话；非IP数据包全部保存，
+    /// IPv4的后续分片按地址和协议计算。None表示不按流抽样
+    pub flow_sample_every: Option<u64>,
+    /// 抽样使用的随机数种子，相同的种子和数据包序列得到相同的抽样结果，便于在测试中复现；
+    /// None表示每次捕获使用不同的种子。按流抽样时种子决定保留哪些流，None时使用固定的种子，
+    /// 多个探针使用相同设置时保留相同的流
+    pub sample_seed: Option<u64>,
+    /// 改写所有数据包的时间戳：平移固定的时间，或让第一个数据包从指定时间（例如0或当前时间）
+    /// 开始。主要用于从文件重新保存时隐藏原始的捕获时间或制作测试数据。在过滤和滚动之前改写，
+    /// 按时间的过滤、分段和文件名都使用改写后的时间戳；None表示保持原样
+    pub time_shift: Option<TimeShift>,
+    /// 只保存一条TCP连接：第一个匹配`trigger`的TCP数据包（默认为SYN）确定连接的地址和端口，
+    /// 之后只保存这条连接两个方向的数据包，连接关闭（RST，或双方都发送FIN并确认）后结束捕获。
+    /// 在其他过滤和抽样条件之后判断；None表示不跟踪
+    pub follow_tcp: Option<FollowTcp>,
+    /// BPF过滤表达式（libpcap语法），对通过libpcap打开的数据来源，以及文件、标准输入等
+    /// pcap/pcapng流生效（后者在用户态求值）；不过滤用户提供的数据包
+    pub filter: Option<String>,
+    /// 保存为pcap格式时，为每个文件写一个同名的`.json`元数据文件，记录主机名、操作系统、
+    /// 接口、过滤表达式和save_pcap版本；pcapng格式直接记录在文件的节头块和接口描述块中
+    pub metadata_sidecar: bool,
+    /// 保留以太网帧末尾的FCS，用于链路层错误分析。从网卡捕获时通过ethtool开启`rx-fcs`
+    /// （尽量同时开启`rx-all`以接收FCS错误的帧），捕获结束后恢复，仅Linux；
+    /// 用户提供的数据包视为已带有FCS。pcapng文件中记录`if_fcslen`和`epb_flags`
+    pub preserve_fcs: bool,
+    /// 写入前检查每个数据包（最小帧长、长度字段一致性、可选的IPv4校验和），
+    /// 按`action`丢弃、标记或转存异常数据包；None表示不检查
+    pub sanity_check: Option<SanityCheck>,
+    /// 用户提供的数据包未写入捕获文件时（超过快照长度、与链路层类型不符、未通过
+    /// `packet_validation`或被`sanity_check`丢弃或转存），把序号和原因发送到该通道；
+    /// None表示只记录日志
+    pub rejected_packets: Option<Sender<RejectedPacket>>,
+    /// 用户提供的数据包时间戳倒退时的处理方式：原样写入、改为上一个时间戳，或在窗口内缓存排序
+    pub timestamp_policy: TimestampPolicy,
+    /// 写入前解析IP和TCP/UDP/ICMP头部，校验长度字段和校验和，按`action`拒绝、修正或标记
+    /// 异常的数据包，用于检查用户提供的数据包（需要启用`validate` feature）；None表示不校验
+    #[cfg(feature = "validate")]
+    pub packet_validation: Option<PacketValidation>,
+    /// 写入前在TCP/UDP载荷（其他数据包为整个数据包）中查找并遮盖敏感内容，例如银行卡号或
+    /// API令牌，遮盖后的数据包写入所有输出；遮盖处数计入`CaptureStats::redactions`和每个
+    /// 文件的`ClosedFile::redactions`。按原长度替换，不重新计算校验和；None表示不遮盖
+    pub redaction: Option<RedactionOptions>,
+    /// 关闭文件时以首尾数据包的时间戳重命名，例如`capture_20240101T100000-20240101T101500.pcap`，
+    /// 不用打开文件就能找到某个时间段对应的文件
+    pub time_range_file_names: bool,
+    /// 写入中的文件名后加`.part`，例如`capture_20240101_120000.pcap.part`，关闭时写出并同步到
+    /// 磁盘后再重命名为最终的文件名，监视输出目录的上传或索引程序不会读到写了一半的文件
+    pub part_files: bool,
+    /// 文件名以从该值开始、每个文件递增的5位序号开头，例如`capture_00001_20240101_100000.pcap`，
+    /// 便于按顺序处理文件，`CaptureManager`恢复会话时从上次的序号继续；None表示不加序号
+    pub file_sequence: Option<u64>,
+    /// 开始捕获前扫描输出目录，修复上次异常退出时留下的不完整捕获文件
+    pub repair_on_startup: bool,
+    /// 捕获期间锁住输出目录中的`.<file_prefix>.lock`，另一个使用相同目录和前缀的捕获
+    /// 立即返回`SavePcapError::OutputLocked`，避免两个实例交替滚动、覆盖对方的文件
+    pub lock_output: bool,
+    /// 从TLS ClientHello中提取SNI，连同时间戳和数据包在文件中的偏移写入每个文件的
+    /// `.index.jsonl`会话索引，用于查找包含某个域名流量的捕获文件
+    pub tls_sni_index: bool,
+    /// 从明文HTTP请求中提取请求方法、Host和路径，写入同一个`.index.jsonl`会话索引，
+    /// 捕获文件本身不做任何改动
+    pub http_request_index: bool,
+    /// 解码DNS查询和响应，为每个文件写一个`.dns.jsonl`查询日志，记录时间戳、查询名称、
+    /// 查询类型、响应码和解析到的地址
+    pub dns_log: bool,
+    /// 把DNS响应中解析到的地址和名称写入pcapng文件的名称解析块(NRB)，
+    /// Wireshark打开文件即可显示域名（需要pcapng格式）
+    pub dns_name_resolution: bool,
+    /// 每个文件关闭时（滚动或捕获结束）写一个`.talkers.json`摘要，列出流量最大的
+    /// 源地址、目的地址和服务端口及其数据包数、字节数，值为每个列表的条目数；None表示不生成
+    pub top_talkers: Option<usize>,
+    /// 按以太网类型、IP协议和常见服务端口统计已写入的流量，通过`CaptureStats::protocols`
+    /// 和统计文件获取
+    pub protocol_stats: bool,
+    /// 为每个文件写一个同名的`.pktidx`二进制索引，记录每个数据包的偏移和时间戳，
+    /// `IndexedCaptureReader`据此直接定位到第N个数据包或某个时间点
+    pub packet_index: bool,
+    /// 为每个数据包附加键值对注释，例如应用中的请求ID。pcapng格式写入数据包注释
+    /// （`key=value`，以`; `分隔），同时为每个文件写一个同名的`.annotations.jsonl`，
+    /// 记录带注释的数据包的时间戳、偏移和注释；None表示不注释
+    pub annotator: Option<PacketAnnotator>,
+    /// 每隔`stats_interval_seconds`秒以JSON格式覆盖写入的统计文件路径，供仪表盘等外部程序读取；
+    /// None表示不写
+    pub stats_file: Option<String>,
+    pub stats_interval_seconds: u64,
+    /// 按该秒数（例如1、10、60）划分时间段，在输出目录中写一个`{prefix}_bandwidth_*.csv`，
+    /// 记录每个时间段的数据包数、字节数、pps和bps；None表示不写
+    pub bandwidth_interval_seconds: Option<u64>,
+    /// 统计数据包到达间隔和长度的分布，结果包含在`CaptureReport`中；None表示不统计
+    pub histograms: Option<HistogramOptions>,
+    /// 丢包、速率或文件增长速度超过阈值时记录警告日志并调用回调；None表示不检查
+    pub alerts: Option<AlertOptions>,
+    /// `CaptureHandle::health()`使用的阈值
+    pub health: HealthOptions,
+    /// 把捕获事件以带会话ID、当前文件和计数的JSON输出到日志或文件；None表示不输出
+    pub json_events: Option<JsonEventOptions>,
+    /// 把开始、滚动、结束和错误事件发送到syslog（需要启用`syslog` feature）；None表示不发送
+    #[cfg(feature = "syslog")]
+    pub syslog: Option<SyslogOptions>,
+    /// 通过OpenTelemetry导出捕获指标和会话、文件span（需要启用`otel` feature）；None表示不导出
+    #[cfg(feature = "otel")]
+    pub otel: Option<OtelOptions>,
+    /// 通过WebSocket向浏览器等客户端实时推送写入的数据包（需要启用`websocket` feature）；
+    /// None表示不推送
+    #[cfg(feature = "websocket")]
+    pub live_stream: Option<LiveStreamOptions>,
+    /// 同时写入的额外输出，例如`OutputOptions::format(FileFormat::Pcap)`在保存pcapng的同时
+    /// 保存一份pcap；额外输出沿用主输出的滚动设置，只写入通过合法性检查的数据包
+    pub outputs: Vec<OutputOptions>,
+    /// 按顺序匹配的分类规则，每个数据包另外写入第一条匹配规则的输出（文件前缀为输出名）；
+    /// None表示不分类
+    pub rules: Option<ClassificationRules>,
+    /// 额外输出（包括分类规则的输出）同时打开的文件数上限，超过时暂时关闭最久未写入的输出，
+    /// 下次写入时重新打开并在原文件末尾继续写入；None表示不限制。不论是否设置，打开文件时
+    /// 文件描述符耗尽都会先关闭最久未写入的输出再重试
+    pub max_open_outputs: Option<usize>,
+    /// 按MaxMind数据库查询每个文件中出现的IP地址，文件关闭时写一个同名的`.geoip.jsonl`文件，
+    /// 每行记录一个地址的数据包数、字节数、国家代码和自治系统（需要启用`geoip` feature）
+    #[cfg(feature = "geoip")]
+    pub geoip: Option<GeoIpOptions>,
 }
 
 impl Default for PcapCaptureOptions {
@@ -58,566 +540,1263 @@ impl Default for PcapCaptureOptions {
             packet_source: PacketSource::NetworkDevice(String::new()),
             file_prefix: "capture".to_string(),
             file_path: ".".to_string(),
+            base_dir: None,
+            staging_dir: None,
+            output_layout: None,
+            share_retry: None,
             file_format: FileFormat::Pcap,
             packet_limit: None,
+            byte_limit: None,
+            stop_after_quiet_seconds: None,
             snaplen: 65535,
+            slice_bytes: None,
             timeout_ms: 1000,
             continuous_capture: false,
             rollover_time_seconds: None,
             rollover_packet_count: None,
             rollover_file_size_mb: None,
+            strict_size_rollover: false,
+            rollover_idle_seconds: None,
+            heartbeat_interval_seconds: None,
+            time_buckets: None,
+            late_packet_policy: LatePacketPolicy::default(),
+            rfmon: false,
+            linktype: None,
+            worker_id: None,
+            device_in_file_name: false,
+            labels: Vec::new(),
+            labels_in_file_name: false,
+            writer_queue_capacity: None,
+            memory_limit_bytes: None,
+            memory_limit_policy: MemoryLimitPolicy::Block,
+            memory_fallback_bytes: None,
+            direct_io: false,
+            preallocate: true,
+            disk_full_policy: DiskFullPolicy::Stop,
+            write_error_policy: WriteErrorPolicy::Abort,
+            conflict_policy: ConflictPolicy::default(),
+            min_packet_len: None,
+            max_packet_len: None,
+            time_of_day_windows: Vec::new(),
+            discard_first_seconds: None,
+            discard_first_packets: None,
+            sample_probability: None,
+            flow_sample_every: None,
+            time_shift: None,
+            follow_tcp: None,
+            sample_seed: None,
+            filter: None,
+            metadata_sidecar: true,
+            preserve_fcs: false,
+            sanity_check: None,
+            rejected_packets: None,
+            timestamp_policy: TimestampPolicy::Accept,
+            #[cfg(feature = "validate")]
+            packet_validation: None,
+            redaction: None,
+            time_range_file_names: false,
+            part_files: false,
+            file_sequence: None,
+            repair_on_startup: false,
+            lock_output: false,
+            tls_sni_index: false,
+            http_request_index: false,
+            dns_log: false,
+            dns_name_resolution: false,
+            top_talkers: None,
+            protocol_stats: false,
+            packet_index: false,
+            annotator: None,
+            stats_file: None,
+            stats_interval_seconds: 10,
+            bandwidth_interval_seconds: None,
+            histograms: None,
+            health: HealthOptions::default(),
+            json_events: None,
+            #[cfg(feature = "syslog")]
+            syslog: None,
+            #[cfg(feature = "otel")]
+            otel: None,
+            #[cfg(feature = "websocket")]
+            live_stream: None,
+            alerts: None,
+            outputs: Vec::new(),
+            rules: None,
+            max_open_outputs: None,
+            #[cfg(feature = "geoip")]
+            geoip: None,
         }
     }
 }
 
-#[derive(Debug)]
-pub struct UserPacket {
-    pub data: Vec<u8>,
-    pub timestamp: Option<Duration>,
-}
-
-pub struct PcapCapturer {
-    options: PcapCaptureOptions,
-    packet_receiver: Option<Receiver<UserPacket>>,
-    packet_sender: Option<Sender<UserPacket>>,
-}
-
-impl PcapCapturer {
-    pub fn new(options: PcapCaptureOptions) -> Self {
-        let (packet_sender, packet_receiver) = match &options.packet_source {
-            PacketSource::UserProvided => {
-                let (sender, receiver) = channel();
-                (Some(sender), Some(receiver))
-            }
-            _ => (None, None),
-        };
-
+impl PcapCaptureOptions {
+    /// 802.11无线抓包预设：开启监听模式并使用radiotap链路层类型，
+    /// 保留每个数据包的信号强度(dBm)、信道等radiotap信息
+    pub fn wireless(device_name: impl Into<String>) -> Self {
         Self {
-            options,
-            packet_receiver,
-            packet_sender,
+            packet_source: PacketSource::NetworkDevice(device_name.into()),
+            file_prefix: "wireless".to_string(),
+            snaplen: 65535,
+            rfmon: true,
+            linktype: Some(Linktype::IEEE802_11_RADIOTAP.0),
+            ..Default::default()
         }
     }
 
-    pub fn capture(&self) -> Result<(), SavePcapError> {
-        let path = Path::new(&self.options.file_path);
-        if !path.exists() {
-            if let Err(e) = fs::create_dir_all(path) {
-                return Err(SavePcapError::DirectoryCreationFailed(format!(
-                    "Failed to create directory: {}, error: {}",
-                    self.options.file_path, e
-                )));
-            }
+    /// USB抓包预设(Linux usbmon)：使用带mmap扩展头的USB链路层类型，
+    /// 并放宽快照长度以保留完整的批量传输数据
+    pub fn usb(device_name: impl Into<String>) -> Self {
+        Self {
+            packet_source: PacketSource::NetworkDevice(device_name.into()),
+            file_prefix: "usb".to_string(),
+            snaplen: USB_SNAPLEN,
+            linktype: Some(Linktype::USB_LINUX_MMAPPED.0),
+            ..Default::default()
         }
+    }
 
-        match &self.options.packet_source {
-            PacketSource::NetworkDevice(device_name) => {
-                let devices = Device::list()?;
-                let device_exists = devices.iter().any(|d| d.name == *device_name);
-
-                if !device_exists {
-                    return Err(SavePcapError::InvalidDevice(device_name.clone()));
-                }
-
-                let mut cap = Capture::from_device(device_name.as_str())?
-                    .snaplen(self.options.snaplen)
-                    .promisc(true)
-                    .timeout(self.options.timeout_ms)
-                    .open()?;
-
-                info!("Starting capture on device: {}", device_name);
-
-                if self.options.continuous_capture {
-                    self.continuous_capture_with_rollover(&mut cap)?;
-                } else {
-                    let (_file_name, full_path) = self.create_new_file()?;
-                    info!("Saving to file: {:?}", full_path);
-
-                    let file = File::create(&full_path)?;
-                    let mut buf_writer = BufWriter::new(file);
+    /// 排障场景预设，见`Scenario`
+    pub fn scenario(scenario: Scenario, device_name: impl Into<String>) -> Self {
+        let mut options = Self {
+            packet_source: PacketSource::NetworkDevice(device_name.into()),
+            ..Default::default()
+        };
+        scenario.apply(&mut options);
+        options
+    }
 
-                    match self.options.file_format {
-                        FileFormat::Pcap => self.capture_to_pcap(&mut cap, &mut buf_writer)?,
-                        FileFormat::PcapNg => self.capture_to_pcapng(&mut cap, &mut buf_writer)?,
-                    }
+    pub(crate) fn create_new_file(
+        &self,
+        sequence: Option<u64>,
+        policy: ConflictPolicy,
+    ) -> Result<(String, std::path::PathBuf), SavePcapError> {
+        let now: DateTime<Local> = Local::now();
+        self.create_file_at(&now.format("%Y%m%d_%H%M%S").to_string(), sequence, policy)
+    }
 
-                    info!(
-                        "Capture completed. Packets saved to: {}",
-                        full_path.display()
-                    );
-                }
+    /// 与`create_new_file`相同，time为文件名中的时间部分，例如`time_buckets`的时间段
+    pub(crate) fn create_file_at(
+        &self,
+        time: &str,
+        sequence: Option<u64>,
+        policy: ConflictPolicy,
+    ) -> Result<(String, PathBuf), SavePcapError> {
+        // 按`output_layout`生成的子目录，在输出目录（设置`staging_dir`时为暂存目录）中按需创建
+        let relative = match &self.output_layout {
+            Some(layout) => {
+                let relative = layout
+                    .render(&self.labels, Local::now())
+                    .map_err(SavePcapError::InvalidOutput)?;
+                let base = self.staging_dir.as_deref().unwrap_or(&self.file_path);
+                layout.create_dirs(Path::new(base), &relative)?;
+                relative
             }
-            PacketSource::UserProvided => {
-                if let Some(receiver) = &self.packet_receiver {
-                    info!("Starting user-provided packet capture");
-
-                    if self.options.continuous_capture {
-                        self.continuous_user_packet_capture_with_rollover(receiver)?;
-                    } else {
-                        let (_file_name, full_path) = self.create_new_file()?;
-                        info!("Saving to file: {:?}", full_path);
-
-                        let file = File::create(&full_path)?;
-                        let mut buf_writer = BufWriter::new(file);
+            None => PathBuf::new(),
+        };
+        let path = Path::new(&self.file_path).join(&relative);
+        let time_part = with_sequence(sequence, time.to_string());
+        let mut file_name = self.file_name_with(&time_part);
+        let mut full_path = path.join(&file_name);
+        let staged = |file_name: &str| {
+            self.staging_dir
+                .as_ref()
+                .map(|staging_dir| Path::new(staging_dir).join(&relative).join(file_name))
+        };
+        // 已关闭的文件和写入中的文件都算占用了文件名，暂存目录中的文件也一样
+        let taken = |full_path: &Path| {
+            let file_name = full_path.file_name().unwrap_or_default().to_string_lossy();
+            iter::once(full_path.to_path_buf())
+                .chain(staged(&file_name))
+                .any(|path| path.exists() || part_path(&path).exists())
+        };
 
-                        match self.options.file_format {
-                            FileFormat::Pcap => {
-                                self.user_packets_to_pcap(receiver, &mut buf_writer)?
-                            }
-                            FileFormat::PcapNg => {
-                                self.user_packets_to_pcap(receiver, &mut buf_writer)?
-                            }
+        if taken(&full_path) {
+            match policy {
+                ConflictPolicy::Error => {
+                    return Err(SavePcapError::FileExists(full_path.display().to_string()));
+                }
+                ConflictPolicy::Overwrite => warn!("Overwriting existing file {:?}", full_path),
+                // 同一秒内滚动多次时也会走到这里
+                ConflictPolicy::AppendSuffix => {
+                    for suffix in 1.. {
+                        file_name = self.file_name_with(&format!("{}_{}", time_part, suffix));
+                        full_path = path.join(&file_name);
+                        if !taken(&full_path) {
+                            break;
                         }
-
-                        info!(
-                            "Capture completed. Packets saved to: {}",
-                            full_path.display()
-                        );
                     }
-                } else {
-                    return Err(SavePcapError::InvalidDevice(
-                        "No packet receiver available".to_string(),
-                    ));
                 }
             }
         }
 
-        Ok(())
+        if let Some(staged) = staged(&file_name) {
+            full_path = staged;
+        }
+        if self.part_files {
+            file_name.push_str(PART_SUFFIX);
+            full_path = part_path(&full_path);
+        }
+        Ok((file_name, full_path))
     }
 
-    pub fn get_packet_sender(&self) -> Option<Sender<UserPacket>> {
-        self.packet_sender.clone()
+    /// 以文件中首尾数据包的时间戳命名，例如`capture_20240101T100000-20240101T101500.pcap`
+    pub(crate) fn time_range_file_name(
+        &self,
+        first: Duration,
+        last: Duration,
+        sequence: Option<u64>,
+    ) -> String {
+        let format = |timestamp: Duration| {
+            DateTime::<Local>::from(UNIX_EPOCH + timestamp)
+                .format("%Y%m%dT%H%M%S")
+                .to_string()
+        };
+        self.file_name_with(&with_sequence(
+            sequence,
+            format!("{}-{}", format(first), format(last)),
+        ))
     }
 
-    fn create_new_file(&self) -> Result<(String, std::path::PathBuf), SavePcapError> {
-        let path = Path::new(&self.options.file_path);
-        let now: DateTime<Local> = Local::now();
-        let timestamp = now.format("%Y%m%d_%H%M%S").to_string();
-        let file_extension = match self.options.file_format {
+    /// 异常数据包单独保存的文件名，例如`capture_errors_20240101_120000.pcapng`
+    pub(crate) fn errors_file_name(&self) -> String {
+        let timestamp = Local::now().format("%Y%m%d_%H%M%S");
+        self.file_name_with(&format!("errors_{}", timestamp))
+    }
+
+    pub(crate) fn bandwidth_file_name(&self) -> String {
+        let timestamp = Local::now().format("%Y%m%d_%H%M%S");
+        let worker_suffix = self
+            .worker_id
+            .map(|id| format!("_w{}", id))
+            .unwrap_or_default();
+        format!(
+            "{}_bandwidth_{}{}.csv",
+            self.file_prefix, timestamp, worker_suffix
+        )
+    }
+
+    fn file_name_with(&self, time_part: &str) -> String {
+        let file_extension = match self.file_format {
             FileFormat::Pcap => "pcap",
             FileFormat::PcapNg => "pcapng",
         };
 
-        let file_name = format!(
-            "{}_{}.{}",
-            self.options.file_prefix, timestamp, file_extension
-        );
-        let full_path = path.join(&file_name);
+        let worker_suffix = self
+            .worker_id
+            .map(|id| format!("_w{}", id))
+            .unwrap_or_default();
 
-        Ok((file_name, full_path))
-    }
+        let device_part = self
+            .file_name_device()
+            .map(|device| format!("{}_", device))
+            .unwrap_or_default();
+        let labels_part = self
+            .file_name_labels()
+            .map(|labels| format!("{}_", labels))
+            .unwrap_or_default();
 
-    fn continuous_capture_with_rollover(
-        &self,
-        cap: &mut Capture<Active>,
-    ) -> Result<(), SavePcapError> {
-        let mut packet_count_total = 0;
-        let mut current_file_packet_count = 0;
-        let mut current_file_size_bytes = 0;
-        let mut file_creation_time = std::time::SystemTime::now();
+        format!(
+            "{}_{}{}{}{}.{}",
+            self.file_prefix, device_part, labels_part, time_part, worker_suffix, file_extension
+        )
+    }
 
-        let (mut current_file_name, mut current_full_path) = self.create_new_file()?;
-        info!(
-            "Starting continuous capture, first file: {:?}",
-            current_full_path
-        );
+    // 文件名中的标签值，按`paths::sanitize_file_name_part`处理，处理后为空的值不加入
+    pub(crate) fn file_name_labels(&self) -> Option<String> {
+        if !self.labels_in_file_name {
+            return None;
+        }
+        let values: Vec<_> = self
+            .labels
+            .iter()
+            .filter_map(|(_, value)| paths::sanitize_file_name_part(value))
+            .collect();
+        (!values.is_empty()).then(|| values.join("_"))
+    }
 
-        // Create the initial file and writers
-        let file = File::create(&current_full_path)?;
-        let buf_writer = BufWriter::new(file);
-        let mut pcap_writer = match PcapWriter::new(buf_writer) {
-            Ok(writer) => writer,
-            Err(e) => return Err(SavePcapError::PcapFileError(e.to_string())),
+    // 文件名中的设备名，按`paths::sanitize_file_name_part`处理
+    pub(crate) fn file_name_device(&self) -> Option<String> {
+        if !self.device_in_file_name {
+            return None;
+        }
+        let device = match &self.packet_source {
+            PacketSource::NetworkDevice(name) | PacketSource::SharedDevice(name) => {
+                // Windows设备名形如"\\Device\\NPF_{GUID}"，只保留GUID部分
+                let name = name.rsplit('\\').next().unwrap_or(name);
+                name.strip_prefix("NPF_").unwrap_or(name).to_string()
+            }
+            PacketSource::BluetoothHci(name) | PacketSource::CanInterface(name) => name.clone(),
+            PacketSource::Nflog(group) => format!("nflog{}", group),
+            PacketSource::Pktmon => "pktmon".to_string(),
+            PacketSource::UserProvided | PacketSource::File(_) | PacketSource::Stdin => {
+                return None;
+            }
         };
+        paths::sanitize_file_name_part(&device)
+    }
 
-        loop {
-            if let Some(global_limit) = self.options.packet_limit {
-                if packet_count_total >= global_limit {
-                    info!(
-                        "Reached global packet limit of {}, stopping capture.",
-                        global_limit
-                    );
-                    break;
-                }
-            }
+    /// 数据包是否满足长度范围和每日时间段的限制
+    pub(crate) fn packet_allowed(&self, packet: &SourcePacket) -> bool {
+        self.packet_len_allowed(packet.orig_len as usize)
+            && time_window::in_windows(&self.time_of_day_windows, packet.timestamp)
+    }
 
-            let needs_rollover = self.check_needs_rollover(
-                current_file_packet_count,
-                current_file_size_bytes,
-                &file_creation_time,
-            );
+    /// 数据包长度是否在`min_packet_len`和`max_packet_len`之间
+    pub(crate) fn packet_len_allowed(&self, len: usize) -> bool {
+        self.min_packet_len.is_none_or(|min| len >= min)
+            && self.max_packet_len.is_none_or(|max| len <= max)
+    }
 
-            if needs_rollover {
-                // Flush and close the current file by dropping the pcap_writer
-                if let Err(e) = pcap_writer.flush() {
-                    error!("Failed to flush file: {}, error: {}", current_file_name, e);
-                }
-                drop(pcap_writer);
+    /// 写入文件的数据包数据，按`slice_bytes`截断
+    pub(crate) fn stored_data<'p>(&self, data: &'p [u8]) -> &'p [u8] {
+        match self.slice_bytes {
+            Some(slice_bytes) => &data[..data.len().min(slice_bytes)],
+            None => data,
+        }
+    }
 
-                info!(
-                    "Rolling over to new file after {} packets in {}",
-                    current_file_packet_count, current_file_name
-                );
+    /// 报告未写入捕获文件的用户数据包，其他来源的数据包只记录日志
+    pub(crate) fn report_rejected(&self, packet: &SourcePacket, reason: &str) {
+        debug!("Rejected packet: {}", reason);
+        if let (Some(sender), Some(index)) = (&self.rejected_packets, packet.user_index) {
+            // 发送方不再接收报告时忽略
+            let _ = sender.send(RejectedPacket {
+                index,
+                reason: reason.to_string(),
+            });
+        }
+    }
 
-                // Create new file and reset counters
-                let (new_file_name, new_full_path) = self.create_new_file()?;
-                current_file_name = new_file_name;
-                current_full_path = new_full_path;
-
-                current_file_packet_count = 0;
-                current_file_size_bytes = 0;
-                file_creation_time = std::time::SystemTime::now();
-
-                // Create new writers for the new file
-                let new_file = File::create(&current_full_path)?;
-                let new_buf_writer = BufWriter::new(new_file);
-                pcap_writer = match PcapWriter::new(new_buf_writer) {
-                    Ok(writer) => writer,
-                    Err(e) => return Err(SavePcapError::PcapFileError(e.to_string())),
-                };
-            }
+    /// 文件头中记录的快照长度，设置了`slice_bytes`时取两者中较小的
+    pub(crate) fn file_snaplen(&self) -> u32 {
+        let snaplen = self.snaplen.max(0) as u32;
+        match self.slice_bytes {
+            Some(slice_bytes) => snaplen.min(slice_bytes.try_into().unwrap_or(u32::MAX)),
+            None => snaplen,
+        }
+    }
 
-            match cap.next_packet() {
-                Ok(packet) => {
-                    let pcap_packet = PcapPacket {
-                        timestamp: Duration::new(
-                            packet.header.ts.tv_sec as u64,
-                            packet.header.ts.tv_usec as u32 * 1_000,
-                        ),
-                        orig_len: packet.data.len() as u32,
-                        data: Cow::Owned(packet.data.to_vec()),
-                    };
+    /// 判断输出目录中的文件是否为本配置生成的捕获文件
+    pub(crate) fn is_capture_file_name(&self, file_name: &str) -> bool {
+        file_name.starts_with(&format!("{}_", self.file_prefix))
+            && (file_name.ends_with(".pcap") || file_name.ends_with(".pcapng"))
+    }
+}
 
-                    if let Err(e) = pcap_writer.write_packet(&pcap_packet) {
-                        return Err(SavePcapError::PcapFileError(e.to_string()));
-                    }
+// 启用`part_files`时写入中的文件名后缀
+pub(crate) const PART_SUFFIX: &str = ".part";
 
-                    packet_count_total += 1;
-                    current_file_packet_count += 1;
-                    current_file_size_bytes += packet.data.len() as u64;
+pub(crate) fn part_path(path: &Path) -> PathBuf {
+    metadata::sidecar_path_with(path, PART_SUFFIX)
+}
 
-                    if packet_count_total % 1000 == 0 {
-                        debug!("Captured {} packets total", packet_count_total);
-                    }
-                }
-                Err(e) => {
-                    let error_str = e.to_string();
-                    // 检查错误信息是否包含"timeout"关键词，以处理不同形式的超时错误
-                    if error_str.contains("timeout") {
-                        continue;
-                    } else {
-                        error!("Capture error: {}", e);
-                        break;
-                    }
-                }
-            }
+// 同名的标签保留最后一个，位置按第一次出现的位置
+fn dedup_labels(labels: Vec<(String, String)>) -> Vec<(String, String)> {
+    let mut deduped: Vec<(String, String)> = Vec::with_capacity(labels.len());
+    for (key, value) in labels {
+        match deduped.iter_mut().find(|(existing, _)| *existing == key) {
+            Some(label) => label.1 = value,
+            None => deduped.push((key, value)),
         }
+    }
+    deduped
+}
 
-        Ok(())
+// 前缀可能来自配置文件或命令行，其中的路径分隔符等字符不能进入文件名；处理后为空时使用默认前缀
+fn sanitize_prefix(prefix: &str) -> String {
+    let sanitized = paths::sanitize_file_name_part(prefix).unwrap_or_else(|| "capture".to_string());
+    if sanitized != prefix {
+        warn!("File prefix {:?} changed to {:?}", prefix, sanitized);
     }
+    sanitized
+}
 
-    fn continuous_user_packet_capture_with_rollover(
-        &self,
-        receiver: &Receiver<UserPacket>,
-    ) -> Result<(), SavePcapError> {
-        let mut packet_count_total = 0;
-        let mut current_file_packet_count = 0;
-        let mut current_file_size_bytes = 0;
-        let mut file_creation_time = std::time::SystemTime::now();
+// 文件序号在时间之前，按文件名排序即为写入顺序
+fn with_sequence(sequence: Option<u64>, time_part: String) -> String {
+    match sequence {
+        Some(sequence) => format!("{:05}_{}", sequence, time_part),
+        None => time_part,
+    }
+}
 
-        let (mut current_file_name, mut current_full_path) = self.create_new_file()?;
-        info!(
-            "Starting continuous user packet capture, first file: {:?}",
-            current_full_path
-        );
+// usbmon单次传输可达数百KB，与libpcap的最大快照长度保持一致
+const USB_SNAPLEN: i32 = 262144;
 
-        // Create the initial file and writers
-        let file = File::create(&current_full_path)?;
-        let buf_writer = BufWriter::new(file);
-        let mut pcap_writer = match PcapWriter::new(buf_writer) {
-            Ok(writer) => writer,
-            Err(e) => return Err(SavePcapError::PcapFileError(e.to_string())),
-        };
+fn ensure_linux(feature: &str) -> Result<(), SavePcapError> {
+    if cfg!(target_os = "linux") {
+        Ok(())
+    } else {
+        Err(SavePcapError::UnsupportedSource(format!(
+            "{} is only supported on Linux",
+            feature
+        )))
+    }
+}
 
-        loop {
-            if let Some(global_limit) = self.options.packet_limit {
-                if packet_count_total >= global_limit {
-                    info!(
-                        "Reached global packet limit of {}, stopping capture.",
-                        global_limit
-                    );
-                    break;
-                }
-            }
+fn ensure_windows(feature: &str) -> Result<(), SavePcapError> {
+    if cfg!(windows) {
+        Ok(())
+    } else {
+        Err(SavePcapError::UnsupportedSource(format!(
+            "{} is only supported on Windows",
+            feature
+        )))
+    }
+}
 
-            let needs_rollover = self.check_needs_rollover(
-                current_file_packet_count,
-                current_file_size_bytes,
-                &file_creation_time,
-            );
+// 根据设备名推断需要显式选择的链路层类型
+fn default_linktype_for_device(device_name: &str) -> Option<i32> {
+    if device_name.starts_with("usbmon") {
+        // usbmon默认提供48字节头部的DLT_USB_LINUX，mmap版本包含ISO描述符等完整信息
+        Some(Linktype::USB_LINUX_MMAPPED.0)
+    } else {
+        None
+    }
+}
 
-            if needs_rollover {
-                // Flush and close the current file by dropping the pcap_writer
-                if let Err(e) = pcap_writer.flush() {
-                    error!("Failed to flush file: {}, error: {}", current_file_name, e);
-                }
-                drop(pcap_writer);
+#[derive(Debug)]
+pub struct UserPacket {
+    pub data: Vec<u8>,
+    pub timestamp: Option<Duration>,
+}
 
-                info!(
-                    "Rolling over to new file after {} packets in {}",
-                    current_file_packet_count, current_file_name
-                );
+/// 被拒绝、没有写入捕获文件的用户数据包，通过`rejected_packets`通道报告给发送方
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RejectedPacket {
+    /// 数据包在发送顺序中的序号，从0开始
+    pub index: u64,
+    pub reason: String,
+}
 
-                // Create new file and reset counters
-                let (new_file_name, new_full_path) = self.create_new_file()?;
-                current_file_name = new_file_name;
-                current_full_path = new_full_path;
-
-                current_file_packet_count = 0;
-                current_file_size_bytes = 0;
-                file_creation_time = std::time::SystemTime::now();
-
-                // Create new writers for the new file
-                let new_file = File::create(&current_full_path)?;
-                let new_buf_writer = BufWriter::new(new_file);
-                pcap_writer = match PcapWriter::new(new_buf_writer) {
-                    Ok(writer) => writer,
-                    Err(e) => return Err(SavePcapError::PcapFileError(e.to_string())),
-                };
-            }
+// 写入队列为空时写入线程的等待时间
+const WRITER_IDLE_SLEEP: Duration = Duration::from_micros(100);
+// 实时统计（速率、丢包数、当前文件大小）的更新间隔
+pub(crate) const LIVE_STATS_INTERVAL: Duration = Duration::from_secs(1);
+// 每入队多少个数据包更新一次写入队列占用量
+const WRITER_QUEUE_STATS_INTERVAL: usize = 64;
 
-            match receiver.recv() {
-                Ok(user_packet) => {
-                    let timestamp = user_packet.timestamp.unwrap_or_else(|| {
-                        let now = std::time::SystemTime::now()
-                            .duration_since(std::time::UNIX_EPOCH)
-                            .unwrap_or_default();
-                        Duration::new(now.as_secs(), now.subsec_nanos())
-                    });
+// 捕获循环交给sink处理的内容，启用独立写入线程时也是写入队列中的元素
+enum SinkItem {
+    Packet(SourcePacket),
+    // 暂时没有数据包
+    Idle,
+    // 写出之前的数据包并同步到磁盘后通过该发送端确认
+    Flush(Sender<()>),
+    // 结束当前文件并开始新文件
+    Rotate,
+}
 
-                    let pcap_packet = PcapPacket {
-                        timestamp,
-                        orig_len: user_packet.data.len() as u32,
-                        data: Cow::Owned(user_packet.data.clone()),
-                    };
+type PacketSink<'a> = dyn FnMut(SinkItem, &mut BufferPool) -> Result<(), SavePcapError> + 'a;
 
-                    if let Err(e) = pcap_writer.write_packet(&pcap_packet) {
-                        return Err(SavePcapError::PcapFileError(e.to_string()));
-                    }
+/// 捕获控制句柄，可在其他线程（或信号、服务控制回调）中停止、暂停和恢复捕获
+#[derive(Debug, Clone, Default)]
+pub struct CaptureHandle {
+    stopped: Arc<AtomicBool>,
+    paused: Arc<AtomicBool>,
+    rotate: Arc<AtomicBool>,
+    stats: Arc<StatsCounters>,
+    status: Arc<SessionStatus>,
+    health: Arc<HealthContext>,
+}
 
-                    packet_count_total += 1;
-                    current_file_packet_count += 1;
-                    current_file_size_bytes += user_packet.data.len() as u64;
+impl CaptureHandle {
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
 
-                    if packet_count_total % 1000 == 0 {
-                        debug!("Processed {} user packets total", packet_count_total);
-                    }
-                }
-                Err(_) => {
-                    info!("Sender disconnected, stopping user packet processing");
-                    break;
-                }
-            }
-        }
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
 
-        Ok(())
+    /// 暂停期间仍会读取数据包（避免内核缓冲区溢出），但不写入文件
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+        self.status.set_paused(true);
     }
 
-    fn user_packets_to_pcap<W: Write>(
-        &self,
-        receiver: &Receiver<UserPacket>,
-        writer: &mut W,
-    ) -> Result<(), SavePcapError> {
-        let mut pcap_writer = match PcapWriter::new(writer) {
-            Ok(writer) => writer,
-            Err(e) => return Err(SavePcapError::PcapFileError(e.to_string())),
-        };
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+        self.status.set_paused(false);
+    }
 
-        let mut packet_count = 0;
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
 
-        loop {
-            if let Some(limit) = self.options.packet_limit {
-                if packet_count >= limit {
-                    break;
-                }
-            }
+    /// 结束当前文件并开始新文件，不论是否开启了持续捕获。请求之前读取的数据包都写入当前文件，
+    /// 之后读取的写入新文件，适合在测试用例之间切分文件；额外输出同时滚动
+    pub fn rotate_now(&self) {
+        self.rotate.store(true, Ordering::SeqCst);
+    }
 
-            match receiver.recv() {
-                Ok(user_packet) => {
-                    let timestamp = user_packet.timestamp.unwrap_or_else(|| {
-                        let now = std::time::SystemTime::now()
-                            .duration_since(std::time::UNIX_EPOCH)
-                            .unwrap_or_default();
-                        Duration::new(now.as_secs(), now.subsec_nanos())
-                    });
+    /// 获取当前的捕获统计，包括写入队列的占用情况
+    pub fn stats(&self) -> CaptureStats {
+        self.stats.snapshot()
+    }
 
-                    let pcap_packet = PcapPacket {
-                        timestamp,
-                        orig_len: user_packet.data.len() as u32,
-                        data: Cow::Owned(user_packet.data.clone()),
-                    };
+    /// 获取汇总报告（统计和直方图），捕获结束后调用可得到完整结果
+    pub fn report(&self) -> CaptureReport {
+        self.stats.report()
+    }
 
-                    if let Err(e) = pcap_writer.write_packet(&pcap_packet) {
-                        return Err(SavePcapError::PcapFileError(e.to_string()));
-                    }
+    /// 获取实时速率、丢包数和当前文件，供状态显示界面每秒轮询
+    pub fn live_stats(&self) -> LiveStats {
+        self.stats.live_snapshot()
+    }
 
-                    packet_count += 1;
+    /// 检查捕获是否正常，供服务的存活/就绪探针使用。Ready表示正在捕获且没有发现问题；
+    /// Degraded表示尚未开始或需要关注（磁盘空间不足、写入队列将满、长时间没有数据包）；
+    /// Failed表示捕获已结束或网卡已断开
+    pub fn health(&self) -> Health {
+        self.health.check(
+            self.status.state(),
+            self.status.error(),
+            &self.stats.snapshot(),
+            self.stats.idle(),
+        )
+    }
 
-                    if packet_count % 1000 == 0 {
-                        debug!("Processed {} user packets", packet_count);
-                    }
-                }
-                Err(_) => {
-                    info!("Sender disconnected, stopping user packet processing");
-                    break;
-                }
-            }
-        }
+    /// 会话当前的生命周期状态
+    pub fn state(&self) -> SessionState {
+        self.status.state()
+    }
 
-        Ok(())
+    /// 订阅之后的状态变化，每次调用返回一个独立的接收端
+    pub fn subscribe(&self) -> Receiver<StateChange> {
+        self.status.subscribe()
     }
+}
 
-    fn check_needs_rollover(
-        &self,
-        current_packet_count: usize,
-        current_file_size_bytes: u64,
-        file_creation_time: &std::time::SystemTime,
-    ) -> bool {
-        if let Some(rollover_seconds) = self.options.rollover_time_seconds {
-            if let Ok(elapsed) = file_creation_time.elapsed() {
-                if elapsed.as_secs() >= rollover_seconds {
-                    return true;
-                }
+pub struct PcapCapturer {
+    options: PcapCaptureOptions,
+    packet_receiver: Option<Receiver<UserPacket>>,
+    packet_sender: Option<Sender<UserPacket>>,
+    // `PacketSender::flush`的刷新请求
+    flush_receiver: Option<Receiver<Sender<()>>>,
+    flush_sender: Option<Sender<Sender<()>>>,
+    handle: CaptureHandle,
+}
+
+impl PcapCapturer {
+    pub fn new(mut options: PcapCaptureOptions) -> Self {
+        // `~`和相对路径在这里解析，开始捕获时再检查目录是否可写
+        let base_dir = options.base_dir.as_deref();
+        options.file_path = paths::resolve(&options.file_path, base_dir);
+        if let Some(staging_dir) = &mut options.staging_dir {
+            *staging_dir = paths::resolve(staging_dir, base_dir);
+        }
+        options.file_prefix = sanitize_prefix(&options.file_prefix);
+        options.labels = dedup_labels(options.labels);
+        for output in &mut options.outputs {
+            if let Some(file_path) = &mut output.file_path {
+                *file_path = paths::resolve(file_path, base_dir);
+            }
+            if let Some(file_prefix) = &mut output.file_prefix {
+                *file_prefix = sanitize_prefix(file_prefix);
             }
         }
-
-        if let Some(max_packets) = self.options.rollover_packet_count {
-            if current_packet_count >= max_packets {
-                return true;
+        let device = match &options.packet_source {
+            // 健康检查按系统设备标识查看网卡状态，友好名称需先解析
+            PacketSource::NetworkDevice(name) | PacketSource::SharedDevice(name)
+                if !name.is_empty() =>
+            {
+                Some(devices::resolve_device(name).unwrap_or_else(|_| name.clone()))
             }
+            PacketSource::CanInterface(name) if !name.is_empty() => Some(name.clone()),
+            _ => None,
+        };
+        let health = Arc::new(HealthContext {
+            options: options.health.clone(),
+            output_dir: PathBuf::from(&options.file_path),
+            device,
+        });
+        let ((packet_sender, packet_receiver), (flush_sender, flush_receiver)) =
+            match &options.packet_source {
+                PacketSource::UserProvided => {
+                    let (sender, receiver) = channel();
+                    let (flush_sender, flush_receiver) = channel();
+                    (
+                        (Some(sender), Some(receiver)),
+                        (Some(flush_sender), Some(flush_receiver)),
+                    )
+                }
+                _ => ((None, None), (None, None)),
+            };
+
+        let handle = CaptureHandle {
+            health,
+            ..Default::default()
+        };
+        if let Some(json_events) = &options.json_events {
+            handle.status.add_observer(Arc::new(JsonEventLog::new(
+                json_events,
+                &options,
+                handle.stats.clone(),
+            )));
+        }
+        #[cfg(feature = "syslog")]
+        if let Some(syslog) = &options.syslog {
+            handle
+                .status
+                .add_observer(Arc::new(SyslogNotifier::new(syslog)));
+        }
+        #[cfg(feature = "otel")]
+        if let Some(otel) = &options.otel {
+            handle
+                .status
+                .add_observer(Arc::new(OtelExporter::new(otel, handle.stats.clone())));
         }
 
-        if let Some(max_size_mb) = self.options.rollover_file_size_mb {
-            let max_size_bytes = max_size_mb * 1024 * 1024;
-            if current_file_size_bytes >= max_size_bytes {
-                return true;
-            }
+        Self {
+            options,
+            packet_receiver,
+            packet_sender,
+            flush_receiver,
+            flush_sender,
+            handle,
         }
+    }
 
-        false
+    /// 阻塞直到捕获结束，结束后会话进入Stopped或Failed状态
+    pub fn capture(&self) -> Result<(), SavePcapError> {
+        let result = self.capture_source();
+        self.finish(result)
     }
 
-    fn capture_to_pcap<W: Write>(
-        &self,
-        cap: &mut Capture<Active>,
-        writer: &mut W,
-    ) -> Result<(), SavePcapError> {
-        let mut pcap_writer = match PcapWriter::new(writer) {
-            Ok(writer) => writer,
-            Err(e) => return Err(SavePcapError::PcapFileError(e.to_string())),
-        };
+    /// 与`capture`相同，但忽略`packet_source`，从reader读取pcap/pcapng流（例如管道或套接字），
+    /// 按当前配置过滤、滚动和保存，流结束时返回
+    pub fn capture_from_reader<R: Read>(&self, reader: R) -> Result<(), SavePcapError> {
+        let result = self
+            .prepare_output()
+            .and_then(|_lock| self.run_reader(BufReader::new(reader)));
+        self.finish(result)
+    }
 
-        let mut packet_count = 0;
+    fn finish(&self, result: Result<(), SavePcapError>) -> Result<(), SavePcapError> {
+        self.handle.status.finish(
+            result.as_ref().err().map(|e| e.to_string()),
+            &self.handle.stats(),
+        );
+        result
+    }
 
-        loop {
-            if let Some(limit) = self.options.packet_limit {
-                if packet_count >= limit {
-                    break;
-                }
-            }
+    // 返回的锁需要保持到捕获结束
+    fn prepare_output(&self) -> Result<Option<OutputLock>, SavePcapError> {
+        sampling::validate(&self.options)?;
+        paths::ensure_writable(Path::new(&self.options.file_path))?;
+        if let Some(staging_dir) = &self.options.staging_dir {
+            paths::ensure_writable(Path::new(staging_dir))?;
+        }
+        // 先加锁再修复，以免修复正在被另一个实例写入的文件
+        let lock = if self.options.lock_output {
+            Some(OutputLock::acquire(&self.options)?)
+        } else {
+            None
+        };
 
-            match cap.next_packet() {
-                Ok(packet) => {
-                    let pcap_packet = PcapPacket {
-                        timestamp: Duration::new(
-                            packet.header.ts.tv_sec as u64,
-                            packet.header.ts.tv_usec as u32 * 1_000,
-                        ),
-                        orig_len: packet.data.len() as u32,
-                        data: Cow::Owned(packet.data.to_vec()),
-                    };
+        if self.options.repair_on_startup {
+            self.repair_existing_files()?;
+        }
+        Ok(lock)
+    }
 
-                    if let Err(e) = pcap_writer.write_packet(&pcap_packet) {
-                        return Err(SavePcapError::PcapFileError(e.to_string()));
-                    }
+    fn capture_source(&self) -> Result<(), SavePcapError> {
+        let _lock = self.prepare_output()?;
 
-                    packet_count += 1;
+        match &self.options.packet_source {
+            PacketSource::NetworkDevice(device_name) => {
+                // 也接受友好名称，例如"Ethernet 2"或"Wi-Fi"
+                let device_name = &devices::resolve_device(device_name)?;
+                let linktype = self
+                    .options
+                    .linktype
+                    .or_else(|| default_linktype_for_device(device_name));
+                let _fcs_guard = if self.options.preserve_fcs {
+                    ensure_linux("Requesting the FCS from the adapter")?;
+                    Some(FcsGuard::enable(device_name)?)
+                } else {
+                    None
+                };
+                let mut cap = self.open_device(device_name, linktype)?;
+                info!("Starting capture on device: {}", device_name);
+                self.run_capture(&mut cap)?;
+            }
+            PacketSource::BluetoothHci(device_name) => {
+                ensure_linux("Bluetooth HCI capture")?;
 
-                    if packet_count % 1000 == 0 {
-                        debug!("Captured {} packets", packet_count);
-                    }
-                }
-                Err(e) => {
-                    let error_str = e.to_string();
-                    // 检查错误信息是否包含"timeout"关键词，以处理不同形式的超时错误
-                    if error_str.contains("timeout") {
-                        continue;
-                    } else {
-                        error!("Capture error: {}", e);
-                        break;
-                    }
-                }
+                // 使用带方向伪头部的H4格式，与btmon/Wireshark保持一致
+                let mut cap =
+                    self.open_device(device_name, Some(Linktype::BLUETOOTH_HCI_H4_WITH_PHDR.0))?;
+                info!("Starting Bluetooth HCI capture on device: {}", device_name);
+                self.run_capture(&mut cap)?;
             }
-        }
+            PacketSource::CanInterface(interface_name) => {
+                ensure_linux("SocketCAN capture")?;
 
-        Ok(())
-    }
+                let mut cap = self.open_device(interface_name, Some(Linktype::CAN_SOCKETCAN.0))?;
+                info!(
+                    "Starting SocketCAN capture on interface: {}",
+                    interface_name
+                );
+                self.run_capture(&mut cap)?;
+            }
+            PacketSource::Nflog(group) => {
+                ensure_linux("NFLOG capture")?;
 
-    fn capture_to_pcapng<W: Write>(
-        &self,
-        cap: &mut Capture<Active>,
-        writer: &mut W,
-    ) -> Result<(), SavePcapError> {
-        let mut pcap_writer = match PcapWriter::new(writer) {
-            Ok(writer) => writer,
-            Err(e) => return Err(SavePcapError::PcapFileError(e.to_string())),
-        };
+                // 设备列表中只有"nflog"，具体的组号通过"nflog:<group>"指定
+                let device_name = format!("nflog:{}", group);
+                let mut cap = self.open_capture(&device_name, Some(Linktype::NFLOG.0))?;
+                info!("Starting NFLOG capture on group: {}", group);
+                self.run_capture(&mut cap)?;
+            }
+            PacketSource::UserProvided => {
+                if let (Some(receiver), Some(flushes)) =
+                    (&self.packet_receiver, &self.flush_receiver)
+                {
+                    info!("Starting user-provided packet capture");
+                    let mut stream = UserPacketStream::new(
+                        receiver,
+                        flushes,
+                        self.user_datalink(),
+                        Duration::from_millis(self.options.timeout_ms.max(0) as u64),
+                        &self.options,
+                        self.handle.stats.clone(),
+                    );
+                    self.run_capture(&mut stream)?;
+                } else {
+                    return Err(SavePcapError::InvalidDevice(
+                        "No packet receiver available".to_string(),
+                    ));
+                }
+            }
+            PacketSource::Pktmon => {
+                ensure_windows("Pktmon capture")?;
+
+                let mut stream = PktmonStream::start(
+                    pktmon::staging_dir(Path::new(&self.options.file_path)),
+                    self.options.snaplen,
+                    Duration::from_millis(self.options.timeout_ms.max(0) as u64),
+                )?;
+                info!("Starting Pktmon capture");
+                self.run_capture(&mut stream)?;
+            }
+            PacketSource::File(input) => {
+                let reader = BufReader::new(fs::File::open(input)?);
+                info!("Reading packets from file: {}", input);
+                self.run_reader(reader)?;
+            }
+            PacketSource::Stdin => {
+                info!("Reading packets from standard input");
+                self.run_reader(io::stdin().lock())?;
+            }
+            PacketSource::SharedDevice(device_name) => {
+                let mut stream = SharedStream::attach(device_name, &self.options)?;
+                info!("Starting capture on shared device: {}", device_name);
+                self.run_capture(&mut stream)?;
+            }
+        }
 
-        let mut packet_count = 0;
+        Ok(())
+    }
 
-        loop {
-            if let Some(limit) = self.options.packet_limit {
-                if packet_count >= limit {
-                    break;
+    pub fn get_packet_sender(&self) -> Option<Sender<UserPacket>> {
+        self.packet_sender.clone()
+    }
+
+    /// 获取带ID的发送端，发送的数据包数和字节数按ID计入`CaptureHandle::report()`，
+    /// 用于多个线程发送数据包时排查各个来源。相同ID的发送端共用统计。
+    /// 需要等待数据包写入磁盘时（`PacketSender::flush`）也使用这个发送端
+    pub fn get_packet_sender_with_id(&self, id: &str) -> Option<PacketSender> {
+        Some(PacketSender::new(
+            id,
+            self.packet_sender.clone()?,
+            self.flush_sender.clone()?,
+            self.handle.clone(),
+        ))
+    }
+
+    /// 注册捕获活动的观察者，可注册多个，按注册顺序通知
+    pub fn add_observer(&self, observer: Arc<dyn Observer>) {
+        self.handle.status.add_observer(observer);
+    }
+
+    /// 获取停止句柄，调用`stop()`后捕获会在当前数据包处理完后结束并正常关闭文件
+    pub fn handle(&self) -> CaptureHandle {
+        self.handle.clone()
+    }
+
+    // 修复输出目录（包括按`output_layout`生成的子目录）中上次异常退出时留下的捕获文件，
+    // 单个文件修复失败不影响本次捕获
+    fn repair_existing_files(&self) -> Result<(), SavePcapError> {
+        let layout = self.options.output_layout.as_ref();
+        for dir in layout::capture_dirs(layout, Path::new(&self.options.file_path)) {
+            self.repair_files_in(&dir)?;
+        }
+        // 上次捕获留在暂存目录中的文件修复后移动到输出目录
+        if let Some(staging_dir) = &self.options.staging_dir {
+            for dir in layout::capture_dirs(layout, Path::new(staging_dir)) {
+                for path in self.repair_files_in(&dir)? {
+                    writer::move_staged(&self.options, &path);
                 }
             }
+        }
+        Ok(())
+    }
 
-            match cap.next_packet() {
-                Ok(packet) => {
-                    let pcap_packet = PcapPacket {
-                        timestamp: Duration::new(
-                            packet.header.ts.tv_sec as u64,
-                            packet.header.ts.tv_usec as u32 * 1_000,
-                        ),
-                        orig_len: packet.data.len() as u32,
-                        data: Cow::Owned(packet.data.to_vec()),
-                    };
+    // 修复目录中的捕获文件，返回修复后保留的文件
+    fn repair_files_in(&self, dir: &Path) -> Result<Vec<PathBuf>, SavePcapError> {
+        let mut kept = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            // 上次写到一半的`.part`文件修复后改回最终的文件名
+            let published = name
+                .strip_suffix(PART_SUFFIX)
+                .map(|name| path.with_file_name(name));
+            let is_capture_file = self
+                .options
+                .is_capture_file_name(name.strip_suffix(PART_SUFFIX).unwrap_or(&name));
+            if !is_capture_file || !path.is_file() {
+                continue;
+            }
 
-                    if let Err(e) = pcap_writer.write_packet(&pcap_packet) {
-                        return Err(SavePcapError::PcapFileError(e.to_string()));
+            match repair(&path) {
+                Ok(report) if report.repaired_len == 0 => {
+                    warn!("Removing unrecoverable capture file {:?}", path);
+                    if let Err(e) = fs::remove_file(&path) {
+                        warn!("Failed to remove {:?}: {}", path, e);
+                    }
+                    metadata::remove_sidecar(&path);
+                }
+                Ok(report) => {
+                    if report.is_modified() {
+                        info!(
+                            "Repaired {:?}: {} -> {} bytes, {} packets",
+                            path, report.original_len, report.repaired_len, report.packets
+                        );
                     }
+                    let mut path = path;
+                    if let Some(published) = published.filter(|published| !published.exists()) {
+                        match fs::rename(&path, &published) {
+                            Ok(()) => {
+                                metadata::remove_sidecar(&path);
+                                path = published;
+                            }
+                            Err(e) => warn!("Failed to rename {:?}: {}", path, e),
+                        }
+                    }
+                    kept.push(path);
+                }
+                Err(e) => warn!("Failed to repair {:?}: {}", path, e),
+            }
+        }
+
+        Ok(kept)
+    }
+
+    fn open_device(
+        &self,
+        device_name: &str,
+        linktype: Option<i32>,
+    ) -> Result<Capture<Active>, SavePcapError> {
+        let devices = Device::list().map_err(|e| diagnose::device_error(device_name, e))?;
+        let device_exists = devices.iter().any(|d| d.name == device_name);
+
+        if !device_exists {
+            return Err(diagnose::missing_device_error(device_name));
+        }
+
+        self.open_capture(device_name, linktype)
+    }
+
+    fn open_capture(
+        &self,
+        device_name: &str,
+        linktype: Option<i32>,
+    ) -> Result<Capture<Active>, SavePcapError> {
+        let mut cap = Capture::from_device(device_name)
+            .and_then(|cap| {
+                cap.snaplen(self.options.snaplen)
+                    .promisc(true)
+                    .rfmon(self.options.rfmon)
+                    .timeout(self.options.timeout_ms)
+                    .open()
+            })
+            .map_err(|e| diagnose::device_error(device_name, e))?;
+
+        if let Some(linktype) = linktype {
+            cap.set_datalink(Linktype(linktype))?;
+        }
+        if let Some(filter) = &self.options.filter {
+            cap.filter(filter, true)?;
+        }
+
+        Ok(cap)
+    }
+
+    // 读取pcap/pcapng流。这类数据来源不经过libpcap，filter在用户态求值
+    fn run_reader<R: BufRead>(&self, reader: R) -> Result<(), SavePcapError> {
+        let mut reader = CaptureReader::new(reader)?;
+        match &self.options.filter {
+            Some(filter) => {
+                let filter = query::compile(filter, reader.datalink())?;
+                self.run_capture(&mut FilteredStream::new(&mut reader, filter))
+            }
+            None => self.run_capture(&mut reader),
+        }
+    }
+
+    // 所有数据来源共用的保存循环：检查停止/暂停请求和数据包上限，由RotatingWriter写入并滚动文件
+    fn run_capture(&self, stream: &mut dyn PacketStream) -> Result<(), SavePcapError> {
+        let datalink = stream.datalink();
+        let stats = &self.handle.stats;
+        let outputs = fanout::prepare(&self.options)?;
+        let follower = match &self.options.follow_tcp {
+            Some(follow) => Some(TcpFollower::new(follow, datalink)?),
+            None => None,
+        };
+        let writer = Fanout::new(
+            &self.options,
+            stats,
+            &self.handle.status,
+            datalink,
+            &outputs,
+        )?;
+        self.handle.status.start();
+
+        match self.options.writer_queue_capacity {
+            Some(capacity) => self.run_decoupled(stream, follower, writer, capacity),
+            None => {
+                let mut writer = writer;
+                // 直接写入时同一时刻只有一个数据包在途，一个缓冲区即可循环使用
+                let mut pool = BufferPool::new(self.buffer_size(), 1);
+                let read_result =
+                    self.read_packets(stream, follower, &mut pool, &mut |item, pool| match item {
+                        SinkItem::Packet(packet) => {
+                            writer.write(&packet)?;
+                            pool.give(packet.data);
+                            Ok(())
+                        }
+                        SinkItem::Idle => writer.tick(),
+                        SinkItem::Flush(ack) => {
+                            writer.flush()?;
+                            let _ = ack.send(());
+                            Ok(())
+                        }
+                        SinkItem::Rotate => writer.rotate_now(),
+                    });
+                // 出错时同样需要关闭当前文件
+                let finish_result = writer.finish();
+                read_result.and(finish_result)
+            }
+        }
+    }
 
-                    packet_count += 1;
+    // 捕获线程只读取数据包并放入无锁队列，由独立的写入线程写文件，
+    // 磁盘写入的抖动不会直接阻塞数据包的读取
+    fn run_decoupled(
+        &self,
+        stream: &mut dyn PacketStream,
+        follower: Option<TcpFollower>,
+        mut writer: Fanout<'_>,
+        capacity: usize,
+    ) -> Result<(), SavePcapError> {
+        let stats = &self.handle.stats;
+        let (mut producer, mut consumer) = spsc::channel::<SinkItem>(capacity);
+        stats.set_writer_queue_capacity(producer.capacity());
+        // 队列中的数据包加上两端各自正在处理的数据包，就是同时在途的最大数量
+        let (mut pool, mut returns) =
+            BufferPool::with_returns(self.buffer_size(), producer.capacity() + 2);
 
-                    if packet_count % 1000 == 0 {
-                        debug!("Captured {} packets", packet_count);
+        thread::scope(|scope| {
+            let writer_thread = scope.spawn(move || -> Result<(), SavePcapError> {
+                let write_result = loop {
+                    match consumer.pop() {
+                        Some(SinkItem::Packet(packet)) => {
+                            stats.release_memory(packet.memory_size());
+                            if let Err(e) = writer.write(&packet) {
+                                break Err(e);
+                            }
+                            // 归还队列满时说明池已足够大，直接释放即可
+                            let _ = returns.push(packet.data);
+                        }
+                        Some(SinkItem::Flush(ack)) => {
+                            if let Err(e) = writer.flush() {
+                                break Err(e);
+                            }
+                            let _ = ack.send(());
+                        }
+                        Some(SinkItem::Rotate) => {
+                            if let Err(e) = writer.rotate_now() {
+                                break Err(e);
+                            }
+                        }
+                        None if consumer.is_finished() => break Ok(()),
+                        Some(SinkItem::Idle) | None => {
+                            if let Err(e) = writer.tick() {
+                                break Err(e);
+                            }
+                            thread::sleep(WRITER_IDLE_SLEEP);
+                        }
                     }
+                };
+                // 先释放队列使捕获线程尽快停止，再关闭当前文件
+                drop(consumer);
+                let finish_result = writer.finish();
+                write_result.and(finish_result)
+            });
+
+            let mut pushed: usize = 0;
+            let read_result = self.read_packets(stream, follower, &mut pool, &mut |item, pool| {
+                if let SinkItem::Idle = item {
+                    stats.record_writer_queue_len(producer.len());
+                    return Ok(());
                 }
-                Err(e) => {
-                    let error_str = e.to_string();
-                    // 检查错误信息是否包含"timeout"关键词，以处理不同形式的超时错误
-                    if error_str.contains("timeout") {
-                        continue;
-                    } else {
-                        error!("Capture error: {}", e);
+
+                let bytes = match &item {
+                    SinkItem::Packet(packet) => packet.memory_size(),
+                    _ => 0,
+                };
+                let limit = self.options.memory_limit_bytes;
+                while bytes > 0 && !stats.reserve_memory(bytes, limit) {
+                    // 队列为空时超出的内存不在队列中，等待写入线程没有意义
+                    if producer.len() == 0 {
+                        stats.reserve_memory(bytes, None);
                         break;
                     }
+                    match self.options.memory_limit_policy {
+                        MemoryLimitPolicy::Block => {
+                            if producer.is_closed() {
+                                return Err(SavePcapError::CaptureInterrupted);
+                            }
+                            thread::yield_now();
+                        }
+                        MemoryLimitPolicy::Drop => {
+                            stats.record_memory_dropped();
+                            if let SinkItem::Packet(packet) = item {
+                                pool.give(packet.data);
+                            }
+                            return Ok(());
+                        }
+                        MemoryLimitPolicy::Abort => {
+                            return Err(SavePcapError::MemoryLimit(format!(
+                                "{} bytes of packets buffered, limit is {} bytes",
+                                stats.memory_used(),
+                                limit.unwrap_or_default()
+                            )));
+                        }
+                    }
+                }
+
+                if let Err(mut item) = producer.push(item) {
+                    stats.record_writer_queue_full();
+                    stats.record_writer_queue_len(producer.capacity());
+                    // 队列满时等待写入线程腾出空间，把背压留给内核缓冲区而不是在这里丢包
+                    loop {
+                        // 写入线程已退出（通常是写入出错），停止读取
+                        if producer.is_closed() {
+                            return Err(SavePcapError::CaptureInterrupted);
+                        }
+                        thread::yield_now();
+                        match producer.push(item) {
+                            Ok(()) => break,
+                            Err(returned) => item = returned,
+                        }
+                    }
+                }
+
+                pushed += 1;
+                // 读取消费者位置需要跨核访问，只定期更新占用量
+                if pushed.is_multiple_of(WRITER_QUEUE_STATS_INTERVAL) {
+                    stats.record_writer_queue_len(producer.len());
+                }
+                Ok(())
+            });
+            drop(producer);
+
+            let write_result = writer_thread
+                .join()
+                .unwrap_or(Err(SavePcapError::CaptureInterrupted));
+            stats.record_writer_queue_len(0);
+
+            // 写入线程的错误比它导致的读取中断更有意义
+            write_result.and(read_result)
+        })
+    }
+
+    // 读取数据包并交给sink
+    fn read_packets(
+        &self,
+        stream: &mut dyn PacketStream,
+        mut follower: Option<TcpFollower>,
+        pool: &mut BufferPool,
+        sink: &mut PacketSink<'_>,
+    ) -> Result<(), SavePcapError> {
+        let mut packet_count_total = 0;
+        let mut byte_count_total = 0;
+        let mut sampler = self
+            .options
+            .sample_probability
+            .map(|probability| Sampler::new(probability, self.options.sample_seed));
+        let flow_sampler = self
+            .options
+            .flow_sample_every
+            .map(|every| FlowSampler::new(every, self.options.sample_seed.unwrap_or_default()));
+        let datalink = stream.datalink();
+        let mut time_shifter = self.options.time_shift.map(TimeShifter::new);
+        // 预热期：第一个数据包的时间戳和已丢弃的数据包数，预热结束后为None
+        let mut warm_up = (self.options.discard_first_seconds.is_some()
+            || self.options.discard_first_packets.is_some())
+        .then_some((None, 0u64));
+        let mut paused = false;
+        let mut drops_checked = Instant::now();
+        // 最后一个需要保存的数据包的时间，用于`stop_after_quiet_seconds`
+        let mut last_kept = Instant::now();
+        // 已请求停止，只取出数据来源中仍在缓存的数据包
+        let mut draining = false;
+
+        loop {
+            if drops_checked.elapsed() >= LIVE_STATS_INTERVAL {
+                drops_checked = Instant::now();
+                if let Some(dropped) = stream.dropped() {
+                    self.handle.stats.record_kernel_dropped(dropped);
+                }
+                if let Some(pcap_stats) = stream.pcap_stats() {
+                    self.handle.stats.record_pcap_stats(pcap_stats);
+                }
+            }
+
+            if !draining && self.handle.is_stopped() {
+                info!("Stop requested, stopping capture.");
+                draining = true;
+            }
+
+            if let Some(limit) = self.options.packet_limit
+                && packet_count_total >= limit
+            {
+                info!("Reached packet limit of {}, stopping capture.", limit);
+                break;
+            }
+            if let Some(limit) = self.options.byte_limit
+                && byte_count_total >= limit
+            {
+                info!("Reached byte limit of {}, stopping capture.", limit);
+                break;
+            }
+            if self.handle.is_paused() {
+                last_kept = Instant::now();
+            }
+            if let Some(quiet) = self.options.stop_after_quiet_seconds
+                && last_kept.elapsed() >= Duration::from_secs(quiet)
+            {
+                info!(
+                    "No packets for {} seconds, stopping capture.",
+                    last_kept.elapsed().as_secs()
+                );
+                break;
+            }
+
+            // 与数据包按读取顺序排队，之前读取的数据包不会写入新文件
+            if self.handle.rotate.swap(false, Ordering::SeqCst) {
+                info!("Rotation requested");
+                sink(SinkItem::Rotate, pool)?;
+            }
+
+            // 缓存的数据包与读取的数据包一样经过暂停、过滤、限制等处理
+            let next = if draining {
+                stream.drain().map_or(NextPacket::End, NextPacket::Packet)
+            } else {
+                stream.next_packet(pool)?
+            };
+            let mut packet = match next {
+                NextPacket::Packet(packet) => packet,
+                NextPacket::Idle => {
+                    sink(SinkItem::Idle, pool)?;
+                    continue;
                 }
+                NextPacket::End => break,
+                NextPacket::Flush(ack) => {
+                    sink(SinkItem::Flush(ack), pool)?;
+                    continue;
+                }
+            };
+            if let Some(time_shifter) = &mut time_shifter {
+                packet.timestamp = time_shifter.shift(packet.timestamp);
+            }
+
+            if let Some((first, discarded)) = &mut warm_up {
+                let first = *first.get_or_insert(packet.timestamp);
+                let by_count = self
+                    .options
+                    .discard_first_packets
+                    .is_some_and(|count| *discarded < count);
+                let by_time = self.options.discard_first_seconds.is_some_and(|seconds| {
+                    packet.timestamp.saturating_sub(first) < Duration::from_secs(seconds)
+                });
+                if by_count || by_time {
+                    *discarded += 1;
+                    pool.give(packet.data);
+                    sink(SinkItem::Idle, pool)?;
+                    continue;
+                }
+                info!("Warm-up over, discarded the first {} packets", discarded);
+                warm_up = None;
+            }
+
+            if self.handle.is_paused() != paused {
+                paused = !paused;
+                info!("Capture {}", if paused { "paused" } else { "resumed" });
+            }
+            if paused
+                || !self.options.packet_allowed(&packet)
+                || sampler.as_mut().is_some_and(|sampler| !sampler.keep())
+                || flow_sampler
+                    .as_ref()
+                    .is_some_and(|sampler| !sampler.keep(datalink, &packet.data))
+            {
+                pool.give(packet.data);
+                sink(SinkItem::Idle, pool)?;
+                continue;
+            }
+            let followed = follower
+                .as_mut()
+                .map(|follower| follower.check(&packet.data));
+            if let Some(Followed::Other) = followed {
+                pool.give(packet.data);
+                sink(SinkItem::Idle, pool)?;
+                continue;
+            }
+
+            byte_count_total += self.options.stored_data(&packet.data).len() as u64;
+            last_kept = Instant::now();
+            sink(SinkItem::Packet(packet), pool)?;
+
+            packet_count_total += 1;
+            if packet_count_total % 1000 == 0 {
+                debug!("Captured {} packets total", packet_count_total);
+            }
+            if let Some(Followed::Closed) = followed {
+                info!("Followed TCP connection closed, stopping capture.");
+                break;
             }
         }
 
         Ok(())
     }
+
+    fn buffer_size(&self) -> usize {
+        self.options.snaplen.max(0) as usize
+    }
+
+    fn user_datalink(&self) -> DataLink {
+        self.options
+            .linktype
+            .map(|linktype| DataLink::from(linktype as u32))
+            .unwrap_or(DataLink::ETHERNET)
+    }
 }
 
 pub fn get_available_devices() -> Result<Vec<String>, SavePcapError> {
@@ -629,6 +1808,50 @@ pub fn get_available_devices() -> Result<Vec<String>, SavePcapError> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use chrono::TimeZone;
+
+    // 测试用的输出目录，每个进程一个，已存在时先删除
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("save_pcap_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    // 把用户提供的数据包写入dir的捕获，其余设置取自options
+    fn user_capturer(dir: &Path, options: PcapCaptureOptions) -> PcapCapturer {
+        PcapCapturer::new(PcapCaptureOptions {
+            packet_source: PacketSource::UserProvided,
+            file_path: dir.display().to_string(),
+            ..options
+        })
+    }
+
+    // 运行捕获，另一个线程按顺序发送packets，确认都已写入后停止；
+    // 捕获先因`packet_limit`等结束时不再停止
+    fn capture_packets(capturer: &PcapCapturer, packets: Vec<UserPacket>) {
+        let sender = capturer.get_packet_sender_with_id("test").unwrap();
+        let handle = capturer.handle();
+        let producer = thread::spawn(move || {
+            for packet in packets {
+                if sender.send(packet).is_err() {
+                    return;
+                }
+            }
+            if sender.flush().is_ok() {
+                handle.stop();
+            }
+        });
+        capturer.capture().unwrap();
+        producer.join().unwrap();
+    }
+
+    // 等待统计满足done，最多10秒，条件一直不满足时由之后的断言报告失败
+    fn wait_for_stats(handle: &CaptureHandle, done: impl Fn(&CaptureStats) -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !done(&handle.stats()) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
 
     #[test]
     fn test_get_available_devices() {
@@ -644,4 +1867,3440 @@ mod tests {
         assert_eq!(options.snaplen, 65535);
         assert_eq!(options.timeout_ms, 1000);
     }
+
+    #[test]
+    fn test_wireless_preset() {
+        let options = PcapCaptureOptions::wireless("wlan0mon");
+        assert!(options.rfmon);
+        assert_eq!(options.linktype, Some(127));
+        assert!(matches!(
+            options.packet_source,
+            PacketSource::NetworkDevice(ref name) if name == "wlan0mon"
+        ));
+    }
+
+    #[test]
+    fn test_scenario_presets() {
+        let scenario: Scenario = "VOIP_quality".parse().unwrap();
+        assert_eq!(scenario, Scenario::VoipQuality);
+        let options = PcapCaptureOptions::scenario(scenario, "eth0");
+        assert_eq!(options.file_prefix, "voip_quality");
+        assert!(options.filter.as_deref().unwrap().contains("5060"));
+        assert!(options.histograms.is_some());
+        assert!(matches!(
+            "voip".parse::<Scenario>(),
+            Err(SavePcapError::UnknownScenario(_))
+        ));
+    }
+
+    #[test]
+    fn test_usbmon_linktype_detection() {
+        assert_eq!(default_linktype_for_device("usbmon1"), Some(220));
+        assert_eq!(default_linktype_for_device("eth0"), None);
+
+        let options = PcapCaptureOptions::usb("usbmon0");
+        assert_eq!(options.linktype, Some(220));
+        assert_eq!(options.snaplen, 262144);
+    }
+
+    #[test]
+    fn test_worker_file_suffix() {
+        let capturer = PcapCapturer::new(PcapCaptureOptions {
+            worker_id: Some(1),
+            ..Default::default()
+        });
+        let (file_name, _) = capturer
+            .options
+            .create_new_file(None, ConflictPolicy::Error)
+            .unwrap();
+        assert!(file_name.starts_with("capture_"));
+        assert!(file_name.ends_with("_w1.pcap"));
+    }
+
+    #[test]
+    fn test_time_range_file_name() {
+        let options = PcapCaptureOptions {
+            file_format: FileFormat::PcapNg,
+            worker_id: Some(2),
+            ..Default::default()
+        };
+        let name = options.time_range_file_name(
+            Duration::from_secs(1_700_000_000),
+            Duration::from_secs(1_700_000_900),
+            None,
+        );
+        assert!(name.starts_with("capture_"));
+        assert!(name.ends_with("_w2.pcapng"));
+        assert!(options.is_capture_file_name(&name));
+
+        // 两个时间各为15个字符（YYYYMMDDTHHMMSS），中间以"-"连接
+        let range = &name["capture_".len()..name.len() - "_w2.pcapng".len()];
+        let (first, last) = range.split_once('-').unwrap();
+        assert_eq!((first.len(), last.len()), (15, 15));
+        assert!(first < last);
+    }
+
+    #[test]
+    fn test_packet_len_range() {
+        let jumbo_only = PcapCaptureOptions {
+            min_packet_len: Some(1519),
+            ..Default::default()
+        };
+        assert!(!jumbo_only.packet_len_allowed(1518));
+        assert!(jumbo_only.packet_len_allowed(9000));
+
+        let tiny_only = PcapCaptureOptions {
+            max_packet_len: Some(64),
+            ..Default::default()
+        };
+        assert!(tiny_only.packet_len_allowed(64));
+        assert!(!tiny_only.packet_len_allowed(65));
+        assert!(PcapCaptureOptions::default().packet_len_allowed(0));
+    }
+
+    #[test]
+    fn test_time_window() {
+        use chrono::NaiveTime;
+
+        let time = |h, m| NaiveTime::from_hms_opt(h, m, 0).unwrap();
+        let business: TimeWindow = "09:00-17:30".parse().unwrap();
+        assert!(business.contains(time(9, 0)));
+        assert!(business.contains(time(17, 29)));
+        assert!(!business.contains(time(17, 30)));
+
+        let night: TimeWindow = "22:00:00-06:00:00".parse().unwrap();
+        assert!(night.contains(time(23, 0)));
+        assert!(night.contains(time(5, 59)));
+        assert!(!night.contains(time(12, 0)));
+
+        assert!("9-17".parse::<TimeWindow>().is_err());
+    }
+
+    #[test]
+    fn test_sanity_check_ipv4() {
+        let check = SanityCheck {
+            verify_ip_checksum: true,
+            ..Default::default()
+        };
+        // 以太网头 + 20字节IPv4头 + 4字节数据
+        let mut data = vec![0u8; 12];
+        data.extend_from_slice(&[0x08, 0x00]);
+        data.extend_from_slice(&[
+            0x45, 0x00, 0x00, 0x18, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0xB8, 0xBC, 0xC0, 0xA8,
+            0x00, 0x01, 0xC0, 0xA8, 0x00, 0xC7,
+        ]);
+        data.extend_from_slice(&[0xDE, 0xAD, 0xBE, 0xEF]);
+        let packet = |data: Vec<u8>| SourcePacket {
+            timestamp: Duration::ZERO,
+            orig_len: data.len() as u32,
+            data,
+            user_index: None,
+        };
+
+        assert_eq!(check.check(DataLink::ETHERNET, &packet(data.clone())), None);
+
+        let mut bad_checksum = data.clone();
+        bad_checksum[24] ^= 0xFF;
+        assert!(
+            check
+                .check(DataLink::ETHERNET, &packet(bad_checksum))
+                .is_some()
+        );
+
+        let mut short_payload = data.clone();
+        short_payload.truncate(data.len() - 2);
+        assert!(
+            check
+                .check(DataLink::ETHERNET, &packet(short_payload))
+                .is_some()
+        );
+
+        assert!(
+            check
+                .check(DataLink::ETHERNET, &packet(vec![0; 10]))
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn test_client_hello_sni() {
+        let name = b"api.example.com";
+        // server_name扩展：列表长度、名称类型、名称长度、名称
+        let mut extension = vec![0x00, 0x00];
+        extension.extend_from_slice(&(name.len() as u16 + 5).to_be_bytes());
+        extension.extend_from_slice(&(name.len() as u16 + 3).to_be_bytes());
+        extension.push(0x00);
+        extension.extend_from_slice(&(name.len() as u16).to_be_bytes());
+        extension.extend_from_slice(name);
+
+        // 客户端版本、随机数、空会话ID、一个密码套件、一种压缩方法、扩展
+        let mut hello = vec![0x03, 0x03];
+        hello.extend_from_slice(&[0; 32]);
+        hello.extend_from_slice(&[0x00, 0x00, 0x02, 0x13, 0x01, 0x01, 0x00]);
+        hello.extend_from_slice(&(extension.len() as u16).to_be_bytes());
+        hello.extend_from_slice(&extension);
+
+        let mut payload = vec![0x16, 0x03, 0x01];
+        payload.extend_from_slice(&(hello.len() as u16 + 4).to_be_bytes());
+        payload.extend_from_slice(&[0x01, 0x00]);
+        payload.extend_from_slice(&(hello.len() as u16).to_be_bytes());
+        payload.extend_from_slice(&hello);
+
+        assert_eq!(tls::client_hello_sni(&payload), Some("api.example.com"));
+        assert_eq!(tls::client_hello_sni(&payload[..payload.len() - 4]), None);
+        payload[5] = 0x02;
+        assert_eq!(tls::client_hello_sni(&payload), None);
+    }
+
+    #[test]
+    fn test_dns_response_decoding() {
+        // 响应：一个问题example.com A，一条以压缩指针引用问题名称的A记录
+        let mut dns = vec![0x12, 0x34, 0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0];
+        dns.extend_from_slice(b"\x07example\x03com\x00");
+        dns.extend_from_slice(&[0x00, 0x01, 0x00, 0x01]);
+        dns.extend_from_slice(&[0xC0, 0x0C, 0x00, 0x01, 0x00, 0x01, 0, 0, 0x0E, 0x10, 0, 4]);
+        dns.extend_from_slice(&[93, 184, 216, 34]);
+
+        let mut data = vec![
+            0x45, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x11, 0x00, 0x00, 8, 8, 8, 8,
+            192, 168, 0, 1,
+        ];
+        let total_len = (20 + 8 + dns.len()) as u16;
+        data[2..4].copy_from_slice(&total_len.to_be_bytes());
+        data.extend_from_slice(&[0x00, 0x35, 0xC3, 0x50]);
+        data.extend_from_slice(&((8 + dns.len()) as u16).to_be_bytes());
+        data.extend_from_slice(&[0x00, 0x00]);
+        data.extend_from_slice(&dns);
+
+        let (transport, message) = dns::decode(DataLink::RAW, &data).unwrap();
+        assert_eq!(transport.source_port, 53);
+        assert_eq!(message.id, 0x1234);
+        assert!(message.response);
+        assert_eq!(message.rcode, 0);
+        assert_eq!(message.qname.as_deref(), Some("example.com"));
+        assert_eq!(message.qtype, Some(1));
+        assert_eq!(
+            message.addresses,
+            vec![("example.com".to_string(), "93.184.216.34".parse().unwrap())]
+        );
+
+        // 指向自身的压缩指针不能导致死循环
+        let mut looped = data.clone();
+        let name_offset = 20 + 8 + 12;
+        looped[name_offset..name_offset + 2].copy_from_slice(&[0xC0, 0x0C]);
+        assert!(dns::decode(DataLink::RAW, &looped).is_none());
+    }
+
+    #[test]
+    fn test_spsc_ring_wraps_in_order() {
+        let (mut producer, mut consumer) = spsc::channel(4);
+        for round in 0..3 {
+            for i in 0..4 {
+                assert!(producer.push(round * 4 + i).is_ok());
+            }
+            assert_eq!(producer.push(99), Err(99));
+            assert_eq!(producer.len(), 4);
+            for i in 0..4 {
+                assert_eq!(consumer.pop(), Some(round * 4 + i));
+            }
+            assert_eq!(consumer.pop(), None);
+        }
+
+        drop(producer);
+        assert!(consumer.is_finished());
+    }
+
+    #[test]
+    fn test_repair_truncates_partial_packet() {
+        let path =
+            std::env::temp_dir().join(format!("save_pcap_repair_{}.pcap", std::process::id()));
+        let mut data = vec![0xD4, 0xC3, 0xB2, 0xA1, 0x02, 0x00, 0x04, 0x00];
+        data.extend_from_slice(&[0; 8]);
+        data.extend_from_slice(&4u32.to_le_bytes());
+        data.extend_from_slice(&1u32.to_le_bytes());
+        // 一个完整的8字节数据包，随后是只写了一半的第二个数据包
+        for _ in 0..2 {
+            data.extend_from_slice(&[0; 8]);
+            data.extend_from_slice(&8u32.to_le_bytes());
+            data.extend_from_slice(&8u32.to_le_bytes());
+            data.extend_from_slice(&[0xAA; 8]);
+        }
+        data.truncate(data.len() - 5);
+        fs::write(&path, &data).unwrap();
+
+        let report = repair(&path).unwrap();
+        let repaired = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(report.packets, 1);
+        assert_eq!(report.repaired_len, 48);
+        assert!(report.header_rewritten);
+        assert_eq!(repaired.len(), 48);
+        assert_eq!(&repaired[16..20], &8u32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_bandwidth_log_fills_empty_intervals() {
+        let path =
+            std::env::temp_dir().join(format!("save_pcap_bandwidth_{}.csv", std::process::id()));
+        let mut log = bandwidth::BandwidthLog::create(&path, 1).unwrap();
+        log.record(Duration::from_millis(100_200), 100).unwrap();
+        log.record(Duration::from_millis(100_700), 150).unwrap();
+        log.record(Duration::from_millis(103_100), 1000).unwrap();
+        log.finish().unwrap();
+
+        let csv = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let rows: Vec<Vec<&str>> = csv
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').skip(1).collect())
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                vec!["2", "250", "2.0", "2000"],
+                vec!["0", "0", "0.0", "0"],
+                vec!["0", "0", "0.0", "0"],
+                vec!["1", "1000", "1.0", "8000"],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_histogram_buckets() {
+        let mut histogram = Histogram::new(vec![1000, 10, 100]);
+        for value in [0, 10, 11, 100, 5000] {
+            histogram.record(value);
+        }
+        assert_eq!(histogram.upper_bounds, vec![10, 100, 1000]);
+        assert_eq!(histogram.counts, vec![2, 2, 0, 1]);
+        assert_eq!(histogram.count(), 5);
+        assert_eq!((histogram.min, histogram.max), (Some(0), Some(5000)));
+    }
+
+    #[test]
+    fn test_alerts_fire_once_per_crossing() {
+        let alerts = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let received = alerts.clone();
+        let mut monitor = alert::AlertMonitor::new(&AlertOptions {
+            kernel_drops: true,
+            max_packets_per_second: Some(1000.0),
+            callback: Some(Arc::new(move |alert: &Alert| {
+                received.lock().unwrap().push(alert.clone())
+            })),
+            ..Default::default()
+        });
+        for (kernel_dropped, packets_per_second) in
+            [(0, 500.0), (5, 2000.0), (5, 3000.0), (9, 800.0)]
+        {
+            monitor.check(&LiveStats {
+                kernel_dropped,
+                packets_per_second,
+                ..Default::default()
+            });
+        }
+
+        let alerts = alerts.lock().unwrap();
+        assert_eq!(alerts.len(), 3);
+        assert_eq!(
+            alerts[0],
+            Alert::KernelDrops {
+                dropped: 5,
+                total: 5
+            }
+        );
+        assert!(matches!(alerts[1], Alert::PacketRate { threshold, .. } if threshold == 1000.0));
+        assert_eq!(
+            alerts[2],
+            Alert::KernelDrops {
+                dropped: 4,
+                total: 9
+            }
+        );
+    }
+
+    #[test]
+    fn test_capture_manager_sessions() {
+        let dir = std::env::temp_dir().join(format!("save_pcap_manager_{}", std::process::id()));
+        let mut manager = CaptureManager::new();
+        for name in ["a", "b"] {
+            let options = PcapCaptureOptions {
+                packet_source: PacketSource::UserProvided,
+                file_path: dir.join(name).display().to_string(),
+                timeout_ms: 10,
+                metadata_sidecar: false,
+                ..Default::default()
+            };
+            manager.start(name, options).unwrap();
+        }
+        assert!(matches!(
+            manager.start("a", PcapCaptureOptions::default()),
+            Err(SavePcapError::AlreadyRunning(_))
+        ));
+
+        let sender = manager.packet_sender("a").unwrap();
+        for _ in 0..3 {
+            sender
+                .send(UserPacket {
+                    data: vec![0; 60],
+                    timestamp: None,
+                })
+                .unwrap();
+        }
+        while manager.info("a").unwrap().stats.packets_written < 3 {
+            thread::sleep(Duration::from_millis(10));
+        }
+        manager.stop("a").unwrap();
+
+        let sessions = manager.list();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].state, SessionState::Stopped);
+        assert!(sessions[0].error.is_none());
+        assert_eq!(sessions[1].state, SessionState::Running);
+        assert_eq!(sessions[1].stats.packets_written, 0);
+        assert!(matches!(
+            manager.stop("c"),
+            Err(SavePcapError::SessionNotFound(_))
+        ));
+        drop(manager);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_session_state_changes() {
+        let dir = std::env::temp_dir().join(format!("save_pcap_state_{}", std::process::id()));
+        let capturer = user_capturer(
+            &dir,
+            PcapCaptureOptions {
+                timeout_ms: 10,
+                continuous_capture: true,
+                rollover_packet_count: Some(1),
+                metadata_sidecar: false,
+                ..Default::default()
+            },
+        );
+        let handle = capturer.handle();
+        let changes = handle.subscribe();
+        capturer
+            .get_packet_sender()
+            .unwrap()
+            .send(UserPacket {
+                data: vec![0; 60],
+                timestamp: None,
+            })
+            .unwrap();
+        assert_eq!(handle.state(), SessionState::Created);
+
+        let capture = thread::spawn(move || capturer.capture());
+        while handle.stats().rotations == 0 {
+            thread::sleep(Duration::from_millis(10));
+        }
+        handle.pause();
+        handle.resume();
+        handle.stop();
+        capture.join().unwrap().unwrap();
+
+        let states: Vec<_> = changes.try_iter().map(|change| change.to).collect();
+        assert_eq!(
+            states,
+            vec![
+                SessionState::Running,
+                SessionState::Rotating,
+                SessionState::Running,
+                SessionState::Paused,
+                SessionState::Running,
+                SessionState::Stopped,
+            ]
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_capture_manager_restores_sessions() {
+        let dir = test_dir("restore");
+        let state_path = dir.join("sessions.state");
+        let options = PcapCaptureOptions {
+            packet_source: PacketSource::UserProvided,
+            file_path: dir.join("a").display().to_string(),
+            timeout_ms: 10,
+            continuous_capture: true,
+            rollover_packet_count: Some(1),
+            metadata_sidecar: false,
+            filter: Some("udp port 53".to_string()),
+            file_sequence: Some(1),
+            ..Default::default()
+        };
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut manager = CaptureManager::with_state_file(&state_path);
+        manager.start("a", options).unwrap();
+        let sender = manager.packet_sender("a").unwrap();
+        for _ in 0..2 {
+            sender
+                .send(UserPacket {
+                    data: vec![0; 60],
+                    timestamp: None,
+                })
+                .unwrap();
+        }
+        while manager.info("a").unwrap().stats.rotations < 2 {
+            thread::sleep(Duration::from_millis(10));
+        }
+        // 进程退出时停止的会话保留在状态文件中
+        drop(manager);
+
+        let manager = CaptureManager::restore(&state_path).unwrap();
+        let sessions = manager.list();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].source, "UserProvided");
+        while manager
+            .handle("a")
+            .unwrap()
+            .live_stats()
+            .current_file
+            .is_none()
+        {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let live = manager.handle("a").unwrap().live_stats();
+        assert_eq!(live.current_file_sequence, Some(4));
+        assert!(
+            fs::read_to_string(&state_path)
+                .unwrap()
+                .contains("filter = udp port 53")
+        );
+        drop(manager);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_dual_format_outputs() {
+        let dir = test_dir("outputs");
+        let capturer = user_capturer(
+            &dir,
+            PcapCaptureOptions {
+                file_format: FileFormat::PcapNg,
+                packet_limit: Some(2),
+                metadata_sidecar: false,
+                outputs: vec![
+                    OutputOptions::format(FileFormat::Pcap),
+                    OutputOptions {
+                        file_path: Some(dir.join("headers").display().to_string()),
+                        snaplen: Some(20),
+                        ..OutputOptions::format(FileFormat::Pcap)
+                    },
+                ],
+                ..Default::default()
+            },
+        );
+        let sender = capturer.get_packet_sender().unwrap();
+        for _ in 0..2 {
+            sender
+                .send(UserPacket {
+                    data: vec![0; 60],
+                    timestamp: None,
+                })
+                .unwrap();
+        }
+        capturer.capture().unwrap();
+
+        let headers = fs::read_dir(dir.join("headers")).unwrap().next().unwrap();
+        let headers_len = headers.unwrap().metadata().unwrap().len();
+        assert_eq!(headers_len, 24 + 2 * (16 + 20));
+        fs::remove_dir_all(dir.join("headers")).unwrap();
+
+        let mut extensions: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| {
+                let path = entry.unwrap().path();
+                let len = fs::metadata(&path).unwrap().len();
+                (
+                    path.extension().unwrap().to_string_lossy().into_owned(),
+                    len,
+                )
+            })
+            .collect();
+        extensions.sort();
+        assert_eq!(extensions.len(), 2);
+        // pcap文件头24字节，每个数据包16字节记录头
+        assert_eq!(extensions[0], ("pcap".to_string(), 24 + 2 * (16 + 60)));
+        assert_eq!(extensions[1].0, "pcapng");
+
+        let conflicting = user_capturer(
+            &dir,
+            PcapCaptureOptions {
+                outputs: vec![OutputOptions::format(FileFormat::Pcap)],
+                ..Default::default()
+            },
+        );
+        assert!(matches!(
+            conflicting.capture(),
+            Err(SavePcapError::InvalidOutput(_))
+        ));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_sampled_output() {
+        let dir = test_dir("sampled");
+        let capturer = PcapCapturer::new(PcapCaptureOptions {
+            packet_source: PacketSource::UserProvided,
+            file_path: dir.join("full").display().to_string(),
+            file_format: FileFormat::Pcap,
+            packet_limit: Some(10),
+            metadata_sidecar: false,
+            outputs: vec![OutputOptions {
+                file_path: Some(dir.join("sampled").display().to_string()),
+                sample_every: Some(4),
+                ..OutputOptions::format(FileFormat::Pcap)
+            }],
+            ..Default::default()
+        });
+        let sender = capturer.get_packet_sender().unwrap();
+        for _ in 0..10 {
+            sender
+                .send(UserPacket {
+                    data: vec![0; 60],
+                    timestamp: None,
+                })
+                .unwrap();
+        }
+        capturer.capture().unwrap();
+
+        let file_len = |name: &str| {
+            let entry = fs::read_dir(dir.join(name)).unwrap().next().unwrap();
+            entry.unwrap().metadata().unwrap().len()
+        };
+        assert_eq!(file_len("full"), 24 + 10 * (16 + 60));
+        // 第1、5、9个数据包
+        assert_eq!(file_len("sampled"), 24 + 3 * (16 + 60));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_outputs_independent_of_main() {
+        let dir = test_dir("independent");
+        let (main, extra) = (dir.join("main"), dir.join("extra"));
+        let capturer = user_capturer(
+            &main,
+            PcapCaptureOptions {
+                metadata_sidecar: false,
+                timeout_ms: 10,
+                continuous_capture: true,
+                rollover_packet_count: Some(1),
+                memory_fallback_bytes: Some(1 << 20),
+                outputs: vec![OutputOptions {
+                    file_path: Some(extra.display().to_string()),
+                    ..OutputOptions::format(FileFormat::Pcap)
+                }],
+                ..Default::default()
+            },
+        );
+        let sender = capturer.get_packet_sender_with_id("test").unwrap();
+        let handle = capturer.handle();
+        let output = main.clone();
+        let producer = thread::spawn(move || {
+            let send = |seconds| {
+                sender
+                    .send(UserPacket {
+                        data: vec![0; 60],
+                        timestamp: Some(Duration::from_secs(seconds)),
+                    })
+                    .unwrap();
+            };
+            send(1);
+            wait_for_stats(&handle, |stats| stats.rotations == 1);
+            fs::remove_dir_all(&output).unwrap();
+            send(2);
+            send(3);
+            wait_for_stats(&handle, |stats| stats.fallback_buffered == 1);
+            fs::create_dir_all(&output).unwrap();
+            wait_for_stats(&handle, |stats| stats.fallback_buffered == 0);
+            handle.stop();
+        });
+        capturer.capture().unwrap();
+        producer.join().unwrap();
+
+        // 主输出缓存在内存中的数据包同样写入了额外输出
+        let mut timestamps = Vec::new();
+        for entry in fs::read_dir(&extra).unwrap() {
+            let mut reader = CaptureReader::open(&entry.unwrap().path()).unwrap();
+            while let Some(packet) = reader.read_packet() {
+                timestamps.push(packet.unwrap().timestamp.as_secs());
+            }
+        }
+        timestamps.sort();
+        assert_eq!(timestamps, [1, 2, 3]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_slice_bytes() {
+        let dir = test_dir("slice");
+        let capturer = user_capturer(
+            &dir,
+            PcapCaptureOptions {
+                packet_limit: Some(3),
+                metadata_sidecar: false,
+                slice_bytes: Some(32),
+                ..Default::default()
+            },
+        );
+        let sender = capturer.get_packet_sender().unwrap();
+        for len in [20, 60, 100] {
+            sender
+                .send(UserPacket {
+                    data: vec![0; len],
+                    timestamp: None,
+                })
+                .unwrap();
+        }
+        capturer.capture().unwrap();
+
+        let entry = fs::read_dir(&dir).unwrap().next().unwrap().unwrap();
+        let contents = fs::read(entry.path()).unwrap();
+        assert_eq!(contents.len(), 24 + 3 * 16 + 20 + 32 + 32);
+        // 文件头的快照长度
+        assert_eq!(u32::from_le_bytes(contents[16..20].try_into().unwrap()), 32);
+        // 第二个数据包记录头中的截断长度和原始长度
+        let record = 24 + 16 + 20;
+        let lengths = &contents[record + 8..record + 16];
+        assert_eq!(u32::from_le_bytes(lengths[..4].try_into().unwrap()), 32);
+        assert_eq!(u32::from_le_bytes(lengths[4..].try_into().unwrap()), 60);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_packet_index_seek() {
+        let dir = test_dir("pktidx");
+        let capturer = user_capturer(
+            &dir,
+            PcapCaptureOptions {
+                packet_limit: Some(5),
+                metadata_sidecar: false,
+                packet_index: true,
+                ..Default::default()
+            },
+        );
+        let sender = capturer.get_packet_sender().unwrap();
+        for i in 0..5u8 {
+            sender
+                .send(UserPacket {
+                    data: vec![i; 40 + i as usize],
+                    timestamp: Some(Duration::from_secs(100 + i as u64)),
+                })
+                .unwrap();
+        }
+        capturer.capture().unwrap();
+
+        let capture_path = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.extension().is_some_and(|ext| ext == "pcap"))
+            .unwrap();
+        let mut reader = IndexedCaptureReader::open(&capture_path).unwrap();
+        assert_eq!(reader.index().len(), 5);
+
+        reader.seek_packet(3);
+        let packet = reader.next().unwrap().unwrap();
+        assert_eq!(packet.number, 3);
+        assert_eq!(packet.data, vec![3; 43]);
+        assert_eq!(packet.orig_len, 43);
+
+        reader.seek_time(Duration::from_millis(101_500));
+        let numbers: Vec<_> = reader.map(|packet| packet.unwrap().number).collect();
+        assert_eq!(numbers, vec![2, 3, 4]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_extract_time_window() {
+        let dir = test_dir("extract");
+        let capturer = user_capturer(
+            &dir,
+            PcapCaptureOptions {
+                packet_limit: Some(6),
+                continuous_capture: true,
+                rollover_packet_count: Some(2),
+                file_sequence: Some(1),
+                metadata_sidecar: false,
+                packet_index: true,
+                ..Default::default()
+            },
+        );
+        let sender = capturer.get_packet_sender().unwrap();
+        let base = std::time::SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap();
+        for i in 0..6u64 {
+            sender
+                .send(UserPacket {
+                    data: vec![i as u8; 40],
+                    timestamp: Some(base + Duration::from_secs(i * 10)),
+                })
+                .unwrap();
+        }
+        capturer.capture().unwrap();
+
+        let output = dir.join("window.pcap");
+        let at = |seconds: u64| UNIX_EPOCH + base + Duration::from_secs(seconds);
+        // 跨越第二、三个文件
+        assert_eq!(extract(&dir, at(15), at(45), &output).unwrap(), 3);
+        assert_eq!(fs::metadata(&output).unwrap().len(), 24 + 3 * (16 + 40));
+        // 输出文件本身不作为输入
+        assert_eq!(extract(&dir, at(0), at(60), &output).unwrap(), 6);
+        assert!(matches!(
+            extract(&dir, at(45), at(15), &output),
+            Err(SavePcapError::InvalidTimeWindow(_))
+        ));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_query_directory() {
+        let dir = test_dir("query");
+        let capturer = user_capturer(
+            &dir,
+            PcapCaptureOptions {
+                packet_limit: Some(6),
+                continuous_capture: true,
+                rollover_packet_count: Some(2),
+                file_sequence: Some(1),
+                metadata_sidecar: false,
+                ..Default::default()
+            },
+        );
+        let sender = capturer.get_packet_sender().unwrap();
+        for i in 0..6u8 {
+            sender
+                .send(UserPacket {
+                    data: vec![i; 40],
+                    timestamp: None,
+                })
+                .unwrap();
+        }
+        capturer.capture().unwrap();
+
+        let reported = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let progress = reported.clone();
+        let output = dir.join("matches.pcap");
+        let result = query(
+            &dir,
+            &output,
+            &QueryOptions {
+                matcher: Some(Arc::new(|packet: &QueryPacket| {
+                    packet.data[0].is_multiple_of(2)
+                })),
+                progress: Some(Arc::new(move |update: &QueryProgress| {
+                    progress.lock().unwrap().push(update.files_done)
+                })),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(result.files_total, 3);
+        assert_eq!(result.packets_scanned, 6);
+        assert_eq!(result.matches, 3);
+        assert_eq!(*reported.lock().unwrap(), vec![1, 2, 3]);
+        assert_eq!(fs::metadata(&output).unwrap().len(), 24 + 3 * (16 + 40));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_replay_keeps_gaps() {
+        let dir = test_dir("replay");
+        let capturer = user_capturer(
+            &dir,
+            PcapCaptureOptions {
+                packet_limit: Some(3),
+                metadata_sidecar: false,
+                ..Default::default()
+            },
+        );
+        let sender = capturer.get_packet_sender().unwrap();
+        for i in 0..3u64 {
+            sender
+                .send(UserPacket {
+                    data: vec![i as u8; 60],
+                    timestamp: Some(Duration::from_millis(1_000 + i * 30)),
+                })
+                .unwrap();
+        }
+        capturer.capture().unwrap();
+        let input = fs::read_dir(&dir).unwrap().next().unwrap().unwrap().path();
+
+        let mut sent = Vec::new();
+        let options = ReplayOptions {
+            timing: ReplayTiming::Accurate,
+            ..Default::default()
+        };
+        let report = replay_with(&input, &options, |data| {
+            sent.push(data[0]);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(sent, vec![0, 1, 2]);
+        assert_eq!(report.packets, 3);
+        assert_eq!(report.bytes, 180);
+        assert!(report.elapsed >= Duration::from_millis(60));
+
+        // 两倍速重放两次
+        let options = ReplayOptions {
+            speed: 2.0,
+            loop_count: 2,
+            ..Default::default()
+        };
+        let report = replay_with(&input, &options, |_| Ok(())).unwrap();
+        assert_eq!(report.loops, 2);
+        assert_eq!(report.packets, 6);
+        // 每轮30毫秒，只检查下限，负载高的机器上可能更慢
+        assert!(report.elapsed >= Duration::from_millis(60));
+        let options = ReplayOptions {
+            speed: 1000.0,
+            ..Default::default()
+        };
+        assert!(matches!(
+            replay_with(&input, &options, |_| Ok(())),
+            Err(SavePcapError::InvalidReplayOptions(_))
+        ));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_traffic_generator() {
+        use testgen::{PacketSizes, TrafficGenerator, TrafficOptions, TrafficProtocol};
+
+        let options = TrafficOptions {
+            packet_sizes: PacketSizes::Uniform { min: 60, max: 200 },
+            protocols: vec![
+                TrafficProtocol::Udp,
+                TrafficProtocol::Tcp,
+                TrafficProtocol::Icmp,
+            ],
+            flows: 6,
+            packet_count: 30,
+            packet_rate: Some(1000),
+            ..Default::default()
+        };
+        let check = SanityCheck {
+            verify_ip_checksum: true,
+            ..Default::default()
+        };
+        let packets: Vec<UserPacket> = TrafficGenerator::new(options.clone()).unwrap().collect();
+        assert_eq!(packets.len(), 30);
+        for (i, packet) in packets.iter().enumerate() {
+            assert!((60..=200).contains(&packet.data.len()));
+            // IPv4总长度与帧长一致，协议按流轮换
+            let ip_len = u16::from_be_bytes([packet.data[16], packet.data[17]]) as usize;
+            assert_eq!(ip_len + 14, packet.data.len());
+            assert_eq!(packet.data[23], [17, 6, 1][i % 6 % 3]);
+            let source_packet = SourcePacket {
+                timestamp: Duration::ZERO,
+                orig_len: packet.data.len() as u32,
+                data: packet.data.clone(),
+                user_index: None,
+            };
+            assert_eq!(check.check(DataLink::ETHERNET, &source_packet), None);
+        }
+        // 时间戳间隔由速率决定，相同的种子生成相同的数据
+        let gap = packets[1].timestamp.unwrap() - packets[0].timestamp.unwrap();
+        assert_eq!(gap, Duration::from_millis(1));
+        let again: Vec<UserPacket> = TrafficGenerator::new(options.clone()).unwrap().collect();
+        assert!(packets.iter().zip(&again).all(|(a, b)| a.data == b.data));
+
+        let dir = test_dir("testgen");
+        let stats = TrafficGenerator::new(TrafficOptions {
+            packet_rate: None,
+            ..options.clone()
+        })
+        .unwrap()
+        .write(PcapCaptureOptions {
+            file_path: dir.display().to_string(),
+            metadata_sidecar: false,
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(stats.packets_written, 30);
+
+        let capturer = user_capturer(
+            &dir,
+            PcapCaptureOptions {
+                packet_limit: Some(30),
+                metadata_sidecar: false,
+                ..Default::default()
+            },
+        );
+        let sender = capturer.get_packet_sender().unwrap();
+        let generator = TrafficGenerator::new(options).unwrap();
+        let sending = thread::spawn(move || generator.send_to(&sender).unwrap());
+        capturer.capture().unwrap();
+        assert_eq!(sending.join().unwrap(), 30);
+        assert_eq!(capturer.handle().stats().packets_written, 30);
+
+        assert!(matches!(
+            TrafficGenerator::new(TrafficOptions {
+                flows: 0,
+                ..Default::default()
+            }),
+            Err(SavePcapError::InvalidTrafficOptions(_))
+        ));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_packet_builder() {
+        use packet_builder::checksum;
+        use std::net::{Ipv4Addr, Ipv6Addr};
+
+        let source = Ipv4Addr::new(10, 0, 0, 1);
+        let destination = Ipv4Addr::new(10, 0, 0, 2);
+        let packet = EthernetFrame::new()
+            .ipv4(source, destination)
+            .udp(5000, 53)
+            .payload(&b"hello"[..])
+            .timestamp(Duration::from_secs(1))
+            .build()
+            .unwrap();
+        let data = &packet.data;
+        assert_eq!(data.len(), 14 + 20 + 8 + 5);
+        assert_eq!(packet.timestamp, Some(Duration::from_secs(1)));
+        assert_eq!(&data[12..14], &[0x08, 0x00]);
+        assert_eq!(u16::from_be_bytes([data[16], data[17]]), 33);
+        assert_eq!(u16::from_be_bytes([data[38], data[39]]), 13);
+        // 校验和正确时，包含校验和字段在内的校验和为0
+        assert_eq!(checksum(&[&data[14..34]]), 0);
+        let mut pseudo_header = Vec::new();
+        pseudo_header.extend_from_slice(&source.octets());
+        pseudo_header.extend_from_slice(&destination.octets());
+        pseudo_header.extend_from_slice(&[0, 17, 0, 13]);
+        assert_eq!(checksum(&[&pseudo_header, &data[34..]]), 0);
+
+        let tcp = EthernetFrame::new()
+            .vlan(100)
+            .ipv4(source, destination)
+            .tcp(40000, 80)
+            .tcp_sequence(1, 2)
+            .tcp_flags(TcpFlags::SYN | TcpFlags::ACK, 1024)
+            .build()
+            .unwrap();
+        assert_eq!(&tcp.data[12..16], &[0x81, 0x00, 0x00, 100]);
+        assert_eq!(tcp.data[18 + 20 + 13], 0x12);
+        pseudo_header.truncate(8);
+        pseudo_header.extend_from_slice(&[0, 6, 0, 20]);
+        assert_eq!(checksum(&[&pseudo_header, &tcp.data[38..]]), 0);
+
+        let icmp = EthernetFrame::new()
+            .ipv4(source, destination)
+            .icmp_echo(1, 1)
+            .payload(vec![0xAB; 3])
+            .build()
+            .unwrap();
+        assert_eq!(checksum(&[&icmp.data[34..]]), 0);
+
+        let v6 = EthernetFrame::new()
+            .ipv6(Ipv6Addr::LOCALHOST, Ipv6Addr::LOCALHOST)
+            .icmp_echo(1, 1)
+            .build()
+            .unwrap();
+        assert_eq!(&v6.data[12..14], &[0x86, 0xDD]);
+        assert_eq!(v6.data[20], 58);
+        assert_eq!(v6.data[54], 128);
+
+        assert!(matches!(
+            EthernetFrame::new().udp(1, 2).build(),
+            Err(SavePcapError::InvalidPacket(_))
+        ));
+        assert!(matches!(
+            EthernetFrame::new()
+                .ipv4(source, destination)
+                .payload(vec![0; 70_000])
+                .build(),
+            Err(SavePcapError::InvalidPacket(_))
+        ));
+        let raw = EthernetFrame::new()
+            .ethertype(0x88B5)
+            .payload(vec![1, 2])
+            .build()
+            .unwrap();
+        assert_eq!(raw.data.len(), 16);
+    }
+
+    #[cfg(feature = "validate")]
+    #[test]
+    fn test_packet_validation() {
+        use std::net::Ipv4Addr;
+
+        let valid = EthernetFrame::new()
+            .ipv4(Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2))
+            .udp(5000, 53)
+            .payload(vec![7; 20])
+            .build()
+            .unwrap()
+            .data;
+        let mut bad_checksum = valid.clone();
+        bad_checksum[50] ^= 0xFF;
+        let mut bad_length = valid.clone();
+        bad_length[39] += 4;
+
+        let validation = PacketValidation::default();
+        assert_eq!(validation.check(DataLink::ETHERNET, &valid), None);
+        assert!(
+            validation
+                .check(DataLink::ETHERNET, &bad_checksum)
+                .is_some()
+        );
+        assert!(validation.check(DataLink::ETHERNET, &bad_length).is_some());
+
+        let dir = std::env::temp_dir().join(format!("save_pcap_validation_{}", std::process::id()));
+        for action in [ValidationAction::Reject, ValidationAction::Fix] {
+            let _ = fs::remove_dir_all(&dir);
+            let (rejected_sender, rejected) = channel();
+            let capturer = user_capturer(
+                &dir,
+                PcapCaptureOptions {
+                    packet_limit: Some(3),
+                    metadata_sidecar: false,
+                    packet_validation: Some(PacketValidation { action }),
+                    rejected_packets: Some(rejected_sender),
+                    ..Default::default()
+                },
+            );
+            let sender = capturer.get_packet_sender().unwrap();
+            for data in [&valid, &bad_checksum, &bad_length] {
+                sender
+                    .send(UserPacket {
+                        data: data.clone(),
+                        timestamp: None,
+                    })
+                    .unwrap();
+            }
+            drop(sender);
+            capturer.capture().unwrap();
+            let stats = capturer.handle().stats();
+            assert_eq!(stats.invalid_packets, 2);
+
+            let entry = fs::read_dir(&dir).unwrap().next().unwrap().unwrap();
+            let mut reader = CaptureReader::open(&entry.path()).unwrap();
+            let mut written = Vec::new();
+            while let Some(packet) = reader.read_packet() {
+                written.push(packet.unwrap().data);
+            }
+            let rejected: Vec<u64> = rejected.try_iter().map(|packet| packet.index).collect();
+            match action {
+                ValidationAction::Reject => {
+                    assert_eq!(written, vec![valid.clone()]);
+                    assert_eq!(rejected, vec![1, 2]);
+                }
+                _ => {
+                    // 长度字段修正后与原数据包相同，负载损坏的数据包只能重新计算校验和
+                    assert_eq!(written.len(), 3);
+                    assert_eq!(written[2], valid);
+                    assert!(rejected.is_empty());
+                    assert!(
+                        written
+                            .iter()
+                            .all(|data| validation.check(DataLink::ETHERNET, data).is_none())
+                    );
+                }
+            }
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_rejected_user_packets() {
+        let dir = test_dir("rejected");
+        let (rejected_sender, rejected) = channel();
+        let capturer = user_capturer(
+            &dir,
+            PcapCaptureOptions {
+                snaplen: 100,
+                metadata_sidecar: false,
+                sanity_check: Some(SanityCheck {
+                    min_frame_len: 60,
+                    action: InvalidPacketAction::Drop,
+                    ..Default::default()
+                }),
+                rejected_packets: Some(rejected_sender),
+                ..Default::default()
+            },
+        );
+        let packets = [vec![], vec![0; 10], vec![0; 60], vec![0; 200], vec![0; 40]]
+            .into_iter()
+            .map(|data| UserPacket {
+                data,
+                timestamp: None,
+            })
+            .collect();
+        capture_packets(&capturer, packets);
+
+        let rejected: Vec<RejectedPacket> = rejected.try_iter().collect();
+        let indices: Vec<u64> = rejected.iter().map(|packet| packet.index).collect();
+        assert_eq!(indices, vec![0, 1, 3, 4]);
+        assert!(rejected[2].reason.contains("snaplen"));
+        assert!(rejected[3].reason.contains("minimum"));
+        assert_eq!(capturer.handle().stats().packets_written, 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_timestamp_policy() {
+        let dir = test_dir("timestamps");
+        let policies = [
+            (TimestampPolicy::Accept, [1, 3, 2, 5, 4]),
+            (TimestampPolicy::Clamp, [1, 3, 3, 5, 5]),
+            (
+                TimestampPolicy::Reorder {
+                    window: Duration::from_secs(10),
+                    max_packets: 100,
+                },
+                [1, 2, 3, 4, 5],
+            ),
+        ];
+        for (policy, expected) in policies {
+            let _ = fs::remove_dir_all(&dir);
+            let capturer = user_capturer(
+                &dir,
+                PcapCaptureOptions {
+                    metadata_sidecar: false,
+                    timestamp_policy: policy,
+                    ..Default::default()
+                },
+            );
+            let packets = [1, 3, 2, 5, 4]
+                .into_iter()
+                .map(|seconds| UserPacket {
+                    data: vec![seconds as u8; 60],
+                    timestamp: Some(Duration::from_secs(seconds)),
+                })
+                .collect();
+            // 窗口远大于测试时间，缓存的数据包在确认写入时按顺序写出
+            capture_packets(&capturer, packets);
+
+            let entry = fs::read_dir(&dir).unwrap().next().unwrap().unwrap();
+            let mut reader = CaptureReader::open(&entry.path()).unwrap();
+            let mut timestamps = Vec::new();
+            while let Some(packet) = reader.read_packet() {
+                timestamps.push(packet.unwrap().timestamp.as_secs());
+            }
+            assert_eq!(timestamps, expected, "{:?}", policy);
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_reorder_multiple_senders() {
+        let dir = test_dir("reorder");
+        // 4个线程交错发送时间戳；缓冲区只能容纳2个数据包时，更早的数据包被提前写出，
+        // 之后迟到的数据包被调整时间戳
+        let interleaved: Vec<Vec<u64>> = (0..4)
+            .map(|producer| (0..50).map(|n| n * 4 + producer).collect())
+            .collect();
+        let cases = [
+            (1000, interleaved, (0..200).collect::<Vec<u64>>(), 0),
+            (2, vec![vec![5, 4, 3, 2, 1]], vec![3, 3, 3, 4, 5], 2),
+        ];
+        for (max_packets, producers, expected, late_packets) in cases {
+            let _ = fs::remove_dir_all(&dir);
+            let capturer = user_capturer(
+                &dir,
+                PcapCaptureOptions {
+                    metadata_sidecar: false,
+                    timestamp_policy: TimestampPolicy::Reorder {
+                        window: Duration::from_secs(10),
+                        max_packets,
+                    },
+                    ..Default::default()
+                },
+            );
+            let producers: Vec<_> = producers
+                .into_iter()
+                .map(|timestamps| {
+                    let sender = capturer.get_packet_sender().unwrap();
+                    thread::spawn(move || {
+                        for millis in timestamps {
+                            sender
+                                .send(UserPacket {
+                                    data: vec![0; 60],
+                                    timestamp: Some(Duration::from_millis(millis)),
+                                })
+                                .unwrap();
+                        }
+                    })
+                })
+                .collect();
+            for producer in producers {
+                producer.join().unwrap();
+            }
+            // 确认写入时包括其他发送端已发送的数据包
+            capture_packets(&capturer, Vec::new());
+
+            let entry = fs::read_dir(&dir).unwrap().next().unwrap().unwrap();
+            let mut reader = CaptureReader::open(&entry.path()).unwrap();
+            let mut timestamps = Vec::new();
+            while let Some(packet) = reader.read_packet() {
+                timestamps.push(packet.unwrap().timestamp.as_millis() as u64);
+            }
+            assert_eq!(timestamps, expected);
+            assert_eq!(capturer.handle().stats().late_packets, late_packets);
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_stop_drains_reorder_buffer() {
+        let dir = test_dir("drain");
+        let capturer = user_capturer(
+            &dir,
+            PcapCaptureOptions {
+                metadata_sidecar: false,
+                timestamp_policy: TimestampPolicy::Reorder {
+                    window: Duration::from_secs(10),
+                    max_packets: 1000,
+                },
+                min_packet_len: Some(50),
+                packet_limit: Some(3),
+                ..Default::default()
+            },
+        );
+        let sender = capturer.get_packet_sender().unwrap();
+        let handle = capturer.handle();
+        let producer = thread::spawn(move || {
+            for (seconds, len) in [(1, 60), (2, 30), (3, 60), (4, 60), (5, 60)] {
+                sender
+                    .send(UserPacket {
+                        data: vec![0; len],
+                        timestamp: Some(Duration::from_secs(seconds)),
+                    })
+                    .unwrap();
+            }
+            // 全部进入排序缓冲区后停止
+            wait_for_stats(&handle, |stats| stats.memory_used == 270);
+            handle.stop();
+        });
+        capturer.capture().unwrap();
+        producer.join().unwrap();
+
+        // 停止时取出的数据包同样按长度过滤，并且不超过packet_limit
+        let entry = fs::read_dir(&dir).unwrap().next().unwrap().unwrap();
+        let mut reader = CaptureReader::open(&entry.path()).unwrap();
+        let mut timestamps = Vec::new();
+        while let Some(packet) = reader.read_packet() {
+            timestamps.push(packet.unwrap().timestamp.as_secs());
+        }
+        assert_eq!(timestamps, [1, 3, 4]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_sender_stats() {
+        let dir = test_dir("senders");
+        let capturer = user_capturer(
+            &dir,
+            PcapCaptureOptions {
+                metadata_sidecar: false,
+                ..Default::default()
+            },
+        );
+        let producers: Vec<_> = [("fast", 20), ("slow", 2)]
+            .into_iter()
+            .map(|(id, count)| {
+                let sender = capturer.get_packet_sender_with_id(id).unwrap();
+                thread::spawn(move || {
+                    for _ in 0..count {
+                        sender
+                            .send(UserPacket {
+                                data: vec![0; 100],
+                                timestamp: None,
+                            })
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        // 没有ID的发送端不计入
+        capturer
+            .get_packet_sender()
+            .unwrap()
+            .send(UserPacket {
+                data: vec![0; 100],
+                timestamp: None,
+            })
+            .unwrap();
+        // 再取一个带ID的发送端确认写入会计入发送端统计，这里按写入的数据包数等待
+        let handle = capturer.handle();
+        let stopper = thread::spawn(move || {
+            wait_for_stats(&handle, |stats| stats.packets_written == 23);
+            handle.stop();
+        });
+        capturer.capture().unwrap();
+        stopper.join().unwrap();
+
+        let report = capturer.handle().report();
+        assert_eq!(report.stats.packets_written, 23);
+        assert_eq!(report.senders.len(), 2);
+        assert_eq!(report.senders["fast"].packets, 20);
+        assert_eq!(report.senders["fast"].bytes, 2000);
+        assert_eq!(report.senders["slow"].packets, 2);
+        assert!(report.senders["slow"].last_sent.is_some());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_sender_flush() {
+        let dir = std::env::temp_dir().join(format!("save_pcap_flush_{}", std::process::id()));
+        for writer_queue_capacity in [None, Some(64)] {
+            let _ = fs::remove_dir_all(&dir);
+            let capturer = user_capturer(
+                &dir,
+                PcapCaptureOptions {
+                    metadata_sidecar: false,
+                    writer_queue_capacity,
+                    ..Default::default()
+                },
+            );
+            let sender = capturer.get_packet_sender_with_id("test").unwrap();
+            let handle = capturer.handle();
+            let dir = dir.clone();
+            let producer = thread::spawn(move || {
+                let count_written = || {
+                    let entry = fs::read_dir(&dir).unwrap().next().unwrap().unwrap();
+                    let mut reader = CaptureReader::open(&entry.path()).unwrap();
+                    let mut count = 0;
+                    while let Some(packet) = reader.read_packet() {
+                        packet.unwrap();
+                        count += 1;
+                    }
+                    count
+                };
+                // 刷新返回后文件内容即已确定，不需要等待
+                for (batch, expected) in [(5, 5), (3, 8)] {
+                    for _ in 0..batch {
+                        sender
+                            .send(UserPacket {
+                                data: vec![0; 60],
+                                timestamp: None,
+                            })
+                            .unwrap();
+                    }
+                    sender.flush().unwrap();
+                    assert_eq!(count_written(), expected);
+                }
+                handle.stop();
+                sender
+            });
+            capturer.capture().unwrap();
+            let sender = producer.join().unwrap();
+            // 捕获结束后刷新不会一直阻塞
+            assert!(sender.flush().is_err());
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_observer() {
+        #[derive(Default)]
+        struct Recorder(std::sync::Mutex<Vec<String>>);
+
+        impl Observer for Recorder {
+            fn on_start(&self) {
+                self.0.lock().unwrap().push("start".to_string());
+            }
+            fn on_rotate(&self, closed: &ClosedFile, opened: &Path) {
+                assert!(closed.path.exists() && opened.exists());
+                assert_eq!(closed.bytes, fs::metadata(&closed.path).unwrap().len());
+                assert!(closed.first_packet <= closed.last_packet);
+                // 用户提供的数据包没有libpcap统计
+                assert_eq!(closed.pcap_stats, None);
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("rotate {}", closed.packets));
+            }
+            fn on_pause(&self) {
+                self.0.lock().unwrap().push("pause".to_string());
+            }
+            fn on_resume(&self) {
+                self.0.lock().unwrap().push("resume".to_string());
+            }
+            fn on_stop(&self, stats: &CaptureStats) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("stop {}", stats.packets_written));
+            }
+        }
+
+        let dir = test_dir("observer");
+        let capturer = user_capturer(
+            &dir,
+            PcapCaptureOptions {
+                metadata_sidecar: false,
+                continuous_capture: true,
+                rollover_packet_count: Some(2),
+                ..Default::default()
+            },
+        );
+        let recorder = Arc::new(Recorder::default());
+        capturer.add_observer(recorder.clone());
+        let sender = capturer.get_packet_sender_with_id("test").unwrap();
+        let handle = capturer.handle();
+        let producer = thread::spawn(move || {
+            let send = |count| {
+                for _ in 0..count {
+                    sender
+                        .send(UserPacket {
+                            data: vec![0; 60],
+                            timestamp: None,
+                        })
+                        .unwrap();
+                }
+                sender.flush().unwrap();
+            };
+            send(3);
+            handle.pause();
+            send(1);
+            handle.resume();
+            handle.stop();
+        });
+        capturer.capture().unwrap();
+        producer.join().unwrap();
+
+        let events = recorder.0.lock().unwrap().clone();
+        assert_eq!(events, ["start", "rotate 2", "pause", "resume", "stop 3"]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_health() {
+        let dir = test_dir("health");
+        let capturer = user_capturer(
+            &dir,
+            PcapCaptureOptions {
+                metadata_sidecar: false,
+                health: HealthOptions {
+                    min_free_disk_mb: 0,
+                    max_idle: Some(Duration::from_millis(100)),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        let handle = capturer.handle();
+        assert_eq!(handle.health().status, HealthStatus::Degraded);
+        assert_eq!(handle.health().issues, [HealthIssue::NotStarted]);
+
+        let sender = capturer.get_packet_sender_with_id("test").unwrap();
+        let probe = thread::spawn(move || {
+            sender
+                .send(UserPacket {
+                    data: vec![0; 60],
+                    timestamp: None,
+                })
+                .unwrap();
+            sender.flush().unwrap();
+            let ready = handle.health();
+            // 超过max_idle没有数据包
+            thread::sleep(Duration::from_millis(300));
+            let idle = handle.health();
+            handle.stop();
+            (ready, idle)
+        });
+        capturer.capture().unwrap();
+        let (ready, idle) = probe.join().unwrap();
+
+        assert_eq!(ready.status, HealthStatus::Ready);
+        assert_eq!(idle.status, HealthStatus::Degraded);
+        assert!(matches!(idle.issues[..], [HealthIssue::NoPackets { .. }]));
+        let stopped = capturer.handle().health();
+        assert_eq!(stopped.status, HealthStatus::Failed);
+        assert_eq!(stopped.issues, [HealthIssue::Stopped]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_json_events() {
+        let dir = test_dir("events");
+        fs::create_dir_all(&dir).unwrap();
+        let events_path = dir.join("events.jsonl");
+        let capturer = user_capturer(
+            &dir,
+            PcapCaptureOptions {
+                metadata_sidecar: false,
+                continuous_capture: true,
+                rollover_packet_count: Some(1),
+                json_events: Some(JsonEventOptions {
+                    session_id: "sensor-\"1\"".to_string(),
+                    target: JsonEventTarget::File(events_path.display().to_string()),
+                }),
+                ..Default::default()
+            },
+        );
+        let sender = capturer.get_packet_sender_with_id("test").unwrap();
+        let handle = capturer.handle();
+        let producer = thread::spawn(move || {
+            for _ in 0..2 {
+                sender
+                    .send(UserPacket {
+                        data: vec![0; 60],
+                        timestamp: None,
+                    })
+                    .unwrap();
+            }
+            sender.flush().unwrap();
+            handle.stop();
+        });
+        capturer.capture().unwrap();
+        producer.join().unwrap();
+
+        let events: Vec<serde_json::Value> = fs::read_to_string(&events_path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let names: Vec<&str> = events
+            .iter()
+            .map(|event| event["event"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["start", "rotate", "stop"]);
+        assert!(
+            events
+                .iter()
+                .all(|event| event["session"] == "sensor-\"1\"")
+        );
+        assert!(events[1]["closed_file"].is_string());
+        assert_eq!(events[2]["packets_written"], 2);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_device_error_diagnosis() {
+        let error = |message: &str| {
+            diagnose::device_error("any", PcapError::PcapError(message.to_string()))
+        };
+
+        let denied = error(
+            "any: You don't have permission to perform this capture on that device (socket: Operation not permitted)",
+        );
+        assert!(matches!(
+            denied,
+            SavePcapError::DeviceUnavailable {
+                cause: DeviceErrorCause::PermissionDenied,
+                ..
+            }
+        ));
+        assert!(denied.to_string().contains("Operation not permitted"));
+        assert!(matches!(
+            error("any: That device is not up"),
+            SavePcapError::DeviceUnavailable {
+                cause: DeviceErrorCause::InterfaceDown,
+                ..
+            }
+        ));
+        // 无法识别原因时保留原始错误
+        assert!(matches!(
+            error("any: some other failure"),
+            SavePcapError::PcapError(_)
+        ));
+    }
+
+    #[test]
+    fn test_device_display_name() {
+        let device = DeviceInfo {
+            name: r"\Device\NPF_{12345678-1234-1234-1234-1234567890AB}".to_string(),
+            friendly_name: "Wi-Fi".to_string(),
+            description: Some("Intel(R) Wi-Fi 6E AX210 160MHz".to_string()),
+        };
+        assert_eq!(
+            device.display_name(),
+            "Wi-Fi (Intel(R) Wi-Fi 6E AX210 160MHz)"
+        );
+
+        // 没有描述或描述与名称相同时只显示名称
+        let device = DeviceInfo {
+            name: "eth0".to_string(),
+            friendly_name: "eth0".to_string(),
+            description: Some("eth0".to_string()),
+        };
+        assert_eq!(device.display_name(), "eth0");
+    }
+
+    #[test]
+    fn test_filtered_outputs() {
+        let dir = test_dir("filtered");
+        let path = dir.display().to_string();
+        let filtered = |prefix: &str, filter: Option<&str>| OutputOptions {
+            file_prefix: Some(prefix.to_string()),
+            filter: filter.map(str::to_string),
+            unmatched: filter.is_none(),
+            ..OutputOptions::format(FileFormat::Pcap)
+        };
+        let capturer = PcapCapturer::new(PcapCaptureOptions {
+            packet_source: PacketSource::UserProvided,
+            file_path: path,
+            file_format: FileFormat::Pcap,
+            packet_limit: Some(4),
+            metadata_sidecar: false,
+            outputs: vec![
+                filtered("dns", Some("udp port 53")),
+                filtered("http", Some("tcp port 80")),
+                filtered("other", None),
+            ],
+            ..Default::default()
+        });
+        let sender = capturer.get_packet_sender().unwrap();
+        let frame = EthernetFrame::new().ipv4([10, 0, 0, 1].into(), [10, 0, 0, 2].into());
+        for frame in [
+            frame.clone().udp(40000, 53),
+            frame.clone().tcp(40000, 80),
+            frame.clone().udp(40000, 53),
+            frame.clone().tcp(40000, 443),
+        ] {
+            sender.send(frame.build().unwrap()).unwrap();
+        }
+        capturer.capture().unwrap();
+
+        let packets = |prefix: &str| {
+            let entry = fs::read_dir(&dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .find(|path| {
+                    let name = path.file_name().unwrap().to_string_lossy();
+                    name.starts_with(&format!("{}_", prefix))
+                })
+                .unwrap();
+            let mut reader = CaptureReader::open(&entry).unwrap();
+            std::iter::from_fn(|| reader.read_packet()).count()
+        };
+        assert_eq!(packets("capture"), 4);
+        assert_eq!(packets("dns"), 2);
+        assert_eq!(packets("http"), 1);
+        assert_eq!(packets("other"), 1);
+
+        // 过滤器有误时在开始写入前报错
+        let capturer = PcapCapturer::new(PcapCaptureOptions {
+            packet_source: PacketSource::UserProvided,
+            file_path: dir.join("invalid").display().to_string(),
+            outputs: vec![filtered("bad", Some("port nonsense"))],
+            ..Default::default()
+        });
+        assert!(matches!(
+            capturer.capture(),
+            Err(SavePcapError::InvalidOutput(_))
+        ));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_classification_rules() {
+        let rules: ClassificationRules = "
+            # 语音VLAN
+            vlan 100 -> voice
+            udp and port 5060-5061 -> sip
+            net 10.1.0.0/16 and tcp -> internal
+            proto 1 -> internal
+        "
+        .parse()
+        .unwrap();
+        assert_eq!(rules.rules.len(), 4);
+        assert_eq!(
+            rules.rules[1].matches,
+            vec![
+                RuleMatch::Protocol(17),
+                RuleMatch::Port {
+                    low: 5060,
+                    high: 5061
+                }
+            ]
+        );
+        assert!(matches!(
+            "port 70000 -> x".parse::<ClassificationRules>(),
+            Err(SavePcapError::InvalidRules(_))
+        ));
+        assert!(matches!(
+            "tcp".parse::<ClassificationRules>(),
+            Err(SavePcapError::InvalidRules(_))
+        ));
+
+        let dir = test_dir("rules");
+        let capturer = user_capturer(
+            &dir,
+            PcapCaptureOptions {
+                file_format: FileFormat::Pcap,
+                packet_limit: Some(5),
+                metadata_sidecar: false,
+                rules: Some(rules),
+                ..Default::default()
+            },
+        );
+        let sender = capturer.get_packet_sender().unwrap();
+        let frame = EthernetFrame::new().ipv4([10, 1, 2, 3].into(), [192, 168, 0, 1].into());
+        for frame in [
+            // 规则按顺序匹配，VLAN 100中的SIP写入voice
+            frame.clone().vlan(100).udp(5060, 5060),
+            frame.clone().udp(40000, 5061),
+            frame.clone().tcp(40000, 443),
+            frame.clone().icmp_echo(1, 1),
+            // 不匹配任何规则，只写入主输出
+            EthernetFrame::new()
+                .ipv4([192, 168, 0, 2].into(), [192, 168, 0, 1].into())
+                .tcp(40000, 443),
+        ] {
+            sender.send(frame.build().unwrap()).unwrap();
+        }
+        capturer.capture().unwrap();
+
+        let packets = |prefix: &str| {
+            let path = fs::read_dir(&dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .find(|path| {
+                    let name = path.file_name().unwrap().to_string_lossy();
+                    name.starts_with(&format!("{}_", prefix))
+                })
+                .unwrap();
+            let mut reader = CaptureReader::open(&path).unwrap();
+            std::iter::from_fn(|| reader.read_packet()).count()
+        };
+        assert_eq!(packets("capture"), 5);
+        assert_eq!(packets("voice"), 1);
+        assert_eq!(packets("sip"), 1);
+        assert_eq!(packets("internal"), 2);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_conflict_policy() {
+        let dir = test_dir("conflict");
+        fs::create_dir_all(&dir).unwrap();
+        let options = PcapCaptureOptions {
+            packet_source: PacketSource::UserProvided,
+            file_path: dir.display().to_string(),
+            file_format: FileFormat::Pcap,
+            metadata_sidecar: false,
+            ..Default::default()
+        };
+
+        let (name, path) = options
+            .create_new_file(None, ConflictPolicy::Error)
+            .unwrap();
+        fs::write(&path, b"evidence").unwrap();
+        match options.create_new_file(None, ConflictPolicy::Error) {
+            Err(SavePcapError::FileExists(_)) => {}
+            // 两次调用之间跨过了整秒，文件名不同
+            Ok((other, _)) => assert_ne!(other, name),
+            Err(e) => panic!("unexpected error: {}", e),
+        }
+        let (suffixed, _) = options
+            .create_new_file(None, ConflictPolicy::AppendSuffix)
+            .unwrap();
+        assert_ne!(suffixed, name);
+        fs::remove_file(&path).unwrap();
+
+        // 同一秒内多次滚动不会覆盖之前的文件
+        let capturer = PcapCapturer::new(PcapCaptureOptions {
+            continuous_capture: true,
+            rollover_packet_count: Some(1),
+            packet_limit: Some(3),
+            ..options
+        });
+        let sender = capturer.get_packet_sender().unwrap();
+        for _ in 0..3 {
+            sender
+                .send(UserPacket {
+                    data: vec![0; 60],
+                    timestamp: None,
+                })
+                .unwrap();
+        }
+        capturer.capture().unwrap();
+        let sizes: Vec<u64> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().metadata().unwrap().len())
+            .collect();
+        assert_eq!(sizes.iter().sum::<u64>(), 3 * (24 + 16 + 60));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_part_files() {
+        let dir = test_dir("part");
+        fs::create_dir_all(&dir).unwrap();
+        let options = PcapCaptureOptions {
+            packet_source: PacketSource::UserProvided,
+            file_path: dir.display().to_string(),
+            file_format: FileFormat::Pcap,
+            metadata_sidecar: false,
+            part_files: true,
+            continuous_capture: true,
+            rollover_packet_count: Some(1),
+            packet_limit: Some(2),
+            ..Default::default()
+        };
+
+        let (name, _) = options
+            .create_new_file(None, ConflictPolicy::Error)
+            .unwrap();
+        assert!(name.ends_with(".pcap.part"));
+
+        let capturer = PcapCapturer::new(options);
+        let sender = capturer.get_packet_sender().unwrap();
+        for _ in 0..2 {
+            sender
+                .send(UserPacket {
+                    data: vec![0; 60],
+                    timestamp: None,
+                })
+                .unwrap();
+        }
+        capturer.capture().unwrap();
+        let names: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        // 滚动和结束时关闭的文件都已去掉后缀
+        assert!(!names.is_empty());
+        assert!(
+            names.iter().all(|name| name.ends_with(".pcap")),
+            "{:?}",
+            names
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_device_in_file_name() {
+        let options = PcapCaptureOptions {
+            packet_source: PacketSource::NetworkDevice("eth0".to_string()),
+            file_format: FileFormat::Pcap,
+            device_in_file_name: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            options.file_name_with("20240101_120000"),
+            "capture_eth0_20240101_120000.pcap"
+        );
+
+        let options = PcapCaptureOptions {
+            packet_source: PacketSource::NetworkDevice(
+                r"\Device\NPF_{4D36E972-E325-11CE}".to_string(),
+            ),
+            ..options
+        };
+        assert_eq!(
+            options.file_name_with("20240101_120000"),
+            "capture_4D36E972-E325-11CE_20240101_120000.pcap"
+        );
+
+        let options = PcapCaptureOptions {
+            packet_source: PacketSource::UserProvided,
+            ..options
+        };
+        assert_eq!(
+            options.file_name_with("20240101_120000"),
+            "capture_20240101_120000.pcap"
+        );
+    }
+
+    #[test]
+    fn test_path_resolution() {
+        let base = std::env::temp_dir();
+        let resolved = paths::resolve("captures/./uplink//", Some(&base.display().to_string()));
+        assert_eq!(Path::new(&resolved), base.join("captures").join("uplink"));
+        assert!(Path::new(&paths::resolve("captures", None)).is_absolute());
+        if let Some(home) = std::env::var_os(if cfg!(windows) { "USERPROFILE" } else { "HOME" }) {
+            assert_eq!(
+                Path::new(&paths::resolve("~/captures", None)),
+                Path::new(&home).join("captures")
+            );
+        }
+
+        // 输出目录是已有的普通文件
+        let file = base.join(format!("save_pcap_not_dir_{}", std::process::id()));
+        fs::write(&file, b"").unwrap();
+        assert!(matches!(
+            paths::ensure_writable(&file),
+            Err(SavePcapError::DirectoryNotWritable(_))
+        ));
+        fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn test_open_output_limit() {
+        let dir = test_dir("open_limit");
+        let capturer = user_capturer(
+            &dir,
+            PcapCaptureOptions {
+                file_format: FileFormat::Pcap,
+                packet_limit: Some(6),
+                metadata_sidecar: false,
+                rules: Some("vlan 1 -> v1\nvlan 2 -> v2\nvlan 3 -> v3".parse().unwrap()),
+                max_open_outputs: Some(1),
+                ..Default::default()
+            },
+        );
+        let sender = capturer.get_packet_sender().unwrap();
+        let frame = EthernetFrame::new().ipv4([10, 0, 0, 1].into(), [10, 0, 0, 2].into());
+        // 每个数据包都写入刚被暂时关闭的输出
+        for vlan in [1, 2, 3, 1, 2, 3] {
+            let packet = frame.clone().vlan(vlan).udp(40000, 53);
+            sender.send(packet.build().unwrap()).unwrap();
+        }
+        capturer.capture().unwrap();
+
+        for prefix in ["v1", "v2", "v3"] {
+            let path = fs::read_dir(&dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .find(|path| {
+                    let name = path.file_name().unwrap().to_string_lossy();
+                    name.starts_with(&format!("{}_", prefix))
+                })
+                .unwrap();
+            let mut reader = CaptureReader::open(&path).unwrap();
+            assert_eq!(std::iter::from_fn(|| reader.read_packet()).count(), 2);
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_rollover_idle() {
+        let dir = test_dir("idle");
+        let capturer = user_capturer(
+            &dir,
+            PcapCaptureOptions {
+                file_format: FileFormat::Pcap,
+                metadata_sidecar: false,
+                continuous_capture: true,
+                rollover_idle_seconds: Some(1),
+                timeout_ms: 100,
+                packet_limit: Some(3),
+                ..Default::default()
+            },
+        );
+        let sender = capturer.get_packet_sender().unwrap();
+        let capture = thread::spawn(move || capturer.capture());
+        let packet = || UserPacket {
+            data: vec![0; 60],
+            timestamp: None,
+        };
+        sender.send(packet()).unwrap();
+        sender.send(packet()).unwrap();
+        thread::sleep(Duration::from_millis(1500));
+        // 空闲期间第一个文件已结束，下一个文件尚未创建
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        sender.send(packet()).unwrap();
+        capture.join().unwrap().unwrap();
+
+        let mut counts: Vec<usize> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| {
+                let mut reader = CaptureReader::open(&entry.unwrap().path()).unwrap();
+                std::iter::from_fn(|| reader.read_packet()).count()
+            })
+            .collect();
+        counts.sort();
+        assert_eq!(counts, vec![1, 2]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_strict_size_rollover() {
+        let dir = test_dir("size");
+        let capturer = user_capturer(
+            &dir,
+            PcapCaptureOptions {
+                file_format: FileFormat::Pcap,
+                metadata_sidecar: false,
+                continuous_capture: true,
+                rollover_file_size_mb: Some(1),
+                strict_size_rollover: true,
+                packet_limit: Some(40),
+                ..Default::default()
+            },
+        );
+        let sender = capturer.get_packet_sender().unwrap();
+        for _ in 0..40 {
+            sender
+                .send(UserPacket {
+                    data: vec![0; 50_000],
+                    timestamp: None,
+                })
+                .unwrap();
+        }
+        capturer.capture().unwrap();
+
+        // 每个文件20个数据包：24 + 20 * (16 + 50000)字节，再写一个就会超过1MB
+        let mut sizes: Vec<u64> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().metadata().unwrap().len())
+            .collect();
+        sizes.sort();
+        assert!(sizes.iter().all(|size| *size <= 1024 * 1024), "{:?}", sizes);
+        assert_eq!(
+            sizes.iter().sum::<u64>(),
+            24 * sizes.len() as u64 + 40 * 50_016
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_rotate_now() {
+        let dir = test_dir("rotate");
+        let capturer = user_capturer(
+            &dir,
+            PcapCaptureOptions {
+                file_format: FileFormat::Pcap,
+                metadata_sidecar: false,
+                timeout_ms: 100,
+                packet_limit: Some(3),
+                ..Default::default()
+            },
+        );
+        let sender = capturer.get_packet_sender_with_id("test").unwrap();
+        let handle = capturer.handle();
+        let capture = thread::spawn(move || capturer.capture());
+        let packet = || UserPacket {
+            data: vec![0; 60],
+            timestamp: None,
+        };
+        sender.send(packet()).unwrap();
+        sender.send(packet()).unwrap();
+        sender.flush().unwrap();
+        // 未开启持续捕获时同样切换到新文件
+        handle.rotate_now();
+        wait_for_stats(&handle, |stats| stats.rotations == 1);
+        sender.send(packet()).unwrap();
+        capture.join().unwrap().unwrap();
+
+        let mut counts: Vec<usize> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| {
+                let mut reader = CaptureReader::open(&entry.unwrap().path()).unwrap();
+                std::iter::from_fn(|| reader.read_packet()).count()
+            })
+            .collect();
+        counts.sort();
+        assert_eq!(counts, vec![1, 2]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_time_buckets() {
+        let dir = test_dir("buckets");
+        let capturer = user_capturer(
+            &dir,
+            PcapCaptureOptions {
+                file_format: FileFormat::Pcap,
+                metadata_sidecar: false,
+                continuous_capture: true,
+                time_buckets: Some(TimeBucket::Hour),
+                late_packet_policy: LatePacketPolicy::Drop,
+                timeout_ms: 100,
+                packet_limit: Some(4),
+                ..Default::default()
+            },
+        );
+        let sender = capturer.get_packet_sender().unwrap();
+        let capture = thread::spawn(move || capturer.capture());
+        let hour = Local
+            .with_ymd_and_hms(2024, 6, 1, 14, 0, 0)
+            .earliest()
+            .unwrap()
+            .timestamp() as u64;
+        let packet = |minutes: u64| UserPacket {
+            data: vec![0; 60],
+            timestamp: Some(Duration::from_secs(hour + minutes * 60)),
+        };
+        sender.send(packet(10)).unwrap();
+        sender.send(packet(65)).unwrap();
+        // 14点的时间段已结束，丢弃
+        sender.send(packet(50)).unwrap();
+        sender.send(packet(70)).unwrap();
+        capture.join().unwrap().unwrap();
+
+        let mut files: Vec<(String, usize)> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| {
+                let path = entry.unwrap().path();
+                let mut reader = CaptureReader::open(&path).unwrap();
+                let count = std::iter::from_fn(|| reader.read_packet()).count();
+                (
+                    path.file_name().unwrap().to_string_lossy().into_owned(),
+                    count,
+                )
+            })
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                ("capture_2024-06-01_14.pcap".to_string(), 1),
+                ("capture_2024-06-01_15.pcap".to_string(), 2),
+            ]
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let pipeline: Pipeline = r#"
+            # 边缘节点
+            source device "Ethernet 2"
+            filter tcp or udp
+            slice 256
+            timeshift -3600
+            demux vlan 100 -> voice
+            sink pcapng dir=/var/captures prefix=edge rotate-mb=100
+            sink pcap prefix=dns filter="port 53"
+        "#
+        .parse()
+        .unwrap();
+        let mut options = PcapCaptureOptions::default();
+        pipeline.apply(&mut options);
+        assert!(
+            matches!(&options.packet_source, PacketSource::NetworkDevice(name) if name == "Ethernet 2")
+        );
+        assert_eq!(options.filter.as_deref(), Some("tcp or udp"));
+        assert_eq!(options.slice_bytes, Some(256));
+        assert_eq!(
+            options.time_shift,
+            Some(TimeShift::Backward(Duration::from_secs(3600)))
+        );
+        assert_eq!(options.rules.unwrap().outputs(), vec!["voice"]);
+        assert_eq!(options.file_format, FileFormat::PcapNg);
+        assert_eq!(options.file_path, "/var/captures");
+        assert_eq!(options.file_prefix, "edge");
+        assert!(options.continuous_capture);
+        assert_eq!(options.rollover_file_size_mb, Some(100));
+        assert_eq!(options.outputs.len(), 1);
+        assert_eq!(options.outputs[0].filter.as_deref(), Some("port 53"));
+
+        for text in [
+            // 缺少输出
+            "source user",
+            "sink pcap",
+            "source user\nsink pcap\nfilter tcp",
+            "source user\nsink pcap\nsink pcap rotate-mb=10",
+            "source user\nsink pcap filter=tcp",
+            "source user\nsink pcap\nsource pktmon",
+            "source user\ntee\nsink pcap",
+            "source user\ntimeshift soon\nsink pcap",
+        ] {
+            assert!(
+                matches!(
+                    text.parse::<Pipeline>(),
+                    Err(SavePcapError::InvalidPipeline(_))
+                ),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn test_annotations() {
+        let dir = test_dir("annotate");
+        let capturer = user_capturer(
+            &dir,
+            PcapCaptureOptions {
+                file_format: FileFormat::Pcap,
+                metadata_sidecar: false,
+                packet_limit: Some(2),
+                annotator: Some(Arc::new(|packet: &AnnotatedPacket| {
+                    match packet.user_index {
+                        Some(1) => vec![("request-id".to_string(), "a\"1".to_string())],
+                        _ => Vec::new(),
+                    }
+                })),
+                ..Default::default()
+            },
+        );
+        let sender = capturer.get_packet_sender().unwrap();
+        for _ in 0..2 {
+            sender
+                .send(UserPacket {
+                    data: vec![0; 60],
+                    timestamp: Some(Duration::from_secs(1_700_000_000)),
+                })
+                .unwrap();
+        }
+        capturer.capture().unwrap();
+
+        let annotations: Vec<PathBuf> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.to_string_lossy().ends_with(".annotations.jsonl"))
+            .collect();
+        assert_eq!(annotations.len(), 1);
+        let log = fs::read_to_string(&annotations[0]).unwrap();
+        // 第二个数据包位于24字节的文件头和第一个数据包（16字节记录头加60字节）之后
+        assert_eq!(log.lines().count(), 1);
+        assert!(log.contains("\"offset\": 100"), "{}", log);
+        assert!(log.contains(r#""request-id": "a\"1""#), "{}", log);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_capture_from_reader() {
+        // 相当于`tcpdump -w -`输出的经典pcap流：文件头和3个60字节的数据包
+        let mut stream = Vec::new();
+        for value in [0xa1b2c3d4u32, 0x0004_0002, 0, 0, 65535, 1] {
+            stream.extend_from_slice(&value.to_le_bytes());
+        }
+        for second in 0..3u32 {
+            for value in [1_700_000_000 + second, 0, 60, 60] {
+                stream.extend_from_slice(&value.to_le_bytes());
+            }
+            stream.extend_from_slice(&[0; 60]);
+        }
+
+        let dir = test_dir("reader");
+        let capturer = PcapCapturer::new(PcapCaptureOptions {
+            packet_source: PacketSource::Stdin,
+            file_path: dir.display().to_string(),
+            file_format: FileFormat::Pcap,
+            metadata_sidecar: false,
+            continuous_capture: true,
+            rollover_packet_count: Some(2),
+            ..Default::default()
+        });
+        capturer
+            .capture_from_reader(io::Cursor::new(stream))
+            .unwrap();
+        assert_eq!(capturer.handle().state(), SessionState::Stopped);
+
+        let mut counts: Vec<usize> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| {
+                let mut reader = CaptureReader::open(&entry.unwrap().path()).unwrap();
+                std::iter::from_fn(|| reader.read_packet()).count()
+            })
+            .collect();
+        counts.sort();
+        assert_eq!(counts, vec![1, 2]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(feature = "http-server")]
+    #[test]
+    fn test_file_server() {
+        use std::io::{Read, Write};
+        use std::net::TcpStream;
+
+        let dir = test_dir("http");
+        let capturer = user_capturer(
+            &dir,
+            PcapCaptureOptions {
+                file_format: FileFormat::Pcap,
+                metadata_sidecar: false,
+                time_range_file_names: true,
+                packet_limit: Some(3),
+                ..Default::default()
+            },
+        );
+        let sender = capturer.get_packet_sender().unwrap();
+        for second in 0..3 {
+            sender
+                .send(UserPacket {
+                    data: vec![0; 60],
+                    timestamp: Some(Duration::from_secs(1_700_000_000 + second)),
+                })
+                .unwrap();
+        }
+        capturer.capture().unwrap();
+
+        let start = |layout: Option<OutputLayout>| {
+            FileServer::start(FileServerOptions {
+                dir: dir.display().to_string(),
+                listen: "127.0.0.1:0".to_string(),
+                layout,
+            })
+            .unwrap()
+        };
+        let get = |server: &FileServer, target: &str| {
+            let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+            write!(stream, "GET {} HTTP/1.1\r\nHost: probe\r\n\r\n", target).unwrap();
+            let mut response = Vec::new();
+            stream.read_to_end(&mut response).unwrap();
+            response
+        };
+
+        let server = start(None);
+        let index = String::from_utf8(get(&server, "/")).unwrap();
+        assert!(index.starts_with("HTTP/1.1 200 OK"), "{}", index);
+        let name = fs::read_dir(&dir)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .file_name()
+            .into_string()
+            .unwrap();
+        assert!(
+            index.contains(&format!("\"name\": \"{}\"", name)),
+            "{}",
+            index
+        );
+
+        let file = get(&server, &format!("/files/{}", name));
+        let size = fs::metadata(dir.join(&name)).unwrap().len() as usize;
+        assert!(file.starts_with(b"HTTP/1.1 200 OK"));
+        assert!(file.ends_with(&fs::read(dir.join(&name)).unwrap()));
+        assert!(file.len() > size);
+        assert!(get(&server, "/files/..%2Fsecret.pcap").starts_with(b"HTTP/1.1 404"));
+
+        // 只取中间一秒的数据包：24字节文件头加一个数据包
+        let extracted = get(&server, "/extract?start=1700000001&end=1700000002");
+        assert!(extracted.starts_with(b"HTTP/1.1 200 OK"));
+        assert!(
+            String::from_utf8_lossy(&extracted).contains("Content-Length: 100\r\n"),
+            "{}",
+            String::from_utf8_lossy(&extracted)
+        );
+        assert!(get(&server, "/extract?start=later").starts_with(b"HTTP/1.1 400"));
+        server.stop();
+
+        // 按布局放在子目录中的文件以相对路径列出和下载，每一级都不能是`..`
+        let nested = dir.join("team-a").join("uplink");
+        fs::create_dir_all(&nested).unwrap();
+        fs::rename(dir.join(&name), nested.join(&name)).unwrap();
+        let layout = OutputLayout {
+            template: "{tenant}/{session}".to_string(),
+            ..OutputLayout::new("team-a", "uplink")
+        };
+        let server = start(Some(layout));
+        let index = String::from_utf8(get(&server, "/")).unwrap();
+        assert!(
+            index.contains(&format!("\"name\": \"team-a/uplink/{}\"", name)),
+            "{}",
+            index
+        );
+        let file = get(&server, &format!("/files/team-a/uplink/{}", name));
+        assert!(file.starts_with(b"HTTP/1.1 200 OK"));
+        let escaped = format!("/files/team-a/../team-a/uplink/{}", name);
+        assert!(get(&server, &escaped).starts_with(b"HTTP/1.1 404"));
+        let extracted = get(&server, "/extract?start=1700000001&end=1700000002");
+        assert!(extracted.starts_with(b"HTTP/1.1 200 OK"));
+        server.stop();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(feature = "websocket")]
+    #[test]
+    fn test_websocket_stream() {
+        use std::io::{BufRead, BufReader, Read, Write};
+        use std::net::{TcpListener, TcpStream};
+
+        let dir = test_dir("websocket");
+        // 先找一个空闲端口
+        let listen = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+        let capturer = user_capturer(
+            &dir,
+            PcapCaptureOptions {
+                metadata_sidecar: false,
+                packet_limit: Some(1),
+                live_stream: Some(LiveStreamOptions {
+                    listen: listen.clone(),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        );
+        let sender = capturer.get_packet_sender().unwrap();
+        let capture = thread::spawn(move || capturer.capture());
+
+        let mut stream = loop {
+            match TcpStream::connect(&listen) {
+                Ok(stream) => break stream,
+                Err(_) => thread::sleep(Duration::from_millis(10)),
+            }
+        };
+        write!(
+            stream,
+            "GET /json HTTP/1.1\r\nHost: probe\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n"
+        )
+        .unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut response = String::new();
+        while !response.ends_with("\r\n\r\n") {
+            assert!(reader.read_line(&mut response).unwrap() > 0);
+        }
+        assert!(response.starts_with("HTTP/1.1 101"), "{}", response);
+        // RFC 6455中的示例
+        assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+        sender
+            .send(UserPacket {
+                data: vec![0; 60],
+                timestamp: Some(Duration::from_secs(1_700_000_000)),
+            })
+            .unwrap();
+        let mut header = [0; 2];
+        reader.read_exact(&mut header).unwrap();
+        assert_eq!(header[0], 0x81);
+        let mut message = vec![0; header[1] as usize];
+        reader.read_exact(&mut message).unwrap();
+        let message = String::from_utf8(message).unwrap();
+        assert!(message.contains("\"length\": 60"), "{}", message);
+
+        // 捕获结束后服务端发送关闭帧
+        capture.join().unwrap().unwrap();
+        reader.read_exact(&mut header).unwrap();
+        assert_eq!(header[0], 0x88);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_byte_limit() {
+        let dir = test_dir("byte_limit");
+        let capturer = user_capturer(
+            &dir,
+            PcapCaptureOptions {
+                metadata_sidecar: false,
+                slice_bytes: Some(40),
+                byte_limit: Some(100),
+                ..Default::default()
+            },
+        );
+        let sender = capturer.get_packet_sender().unwrap();
+        for _ in 0..5 {
+            sender
+                .send(UserPacket {
+                    data: vec![0; 60],
+                    timestamp: None,
+                })
+                .unwrap();
+        }
+        capturer.capture().unwrap();
+
+        // 按截断后的长度计算：40 + 40 + 40字节时达到上限
+        let path = fs::read_dir(&dir).unwrap().next().unwrap().unwrap().path();
+        let mut reader = CaptureReader::open(&path).unwrap();
+        assert_eq!(std::iter::from_fn(|| reader.read_packet()).count(), 3);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_sample_probability() {
+        let dir = test_dir("sampling");
+        let sample = |prefix: &str, probability: f64| {
+            let capturer = user_capturer(
+                &dir,
+                PcapCaptureOptions {
+                    file_prefix: prefix.to_string(),
+                    metadata_sidecar: false,
+                    packet_limit: Some(20),
+                    sample_probability: Some(probability),
+                    sample_seed: Some(7),
+                    ..Default::default()
+                },
+            );
+            let sender = capturer.get_packet_sender().unwrap();
+            for i in 0..200u64 {
+                sender
+                    .send(UserPacket {
+                        data: vec![i as u8; 60],
+                        timestamp: Some(Duration::from_secs(1_700_000_000 + i)),
+                    })
+                    .unwrap();
+            }
+            capturer.capture().map(|()| {
+                let path = fs::read_dir(&dir)
+                    .unwrap()
+                    .map(|entry| entry.unwrap().path())
+                    .find(|path| path.to_string_lossy().contains(prefix))
+                    .unwrap();
+                let mut reader = CaptureReader::open(&path).unwrap();
+                std::iter::from_fn(|| reader.read_packet())
+                    .map(|packet| packet.unwrap().data[0])
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        // 相同的种子抽中相同的数据包，未抽中的数据包不计入packet_limit
+        let first = sample("first", 0.5).unwrap();
+        assert_eq!(first.len(), 20);
+        assert!(first[19] > 19);
+        assert_eq!(sample("second", 0.5).unwrap(), first);
+        assert!(matches!(
+            sample("invalid", 1.5),
+            Err(SavePcapError::InvalidSampling(_))
+        ));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_flow_sampling() {
+        let dir = test_dir("flow_sample");
+        let capturer = user_capturer(
+            &dir,
+            PcapCaptureOptions {
+                file_format: FileFormat::Pcap,
+                metadata_sidecar: false,
+                flow_sample_every: Some(4),
+                ..Default::default()
+            },
+        );
+        let sender = capturer.get_packet_sender_with_id("test").unwrap();
+        let handle = capturer.handle();
+        let (client, server) = ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+        let producer = thread::spawn(move || {
+            // 64条UDP流，每条流各有一个请求和一个响应，最后是一个非IP数据包
+            for port in 1000..1064 {
+                let request = EthernetFrame::new().ipv4(client, server).udp(port, 53);
+                let response = EthernetFrame::new().ipv4(server, client).udp(53, port);
+                sender.send(request.build().unwrap()).unwrap();
+                sender.send(response.build().unwrap()).unwrap();
+            }
+            sender
+                .send(EthernetFrame::new().ethertype(0x0806).build().unwrap())
+                .unwrap();
+            sender.flush().unwrap();
+            handle.stop();
+        });
+        capturer.capture().unwrap();
+        producer.join().unwrap();
+
+        let path = fs::read_dir(&dir).unwrap().next().unwrap().unwrap().path();
+        let mut reader = CaptureReader::open(&path).unwrap();
+        let mut packets = Vec::new();
+        while let Some(packet) = reader.read_packet() {
+            packets.push(packet.unwrap().data);
+        }
+        assert!(packets.pop().is_some_and(|arp| arp[12..14] == [0x08, 0x06]));
+        // 保留的流都是完整的：请求之后紧跟着响应
+        let flows = packets.len() / 2;
+        assert!(flows > 0 && flows < 64, "{} flows", flows);
+        for pair in packets.chunks(2) {
+            let ports = |data: &[u8]| u16::from_be_bytes([data[34], data[35]]);
+            assert_eq!(pair.len(), 2);
+            assert_eq!(
+                ports(&pair[0]),
+                u16::from_be_bytes([pair[1][36], pair[1][37]])
+            );
+            assert_eq!(ports(&pair[1]), 53);
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_warm_up_discard() {
+        let dir = test_dir("warm_up");
+        let capturer = user_capturer(
+            &dir,
+            PcapCaptureOptions {
+                metadata_sidecar: false,
+                packet_limit: Some(2),
+                discard_first_seconds: Some(5),
+                discard_first_packets: Some(2),
+                ..Default::default()
+            },
+        );
+        let sender = capturer.get_packet_sender().unwrap();
+        // 前两个数据包按数量丢弃，第三个仍在前5秒内
+        for (i, second) in [0, 10, 4, 5, 6].into_iter().enumerate() {
+            sender
+                .send(UserPacket {
+                    data: vec![i as u8; 60],
+                    timestamp: Some(Duration::from_secs(1_700_000_000 + second)),
+                })
+                .unwrap();
+        }
+        capturer.capture().unwrap();
+
+        let path = fs::read_dir(&dir).unwrap().next().unwrap().unwrap().path();
+        let mut reader = CaptureReader::open(&path).unwrap();
+        let kept: Vec<u8> = std::iter::from_fn(|| reader.read_packet())
+            .map(|packet| packet.unwrap().data[0])
+            .collect();
+        assert_eq!(kept, [3, 4]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_stop_after_quiet() {
+        let dir = test_dir("quiet");
+        let capturer = user_capturer(
+            &dir,
+            PcapCaptureOptions {
+                metadata_sidecar: false,
+                packet_limit: Some(10),
+                stop_after_quiet_seconds: Some(1),
+                ..Default::default()
+            },
+        );
+        let sender = capturer.get_packet_sender().unwrap();
+        for _ in 0..2 {
+            sender
+                .send(UserPacket {
+                    data: vec![0; 60],
+                    timestamp: None,
+                })
+                .unwrap();
+        }
+        // 发送端仍然存在，只能因为没有新的数据包而结束
+        let started = Instant::now();
+        capturer.capture().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(capturer.handle().stats().packets_written, 2);
+        drop(sender);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_lock_output() {
+        let dir = test_dir("lock");
+        fs::create_dir_all(&dir).unwrap();
+        let capturer = user_capturer(
+            &dir,
+            PcapCaptureOptions {
+                metadata_sidecar: false,
+                packet_limit: Some(1),
+                lock_output: true,
+                ..Default::default()
+            },
+        );
+
+        // 模拟另一个实例正在使用同一个目录和前缀
+        let lock = OutputLock::acquire(&capturer.options).unwrap();
+        assert!(matches!(
+            capturer.capture(),
+            Err(SavePcapError::OutputLocked(_))
+        ));
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        drop(lock);
+        let sender = capturer.get_packet_sender().unwrap();
+        sender
+            .send(UserPacket {
+                data: vec![0; 60],
+                timestamp: None,
+            })
+            .unwrap();
+        capturer.capture().unwrap();
+        assert!(dir.join(".capture.lock").exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_sanitize_file_names() {
+        let sanitize = paths::sanitize_file_name_part;
+        assert_eq!(sanitize("uplink-1.a").as_deref(), Some("uplink-1.a"));
+        assert_eq!(sanitize("抓包 测试").as_deref(), Some("抓包_测试"));
+        assert_eq!(sanitize("a\tb:c*").as_deref(), Some("a_b_c"));
+        assert_eq!(sanitize("CON").as_deref(), Some("CON_"));
+        assert_eq!(sanitize("com1.log").as_deref(), Some("com1_.log"));
+        assert_eq!(sanitize("console").as_deref(), Some("console"));
+        assert_eq!(sanitize("..").as_deref(), None);
+
+        // 前缀中的路径不能让文件写到输出目录之外
+        let dir = std::env::temp_dir().join(format!("save_pcap_sanitize_{}", std::process::id()));
+        let capturer = PcapCapturer::new(PcapCaptureOptions {
+            packet_source: PacketSource::NetworkDevice("eth0/../x y".to_string()),
+            file_path: dir.display().to_string(),
+            file_prefix: "../etc/x y".to_string(),
+            device_in_file_name: true,
+            ..Default::default()
+        });
+        assert_eq!(capturer.options.file_prefix, "etc_x_y");
+        assert_eq!(
+            capturer.options.file_name_with("20240101_120000"),
+            "etc_x_y_eth0_.._x_y_20240101_120000.pcap"
+        );
+    }
+
+    #[test]
+    fn test_verify() {
+        let dir = std::env::temp_dir().join(format!("save_pcap_verify_{}", std::process::id()));
+        let capture = |file_format: FileFormat, seconds: &[u64]| {
+            let _ = fs::remove_dir_all(&dir);
+            let capturer = user_capturer(
+                &dir,
+                PcapCaptureOptions {
+                    file_format,
+                    metadata_sidecar: false,
+                    packet_limit: Some(seconds.len()),
+                    ..Default::default()
+                },
+            );
+            let sender = capturer.get_packet_sender().unwrap();
+            for &seconds in seconds {
+                sender
+                    .send(UserPacket {
+                        data: vec![0; 60],
+                        timestamp: Some(Duration::from_secs(seconds)),
+                    })
+                    .unwrap();
+            }
+            capturer.capture().unwrap();
+            fs::read_dir(&dir).unwrap().next().unwrap().unwrap().path()
+        };
+
+        // 第三个数据包的时间戳早于第二个
+        let path = capture(FileFormat::Pcap, &[100, 102, 101]);
+        let report = verify(&path).unwrap();
+        assert_eq!(report.format, FileFormat::Pcap);
+        assert_eq!(report.packets, 3);
+        assert_eq!(report.valid_len, report.file_len);
+        assert_eq!(report.first_packet, Some(Duration::from_secs(100)));
+        assert!(matches!(
+            report.issues[..],
+            [VerificationIssue::TimestampOutOfOrder { packet: 3, .. }]
+        ));
+
+        let path = capture(FileFormat::PcapNg, &[100, 101, 102]);
+        let report = verify(&path).unwrap();
+        assert_eq!(report.format, FileFormat::PcapNg);
+        assert_eq!(report.packets, 3);
+        assert!(report.is_ok());
+
+        // 末尾写到一半的块
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        io::Write::write_all(&mut file, &[0; 10]).unwrap();
+        drop(file);
+        let report = verify(&path).unwrap();
+        assert_eq!(report.packets, 3);
+        assert_eq!(report.valid_len, report.file_len - 10);
+        assert_eq!(
+            report.issues,
+            [VerificationIssue::Truncated {
+                offset: report.valid_len
+            }]
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_time_shift() {
+        let dir = test_dir("time_shift");
+        let capturer = PcapCapturer::new(PcapCaptureOptions {
+            packet_source: PacketSource::UserProvided,
+            file_path: dir.join("original").display().to_string(),
+            file_format: FileFormat::Pcap,
+            metadata_sidecar: false,
+            packet_limit: Some(3),
+            ..Default::default()
+        });
+        let sender = capturer.get_packet_sender().unwrap();
+        for millis in [1_700_000_000_000, 1_700_000_000_250, 1_700_000_001_000] {
+            sender
+                .send(UserPacket {
+                    data: vec![0; 60],
+                    timestamp: Some(Duration::from_millis(millis)),
+                })
+                .unwrap();
+        }
+        capturer.capture().unwrap();
+        let original = fs::read_dir(dir.join("original"))
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+
+        // 从文件重新保存，第一个数据包从t=0开始，间隔不变
+        let resave = |shift: TimeShift, name: &str| {
+            let output = dir.join(name);
+            let capturer = PcapCapturer::new(PcapCaptureOptions {
+                packet_source: PacketSource::File(original.display().to_string()),
+                file_path: output.display().to_string(),
+                file_format: FileFormat::Pcap,
+                metadata_sidecar: false,
+                time_shift: Some(shift),
+                ..Default::default()
+            });
+            capturer.capture().unwrap();
+            let path = fs::read_dir(&output)
+                .unwrap()
+                .next()
+                .unwrap()
+                .unwrap()
+                .path();
+            let mut reader = CaptureReader::open(&path).unwrap();
+            std::iter::from_fn(|| reader.read_packet())
+                .map(|packet| packet.unwrap().timestamp)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            resave(TimeShift::StartAt(Duration::ZERO), "zero"),
+            [
+                Duration::ZERO,
+                Duration::from_millis(250),
+                Duration::from_millis(1_000)
+            ]
+        );
+        assert_eq!(
+            resave(
+                TimeShift::Backward(Duration::from_secs(1_700_000_000)),
+                "back"
+            ),
+            [
+                Duration::ZERO,
+                Duration::from_millis(250),
+                Duration::from_millis(1_000)
+            ]
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_redaction() {
+        use std::net::Ipv4Addr;
+
+        let dir = test_dir("redaction");
+        let capturer = user_capturer(
+            &dir,
+            PcapCaptureOptions {
+                file_format: FileFormat::Pcap,
+                packet_limit: Some(2),
+                redaction: Some(RedactionOptions::new(vec![
+                    RedactionPattern::CardNumber,
+                    RedactionPattern::TokenAfter(b"Bearer ".to_vec()),
+                    RedactionPattern::Bytes(vec![10, 0, 0, 1]),
+                ])),
+                ..Default::default()
+            },
+        );
+        let sender = capturer.get_packet_sender().unwrap();
+        let payloads: [&[u8]; 2] = [
+            b"card=4111 1111 1111 1111&order=1234567890123&ip=\x0a\x00\x00\x01",
+            b"Authorization: Bearer abc.DEF-123\r\n",
+        ];
+        for payload in payloads {
+            let packet = EthernetFrame::new()
+                .ipv4(Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2))
+                .tcp(40000, 80)
+                .payload(payload)
+                .build()
+                .unwrap();
+            sender.send(packet).unwrap();
+        }
+        capturer.capture().unwrap();
+
+        let path = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.extension().is_some_and(|ext| ext == "pcap"))
+            .unwrap();
+        let mut reader = CaptureReader::open(&path).unwrap();
+        let packets: Vec<_> = std::iter::from_fn(|| reader.read_packet())
+            .map(|packet| packet.unwrap().data)
+            .collect();
+        // 载荷中的卡号、令牌和字节序列被等长遮盖，未通过Luhn校验的数字和IP头部中的地址保留
+        assert_eq!(
+            &packets[0][54..],
+            b"card=*******************&order=1234567890123&ip=****"
+        );
+        assert_eq!(&packets[0][26..30], &[10, 0, 0, 1]);
+        assert_eq!(&packets[1][54..], b"Authorization: Bearer ***********\r\n");
+        assert_eq!(capturer.handle().stats().redactions, 3);
+        let sidecar = fs::read_to_string(format!("{}.json", path.display())).unwrap();
+        assert!(sidecar.contains("\"redactions\": 3"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_heartbeat() {
+        let dir = test_dir("heartbeat");
+        let capturer = user_capturer(
+            &dir,
+            PcapCaptureOptions {
+                file_format: FileFormat::Pcap,
+                metadata_sidecar: false,
+                packet_limit: Some(2),
+                heartbeat_interval_seconds: Some(1),
+                timeout_ms: 100,
+                ..Default::default()
+            },
+        );
+        let sender = capturer.get_packet_sender().unwrap();
+        let handle = capturer.handle();
+        let producer = thread::spawn(move || {
+            let packet = || UserPacket {
+                data: vec![0; 60],
+                timestamp: None,
+            };
+            sender.send(packet()).unwrap();
+            // 静默期间每秒写入一个心跳
+            wait_for_stats(&handle, |stats| stats.heartbeats >= 2);
+            sender.send(packet()).unwrap();
+        });
+        capturer.capture().unwrap();
+        producer.join().unwrap();
+
+        let path = fs::read_dir(&dir).unwrap().next().unwrap().unwrap().path();
+        let mut reader = pcap_file::pcap::PcapReader::new(fs::File::open(&path).unwrap()).unwrap();
+        let mut lengths = Vec::new();
+        while let Some(packet) = reader.next_packet() {
+            lengths.push(packet.unwrap().orig_len);
+        }
+        // 读取数据包时跳过心跳
+        let mut reader = CaptureReader::open(&path).unwrap();
+        assert_eq!(std::iter::from_fn(|| reader.read_packet()).count(), 2);
+        assert_eq!(lengths.first(), Some(&60));
+        assert_eq!(lengths.last(), Some(&60));
+        let heartbeats = lengths.iter().filter(|&&len| len == 0).count() as u64;
+        let stats = capturer.handle().stats();
+        assert!(heartbeats >= 2, "{:?}", lengths);
+        assert_eq!(stats.heartbeats, heartbeats);
+        assert_eq!(stats.packets_written, 2);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_follow_tcp() {
+        use std::net::Ipv4Addr;
+
+        let dir = test_dir("follow_tcp");
+        let capturer = user_capturer(
+            &dir,
+            PcapCaptureOptions {
+                file_format: FileFormat::Pcap,
+                metadata_sidecar: false,
+                follow_tcp: Some(FollowTcp::default()),
+                ..Default::default()
+            },
+        );
+        let client = Ipv4Addr::new(10, 0, 0, 1);
+        let server = Ipv4Addr::new(10, 0, 0, 2);
+        let other = Ipv4Addr::new(10, 0, 0, 3);
+        let segment = |source, source_port, destination, destination_port, flags| {
+            EthernetFrame::new()
+                .ipv4(source, destination)
+                .tcp(source_port, destination_port)
+                .tcp_flags(flags, 1024)
+                .build()
+                .unwrap()
+        };
+        let packets = [
+            // 开始跟踪之前的数据包
+            EthernetFrame::new()
+                .ipv4(client, server)
+                .udp(5000, 53)
+                .build()
+                .unwrap(),
+            segment(other, 50000, server, 80, TcpFlags::ACK),
+            // 跟踪的连接，其间夹杂另一条连接的SYN
+            segment(client, 40000, server, 80, TcpFlags::SYN),
+            segment(other, 50001, server, 80, TcpFlags::SYN),
+            segment(server, 80, client, 40000, TcpFlags::SYN | TcpFlags::ACK),
+            segment(client, 40000, server, 80, TcpFlags::ACK | TcpFlags::PSH),
+            segment(client, 40000, server, 80, TcpFlags::FIN | TcpFlags::ACK),
+            segment(server, 80, client, 40000, TcpFlags::FIN | TcpFlags::ACK),
+            segment(client, 40000, server, 80, TcpFlags::ACK),
+            // 连接关闭后捕获已经结束
+            segment(client, 40000, server, 80, TcpFlags::ACK),
+        ];
+        let sender = capturer.get_packet_sender().unwrap();
+        for packet in packets {
+            sender.send(packet).unwrap();
+        }
+        capturer.capture().unwrap();
+
+        let path = fs::read_dir(&dir).unwrap().next().unwrap().unwrap().path();
+        let mut reader = CaptureReader::open(&path).unwrap();
+        let segments: Vec<_> = std::iter::from_fn(|| reader.read_packet())
+            .map(|packet| {
+                let data = packet.unwrap().data;
+                let transport = parse::transport(DataLink::ETHERNET, &data).unwrap();
+                (transport.source_port, transport.tcp_flags)
+            })
+            .collect();
+        assert_eq!(
+            segments,
+            [
+                (40000, 0x02),
+                (80, 0x12),
+                (40000, 0x18),
+                (40000, 0x11),
+                (80, 0x11),
+                (40000, 0x10)
+            ]
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let dir = test_dir("memory_limit");
+        // 每个数据包的缓冲区为100字节，排序缓冲区最多缓存两个
+        let capturer = user_capturer(
+            &dir,
+            PcapCaptureOptions {
+                file_format: FileFormat::Pcap,
+                metadata_sidecar: false,
+                timestamp_policy: TimestampPolicy::Reorder {
+                    window: Duration::from_secs(60),
+                    max_packets: 1000,
+                },
+                memory_limit_bytes: Some(250),
+                ..Default::default()
+            },
+        );
+        let packets = [5, 4, 3, 2, 1]
+            .into_iter()
+            .map(|seconds| {
+                let mut data = Vec::with_capacity(100);
+                data.resize(60, 0);
+                UserPacket {
+                    data,
+                    timestamp: Some(Duration::from_secs(seconds)),
+                }
+            })
+            .collect();
+        capture_packets(&capturer, packets);
+
+        // 超过上限时提前写出最早的数据包，之后更早的数据包按Clamp处理
+        let path = fs::read_dir(&dir).unwrap().next().unwrap().unwrap().path();
+        let mut reader = CaptureReader::open(&path).unwrap();
+        let timestamps: Vec<_> = std::iter::from_fn(|| reader.read_packet())
+            .map(|packet| packet.unwrap().timestamp.as_secs())
+            .collect();
+        assert_eq!(timestamps, [3, 3, 3, 4, 5]);
+        let stats = capturer.handle().stats();
+        assert_eq!(stats.late_packets, 2);
+        assert_eq!(stats.memory_peak, 300);
+        assert_eq!(stats.memory_used, 0);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_staging_dir() {
+        let dir = test_dir("staging");
+        let (staging, share) = (dir.join("staging"), dir.join("share"));
+        let capturer = user_capturer(
+            &share,
+            PcapCaptureOptions {
+                staging_dir: Some(staging.display().to_string()),
+                share_retry: Some(ShareRetry::default()),
+                packet_limit: Some(3),
+                continuous_capture: true,
+                rollover_packet_count: Some(1),
+                file_sequence: Some(1),
+                part_files: true,
+                ..Default::default()
+            },
+        );
+        let packets = (0..3)
+            .map(|_| UserPacket {
+                data: vec![0; 60],
+                timestamp: None,
+            })
+            .collect();
+        capture_packets(&capturer, packets);
+
+        // 写完的文件连同元数据文件都在输出目录中，暂存目录为空
+        let mut names: Vec<_> = fs::read_dir(&share)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names.len(), 6, "{:?}", names);
+        for name in names.iter().filter(|name| name.ends_with(".pcap")) {
+            assert!(names.contains(&format!("{}.json", name)), "{:?}", names);
+            let mut reader = CaptureReader::open(&share.join(name)).unwrap();
+            assert_eq!(std::iter::from_fn(|| reader.read_packet()).count(), 1);
+        }
+        assert_eq!(fs::read_dir(&staging).unwrap().count(), 0);
+
+        assert!(share::is_transient(&io::Error::from(
+            io::ErrorKind::StaleNetworkFileHandle
+        )));
+        assert!(!share::is_transient(&io::Error::from(
+            io::ErrorKind::PermissionDenied
+        )));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_memory_fallback() {
+        #[derive(Default)]
+        struct Recorder(std::sync::Mutex<Vec<String>>);
+
+        impl Observer for Recorder {
+            fn on_output_unavailable(&self, _error: &str) {
+                self.0.lock().unwrap().push("unavailable".to_string());
+            }
+            fn on_output_restored(&self, backlog: u64) {
+                self.0.lock().unwrap().push(format!("restored {}", backlog));
+            }
+        }
+
+        let dir = test_dir("fallback");
+        let capturer = user_capturer(
+            &dir,
+            PcapCaptureOptions {
+                metadata_sidecar: false,
+                timeout_ms: 10,
+                continuous_capture: true,
+                rollover_packet_count: Some(1),
+                memory_fallback_bytes: Some(1 << 20),
+                ..Default::default()
+            },
+        );
+        let recorder = Arc::new(Recorder::default());
+        capturer.add_observer(recorder.clone());
+        let sender = capturer.get_packet_sender_with_id("test").unwrap();
+        let handle = capturer.handle();
+        let output = dir.clone();
+        let producer = thread::spawn(move || {
+            let send = |seconds| {
+                sender
+                    .send(UserPacket {
+                        data: vec![0; 60],
+                        timestamp: Some(Duration::from_secs(seconds)),
+                    })
+                    .unwrap();
+            };
+            send(1);
+            // 写满一个数据包后在空闲时打开下一个文件
+            wait_for_stats(&handle, |stats| stats.rotations == 1);
+            // 目录消失后，写入已删除的文件仍会成功，创建下一个文件时才发现输出位置不可用
+            fs::remove_dir_all(&output).unwrap();
+            send(2);
+            send(3);
+            wait_for_stats(&handle, |stats| stats.fallback_buffered == 1);
+            fs::create_dir_all(&output).unwrap();
+            wait_for_stats(&handle, |stats| stats.fallback_buffered == 0);
+            send(4);
+            sender.flush().unwrap();
+            handle.stop();
+        });
+        capturer.capture().unwrap();
+        producer.join().unwrap();
+
+        // 恢复后先写出缓存的数据包，再写入之后的数据包
+        let mut timestamps = Vec::new();
+        for entry in fs::read_dir(&dir).unwrap() {
+            let mut reader = CaptureReader::open(&entry.unwrap().path()).unwrap();
+            timestamps.extend(
+                std::iter::from_fn(|| reader.read_packet())
+                    .map(|packet| packet.unwrap().timestamp.as_secs()),
+            );
+        }
+        timestamps.sort();
+        assert_eq!(timestamps, [3, 4]);
+        assert_eq!(*recorder.0.lock().unwrap(), ["unavailable", "restored 1"]);
+        assert_eq!(capturer.handle().stats().fallback_buffered, 0);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_session_labels() {
+        let dir = test_dir("labels");
+        let state_path = dir.join("sessions.state");
+        fs::create_dir_all(&dir).unwrap();
+        let options = PcapCaptureOptions {
+            packet_source: PacketSource::UserProvided,
+            file_path: dir.join("a").display().to_string(),
+            packet_limit: Some(1),
+            labels: vec![
+                ("ticket".to_string(), "INC-1234".to_string()),
+                ("customer".to_string(), "acme corp".to_string()),
+                ("ticket".to_string(), "INC-5678".to_string()),
+            ],
+            labels_in_file_name: true,
+            ..Default::default()
+        };
+        // 标签随会话配置保存，值中可以有`=`
+        let mut saved = options.clone();
+        saved.labels.push(("query".to_string(), "a=b".to_string()));
+        let config = state_file::encode_options(&saved);
+        state_file::write(&state_path, &[("a", &config, None)]).unwrap();
+        let restored = state_file::read(&state_path).unwrap();
+        assert_eq!(restored[0].1.labels, saved.labels);
+
+        let mut manager = CaptureManager::with_state_file(&state_path);
+        manager.start("a", options).unwrap();
+        manager
+            .packet_sender("a")
+            .unwrap()
+            .send(UserPacket {
+                data: vec![0; 60],
+                timestamp: None,
+            })
+            .unwrap();
+        manager.stop("a").unwrap();
+        let found = manager.find_by_label("customer", Some("acme corp"));
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].labels.len(), 2);
+        assert!(manager.find_by_label("ticket", Some("INC-1")).is_empty());
+        assert_eq!(manager.find_by_label("ticket", None).len(), 1);
+
+        // 同名的标签只保留最后一个
+        let path = fs::read_dir(dir.join("a"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.extension().is_some_and(|ext| ext == "pcap"))
+            .unwrap();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        assert!(name.starts_with("capture_INC-5678_acme_corp_"), "{}", name);
+        let sidecar = fs::read_to_string(metadata::sidecar_path(&path)).unwrap();
+        assert!(
+            sidecar.contains(r#""labels": {"ticket": "INC-5678", "customer": "acme corp"}"#),
+            "{}",
+            sidecar
+        );
+        drop(manager);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_output_layout() {
+        let dir = test_dir("layout");
+        let (staging, share) = (dir.join("staging"), dir.join("share"));
+        let layout = OutputLayout {
+            template: "{tenant}/{date}/{label:site}/{session}".to_string(),
+            ..OutputLayout::new("../team a", "uplink")
+        };
+        let capturer = PcapCapturer::new(PcapCaptureOptions {
+            packet_source: PacketSource::UserProvided,
+            file_path: share.display().to_string(),
+            staging_dir: Some(staging.display().to_string()),
+            output_layout: Some(layout.clone()),
+            labels: vec![("site".to_string(), "ams".to_string())],
+            packet_limit: Some(2),
+            continuous_capture: true,
+            rollover_packet_count: Some(1),
+            ..Default::default()
+        });
+        let sender = capturer.get_packet_sender().unwrap();
+        for _ in 0..2 {
+            sender
+                .send(UserPacket {
+                    data: vec![0; 60],
+                    timestamp: None,
+                })
+                .unwrap();
+        }
+        capturer.capture().unwrap();
+
+        // 租户名中的`/`和`..`不会跳出输出目录，文件从暂存目录移动到同样的子目录中
+        let tenant = share.join("team_a");
+        let date = fs::read_dir(&tenant)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let leaf = date.join("ams").join("uplink");
+        let files: Vec<PathBuf> = fs::read_dir(&leaf)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "pcap"))
+            .collect();
+        assert_eq!(files.len(), 2, "{:?}", files);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+            assert_eq!(mode(&tenant), 0o750);
+            assert_eq!(mode(&leaf), 0o750);
+            assert_eq!(mode(&files[0]), 0o640);
+        }
+        // 提取时按布局查找子目录
+        let in_range = |layout: Option<&OutputLayout>| {
+            extract::files_in_range(&share, layout, Path::new(""), Duration::ZERO, Duration::MAX)
+                .unwrap()
+                .len()
+        };
+        assert_eq!(in_range(Some(&layout)), 2);
+        assert_eq!(in_range(None), 0);
+
+        assert!(
+            OutputLayout {
+                template: "{team}".to_string(),
+                ..layout
+            }
+            .render(&[], Local::now())
+            .is_err()
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_disk_full_with_staging() {
+        let dir = test_dir("full");
+        let (staging, share) = (dir.join("staging"), dir.join("share"));
+        fs::create_dir_all(&staging).unwrap();
+        fs::create_dir_all(&share).unwrap();
+        // 输出目录中的文件比暂存目录中的更旧
+        for name in [
+            "capture_20240101_000000.pcap",
+            "capture_20240101_000100.pcap",
+        ] {
+            fs::write(share.join(name), b"archived").unwrap();
+        }
+        let current = staging.join("capture_20240101_000300.pcap");
+        fs::write(&current, b"current").unwrap();
+        let options = PcapCaptureOptions {
+            file_path: share.display().to_string(),
+            staging_dir: Some(staging.display().to_string()),
+            disk_full_policy: DiskFullPolicy::DeleteOldest,
+            ..Default::default()
+        };
+
+        // 暂存目录中只有当前文件时没有可以删除的文件，输出目录中的文件保留
+        assert_eq!(
+            writer::oldest_capture_file(&options, &current).unwrap(),
+            None
+        );
+        let staged = staging.join("capture_20240101_000200.pcap");
+        fs::write(&staged, b"staged").unwrap();
+        assert_eq!(
+            writer::oldest_capture_file(&options, &current).unwrap(),
+            Some(staged)
+        );
+        assert_eq!(fs::read_dir(&share).unwrap().count(), 2);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_replay_skips_heartbeats() {
+        let dir = test_dir("hb_replay");
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("capture.pcap");
+        // 数据包之间夹着与`heartbeat_interval_seconds`写入的相同的零长度心跳记录
+        let mut writer = pcap_file::pcap::PcapWriter::with_header(
+            fs::File::create(&input).unwrap(),
+            pcap_file::pcap::PcapHeader::default(),
+        )
+        .unwrap();
+        for (i, len) in [(0u8, 60), (0, 0), (1, 60), (0, 0)] {
+            writer
+                .write_packet(&pcap_file::pcap::PcapPacket {
+                    timestamp: Duration::from_secs(1 + i as u64),
+                    orig_len: len as u32,
+                    data: vec![i; len].into(),
+                })
+                .unwrap();
+        }
+        drop(writer);
+
+        let mut sent = Vec::new();
+        let options = ReplayOptions {
+            timing: ReplayTiming::AsFastAsPossible,
+            ..Default::default()
+        };
+        let report = replay_with(&input, &options, |data| {
+            sent.push(data[0]);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(sent, [0, 1]);
+        assert_eq!(report.packets, 2);
+        assert_eq!(report.bytes, 120);
+        let _ = fs::remove_dir_all(&dir);
+    }
 }
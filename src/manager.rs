@@ -0,0 +1,311 @@
+use crate::state_file;
+use crate::{
+    CaptureHandle, CaptureStats, PcapCaptureOptions, PcapCapturer, SavePcapError, SessionState,
+    UserPacket,
+};
+use log::{error, info, warn};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// `CaptureManager::list()`返回的会话信息
+#[derive(Debug, Clone)]
+pub struct SessionInfo {
+    pub name: String,
+    /// 数据来源的描述，例如`NetworkDevice("eth0")`
+    pub source: String,
+    /// 会话的`labels`
+    pub labels: Vec<(String, String)>,
+    pub state: SessionState,
+    /// 捕获出错结束时的错误信息
+    pub error: Option<String>,
+    pub stats: CaptureStats,
+}
+
+struct Session {
+    source: String,
+    labels: Vec<(String, String)>,
+    // 写入状态文件的配置
+    config: String,
+    first_sequence: Option<u64>,
+    // 重启后是否恢复：通过stop()停止或自行正常结束的会话不恢复
+    resume: bool,
+    handle: CaptureHandle,
+    packet_sender: Option<Sender<UserPacket>>,
+    thread: Option<JoinHandle<Result<(), SavePcapError>>>,
+    // 捕获线程出错退出时写入，不需要join就能在list()中看到
+    error: Arc<Mutex<Option<String>>>,
+}
+
+impl Session {
+    fn is_running(&self) -> bool {
+        self.thread
+            .as_ref()
+            .is_some_and(|thread| !thread.is_finished())
+    }
+
+    fn info(&self, name: &str) -> SessionInfo {
+        SessionInfo {
+            name: name.to_string(),
+            source: self.source.clone(),
+            labels: self.labels.clone(),
+            state: self.handle.state(),
+            error: self.error.lock().ok().and_then(|error| error.clone()),
+            stats: self.handle.stats(),
+        }
+    }
+
+    // 下一个文件的序号，会话还没有打开文件时为起始序号
+    fn next_sequence(&self) -> Option<u64> {
+        self.handle
+            .live_stats()
+            .current_file_sequence
+            .map(|sequence| sequence + 1)
+            .or(self.first_sequence)
+    }
+
+    // 请求停止并等待捕获线程关闭当前文件
+    fn stop(&mut self) -> Result<(), SavePcapError> {
+        self.handle.stop();
+        match self.thread.take() {
+            Some(thread) => thread
+                .join()
+                .unwrap_or(Err(SavePcapError::CaptureInterrupted)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// 管理多个命名的捕获会话（不同的设备、过滤条件和输出目录），每个会话在独立线程中运行。
+/// 管理器被丢弃时停止所有会话
+#[derive(Default)]
+pub struct CaptureManager {
+    sessions: BTreeMap<String, Session>,
+    state_file: Option<PathBuf>,
+}
+
+impl CaptureManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 每次启动、停止或移除会话时把会话配置和文件序号写入状态文件，不读取已有的内容
+    pub fn with_state_file(path: impl Into<PathBuf>) -> Self {
+        Self {
+            sessions: BTreeMap::new(),
+            state_file: Some(path.into()),
+        }
+    }
+
+    /// 按状态文件重新启动上次的会话（例如进程崩溃或重启后），并继续写入该状态文件。
+    /// 开启了`file_sequence`的会话从保存的序号和输出目录中已有文件的下一个序号中较大的继续编号；
+    /// 状态文件不存在时返回没有会话的管理器
+    pub fn restore(path: impl Into<PathBuf>) -> Result<Self, SavePcapError> {
+        let path = path.into();
+        // 全部会话启动后才写状态文件，中途失败时保留原来的内容
+        let mut manager = Self::new();
+        for (name, mut options) in state_file::read(&path)? {
+            if let Some(saved) = options.file_sequence {
+                let existing = state_file::next_sequence_in_dir(&options).unwrap_or(saved);
+                options.file_sequence = Some(saved.max(existing));
+            }
+            info!("Restoring capture session {}", name);
+            manager.start(name, options)?;
+        }
+        manager.state_file = Some(path);
+        manager.persist();
+        Ok(manager)
+    }
+
+    /// 在新线程中开始捕获。同名会话仍在运行时返回`AlreadyRunning`，已结束的同名会话会被替换
+    pub fn start(
+        &mut self,
+        name: impl Into<String>,
+        options: PcapCaptureOptions,
+    ) -> Result<CaptureHandle, SavePcapError> {
+        let name = name.into();
+        if let Some(session) = self.sessions.get_mut(&name) {
+            if session.is_running() {
+                return Err(SavePcapError::AlreadyRunning(format!(
+                    "capture session {}",
+                    name
+                )));
+            }
+            // 回收已结束的线程
+            let _ = session.stop();
+        }
+
+        let source = format!("{:?}", options.packet_source);
+        let config = state_file::encode_options(&options);
+        let first_sequence = options.file_sequence;
+        let capturer = PcapCapturer::new(options);
+        // 标签在创建捕获时去重
+        let labels = capturer.options.labels.clone();
+        let handle = capturer.handle();
+        let packet_sender = capturer.get_packet_sender();
+        let error = Arc::new(Mutex::new(None));
+
+        let thread_error = error.clone();
+        let thread_name = name.clone();
+        let thread = thread::Builder::new()
+            .name(format!("save_pcap-{}", name))
+            .spawn(move || {
+                let result = capturer.capture();
+                if let Err(e) = &result {
+                    error!("Capture session {} failed: {}", thread_name, e);
+                    if let Ok(mut error) = thread_error.lock() {
+                        *error = Some(e.to_string());
+                    }
+                }
+                result
+            })?;
+        info!("Started capture session {} ({})", name, source);
+
+        self.sessions.insert(
+            name,
+            Session {
+                source,
+                labels,
+                config,
+                first_sequence,
+                resume: true,
+                handle: handle.clone(),
+                packet_sender,
+                thread: Some(thread),
+                error,
+            },
+        );
+        self.persist();
+        Ok(handle)
+    }
+
+    /// 停止会话并等待当前文件关闭，返回该会话的捕获结果。会话信息保留在`list()`中
+    pub fn stop(&mut self, name: &str) -> Result<(), SavePcapError> {
+        let session = self
+            .sessions
+            .get_mut(name)
+            .ok_or_else(|| SavePcapError::SessionNotFound(name.to_string()))?;
+        info!("Stopping capture session {}", name);
+        session.resume = false;
+        let result = session.stop();
+        self.persist();
+        result
+    }
+
+    /// 停止（如果仍在运行）并移除会话
+    pub fn remove(&mut self, name: &str) -> Result<(), SavePcapError> {
+        let mut session = self
+            .sessions
+            .remove(name)
+            .ok_or_else(|| SavePcapError::SessionNotFound(name.to_string()))?;
+        let result = session.stop();
+        self.persist();
+        result
+    }
+
+    /// 停止所有会话，单个会话的错误只记录日志。与`stop()`不同，这些会话仍保留在状态文件中，
+    /// 下次`restore()`时恢复
+    pub fn stop_all(&mut self) {
+        self.mark_finished();
+        for (name, session) in &mut self.sessions {
+            if let Err(e) = session.stop() {
+                error!("Capture session {} ended with error: {}", name, e);
+            }
+        }
+        self.persist();
+    }
+
+    /// 立即写入状态文件，例如在定时任务中调用以保存最新的文件序号；未设置状态文件时不做任何事
+    pub fn save_state(&mut self) -> Result<(), SavePcapError> {
+        self.mark_finished();
+        self.write_state()
+    }
+
+    fn write_state(&self) -> Result<(), SavePcapError> {
+        let Some(path) = &self.state_file else {
+            return Ok(());
+        };
+        let sequences: Vec<_> = self
+            .sessions
+            .values()
+            .map(|session| session.next_sequence())
+            .collect();
+        let sessions: Vec<_> = self
+            .sessions
+            .iter()
+            .zip(sequences)
+            .filter(|((_, session), _)| session.resume)
+            .map(|((name, session), sequence)| (name.as_str(), session.config.as_str(), sequence))
+            .collect();
+        state_file::write(path, &sessions)?;
+        Ok(())
+    }
+
+    /// 状态文件的路径
+    pub fn state_file(&self) -> Option<&Path> {
+        self.state_file.as_deref()
+    }
+
+    // 已自行正常结束（数据包上限、读完文件）的会话重启后不再恢复；出错结束的会话仍会恢复
+    fn mark_finished(&mut self) {
+        for session in self.sessions.values_mut() {
+            if session.handle.state() == SessionState::Stopped {
+                session.resume = false;
+            }
+        }
+    }
+
+    // 启动和停止会话时保存状态，失败只记录日志
+    fn persist(&self) {
+        if let Err(e) = self.write_state() {
+            warn!("Failed to write session state file: {}", e);
+        }
+    }
+
+    /// 按名称排序的所有会话
+    pub fn list(&self) -> Vec<SessionInfo> {
+        self.sessions
+            .iter()
+            .map(|(name, session)| session.info(name))
+            .collect()
+    }
+
+    /// 带有该标签的会话，value为None时只要求有该标签，按名称排序
+    pub fn find_by_label(&self, key: &str, value: Option<&str>) -> Vec<SessionInfo> {
+        self.sessions
+            .iter()
+            .filter(|(_, session)| {
+                session.labels.iter().any(|(label, label_value)| {
+                    label == key && value.is_none_or(|value| value == label_value)
+                })
+            })
+            .map(|(name, session)| session.info(name))
+            .collect()
+    }
+
+    pub fn info(&self, name: &str) -> Option<SessionInfo> {
+        self.sessions.get(name).map(|session| session.info(name))
+    }
+
+    /// 会话的控制句柄，用于暂停、恢复或获取统计
+    pub fn handle(&self, name: &str) -> Option<CaptureHandle> {
+        self.sessions
+            .get(name)
+            .map(|session| session.handle.clone())
+    }
+
+    /// `PacketSource::UserProvided`会话的数据包发送端
+    pub fn packet_sender(&self, name: &str) -> Option<Sender<UserPacket>> {
+        self.sessions
+            .get(name)
+            .and_then(|session| session.packet_sender.clone())
+    }
+}
+
+impl Drop for CaptureManager {
+    fn drop(&mut self) {
+        self.stop_all();
+    }
+}
@@ -0,0 +1,111 @@
+use crate::SavePcapError;
+use crate::reader::{CaptureReader, pcap_file_error};
+use crate::source::SourcePacket;
+use log::info;
+use pcap_file::pcap::{PcapHeader, PcapPacket, PcapWriter};
+use pcap_file::{DataLink, Endianness};
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// 按时间戳将多个捕获文件（pcap或pcapng）合并为一个pcap文件，返回写入的数据包数量。
+///
+/// 适用于将各个工作线程分别写入的文件（`_w0`、`_w1`……）重新合并。
+/// 每个输入文件内部需要按时间排序，所有输入文件的链路层类型必须一致。
+pub fn merge_capture_files<P: AsRef<Path>, Q: AsRef<Path>>(
+    inputs: &[P],
+    output: Q,
+) -> Result<usize, SavePcapError> {
+    let readers = inputs
+        .iter()
+        .map(|path| CaptureReader::open(path.as_ref()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let datalink = match readers.first() {
+        Some(reader) => reader.datalink(),
+        None => {
+            return Err(SavePcapError::PcapFileError(
+                "No input files to merge".to_string(),
+            ));
+        }
+    };
+    if let Some((index, _)) = readers
+        .iter()
+        .enumerate()
+        .find(|(_, reader)| reader.datalink() != datalink)
+    {
+        return Err(SavePcapError::PcapFileError(format!(
+            "Link type of {} differs from the first input",
+            inputs[index].as_ref().display()
+        )));
+    }
+
+    let packets = readers
+        .into_iter()
+        .map(|mut reader| {
+            Box::new(std::iter::from_fn(move || reader.read_packet())) as PacketIter<'static>
+        })
+        .collect();
+    let packet_count = write_merged(packets, datalink, output.as_ref())?;
+    info!(
+        "Merged {} packets from {} files into {}",
+        packet_count,
+        inputs.len(),
+        output.as_ref().display()
+    );
+
+    Ok(packet_count)
+}
+
+pub(crate) type PacketIter<'a> = Box<dyn Iterator<Item = Result<SourcePacket, SavePcapError>> + 'a>;
+
+/// 按时间戳合并多个数据包序列并写入一个pcap文件，返回写入的数据包数量
+pub(crate) fn write_merged(
+    mut inputs: Vec<PacketIter<'_>>,
+    datalink: DataLink,
+    output: &Path,
+) -> Result<usize, SavePcapError> {
+    let header = PcapHeader {
+        datalink,
+        endianness: Endianness::native(),
+        ..Default::default()
+    };
+    let mut writer = PcapWriter::with_header(BufWriter::new(File::create(output)?), header)
+        .map_err(pcap_file_error)?;
+
+    // 每个输入只在堆中保留一个待写入的数据包，时间相同时按输入顺序
+    let mut pending: Vec<Option<SourcePacket>> = Vec::with_capacity(inputs.len());
+    let mut heap = BinaryHeap::new();
+    for (index, input) in inputs.iter_mut().enumerate() {
+        let packet = input.next().transpose()?;
+        if let Some(packet) = &packet {
+            heap.push(Reverse((packet.timestamp, index)));
+        }
+        pending.push(packet);
+    }
+
+    let mut packet_count = 0;
+    while let Some(Reverse((_, index))) = heap.pop() {
+        if let Some(packet) = pending[index].take() {
+            writer
+                .write_packet(&PcapPacket {
+                    timestamp: packet.timestamp,
+                    orig_len: packet.orig_len,
+                    data: Cow::Owned(packet.data),
+                })
+                .map_err(pcap_file_error)?;
+            packet_count += 1;
+        }
+
+        if let Some(packet) = inputs[index].next().transpose()? {
+            heap.push(Reverse((packet.timestamp, index)));
+            pending[index] = Some(packet);
+        }
+    }
+
+    writer.flush().map_err(pcap_file_error)?;
+    Ok(packet_count)
+}
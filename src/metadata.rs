@@ -0,0 +1,246 @@
+use crate::{ClosedFile, PacketSource, PcapCaptureOptions};
+use chrono::{DateTime, Local, SecondsFormat};
+use log::warn;
+use std::borrow::Cow;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+// pcapng if_filter选项的第一个字节表示过滤器类型，0为libpcap过滤表达式
+const IF_FILTER_LIBPCAP: u8 = 0;
+
+// 滚动生成的文件经常被单独拷走，脱离了捕获时的上下文，因此在每个文件中记录
+// 捕获主机、操作系统、接口、过滤表达式和写入程序的版本
+pub(crate) struct FileMetadata {
+    pub hostname: Option<String>,
+    pub os: String,
+    pub interface: Option<String>,
+    pub filter: Option<String>,
+    pub application: String,
+    pub labels: Vec<(String, String)>,
+}
+
+impl FileMetadata {
+    pub fn collect(options: &PcapCaptureOptions) -> Self {
+        Self {
+            hostname: hostname(),
+            os: os_description(),
+            interface: interface_name(&options.packet_source),
+            filter: options.filter.clone(),
+            application: format!("save_pcap {}", env!("CARGO_PKG_VERSION")),
+            labels: options.labels.clone(),
+        }
+    }
+
+    /// 每个标签一条节头块注释，例如`label: ticket=INC-1234`
+    pub fn label_comments(&self) -> impl Iterator<Item = String> + '_ {
+        self.labels
+            .iter()
+            .map(|(key, value)| format!("label: {}={}", key, value))
+    }
+
+    pub fn hostname_comment(&self) -> Option<String> {
+        self.hostname
+            .as_ref()
+            .map(|hostname| format!("Captured on {}", hostname))
+    }
+
+    /// pcapng if_filter选项的内容：类型字节加过滤表达式
+    pub fn if_filter(&self) -> Option<Cow<'static, [u8]>> {
+        self.filter.as_ref().map(|filter| {
+            let mut data = Vec::with_capacity(filter.len() + 1);
+            data.push(IF_FILTER_LIBPCAP);
+            data.extend_from_slice(filter.as_bytes());
+            Cow::Owned(data)
+        })
+    }
+
+    /// pcap文件头中没有可扩展的字段，改为在旁边写一个同名的`.json`文件
+    /// 创建文件时首尾数据包时间和统计未知，文件关闭后带上这些信息重新写入
+    pub fn write_sidecar(
+        &self,
+        capture_path: &Path,
+        closed: Option<&ClosedFile>,
+    ) -> io::Result<()> {
+        let file_name = capture_path
+            .file_name()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_default();
+        let time = |time: Option<Duration>| time.map(|time| json_string(&rfc3339(time)));
+        let pcap_stats = closed.and_then(|closed| closed.pcap_stats);
+
+        let mut json = String::from("{\n");
+        let fields = [
+            ("file", Some(json_string(&file_name))),
+            ("hostname", self.hostname.as_deref().map(json_string)),
+            ("os", Some(json_string(&self.os))),
+            ("interface", self.interface.as_deref().map(json_string)),
+            ("filter", self.filter.as_deref().map(json_string)),
+            ("application", Some(json_string(&self.application))),
+            ("labels", Some(labels_json(&self.labels))),
+            (
+                "first_packet",
+                time(closed.and_then(|closed| closed.first_packet)),
+            ),
+            (
+                "last_packet",
+                time(closed.and_then(|closed| closed.last_packet)),
+            ),
+            ("packets", closed.map(|closed| closed.packets.to_string())),
+            (
+                "pcap_received",
+                pcap_stats.map(|stats| stats.received.to_string()),
+            ),
+            (
+                "pcap_dropped",
+                pcap_stats.map(|stats| stats.dropped.to_string()),
+            ),
+            (
+                "pcap_if_dropped",
+                pcap_stats.map(|stats| stats.if_dropped.to_string()),
+            ),
+            (
+                "redactions",
+                closed.map(|closed| closed.redactions.to_string()),
+            ),
+        ];
+        for (index, (key, value)) in fields.iter().enumerate() {
+            let separator = if index + 1 < fields.len() { "," } else { "" };
+            let value = value.as_deref().unwrap_or("null");
+            let _ = writeln!(json, "  \"{}\": {}{}", key, value, separator);
+        }
+        json.push_str("}\n");
+
+        fs::write(sidecar_path(capture_path), json)
+    }
+}
+
+const METADATA_SUFFIX: &str = ".json";
+pub(crate) const GEOIP_SUFFIX: &str = ".geoip.jsonl";
+pub(crate) const INDEX_SUFFIX: &str = ".index.jsonl";
+pub(crate) const DNS_SUFFIX: &str = ".dns.jsonl";
+pub(crate) const TALKERS_SUFFIX: &str = ".talkers.json";
+pub(crate) const PACKET_INDEX_SUFFIX: &str = ".pktidx";
+pub(crate) const ANNOTATIONS_SUFFIX: &str = ".annotations.jsonl";
+// 捕获文件可能带有的所有附加文件，删除或重命名捕获文件时一并清理
+const SIDECAR_SUFFIXES: [&str; 7] = [
+    METADATA_SUFFIX,
+    GEOIP_SUFFIX,
+    INDEX_SUFFIX,
+    DNS_SUFFIX,
+    TALKERS_SUFFIX,
+    PACKET_INDEX_SUFFIX,
+    ANNOTATIONS_SUFFIX,
+];
+
+/// 经典pcap文件对应的元数据文件路径
+pub(crate) fn sidecar_path(capture_path: &Path) -> PathBuf {
+    sidecar_path_with(capture_path, METADATA_SUFFIX)
+}
+
+/// 在捕获文件名后追加后缀得到附加文件的路径
+pub(crate) fn sidecar_path_with(capture_path: &Path, suffix: &str) -> PathBuf {
+    let mut path = capture_path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// 删除捕获文件对应的元数据文件和其他附加文件（如果存在）
+pub(crate) fn remove_sidecar(capture_path: &Path) {
+    for suffix in SIDECAR_SUFFIXES {
+        let path = sidecar_path_with(capture_path, suffix);
+        if let Err(e) = fs::remove_file(&path)
+            && e.kind() != io::ErrorKind::NotFound
+        {
+            warn!("Failed to remove {:?}: {}", path, e);
+        }
+    }
+}
+
+fn interface_name(source: &PacketSource) -> Option<String> {
+    match source {
+        PacketSource::NetworkDevice(name)
+        | PacketSource::BluetoothHci(name)
+        | PacketSource::CanInterface(name)
+        | PacketSource::SharedDevice(name) => Some(name.clone()).filter(|name| !name.is_empty()),
+        PacketSource::Nflog(group) => Some(format!("nflog:{}", group)),
+        PacketSource::Pktmon => Some("pktmon".to_string()),
+        PacketSource::UserProvided | PacketSource::File(_) | PacketSource::Stdin => None,
+    }
+}
+
+#[cfg(unix)]
+fn hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    Some(String::from_utf8_lossy(&buf[..len]).into_owned())
+}
+
+#[cfg(not(unix))]
+fn hostname() -> Option<String> {
+    std::env::var("COMPUTERNAME").ok()
+}
+
+#[cfg(unix)]
+fn os_description() -> String {
+    use std::ffi::CStr;
+
+    let mut uts: libc::utsname = unsafe { std::mem::zeroed() };
+    if unsafe { libc::uname(&mut uts) } != 0 {
+        return format!("{} {}", std::env::consts::OS, std::env::consts::ARCH);
+    }
+    let field = |value: &[libc::c_char]| {
+        unsafe { CStr::from_ptr(value.as_ptr()) }
+            .to_string_lossy()
+            .into_owned()
+    };
+    format!(
+        "{} {} {}",
+        field(&uts.sysname),
+        field(&uts.release),
+        field(&uts.machine)
+    )
+}
+
+#[cfg(not(unix))]
+fn os_description() -> String {
+    format!("{} {}", std::env::consts::OS, std::env::consts::ARCH)
+}
+
+pub(crate) fn rfc3339(timestamp: Duration) -> String {
+    DateTime::<Local>::from(UNIX_EPOCH + timestamp).to_rfc3339_opts(SecondsFormat::Micros, false)
+}
+
+/// 标签转换为JSON对象，例如`{"ticket": "INC-1234", "customer": "acme"}`
+pub(crate) fn labels_json(labels: &[(String, String)]) -> String {
+    let fields: Vec<_> = labels
+        .iter()
+        .map(|(key, value)| format!("{}: {}", json_string(key), json_string(value)))
+        .collect();
+    format!("{{{}}}", fields.join(", "))
+}
+
+pub(crate) fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
@@ -0,0 +1,76 @@
+use crate::{CaptureStats, PcapStats, WriteErrorPolicy};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// 滚动时结束的文件及其统计，上传或建立目录时不需要重新读取文件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClosedFile {
+    /// 文件的最终路径
+    pub path: PathBuf,
+    /// 第一个和最后一个数据包的时间戳（自UNIX纪元起），文件中没有数据包时为None
+    pub first_packet: Option<Duration>,
+    pub last_packet: Option<Duration>,
+    pub packets: u64,
+    /// 写入的字节数，包括文件头和每条记录的头部
+    pub bytes: u64,
+    /// 写入该文件期间内核缓冲区或网卡丢弃的数据包数（其他数据来源为0）
+    pub dropped: u64,
+    /// 写入该文件期间libpcap统计的增量，与`packets`对比可以核查过滤器的效果。
+    /// libpcap统计每秒读取一次，边界附近的数据包可能计入相邻的文件；其他数据来源为None
+    pub pcap_stats: Option<PcapStats>,
+    /// 写入该文件前遮盖的内容处数，见`PcapCaptureOptions::redaction`
+    pub redactions: u64,
+}
+
+/// 捕获活动的观察者，通过`PcapCapturer::add_observer`注册，用于审计日志等集成。
+/// 各方法可能在捕获线程、写入线程或调用`pause()`的线程中调用，应尽快返回
+pub trait Observer: Send + Sync {
+    /// 输出文件已创建，开始读取数据包
+    fn on_start(&self) {}
+    /// 文件滚动完成，closed为已关闭的文件，opened为新文件的路径（尚未完成）
+    fn on_rotate(&self, _closed: &ClosedFile, _opened: &Path) {}
+    fn on_pause(&self) {}
+    fn on_resume(&self) {}
+    /// 写入数据包失败，按policy（`Skip`或`Rotate`）处理后继续捕获；
+    /// `Abort`时捕获结束，通过`on_error`报告
+    fn on_write_error(&self, _error: &str, _policy: WriteErrorPolicy) {}
+    /// 输出位置不可用，之后的数据包缓存在内存中，见`PcapCaptureOptions::memory_fallback_bytes`
+    fn on_output_unavailable(&self, _error: &str) {}
+    /// 输出位置恢复可写，已在新文件中写出缓存的backlog个数据包
+    fn on_output_restored(&self, _backlog: u64) {}
+    /// 捕获因错误结束，之后还会调用`on_stop`
+    fn on_error(&self, _error: &str) {}
+    /// 捕获结束（无论是否出错），stats为最终统计
+    fn on_stop(&self, _stats: &CaptureStats) {}
+}
+
+// 会话状态持有的观察者列表，通知时复制列表，不在锁内调用观察者
+#[derive(Default)]
+pub(crate) struct Observers(Mutex<Vec<Arc<dyn Observer>>>);
+
+impl Observers {
+    pub fn add(&self, observer: Arc<dyn Observer>) {
+        if let Ok(mut observers) = self.0.lock() {
+            observers.push(observer);
+        }
+    }
+
+    pub fn notify(&self, event: impl Fn(&dyn Observer)) {
+        let observers = match self.0.lock() {
+            Ok(observers) => observers.clone(),
+            Err(_) => return,
+        };
+        for observer in observers {
+            event(observer.as_ref());
+        }
+    }
+}
+
+impl fmt::Debug for Observers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let count = self.0.lock().map(|observers| observers.len()).unwrap_or(0);
+        write!(f, "Observers({})", count)
+    }
+}
@@ -0,0 +1,229 @@
+use crate::WriteErrorPolicy;
+use crate::observer::{ClosedFile, Observer};
+use crate::stats::{CaptureStats, StatsCounters};
+use opentelemetry::global::{self, BoxedSpan, BoxedTracer};
+use opentelemetry::trace::{Span, Status, TraceContextExt, Tracer};
+use opentelemetry::{Context, KeyValue};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// 通过OpenTelemetry全局的MeterProvider和TracerProvider导出捕获指标和span
+/// （需要启用`otel` feature）。导出器由调用方配置，应在创建`PcapCapturer`之前设置全局Provider
+///
+/// 指标：`save_pcap.packets`、`save_pcap.bytes`、`save_pcap.dropped`（计数器）和
+/// `save_pcap.packets_per_second`（仪表）；span：整个会话一个`save_pcap.session`，
+/// 每个输出文件一个`save_pcap.file`子span
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OtelOptions {
+    /// 作为`save_pcap.session`属性附加到所有指标和span上
+    pub session_id: String,
+}
+
+impl OtelOptions {
+    pub fn new(session_id: impl Into<String>) -> Self {
+        Self {
+            session_id: session_id.into(),
+        }
+    }
+}
+
+const INSTRUMENTATION_NAME: &str = "save_pcap";
+
+// 作为观察者注册到会话状态；指标通过回调在导出时从捕获统计读取
+pub(crate) struct OtelExporter {
+    tracer: BoxedTracer,
+    session_attribute: KeyValue,
+    session: Mutex<Option<BoxedSpan>>,
+    file: Mutex<Option<BoxedSpan>>,
+    stats: Arc<StatsCounters>,
+}
+
+impl OtelExporter {
+    pub fn new(options: &OtelOptions, stats: Arc<StatsCounters>) -> Self {
+        let session_attribute = KeyValue::new("save_pcap.session", options.session_id.clone());
+        register_metrics(&session_attribute, &stats);
+        Self {
+            tracer: global::tracer(INSTRUMENTATION_NAME),
+            session_attribute,
+            session: Mutex::new(None),
+            file: Mutex::new(None),
+            stats,
+        }
+    }
+
+    // 在会话span下开始新文件的span
+    fn start_file(&self, path: &Path) {
+        let (Ok(session), Ok(mut file)) = (self.session.lock(), self.file.lock()) else {
+            return;
+        };
+        let parent = match session.as_ref() {
+            Some(span) => Context::new().with_remote_span_context(span.span_context().clone()),
+            None => Context::new(),
+        };
+        let span = self
+            .tracer
+            .span_builder("save_pcap.file")
+            .with_attributes([
+                self.session_attribute.clone(),
+                KeyValue::new("save_pcap.file", path.display().to_string()),
+            ])
+            .start_with_context(&self.tracer, &parent);
+        *file = Some(span);
+    }
+
+    // path为文件的最终路径（滚动时可能与打开时不同）
+    fn end_file(&self, closed: Option<&ClosedFile>) {
+        let Ok(mut file) = self.file.lock() else {
+            return;
+        };
+        if let Some(mut span) = file.take() {
+            if let Some(closed) = closed {
+                span.set_attributes([
+                    KeyValue::new("save_pcap.file", closed.path.display().to_string()),
+                    KeyValue::new("save_pcap.file.packets", closed.packets as i64),
+                    KeyValue::new("save_pcap.file.bytes", closed.bytes as i64),
+                    KeyValue::new("save_pcap.file.dropped", closed.dropped as i64),
+                ]);
+            }
+            span.end();
+        }
+    }
+
+    fn add_session_event(&self, name: &'static str) {
+        if let Ok(mut session) = self.session.lock()
+            && let Some(span) = session.as_mut()
+        {
+            span.add_event(name, Vec::new());
+        }
+    }
+}
+
+fn register_metrics(session: &KeyValue, stats: &Arc<StatsCounters>) {
+    let meter = global::meter(INSTRUMENTATION_NAME);
+    // 回调随Provider保留，捕获结束后仍报告最终值
+    let (attributes, counters) = (vec![session.clone()], stats.clone());
+    meter
+        .u64_observable_counter("save_pcap.packets")
+        .with_description("Packets written to capture files")
+        .with_unit("{packet}")
+        .with_callback(move |observer| {
+            observer.observe(counters.live_snapshot().packets_written, &attributes)
+        })
+        .build();
+    let (attributes, counters) = (vec![session.clone()], stats.clone());
+    meter
+        .u64_observable_counter("save_pcap.bytes")
+        .with_description("Bytes written to capture files")
+        .with_unit("By")
+        .with_callback(move |observer| {
+            observer.observe(counters.live_snapshot().bytes_written, &attributes)
+        })
+        .build();
+    let (attributes, counters) = (vec![session.clone()], stats.clone());
+    meter
+        .u64_observable_counter("save_pcap.dropped")
+        .with_description("Packets dropped by the kernel or capture driver")
+        .with_unit("{packet}")
+        .with_callback(move |observer| {
+            observer.observe(counters.live_snapshot().kernel_dropped, &attributes)
+        })
+        .build();
+    let (attributes, counters) = (vec![session.clone()], stats.clone());
+    meter
+        .f64_observable_gauge("save_pcap.packets_per_second")
+        .with_description("Packets written per second over the last stats interval")
+        .with_unit("{packet}/s")
+        .with_callback(move |observer| {
+            observer.observe(counters.live_snapshot().packets_per_second, &attributes)
+        })
+        .build();
+}
+
+impl Observer for OtelExporter {
+    fn on_start(&self) {
+        let span = self
+            .tracer
+            .span_builder("save_pcap.session")
+            .with_attributes([self.session_attribute.clone()])
+            .start(&self.tracer);
+        if let Ok(mut session) = self.session.lock() {
+            *session = Some(span);
+        }
+        if let Some(path) = self.stats.live_snapshot().current_file {
+            self.start_file(&path);
+        }
+    }
+
+    fn on_rotate(&self, closed: &ClosedFile, opened: &Path) {
+        self.end_file(Some(closed));
+        self.start_file(opened);
+    }
+
+    fn on_pause(&self) {
+        self.add_session_event("pause");
+    }
+
+    fn on_resume(&self) {
+        self.add_session_event("resume");
+    }
+
+    fn on_write_error(&self, error: &str, policy: WriteErrorPolicy) {
+        if let Ok(mut session) = self.session.lock()
+            && let Some(span) = session.as_mut()
+        {
+            span.add_event(
+                "write_error",
+                vec![
+                    KeyValue::new("error", error.to_string()),
+                    KeyValue::new("policy", format!("{:?}", policy)),
+                ],
+            );
+        }
+    }
+
+    fn on_output_unavailable(&self, error: &str) {
+        if let Ok(mut session) = self.session.lock()
+            && let Some(span) = session.as_mut()
+        {
+            span.add_event(
+                "output_unavailable",
+                vec![KeyValue::new("error", error.to_string())],
+            );
+        }
+    }
+
+    fn on_output_restored(&self, backlog: u64) {
+        if let Ok(mut session) = self.session.lock()
+            && let Some(span) = session.as_mut()
+        {
+            span.add_event(
+                "output_restored",
+                vec![KeyValue::new("backlog_packets", backlog as i64)],
+            );
+        }
+    }
+
+    fn on_error(&self, error: &str) {
+        if let Ok(mut session) = self.session.lock()
+            && let Some(span) = session.as_mut()
+        {
+            span.set_status(Status::error(error.to_string()));
+        }
+    }
+
+    fn on_stop(&self, stats: &CaptureStats) {
+        self.end_file(None);
+        let Ok(mut session) = self.session.lock() else {
+            return;
+        };
+        if let Some(mut span) = session.take() {
+            span.set_attributes([
+                KeyValue::new("save_pcap.packets_written", stats.packets_written as i64),
+                KeyValue::new("save_pcap.bytes_written", stats.bytes_written as i64),
+                KeyValue::new("save_pcap.kernel_dropped", stats.kernel_dropped as i64),
+                KeyValue::new("save_pcap.rotations", stats.rotations as i64),
+            ]);
+            span.end();
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use crate::{PcapCaptureOptions, SavePcapError};
+use log::{info, warn};
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// 输出目录中每个文件名前缀一个锁文件，捕获期间持有排他锁。锁由操作系统在进程退出
+// （包括崩溃）时释放，不会留下需要手动清理的过期锁。锁文件本身保留在目录中，
+// 删除它会让等待中的另一个进程锁住一个已经不在目录里的文件
+#[derive(Debug)]
+pub(crate) struct OutputLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl OutputLock {
+    /// 另一个捕获（本进程或其他进程）正在使用相同的输出目录和前缀时返回`OutputLocked`
+    pub fn acquire(options: &PcapCaptureOptions) -> Result<Self, SavePcapError> {
+        let path = lock_path(options);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(&path)?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let holder = fs::read_to_string(&path).unwrap_or_default();
+                let holder = holder.trim();
+                return Err(SavePcapError::OutputLocked(if holder.is_empty() {
+                    path.display().to_string()
+                } else {
+                    format!("{} (held by process {})", path.display(), holder)
+                }));
+            }
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+
+        // 写入持有者的pid，便于排查是哪个进程占用了输出
+        file.set_len(0)?;
+        write!(file, "{}", std::process::id())?;
+        info!("Locked output {:?}", path);
+        Ok(Self { file, path })
+    }
+}
+
+impl Drop for OutputLock {
+    fn drop(&mut self) {
+        if let Err(e) = self.file.set_len(0).and_then(|()| self.file.unlock()) {
+            warn!("Failed to release output lock {:?}: {}", self.path, e);
+        }
+    }
+}
+
+// 以点开头，不会被当作捕获文件清理或列出
+fn lock_path(options: &PcapCaptureOptions) -> PathBuf {
+    Path::new(&options.file_path).join(format!(".{}.lock", options.file_prefix))
+}
@@ -0,0 +1,440 @@
+//! 构造`UserProvided`模式使用的数据包：按以太网、IP、传输层逐层设置字段，
+//! 生成时自动填写长度字段和校验和。
+
+use crate::{SavePcapError, UserPacket};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::ops::BitOr;
+use std::time::Duration;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const IPV4_HEADER_LEN: usize = 20;
+const IPV6_HEADER_LEN: usize = 40;
+const UDP_HEADER_LEN: usize = 8;
+const TCP_HEADER_LEN: usize = 20;
+const ICMP_ECHO_HEADER_LEN: usize = 8;
+
+const PROTOCOL_ICMP: u8 = 1;
+const PROTOCOL_TCP: u8 = 6;
+const PROTOCOL_UDP: u8 = 17;
+const PROTOCOL_ICMPV6: u8 = 58;
+
+// 未设置时使用的本地管理MAC地址
+const DEFAULT_SOURCE_MAC: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0x00, 0x02];
+const DEFAULT_DESTINATION_MAC: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0x00, 0x01];
+
+/// TCP标志位，可以用`|`组合，例如`TcpFlags::SYN | TcpFlags::ACK`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TcpFlags(pub u8);
+
+impl TcpFlags {
+    pub const FIN: TcpFlags = TcpFlags(0x01);
+    pub const SYN: TcpFlags = TcpFlags(0x02);
+    pub const RST: TcpFlags = TcpFlags(0x04);
+    pub const PSH: TcpFlags = TcpFlags(0x08);
+    pub const ACK: TcpFlags = TcpFlags(0x10);
+}
+
+impl BitOr for TcpFlags {
+    type Output = TcpFlags;
+
+    fn bitor(self, other: TcpFlags) -> TcpFlags {
+        TcpFlags(self.0 | other.0)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum NetworkLayer {
+    Ipv4 {
+        source: Ipv4Addr,
+        destination: Ipv4Addr,
+        identification: u16,
+    },
+    Ipv6 {
+        source: Ipv6Addr,
+        destination: Ipv6Addr,
+    },
+}
+
+#[derive(Debug, Clone)]
+enum TransportLayer {
+    Udp {
+        source_port: u16,
+        destination_port: u16,
+    },
+    Tcp {
+        source_port: u16,
+        destination_port: u16,
+        sequence: u32,
+        acknowledgment: u32,
+        flags: TcpFlags,
+        window: u16,
+    },
+    // IPv4下为ICMP回显请求，IPv6下为ICMPv6回显请求
+    IcmpEcho {
+        identifier: u16,
+        sequence: u16,
+    },
+}
+
+/// 以太网帧构造器。
+///
+/// ```ignore
+/// let packet = EthernetFrame::new()
+///     .ipv4(Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2))
+///     .udp(5000, 53)
+///     .payload(b"hello")
+///     .build()?;
+/// ```
+///
+/// 没有设置IP层时，`payload`直接跟在以太网头部之后，需要通过`ethertype`指定类型
+#[derive(Debug, Clone)]
+pub struct EthernetFrame {
+    source: [u8; 6],
+    destination: [u8; 6],
+    vlan: Option<u16>,
+    ethertype: Option<u16>,
+    ttl: u8,
+    network: Option<NetworkLayer>,
+    transport: Option<TransportLayer>,
+    payload: Vec<u8>,
+    timestamp: Option<Duration>,
+}
+
+impl Default for EthernetFrame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EthernetFrame {
+    pub fn new() -> Self {
+        Self {
+            source: DEFAULT_SOURCE_MAC,
+            destination: DEFAULT_DESTINATION_MAC,
+            vlan: None,
+            ethertype: None,
+            ttl: 64,
+            network: None,
+            transport: None,
+            payload: Vec::new(),
+            timestamp: None,
+        }
+    }
+
+    pub fn source_mac(mut self, mac: [u8; 6]) -> Self {
+        self.source = mac;
+        self
+    }
+
+    pub fn destination_mac(mut self, mac: [u8; 6]) -> Self {
+        self.destination = mac;
+        self
+    }
+
+    /// 添加802.1Q标签，只使用低12位
+    pub fn vlan(mut self, id: u16) -> Self {
+        self.vlan = Some(id & 0x0FFF);
+        self
+    }
+
+    /// 以太网类型，设置了IP层时不需要也不起作用
+    pub fn ethertype(mut self, ethertype: u16) -> Self {
+        self.ethertype = Some(ethertype);
+        self
+    }
+
+    pub fn ipv4(mut self, source: Ipv4Addr, destination: Ipv4Addr) -> Self {
+        self.network = Some(NetworkLayer::Ipv4 {
+            source,
+            destination,
+            identification: 0,
+        });
+        self
+    }
+
+    /// IPv4标识符，对IPv6无效
+    pub fn ip_identification(mut self, value: u16) -> Self {
+        if let Some(NetworkLayer::Ipv4 { identification, .. }) = &mut self.network {
+            *identification = value;
+        }
+        self
+    }
+
+    pub fn ipv6(mut self, source: Ipv6Addr, destination: Ipv6Addr) -> Self {
+        self.network = Some(NetworkLayer::Ipv6 {
+            source,
+            destination,
+        });
+        self
+    }
+
+    /// IPv4的TTL或IPv6的跳数限制，默认64
+    pub fn ttl(mut self, ttl: u8) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn udp(mut self, source_port: u16, destination_port: u16) -> Self {
+        self.transport = Some(TransportLayer::Udp {
+            source_port,
+            destination_port,
+        });
+        self
+    }
+
+    /// TCP数据段，默认标志为ACK|PSH，序号和确认号为0，窗口65535
+    pub fn tcp(mut self, source_port: u16, destination_port: u16) -> Self {
+        self.transport = Some(TransportLayer::Tcp {
+            source_port,
+            destination_port,
+            sequence: 0,
+            acknowledgment: 0,
+            flags: TcpFlags::ACK | TcpFlags::PSH,
+            window: u16::MAX,
+        });
+        self
+    }
+
+    /// 设置TCP序号和确认号，未调用`tcp`时无效
+    pub fn tcp_sequence(mut self, value: u32, ack: u32) -> Self {
+        if let Some(TransportLayer::Tcp {
+            sequence,
+            acknowledgment,
+            ..
+        }) = &mut self.transport
+        {
+            *sequence = value;
+            *acknowledgment = ack;
+        }
+        self
+    }
+
+    /// 设置TCP标志和窗口，未调用`tcp`时无效
+    pub fn tcp_flags(mut self, value: TcpFlags, window_size: u16) -> Self {
+        if let Some(TransportLayer::Tcp { flags, window, .. }) = &mut self.transport {
+            *flags = value;
+            *window = window_size;
+        }
+        self
+    }
+
+    /// ICMP回显请求（IPv6下为ICMPv6回显请求）
+    pub fn icmp_echo(mut self, identifier: u16, sequence: u16) -> Self {
+        self.transport = Some(TransportLayer::IcmpEcho {
+            identifier,
+            sequence,
+        });
+        self
+    }
+
+    pub fn payload(mut self, payload: impl Into<Vec<u8>>) -> Self {
+        self.payload = payload.into();
+        self
+    }
+
+    /// 数据包时间戳（自UNIX纪元起），不设置时由捕获器取接收时的当前时间
+    pub fn timestamp(mut self, timestamp: Duration) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// 生成`UserPacket`
+    pub fn build(&self) -> Result<UserPacket, SavePcapError> {
+        let mut data = Vec::with_capacity(self.frame_len());
+        self.write_to(&mut data)?;
+        Ok(UserPacket {
+            data,
+            timestamp: self.timestamp,
+        })
+    }
+
+    /// 生成的帧长度（含以太网头部，不含FCS）
+    pub fn frame_len(&self) -> usize {
+        let ethernet = if self.vlan.is_some() { 18 } else { 14 };
+        let network = match self.network {
+            Some(NetworkLayer::Ipv4 { .. }) => IPV4_HEADER_LEN,
+            Some(NetworkLayer::Ipv6 { .. }) => IPV6_HEADER_LEN,
+            None => 0,
+        };
+        ethernet + network + self.transport_header_len() + self.payload.len()
+    }
+
+    /// 把帧追加到out之后，用于重复使用缓冲区
+    pub fn write_to(&self, out: &mut Vec<u8>) -> Result<(), SavePcapError> {
+        let ethertype = match (&self.network, self.ethertype) {
+            (Some(NetworkLayer::Ipv4 { .. }), _) => ETHERTYPE_IPV4,
+            (Some(NetworkLayer::Ipv6 { .. }), _) => ETHERTYPE_IPV6,
+            (None, Some(ethertype)) => ethertype,
+            (None, None) => {
+                return Err(invalid("an ethertype is required without an IP layer"));
+            }
+        };
+        if self.network.is_none() && self.transport.is_some() {
+            return Err(invalid("a transport layer requires an IP layer"));
+        }
+        // IPv4总长度和IPv6负载长度字段都是16位
+        let ip_payload_len = self.transport_header_len() + self.payload.len();
+        let ip_header_len = match self.network {
+            Some(NetworkLayer::Ipv4 { .. }) => IPV4_HEADER_LEN,
+            _ => 0,
+        };
+        if self.network.is_some() && ip_header_len + ip_payload_len > u16::MAX as usize {
+            return Err(invalid(&format!(
+                "IP packet of {} bytes exceeds the 65535 byte limit",
+                ip_header_len + ip_payload_len
+            )));
+        }
+
+        out.extend_from_slice(&self.destination);
+        out.extend_from_slice(&self.source);
+        if let Some(vlan) = self.vlan {
+            out.extend_from_slice(&ETHERTYPE_VLAN.to_be_bytes());
+            out.extend_from_slice(&vlan.to_be_bytes());
+        }
+        out.extend_from_slice(&ethertype.to_be_bytes());
+
+        // 传输层校验和的伪头部（不含长度和协议，计算时再补上）
+        let mut pseudo_addresses = Vec::with_capacity(32);
+        let protocol = self.protocol_number();
+        match &self.network {
+            Some(NetworkLayer::Ipv4 {
+                source,
+                destination,
+                identification,
+            }) => {
+                let start = out.len();
+                out.extend_from_slice(&[0x45, 0]);
+                out.extend_from_slice(&((IPV4_HEADER_LEN + ip_payload_len) as u16).to_be_bytes());
+                out.extend_from_slice(&identification.to_be_bytes());
+                // 不分片
+                out.extend_from_slice(&[0x40, 0, self.ttl, protocol, 0, 0]);
+                out.extend_from_slice(&source.octets());
+                out.extend_from_slice(&destination.octets());
+                let header_checksum = checksum(&[&out[start..]]);
+                out[start + 10..start + 12].copy_from_slice(&header_checksum.to_be_bytes());
+                pseudo_addresses.extend_from_slice(&source.octets());
+                pseudo_addresses.extend_from_slice(&destination.octets());
+            }
+            Some(NetworkLayer::Ipv6 {
+                source,
+                destination,
+            }) => {
+                out.extend_from_slice(&[0x60, 0, 0, 0]);
+                out.extend_from_slice(&(ip_payload_len as u16).to_be_bytes());
+                out.extend_from_slice(&[protocol, self.ttl]);
+                out.extend_from_slice(&source.octets());
+                out.extend_from_slice(&destination.octets());
+                pseudo_addresses.extend_from_slice(&source.octets());
+                pseudo_addresses.extend_from_slice(&destination.octets());
+            }
+            None => {}
+        }
+
+        let transport_start = out.len();
+        let checksum_offset = match &self.transport {
+            Some(TransportLayer::Udp {
+                source_port,
+                destination_port,
+            }) => {
+                out.extend_from_slice(&source_port.to_be_bytes());
+                out.extend_from_slice(&destination_port.to_be_bytes());
+                out.extend_from_slice(&(ip_payload_len as u16).to_be_bytes());
+                out.extend_from_slice(&[0, 0]);
+                Some(6)
+            }
+            Some(TransportLayer::Tcp {
+                source_port,
+                destination_port,
+                sequence,
+                acknowledgment,
+                flags,
+                window,
+            }) => {
+                out.extend_from_slice(&source_port.to_be_bytes());
+                out.extend_from_slice(&destination_port.to_be_bytes());
+                out.extend_from_slice(&sequence.to_be_bytes());
+                out.extend_from_slice(&acknowledgment.to_be_bytes());
+                // 头部长度5个32位字，没有选项
+                out.extend_from_slice(&[0x50, flags.0]);
+                out.extend_from_slice(&window.to_be_bytes());
+                out.extend_from_slice(&[0, 0, 0, 0]);
+                Some(16)
+            }
+            Some(TransportLayer::IcmpEcho {
+                identifier,
+                sequence,
+            }) => {
+                let echo_request = if protocol == PROTOCOL_ICMPV6 { 128 } else { 8 };
+                out.extend_from_slice(&[echo_request, 0, 0, 0]);
+                out.extend_from_slice(&identifier.to_be_bytes());
+                out.extend_from_slice(&sequence.to_be_bytes());
+                Some(2)
+            }
+            None => None,
+        };
+        out.extend_from_slice(&self.payload);
+
+        if let Some(offset) = checksum_offset {
+            let segment = &out[transport_start..];
+            // ICMPv4的校验和不包括伪头部
+            let sum = if protocol == PROTOCOL_ICMP {
+                checksum(&[segment])
+            } else {
+                let mut length_and_protocol = [0u8; 8];
+                length_and_protocol[..4].copy_from_slice(&(segment.len() as u32).to_be_bytes());
+                length_and_protocol[7] = protocol;
+                checksum(&[&pseudo_addresses, &length_and_protocol, segment])
+            };
+            // UDP中全零表示未计算校验和
+            let sum = if sum == 0 && protocol == PROTOCOL_UDP {
+                0xFFFF
+            } else {
+                sum
+            };
+            let at = transport_start + offset;
+            out[at..at + 2].copy_from_slice(&sum.to_be_bytes());
+        }
+        Ok(())
+    }
+
+    fn transport_header_len(&self) -> usize {
+        match self.transport {
+            Some(TransportLayer::Udp { .. }) => UDP_HEADER_LEN,
+            Some(TransportLayer::Tcp { .. }) => TCP_HEADER_LEN,
+            Some(TransportLayer::IcmpEcho { .. }) => ICMP_ECHO_HEADER_LEN,
+            None => 0,
+        }
+    }
+
+    // IP头部中的协议号，没有传输层时为255（保留值）
+    fn protocol_number(&self) -> u8 {
+        match (&self.transport, &self.network) {
+            (Some(TransportLayer::Udp { .. }), _) => PROTOCOL_UDP,
+            (Some(TransportLayer::Tcp { .. }), _) => PROTOCOL_TCP,
+            (Some(TransportLayer::IcmpEcho { .. }), Some(NetworkLayer::Ipv6 { .. })) => {
+                PROTOCOL_ICMPV6
+            }
+            (Some(TransportLayer::IcmpEcho { .. }), _) => PROTOCOL_ICMP,
+            (None, _) => 255,
+        }
+    }
+}
+
+fn invalid(reason: &str) -> SavePcapError {
+    SavePcapError::InvalidPacket(reason.to_string())
+}
+
+// 互联网校验和，parts按顺序拼接计算，除最后一段外长度都应为偶数
+pub(crate) fn checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum: u32 = parts
+        .iter()
+        .flat_map(|part| part.chunks(2))
+        .map(|word| u16::from_be_bytes([word[0], *word.get(1).unwrap_or(&0)]) as u32)
+        .sum();
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
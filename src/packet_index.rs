@@ -0,0 +1,252 @@
+use crate::metadata;
+use crate::repair::Endian;
+use crate::source::SourcePacket;
+use crate::{FileFormat, SavePcapError};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// 数据包偏移索引：每个捕获文件对应一个`.pktidx`文件，文件头之后按数据包顺序每包一条
+// 16字节的记录（小端序的记录偏移和纳秒时间戳），第N个数据包的记录位于固定位置
+const MAGIC: &[u8; 8] = b"SPCPIDX1";
+const ENTRY_LEN: usize = 16;
+
+const PCAP_RECORD_HEADER_LEN: usize = 16;
+// 增强数据包块在数据之前的固定字段：块类型、块长度、接口、时间戳高低位、捕获长度、原始长度
+const PCAPNG_EPB_HEADER_LEN: usize = 28;
+const PCAPNG_EPB_TYPE: u32 = 0x6;
+
+// 捕获文件的数据包偏移索引路径
+pub(crate) fn packet_index_path(capture_path: &Path) -> PathBuf {
+    metadata::sidecar_path_with(capture_path, metadata::PACKET_INDEX_SUFFIX)
+}
+
+// 写入时逐包记录，文件关闭时写出
+pub(crate) struct PacketIndexWriter {
+    entries: Vec<u8>,
+}
+
+impl PacketIndexWriter {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    /// offset为数据包记录在捕获文件中的起始偏移
+    pub fn record(&mut self, packet: &SourcePacket, offset: u64) {
+        self.entries.extend_from_slice(&offset.to_le_bytes());
+        self.entries
+            .extend_from_slice(&(packet.timestamp.as_nanos() as u64).to_le_bytes());
+    }
+
+    pub fn discard(&mut self) {
+        self.entries.clear();
+    }
+
+    /// 没有数据包的文件也写出索引，读取时与捕获文件一一对应
+    pub fn write_sidecar(&mut self, capture_path: &Path) -> io::Result<()> {
+        let mut contents = Vec::with_capacity(MAGIC.len() + self.entries.len());
+        contents.extend_from_slice(MAGIC);
+        contents.append(&mut self.entries);
+        fs::write(packet_index_path(capture_path), contents)
+    }
+}
+
+/// 索引中的一个数据包
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketIndexEntry {
+    /// 数据包记录（pcap记录头或pcapng增强数据包块）在捕获文件中的起始偏移
+    pub offset: u64,
+    pub timestamp: Duration,
+}
+
+/// 捕获文件的数据包偏移索引，由`packet_index`选项在每个文件关闭时写出
+#[derive(Debug, Clone, Default)]
+pub struct PacketIndex {
+    entries: Vec<PacketIndexEntry>,
+}
+
+impl PacketIndex {
+    /// 读取捕获文件对应的`.pktidx`索引
+    pub fn open<P: AsRef<Path>>(capture_path: P) -> Result<Self, SavePcapError> {
+        let path = packet_index_path(capture_path.as_ref());
+        let contents = fs::read(&path)?;
+        let invalid = |reason: &str| {
+            SavePcapError::InvalidPacketIndex(format!("{}: {}", path.display(), reason))
+        };
+        let Some(entries) = contents.strip_prefix(MAGIC) else {
+            return Err(invalid("not a packet index"));
+        };
+        if entries.len() % ENTRY_LEN != 0 {
+            return Err(invalid("truncated entry"));
+        }
+
+        let entries = entries
+            .chunks_exact(ENTRY_LEN)
+            .map(|entry| PacketIndexEntry {
+                offset: u64::from_le_bytes(entry[..8].try_into().unwrap()),
+                timestamp: Duration::from_nanos(u64::from_le_bytes(entry[8..].try_into().unwrap())),
+            })
+            .collect();
+        Ok(Self { entries })
+    }
+
+    /// 文件中的数据包数
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// 第number个数据包（从0开始）
+    pub fn get(&self, number: usize) -> Option<PacketIndexEntry> {
+        self.entries.get(number).copied()
+    }
+
+    /// 第一个时间戳不早于timestamp的数据包序号，所有数据包都更早时返回`len()`。
+    /// 二分查找，要求文件中的时间戳递增
+    pub fn position_at(&self, timestamp: Duration) -> usize {
+        self.entries
+            .partition_point(|entry| entry.timestamp < timestamp)
+    }
+}
+
+/// 通过索引读取的数据包
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexedPacket {
+    /// 在文件中的序号，从0开始
+    pub number: usize,
+    pub timestamp: Duration,
+    /// 线路上的原始长度，截断保存时大于`data`的长度
+    pub orig_len: u32,
+    pub data: Vec<u8>,
+}
+
+/// 按数据包偏移索引读取捕获文件，可以直接定位到第N个数据包或某个时间点，不需要从头扫描。
+/// 作为迭代器从当前位置开始逐个返回数据包
+pub struct IndexedCaptureReader {
+    file: BufReader<File>,
+    format: FileFormat,
+    endian: Endian,
+    index: PacketIndex,
+    position: usize,
+}
+
+impl IndexedCaptureReader {
+    /// 打开捕获文件和对应的`.pktidx`索引
+    pub fn open<P: AsRef<Path>>(capture_path: P) -> Result<Self, SavePcapError> {
+        let capture_path = capture_path.as_ref();
+        let index = PacketIndex::open(capture_path)?;
+        let mut file = BufReader::new(File::open(capture_path)?);
+
+        let mut header = [0u8; 12];
+        file.read_exact(&mut header)?;
+        let (format, endian) = match header {
+            [0xA1, 0xB2, 0xC3, 0xD4, ..] | [0xA1, 0xB2, 0x3C, 0x4D, ..] => {
+                (FileFormat::Pcap, Endian::Big)
+            }
+            [0xD4, 0xC3, 0xB2, 0xA1, ..] | [0x4D, 0x3C, 0xB2, 0xA1, ..] => {
+                (FileFormat::Pcap, Endian::Little)
+            }
+            // 节头块的字节序标识
+            [0x0A, 0x0D, 0x0D, 0x0A, _, _, _, _, 0x1A, 0x2B, 0x3C, 0x4D] => {
+                (FileFormat::PcapNg, Endian::Big)
+            }
+            [0x0A, 0x0D, 0x0D, 0x0A, _, _, _, _, 0x4D, 0x3C, 0x2B, 0x1A] => {
+                (FileFormat::PcapNg, Endian::Little)
+            }
+            _ => {
+                return Err(SavePcapError::PcapFileError(format!(
+                    "{} is not a pcap or pcapng file",
+                    capture_path.display()
+                )));
+            }
+        };
+
+        Ok(Self {
+            file,
+            format,
+            endian,
+            index,
+            position: 0,
+        })
+    }
+
+    pub fn index(&self) -> &PacketIndex {
+        &self.index
+    }
+
+    /// 下一个返回的数据包序号
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// 定位到第number个数据包，超出范围时定位到文件末尾
+    pub fn seek_packet(&mut self, number: usize) {
+        self.position = number.min(self.index.len());
+    }
+
+    /// 定位到第一个时间戳不早于timestamp的数据包
+    pub fn seek_time(&mut self, timestamp: Duration) {
+        self.position = self.index.position_at(timestamp);
+    }
+
+    /// 读取第number个数据包，不改变当前位置
+    pub fn read_packet(&mut self, number: usize) -> Result<IndexedPacket, SavePcapError> {
+        let entry = self.index.get(number).ok_or_else(|| {
+            SavePcapError::InvalidPacketIndex(format!(
+                "packet {} is past the end of the index ({} packets)",
+                number,
+                self.index.len()
+            ))
+        })?;
+        self.file.seek(SeekFrom::Start(entry.offset))?;
+
+        let endian = self.endian;
+        let field = |bytes: &[u8], at: usize| endian.u32(bytes[at..at + 4].try_into().unwrap());
+        let (captured_len, orig_len) = match self.format {
+            FileFormat::Pcap => {
+                let mut header = [0u8; PCAP_RECORD_HEADER_LEN];
+                self.file.read_exact(&mut header)?;
+                (field(&header, 8), field(&header, 12))
+            }
+            FileFormat::PcapNg => {
+                let mut header = [0u8; PCAPNG_EPB_HEADER_LEN];
+                self.file.read_exact(&mut header)?;
+                if field(&header, 0) != PCAPNG_EPB_TYPE {
+                    return Err(SavePcapError::InvalidPacketIndex(format!(
+                        "offset {} of packet {} is not an enhanced packet block",
+                        entry.offset, number
+                    )));
+                }
+                (field(&header, 20), field(&header, 24))
+            }
+        };
+
+        let mut data = vec![0u8; captured_len as usize];
+        self.file.read_exact(&mut data)?;
+        Ok(IndexedPacket {
+            number,
+            timestamp: entry.timestamp,
+            orig_len,
+            data,
+        })
+    }
+}
+
+impl Iterator for IndexedCaptureReader {
+    type Item = Result<IndexedPacket, SavePcapError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position >= self.index.len() {
+            return None;
+        }
+        let packet = self.read_packet(self.position);
+        self.position += 1;
+        Some(packet)
+    }
+}
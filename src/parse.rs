@@ -0,0 +1,140 @@
+//! 写入路径上各项统计和提取功能共用的轻量协议解析，只读取需要的字段，不做完整校验
+
+use pcap_file::DataLink;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+pub(crate) const ETHERNET_HEADER_LEN: usize = 14;
+pub(crate) const ETHERTYPE_IPV4: u16 = 0x0800;
+pub(crate) const ETHERTYPE_IPV6: u16 = 0x86DD;
+const ETHERTYPE_VLAN: [u16; 2] = [0x8100, 0x88A8];
+
+// 返回三层协议类型和三层数据，只识别以太网（含VLAN标签）和原始IP链路层
+pub(crate) fn network_layer(datalink: DataLink, data: &[u8]) -> Option<(u16, &[u8])> {
+    match datalink {
+        DataLink::ETHERNET => {
+            let mut offset = 12;
+            loop {
+                let ethertype = u16::from_be_bytes([*data.get(offset)?, *data.get(offset + 1)?]);
+                if ETHERTYPE_VLAN.contains(&ethertype) {
+                    offset += 4;
+                    continue;
+                }
+                return Some((ethertype, data.get(offset + 2..)?));
+            }
+        }
+        DataLink::RAW => match data.first()? >> 4 {
+            4 => Some((ETHERTYPE_IPV4, data)),
+            6 => Some((ETHERTYPE_IPV6, data)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// 最外层VLAN标签的VLAN ID，没有标签时返回None
+pub(crate) fn vlan_id(datalink: DataLink, data: &[u8]) -> Option<u16> {
+    if datalink != DataLink::ETHERNET {
+        return None;
+    }
+    let ethertype = u16::from_be_bytes([*data.get(12)?, *data.get(13)?]);
+    if !ETHERTYPE_VLAN.contains(&ethertype) {
+        return None;
+    }
+    Some(u16::from_be_bytes([*data.get(14)?, *data.get(15)?]) & 0x0FFF)
+}
+
+// IPv4的协议字段或IPv6的下一个头部字段（不解析扩展头）
+pub(crate) fn ip_protocol(datalink: DataLink, data: &[u8]) -> Option<u8> {
+    match network_layer(datalink, data)? {
+        (ETHERTYPE_IPV4, ip) if ip.len() >= 20 => Some(ip[9]),
+        (ETHERTYPE_IPV6, ip) if ip.len() >= 40 => Some(ip[6]),
+        _ => None,
+    }
+}
+
+/// 数据包的源地址和目的地址
+pub(crate) fn ip_addresses(datalink: DataLink, data: &[u8]) -> Option<(IpAddr, IpAddr)> {
+    match network_layer(datalink, data)? {
+        (ETHERTYPE_IPV4, ip) if ip.len() >= 20 => {
+            let source: [u8; 4] = ip[12..16].try_into().ok()?;
+            let destination: [u8; 4] = ip[16..20].try_into().ok()?;
+            Some((
+                Ipv4Addr::from(source).into(),
+                Ipv4Addr::from(destination).into(),
+            ))
+        }
+        (ETHERTYPE_IPV6, ip) if ip.len() >= 40 => {
+            let source: [u8; 16] = ip[8..24].try_into().ok()?;
+            let destination: [u8; 16] = ip[24..40].try_into().ok()?;
+            Some((
+                Ipv6Addr::from(source).into(),
+                Ipv6Addr::from(destination).into(),
+            ))
+        }
+        _ => None,
+    }
+}
+
+pub(crate) const IPPROTO_TCP: u8 = 6;
+pub(crate) const IPPROTO_UDP: u8 = 17;
+
+/// IP数据包的四层信息，payload不超过IP长度字段声明的范围
+pub(crate) struct Transport<'a> {
+    pub source: IpAddr,
+    pub destination: IpAddr,
+    pub protocol: u8,
+    pub source_port: u16,
+    pub destination_port: u16,
+    /// TCP标志字节（CWR到FIN），UDP为0
+    pub tcp_flags: u8,
+    pub payload: &'a [u8],
+}
+
+// 只识别TCP和UDP，IPv6不解析扩展头，IPv4分片只解析第一片
+pub(crate) fn transport(datalink: DataLink, data: &[u8]) -> Option<Transport<'_>> {
+    let (source, destination, protocol, segment) = match network_layer(datalink, data)? {
+        (ETHERTYPE_IPV4, ip) if ip.len() >= 20 => {
+            let header_len = (ip[0] & 0x0F) as usize * 4;
+            let total_len = (u16::from_be_bytes([ip[2], ip[3]]) as usize).min(ip.len());
+            let fragment_offset = u16::from_be_bytes([ip[6], ip[7]]) & 0x1FFF;
+            if fragment_offset != 0 || header_len < 20 {
+                return None;
+            }
+            let source: [u8; 4] = ip[12..16].try_into().ok()?;
+            let destination: [u8; 4] = ip[16..20].try_into().ok()?;
+            (
+                IpAddr::from(source),
+                IpAddr::from(destination),
+                ip[9],
+                ip.get(header_len..total_len)?,
+            )
+        }
+        (ETHERTYPE_IPV6, ip) if ip.len() >= 40 => {
+            let payload_len = u16::from_be_bytes([ip[4], ip[5]]) as usize;
+            let source: [u8; 16] = ip[8..24].try_into().ok()?;
+            let destination: [u8; 16] = ip[24..40].try_into().ok()?;
+            (
+                IpAddr::from(source),
+                IpAddr::from(destination),
+                ip[6],
+                &ip[40..(40 + payload_len).min(ip.len())],
+            )
+        }
+        _ => return None,
+    };
+
+    let (header_len, tcp_flags) = match protocol {
+        IPPROTO_TCP => ((*segment.get(12)? >> 4) as usize * 4, *segment.get(13)?),
+        IPPROTO_UDP => (8, 0),
+        _ => return None,
+    };
+    Some(Transport {
+        source,
+        destination,
+        protocol,
+        source_port: u16::from_be_bytes([*segment.first()?, *segment.get(1)?]),
+        destination_port: u16::from_be_bytes([*segment.get(2)?, *segment.get(3)?]),
+        tcp_flags,
+        payload: segment.get(header_len..)?,
+    })
+}
@@ -0,0 +1,129 @@
+use crate::SavePcapError;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// 把输出目录解析为规范的绝对路径：展开开头的`~`，相对路径相对于base（None时为当前工作目录），
+/// Windows下把`/`统一为`\`，并去掉多余的分隔符和`.`。`..`保持不变，以免经过符号链接时解析错误
+pub(crate) fn resolve(path: &str, base: Option<&str>) -> String {
+    let path = if cfg!(windows) {
+        path.replace('/', "\\")
+    } else {
+        path.to_string()
+    };
+    let mut resolved = match expand_home(&path) {
+        Some(expanded) => expanded,
+        None => PathBuf::from(&path),
+    };
+    if resolved.is_relative() {
+        let base = match base {
+            Some(base) => Some(PathBuf::from(resolve(base, None))),
+            None => env::current_dir().ok(),
+        };
+        if let Some(base) = base {
+            resolved = base.join(resolved);
+        }
+    }
+    normalize(&resolved).to_string_lossy().into_owned()
+}
+
+// 只展开当前用户的主目录，"~user"形式保持不变
+fn expand_home(path: &str) -> Option<PathBuf> {
+    let rest = path.strip_prefix('~')?;
+    if !(rest.is_empty() || rest.starts_with(std::path::is_separator)) {
+        return None;
+    }
+    let home = env::var_os(if cfg!(windows) { "USERPROFILE" } else { "HOME" })?;
+    let rest = rest.trim_start_matches(std::path::is_separator);
+    Some(PathBuf::from(home).join(rest))
+}
+
+/// 把配置中的字符串（文件名前缀、接口名）转换为可以安全放进文件名的形式：
+/// 保留各种语言的字母和数字以及`-`、`.`、`_`，路径分隔符、空白、控制字符和其他符号替换为`_`，
+/// 去掉开头和结尾的`.`和`_`（避免隐藏文件和`..`），Windows保留的设备名（`CON`、`COM1`等）后加`_`。
+/// 结果为空时返回None
+pub(crate) fn sanitize_file_name_part(name: &str) -> Option<String> {
+    let mut sanitized = String::with_capacity(name.len());
+    for c in name.chars() {
+        let c = match c {
+            '-' | '.' => c,
+            c if c.is_alphanumeric() => c,
+            _ => '_',
+        };
+        // 连续的替换字符合并为一个
+        if !(c == '_' && sanitized.ends_with('_')) {
+            sanitized.push(c);
+        }
+    }
+    let mut sanitized = sanitized.trim_matches(|c| c == '.' || c == '_').to_string();
+    if sanitized.is_empty() {
+        return None;
+    }
+    // Windows按第一个`.`之前的部分判断保留名，不区分大小写
+    let stem = sanitized.split('.').next().unwrap_or_default();
+    if is_windows_reserved(stem) {
+        sanitized.insert(stem.len(), '_');
+    }
+    Some(sanitized)
+}
+
+fn is_windows_reserved(stem: &str) -> bool {
+    let upper = stem.to_ascii_uppercase();
+    match upper.as_str() {
+        "CON" | "PRN" | "AUX" | "NUL" => true,
+        _ => {
+            (upper.starts_with("COM") || upper.starts_with("LPT"))
+                && upper.len() == 4
+                && (b'1'..=b'9').contains(&upper.as_bytes()[3])
+        }
+    }
+}
+
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized: PathBuf = path
+        .components()
+        .filter(|component| *component != Component::CurDir)
+        .collect();
+    if normalized.as_os_str().is_empty() {
+        normalized.push(".");
+    }
+    normalized
+}
+
+/// 确认目录存在（不存在时创建）且可以创建文件，在开始捕获前发现权限和只读文件系统的问题
+pub(crate) fn ensure_writable(dir: &Path) -> Result<(), SavePcapError> {
+    if !dir.exists() {
+        fs::create_dir_all(dir).map_err(|e| {
+            SavePcapError::DirectoryCreationFailed(format!(
+                "Failed to create directory: {}, error: {}",
+                dir.display(),
+                e
+            ))
+        })?;
+    }
+    if !dir.is_dir() {
+        return Err(SavePcapError::DirectoryNotWritable(format!(
+            "{} is not a directory",
+            dir.display()
+        )));
+    }
+
+    probe(dir).map_err(|e| SavePcapError::DirectoryNotWritable(format!("{}: {}", dir.display(), e)))
+}
+
+/// 目录存在且可以创建文件。与`ensure_writable`不同，不创建目录，用于判断已卸载的共享是否恢复
+pub(crate) fn is_writable(dir: &Path) -> bool {
+    dir.is_dir() && probe(dir).is_ok()
+}
+
+fn probe(dir: &Path) -> io::Result<()> {
+    let probe = dir.join(format!(".save_pcap_write_test_{}", std::process::id()));
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&probe)?;
+    let _ = fs::remove_file(&probe);
+    Ok(())
+}
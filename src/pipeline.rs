@@ -0,0 +1,331 @@
+use crate::rules::parse_rule;
+use crate::{
+    ClassificationRules, FileFormat, OutputOptions, PacketSource, PcapCaptureOptions,
+    SavePcapError, TimeShift,
+};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// 数据来源和输出之间的变换
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transform {
+    /// BPF过滤表达式，对应`filter`
+    Filter(String),
+    /// 捕获时的快照长度，对应`snaplen`
+    Snaplen(i32),
+    /// 写入文件时只保存前N字节，对应`slice_bytes`
+    Slice(usize),
+    /// 改写数据包时间戳，对应`time_shift`
+    TimeShift(TimeShift),
+}
+
+/// 主输出的滚动条件，任一条件满足时滚动；都为None时不开启持续捕获
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Rotation {
+    pub time_seconds: Option<u64>,
+    pub packet_count: Option<usize>,
+    pub file_size_mb: Option<u64>,
+}
+
+/// 声明式描述的完整处理流程：数据来源 → 变换 → 分流 → 输出。部署时只需修改配置文件，
+/// 不用改代码。每行一个阶段，阶段必须按此顺序出现，`#`开始注释，含空格的值用双引号括起：
+///
+/// ```text
+/// source device eth0
+/// filter tcp or udp
+/// slice 256
+/// demux udp and port 5060-5061 -> sip
+/// demux vlan 100 -> voice
+/// sink pcapng dir=/var/captures prefix=edge rotate-mb=100
+/// sink pcap prefix=dns filter="port 53"
+/// ```
+///
+/// - `source`：`device NAME`、`shared NAME`、`file PATH`、`stdin`、`user`、`bluetooth NAME`、
+///   `can NAME`、`nflog GROUP`或`pktmon`，必须且只能有一个
+/// - 变换：`filter EXPR`、`snaplen N`、`slice N`、`timeshift`（`+SECONDS`、`-SECONDS`、
+///   `start UNIX_SECONDS`、`zero`或`now`）
+/// - `demux`：一条分类规则，语法同`ClassificationRules`
+/// - `sink FORMAT [key=value...]`：至少一个。第一个为主输出，接受`dir`、`prefix`和滚动条件
+///   `rotate-seconds`、`rotate-packets`、`rotate-mb`；其余为额外输出，接受`dir`、`prefix`、
+///   `snaplen`、`sample`、`filter`和`unmatched`
+///
+/// 所有错误在加载时报告，并带有行号
+#[derive(Debug, Clone)]
+pub struct Pipeline {
+    pub source: PacketSource,
+    pub transforms: Vec<Transform>,
+    pub rules: Option<ClassificationRules>,
+    /// 第一个为主输出
+    pub sinks: Vec<OutputOptions>,
+    pub rotation: Rotation,
+}
+
+impl Pipeline {
+    /// 从流程描述文件读取
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SavePcapError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| {
+            SavePcapError::InvalidPipeline(format!("failed to read {}: {}", path.display(), e))
+        })?;
+        text.parse()
+    }
+
+    /// 覆盖`options`中的数据来源、过滤、快照长度、分类规则、输出和滚动设置，
+    /// 流程中没有描述的其他设置（元数据提取、告警等）保持不变
+    pub fn apply(&self, options: &mut PcapCaptureOptions) {
+        options.packet_source = self.source.clone();
+        for transform in &self.transforms {
+            match transform {
+                Transform::Filter(filter) => options.filter = Some(filter.clone()),
+                Transform::Snaplen(snaplen) => options.snaplen = *snaplen,
+                Transform::Slice(bytes) => options.slice_bytes = Some(*bytes),
+                Transform::TimeShift(shift) => options.time_shift = Some(*shift),
+            }
+        }
+        options.rules = self.rules.clone();
+
+        let (main, outputs) = self
+            .sinks
+            .split_first()
+            .expect("a parsed pipeline has at least one sink");
+        options.file_format = main.file_format;
+        if let Some(file_path) = &main.file_path {
+            options.file_path = file_path.clone();
+        }
+        if let Some(file_prefix) = &main.file_prefix {
+            options.file_prefix = file_prefix.clone();
+        }
+        options.outputs = outputs.to_vec();
+
+        let rotation = &self.rotation;
+        options.rollover_time_seconds = rotation.time_seconds;
+        options.rollover_packet_count = rotation.packet_count;
+        options.rollover_file_size_mb = rotation.file_size_mb;
+        options.continuous_capture = *rotation != Rotation::default();
+    }
+}
+
+// 阶段的顺序，后面的阶段不能出现在前面的阶段之前
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Stage {
+    Source,
+    Transform,
+    Demux,
+    Sink,
+}
+
+impl FromStr for Pipeline {
+    type Err = SavePcapError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut source = None;
+        let mut transforms = Vec::new();
+        let mut rules = Vec::new();
+        let mut sinks = Vec::new();
+        let mut rotation = Rotation::default();
+        let mut stage = Stage::Source;
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |reason: String| {
+                SavePcapError::InvalidPipeline(format!("line {}: {}", number + 1, reason))
+            };
+            let words = split_words(line).map_err(error)?;
+            let Some((name, args)) = words.split_first() else {
+                continue;
+            };
+            let line_stage = match name.as_str() {
+                "source" => Stage::Source,
+                "filter" | "snaplen" | "slice" | "timeshift" => Stage::Transform,
+                "demux" => Stage::Demux,
+                "sink" => Stage::Sink,
+                _ => return Err(error(format!("unknown stage {:?}", name))),
+            };
+            if line_stage < stage {
+                return Err(error(format!(
+                    "{} is out of order; stages go source, transforms, demux, sink",
+                    name
+                )));
+            }
+            stage = line_stage;
+
+            match name.as_str() {
+                "source" => {
+                    if source.is_some() {
+                        return Err(error("only one source is allowed".to_string()));
+                    }
+                    source = Some(parse_source(args).map_err(error)?);
+                }
+                "filter" => {
+                    if args.is_empty() {
+                        return Err(error("filter needs an expression".to_string()));
+                    }
+                    transforms.push(Transform::Filter(args.join(" ")));
+                }
+                "snaplen" => {
+                    let snaplen = number_arg(name, args).map_err(error)?;
+                    transforms.push(Transform::Snaplen(snaplen));
+                }
+                "slice" => {
+                    let bytes = number_arg(name, args).map_err(error)?;
+                    transforms.push(Transform::Slice(bytes));
+                }
+                "timeshift" => {
+                    let shift = parse_time_shift(args).map_err(error)?;
+                    transforms.push(Transform::TimeShift(shift));
+                }
+                "demux" => rules.push(parse_rule(&args.join(" ")).map_err(error)?),
+                _ => {
+                    let main = sinks.is_empty();
+                    let sink = parse_sink(args, main, &mut rotation).map_err(error)?;
+                    sinks.push(sink);
+                }
+            }
+        }
+
+        let source =
+            source.ok_or_else(|| SavePcapError::InvalidPipeline("missing source".to_string()))?;
+        if sinks.is_empty() {
+            return Err(SavePcapError::InvalidPipeline(
+                "at least one sink is required".to_string(),
+            ));
+        }
+        Ok(Self {
+            source,
+            transforms,
+            rules: (!rules.is_empty()).then_some(ClassificationRules { rules }),
+            sinks,
+            rotation,
+        })
+    }
+}
+
+// 按空白分词，双引号括起的部分为一个词
+fn split_words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quoted = false;
+    for c in line.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_word = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            c => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if quoted {
+        return Err("unterminated quote".to_string());
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+fn number_arg<T: FromStr>(name: &str, args: &[String]) -> Result<T, String> {
+    match args {
+        [value] => number(name, value),
+        _ => Err(format!("{} takes one number", name)),
+    }
+}
+
+fn number<T: FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid {} {:?}", name, value))
+}
+
+fn parse_time_shift(args: &[String]) -> Result<TimeShift, String> {
+    let seconds = |value: &str| {
+        value
+            .parse()
+            .ok()
+            .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+            .ok_or_else(|| format!("invalid timeshift seconds {:?}", value))
+    };
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["zero"] => Ok(TimeShift::StartAt(Duration::ZERO)),
+        ["now"] => Ok(TimeShift::StartNow),
+        ["start", value] => Ok(TimeShift::StartAt(seconds(value)?)),
+        [value] if value.starts_with('+') => Ok(TimeShift::Forward(seconds(&value[1..])?)),
+        [value] if value.starts_with('-') => Ok(TimeShift::Backward(seconds(&value[1..])?)),
+        _ => Err("timeshift takes +SECONDS, -SECONDS, start UNIX_SECONDS, zero or now".to_string()),
+    }
+}
+
+fn parse_source(args: &[String]) -> Result<PacketSource, String> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["device", name] => Ok(PacketSource::NetworkDevice(name.to_string())),
+        ["shared", name] => Ok(PacketSource::SharedDevice(name.to_string())),
+        ["file", path] => Ok(PacketSource::File(path.to_string())),
+        ["stdin"] => Ok(PacketSource::Stdin),
+        ["user"] => Ok(PacketSource::UserProvided),
+        ["bluetooth", name] => Ok(PacketSource::BluetoothHci(name.to_string())),
+        ["can", name] => Ok(PacketSource::CanInterface(name.to_string())),
+        ["nflog", group] => group
+            .parse()
+            .map(PacketSource::Nflog)
+            .map_err(|_| format!("invalid NFLOG group {:?}", group)),
+        ["pktmon"] => Ok(PacketSource::Pktmon),
+        _ => Err(format!("unknown source {:?}", args.join(" "))),
+    }
+}
+
+fn parse_sink(
+    args: &[String],
+    main: bool,
+    rotation: &mut Rotation,
+) -> Result<OutputOptions, String> {
+    let (format, params) = args
+        .split_first()
+        .ok_or_else(|| "sink needs a file format".to_string())?;
+    let mut sink = OutputOptions::format(match format.as_str() {
+        "pcap" => FileFormat::Pcap,
+        "pcapng" => FileFormat::PcapNg,
+        _ => return Err(format!("unknown file format {:?}", format)),
+    });
+
+    for param in params {
+        let (key, value) = param.split_once('=').unwrap_or((param, ""));
+        match (key, main) {
+            ("dir", _) => sink.file_path = Some(value.to_string()),
+            ("prefix", _) => sink.file_prefix = Some(value.to_string()),
+            ("rotate-seconds", true) => rotation.time_seconds = Some(number(key, value)?),
+            ("rotate-packets", true) => rotation.packet_count = Some(number(key, value)?),
+            ("rotate-mb", true) => rotation.file_size_mb = Some(number(key, value)?),
+            ("snaplen", false) => sink.snaplen = Some(number(key, value)?),
+            ("sample", false) => sink.sample_every = Some(number(key, value)?),
+            ("filter", false) => sink.filter = Some(value.to_string()),
+            ("unmatched", false) => sink.unmatched = true,
+            (_, true) if matches!(key, "snaplen" | "sample" | "filter" | "unmatched") => {
+                return Err(format!("{} is only supported on additional sinks", key));
+            }
+            (_, false) if key.starts_with("rotate-") => {
+                return Err(format!(
+                    "{} is only supported on the first sink; other sinks follow its rotation",
+                    key
+                ));
+            }
+            _ => return Err(format!("unknown sink parameter {:?}", key)),
+        }
+    }
+    Ok(sink)
+}
@@ -0,0 +1,232 @@
+use crate::SavePcapError;
+use crate::pool::BufferPool;
+use crate::source::{NextPacket, PacketStream, SourcePacket};
+use log::{debug, info, warn};
+use pcap_file::DataLink;
+use pcap_file::pcapng::{Block, PcapNgReader};
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+// pktmon在multi-file模式下按该大小(MB)切分ETL文件，写满的分段才会被转换
+const SEGMENT_SIZE_MB: u32 = 16;
+// etl2pcap输出以太网帧；输出文件头在第一个分段转换前就已写入，整个捕获都按这个链路类型
+const PKTMON_DATALINK: DataLink = DataLink::ETHERNET;
+
+// 使用Windows自带的Pktmon(基于ETW)抓包，无需安装Npcap。
+// pktmon只能输出ETL文件，这里让它滚动写入暂存目录，再将写完的分段
+// 转换为pcapng并逐包读出，交给正常的保存流程。
+pub(crate) struct PktmonStream {
+    staging_dir: PathBuf,
+    poll_interval: Duration,
+    current: Option<(PathBuf, PcapNgReader<BufReader<File>>)>,
+    // 当前分段中各接口的链路类型，按接口描述块的顺序
+    interfaces: Vec<DataLink>,
+    running: bool,
+}
+
+impl PktmonStream {
+    pub fn start(
+        staging_dir: PathBuf,
+        snaplen: i32,
+        poll_interval: Duration,
+    ) -> Result<Self, SavePcapError> {
+        fs::create_dir_all(&staging_dir)?;
+
+        let etl_path = staging_dir.join("pktmon.etl");
+        let pkt_size = snaplen.to_string();
+        let segment_size = SEGMENT_SIZE_MB.to_string();
+        run_pktmon(&[
+            "start",
+            "--capture",
+            "--pkt-size",
+            &pkt_size,
+            "--log-mode",
+            "multi-file",
+            "--file-size",
+            &segment_size,
+            "--file-name",
+            &etl_path.to_string_lossy(),
+        ])?;
+        info!(
+            "Pktmon capture started, staging ETL files in {:?}",
+            staging_dir
+        );
+
+        Ok(Self {
+            staging_dir,
+            poll_interval,
+            current: None,
+            interfaces: Vec::new(),
+            running: true,
+        })
+    }
+
+    // 按写入时间排序的ETL分段；抓包进行中时最新的分段仍在写入，不能读取
+    fn completed_segments(&self) -> Result<Vec<PathBuf>, SavePcapError> {
+        let mut segments = Vec::new();
+        for entry in fs::read_dir(&self.staging_dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "etl") {
+                segments.push((entry.metadata()?.modified()?, path));
+            }
+        }
+        segments.sort();
+
+        if self.running {
+            segments.pop();
+        }
+
+        Ok(segments.into_iter().map(|(_, path)| path).collect())
+    }
+
+    fn open_next_segment(&mut self) -> Result<bool, SavePcapError> {
+        let Some(etl_path) = self.completed_segments()?.into_iter().next() else {
+            return Ok(false);
+        };
+
+        let pcapng_path = etl_path.with_extension("pcapng");
+        run_pktmon(&[
+            "etl2pcap",
+            &etl_path.to_string_lossy(),
+            "--out",
+            &pcapng_path.to_string_lossy(),
+        ])?;
+        fs::remove_file(&etl_path)?;
+        debug!("Converted Pktmon segment {:?}", etl_path);
+
+        let file = File::open(&pcapng_path)?;
+        let reader = PcapNgReader::new(BufReader::new(file))
+            .map_err(|e| SavePcapError::PcapFileError(e.to_string()))?;
+        self.current = Some((pcapng_path, reader));
+        self.interfaces.clear();
+
+        Ok(true)
+    }
+
+    fn finish_segment(&mut self) {
+        if let Some((path, _)) = self.current.take()
+            && let Err(e) = fs::remove_file(&path)
+        {
+            warn!("Failed to remove Pktmon segment {:?}: {}", path, e);
+        }
+    }
+
+    // 从已转换的分段中读出下一个数据包，buffer提供存放数据的缓冲区；没有可读的分段时返回None。
+    // 链路类型与输出文件不同的接口上的数据包无法正确保存，跳过
+    fn read_converted(
+        &mut self,
+        mut buffer: impl FnMut() -> Vec<u8>,
+    ) -> Result<Option<SourcePacket>, SavePcapError> {
+        loop {
+            if let Some((path, reader)) = &mut self.current {
+                let segment_done = match reader.next_block() {
+                    Some(Ok(Block::EnhancedPacket(packet))) => {
+                        if self.interfaces.get(packet.interface_id as usize)
+                            == Some(&PKTMON_DATALINK)
+                        {
+                            let mut data = buffer();
+                            data.extend_from_slice(&packet.data);
+                            return Ok(Some(SourcePacket {
+                                timestamp: packet.timestamp,
+                                orig_len: packet.original_len,
+                                data,
+                                user_index: None,
+                            }));
+                        }
+                        false
+                    }
+                    Some(Ok(Block::InterfaceDescription(interface))) => {
+                        if interface.linktype != PKTMON_DATALINK {
+                            warn!(
+                                "Skipping packets with link type {:?} in Pktmon segment {:?}",
+                                interface.linktype, path
+                            );
+                        }
+                        self.interfaces.push(interface.linktype);
+                        false
+                    }
+                    Some(Ok(_)) => false,
+                    Some(Err(e)) => {
+                        warn!("Failed to read converted Pktmon segment {:?}: {}", path, e);
+                        true
+                    }
+                    None => true,
+                };
+
+                if segment_done {
+                    self.finish_segment();
+                }
+                continue;
+            }
+
+            if !self.open_next_segment()? {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn stop(&mut self) {
+        if self.running {
+            self.running = false;
+            if let Err(e) = run_pktmon(&["stop"]) {
+                warn!("Failed to stop Pktmon: {}", e);
+            }
+        }
+    }
+}
+
+impl PacketStream for PktmonStream {
+    fn datalink(&self) -> DataLink {
+        PKTMON_DATALINK
+    }
+
+    fn next_packet(&mut self, pool: &mut BufferPool) -> Result<NextPacket, SavePcapError> {
+        if let Some(packet) = self.read_converted(|| pool.take())? {
+            return Ok(NextPacket::Packet(packet));
+        }
+
+        if !self.running {
+            return Ok(NextPacket::End);
+        }
+
+        thread::sleep(self.poll_interval);
+        Ok(NextPacket::Idle)
+    }
+
+    // 停止时让pktmon写完仍在写入的分段，之后剩下的分段都可以转换读出
+    fn drain(&mut self) -> Option<SourcePacket> {
+        self.stop();
+        self.read_converted(Vec::new).unwrap_or_else(|e| {
+            warn!("Failed to convert the remaining Pktmon segments: {}", e);
+            None
+        })
+    }
+}
+
+impl Drop for PktmonStream {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn run_pktmon(args: &[&str]) -> Result<(), SavePcapError> {
+    let output = Command::new("pktmon").args(args).output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(SavePcapError::UnsupportedSource(format!(
+            "pktmon {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
+
+pub(crate) fn staging_dir(output_dir: &Path) -> PathBuf {
+    output_dir.join(".pktmon")
+}
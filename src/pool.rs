@@ -0,0 +1,63 @@
+use crate::spsc;
+
+// 数据包缓冲区池：捕获循环从池中取出缓冲区填充数据，写入完成后归还，
+// 稳定运行时不再为每个数据包分配内存。
+// 缓冲区按snaplen预留容量，首次使用时才分配，池的大小由同时在途的数据包数量决定。
+pub(crate) struct BufferPool {
+    free: Vec<Vec<u8>>,
+    // 独立写入线程通过该队列归还缓冲区
+    returns: Option<spsc::Consumer<Vec<u8>>>,
+    buffer_size: usize,
+    max_free: usize,
+}
+
+impl BufferPool {
+    pub fn new(buffer_size: usize, max_free: usize) -> Self {
+        Self {
+            free: Vec::with_capacity(max_free),
+            returns: None,
+            buffer_size,
+            max_free,
+        }
+    }
+
+    /// 缓冲区由另一个线程归还，返回归还端
+    pub fn with_returns(buffer_size: usize, max_free: usize) -> (Self, spsc::Producer<Vec<u8>>) {
+        let (producer, consumer) = spsc::channel(max_free);
+        let mut pool = Self::new(buffer_size, max_free);
+        pool.returns = Some(consumer);
+        (pool, producer)
+    }
+
+    /// 取出一个空缓冲区，池中没有可用缓冲区时才分配新的
+    pub fn take(&mut self) -> Vec<u8> {
+        if let Some(buffer) = self.free.pop() {
+            return buffer;
+        }
+
+        if let Some(returns) = &mut self.returns {
+            while self.free.len() < self.max_free {
+                match returns.pop() {
+                    Some(mut buffer) => {
+                        buffer.clear();
+                        self.free.push(buffer);
+                    }
+                    None => break,
+                }
+            }
+            if let Some(buffer) = self.free.pop() {
+                return buffer;
+            }
+        }
+
+        Vec::with_capacity(self.buffer_size)
+    }
+
+    /// 归还缓冲区，池已满时直接释放
+    pub fn give(&mut self, mut buffer: Vec<u8>) {
+        if self.free.len() < self.max_free {
+            buffer.clear();
+            self.free.push(buffer);
+        }
+    }
+}
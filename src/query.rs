@@ -0,0 +1,218 @@
+use crate::SavePcapError;
+use crate::extract::{self, Candidate};
+use crate::merge::{self, PacketIter};
+use crate::source::SourcePacket;
+use log::{debug, info};
+use pcap::{BpfProgram, Capture, Linktype};
+use pcap_file::DataLink;
+use std::cell::RefCell;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+// 扫描过程中每隔这么多数据包报告一次进度
+const PROGRESS_INTERVAL_PACKETS: u64 = 100_000;
+
+/// 自定义过滤函数，返回true的数据包写入结果文件
+pub type QueryMatcher = Arc<dyn Fn(&QueryPacket) -> bool + Send + Sync>;
+
+/// 查询进度回调
+pub type QueryProgressCallback = Arc<dyn Fn(&QueryProgress) + Send + Sync>;
+
+/// 传给`QueryMatcher`的数据包
+#[derive(Debug, Clone, Copy)]
+pub struct QueryPacket<'a> {
+    /// 数据包所在的捕获文件
+    pub file: &'a Path,
+    pub timestamp: Duration,
+    pub orig_len: u32,
+    pub data: &'a [u8],
+}
+
+/// 查询条件，`filter`和`matcher`同时设置时数据包需要同时满足
+#[derive(Clone, Default)]
+pub struct QueryOptions {
+    /// BPF过滤表达式（libpcap语法），按文件的链路层类型编译
+    pub filter: Option<String>,
+    /// 自定义过滤函数，用于BPF无法表达的条件
+    pub matcher: Option<QueryMatcher>,
+    /// 只查询不早于该时间的数据包
+    pub start: Option<SystemTime>,
+    /// 只查询早于该时间的数据包
+    pub end: Option<SystemTime>,
+    /// 处理完每个文件以及每扫描10万个数据包时调用
+    pub progress: Option<QueryProgressCallback>,
+}
+
+/// 查询进度，`query`结束时返回最终的统计
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryProgress {
+    /// 需要扫描的文件数
+    pub files_total: usize,
+    pub files_done: usize,
+    /// 需要扫描的文件的总大小
+    pub bytes_total: u64,
+    /// 已扫描的数据包记录的大小（不含文件头），用于估算剩余时间
+    pub bytes_scanned: u64,
+    pub packets_scanned: u64,
+    /// 写入结果文件的数据包数
+    pub matches: u64,
+}
+
+// 各文件的数据包序列共享的进度
+struct Tracker {
+    progress: QueryProgress,
+    callback: Option<QueryProgressCallback>,
+}
+
+impl Tracker {
+    fn scanned(&mut self, packet: &SourcePacket) {
+        self.progress.packets_scanned += 1;
+        // pcap记录头16字节
+        self.progress.bytes_scanned += 16 + packet.data.len() as u64;
+        if self
+            .progress
+            .packets_scanned
+            .is_multiple_of(PROGRESS_INTERVAL_PACKETS)
+        {
+            self.report();
+        }
+    }
+
+    fn file_done(&mut self) {
+        self.progress.files_done += 1;
+        self.report();
+    }
+
+    fn report(&self) {
+        if let Some(callback) = &self.callback {
+            callback(&self.progress);
+        }
+    }
+}
+
+/// 在整个捕获目录中查询符合条件的数据包，按时间排序写入output（pcap格式）。
+///
+/// 设置了时间范围时只打开与之重叠的文件，带有`.pktidx`索引的文件直接定位到起始时间。
+/// 与`extract`一样只处理本库生成的文件名
+pub fn query<P: AsRef<Path>, Q: AsRef<Path>>(
+    dir: P,
+    output: Q,
+    options: &QueryOptions,
+) -> Result<QueryProgress, SavePcapError> {
+    let dir = dir.as_ref();
+    let output = output.as_ref();
+    let start = options.start.map(extract::since_epoch).unwrap_or_default();
+    let end = options
+        .end
+        .map(extract::since_epoch)
+        .unwrap_or(Duration::MAX);
+    if end <= start {
+        return Err(SavePcapError::InvalidTimeWindow(
+            "query end time must be after the start time".to_string(),
+        ));
+    }
+
+    let files = extract::files_in_range(dir, None, output, start, end)?;
+    if files.is_empty() {
+        return Err(SavePcapError::PcapFileError(format!(
+            "No capture files to query in {}",
+            dir.display()
+        )));
+    }
+    let tracker = Rc::new(RefCell::new(Tracker {
+        progress: QueryProgress {
+            files_total: files.len(),
+            bytes_total: files
+                .iter()
+                .filter_map(|file| fs::metadata(&file.path).ok())
+                .map(|metadata| metadata.len())
+                .sum(),
+            ..Default::default()
+        },
+        callback: options.progress.clone(),
+    }));
+
+    let mut datalink = None;
+    let mut opened = Vec::with_capacity(files.len());
+    for file in &files {
+        let (file_datalink, packets) = extract::open_range(file, start, end)?;
+        extract::check_datalink(&mut datalink, file_datalink, file)?;
+        opened.push((file, packets));
+    }
+    let datalink = datalink.unwrap_or(DataLink::ETHERNET);
+
+    // 所有文件的链路层类型相同，BPF程序只需编译一次
+    let program = options
+        .filter
+        .as_deref()
+        .map(|filter| compile(filter, datalink))
+        .transpose()?
+        .map(Rc::new);
+    let inputs = opened
+        .into_iter()
+        .map(|(file, packets)| matching(file, packets, program.clone(), options, tracker.clone()))
+        .collect();
+
+    merge::write_merged(inputs, datalink, output)?;
+    let progress = tracker.borrow().progress.clone();
+    info!(
+        "Query matched {} of {} packets in {} files, written to {}",
+        progress.matches,
+        progress.packets_scanned,
+        progress.files_total,
+        output.display()
+    );
+    Ok(progress)
+}
+
+pub(crate) fn compile(filter: &str, datalink: DataLink) -> Result<BpfProgram, SavePcapError> {
+    let capture = Capture::dead(Linktype(u32::from(datalink) as i32))?;
+    Ok(capture.compile(filter, true)?)
+}
+
+// 逐个检查文件中的数据包，只返回符合条件的数据包和读取错误
+fn matching<'a>(
+    file: &'a Candidate,
+    mut packets: PacketIter<'static>,
+    program: Option<Rc<BpfProgram>>,
+    options: &'a QueryOptions,
+    tracker: Rc<RefCell<Tracker>>,
+) -> PacketIter<'a> {
+    let mut finished = false;
+    Box::new(std::iter::from_fn(move || {
+        loop {
+            let Some(packet) = packets.next() else {
+                if !finished {
+                    finished = true;
+                    debug!("Finished querying {:?}", file.path);
+                    tracker.borrow_mut().file_done();
+                }
+                return None;
+            };
+            let Ok(source_packet) = &packet else {
+                return Some(packet);
+            };
+            tracker.borrow_mut().scanned(source_packet);
+
+            let bpf_match = program
+                .as_ref()
+                .is_none_or(|program| program.filter(&source_packet.data));
+            let matched = bpf_match
+                && options.matcher.as_ref().is_none_or(|matcher| {
+                    matcher(&QueryPacket {
+                        file: &file.path,
+                        timestamp: source_packet.timestamp,
+                        orig_len: source_packet.orig_len,
+                        data: &source_packet.data,
+                    })
+                });
+            if matched {
+                tracker.borrow_mut().progress.matches += 1;
+                return Some(packet);
+            }
+        }
+    }))
+}
@@ -0,0 +1,155 @@
+use crate::SavePcapError;
+use crate::pool::BufferPool;
+use crate::source::{NextPacket, PacketStream, SourcePacket};
+use pcap::BpfProgram;
+use pcap_file::DataLink;
+use pcap_file::pcap::PcapReader;
+use pcap_file::pcapng::{Block, PcapNgReader};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+const PCAPNG_MAGIC: [u8; 4] = [0x0A, 0x0D, 0x0D, 0x0A];
+
+// 按文件头的魔数自动识别pcap/pcapng并逐包读取
+pub(crate) enum CaptureReader<R: BufRead> {
+    Pcap(PcapReader<R>),
+    PcapNg {
+        reader: PcapNgReader<R>,
+        datalink: DataLink,
+        // 为确定链路层类型而提前读出的数据包
+        pending: Option<SourcePacket>,
+    },
+}
+
+impl CaptureReader<BufReader<File>> {
+    pub fn open(path: &Path) -> Result<Self, SavePcapError> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: BufRead> CaptureReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, SavePcapError> {
+        let is_pcapng = reader.fill_buf()?.starts_with(&PCAPNG_MAGIC);
+
+        if is_pcapng {
+            let reader = PcapNgReader::new(reader).map_err(pcap_file_error)?;
+            let mut capture_reader = CaptureReader::PcapNg {
+                reader,
+                datalink: DataLink::ETHERNET,
+                pending: None,
+            };
+            // 接口描述块位于第一个数据包之前，预读第一个数据包以确定链路层类型
+            let first = capture_reader.read_packet().transpose()?;
+            if let CaptureReader::PcapNg { pending, .. } = &mut capture_reader {
+                *pending = first;
+            }
+            Ok(capture_reader)
+        } else {
+            Ok(CaptureReader::Pcap(
+                PcapReader::new(reader).map_err(pcap_file_error)?,
+            ))
+        }
+    }
+
+    pub fn datalink(&self) -> DataLink {
+        match self {
+            CaptureReader::Pcap(reader) => reader.header().datalink,
+            CaptureReader::PcapNg { datalink, .. } => *datalink,
+        }
+    }
+
+    pub fn read_packet(&mut self) -> Option<Result<SourcePacket, SavePcapError>> {
+        match self {
+            CaptureReader::Pcap(reader) => loop {
+                match reader.next_packet()? {
+                    // `heartbeat_interval_seconds`写入的心跳记录，不是数据包
+                    Ok(packet) if is_heartbeat(packet.orig_len, &packet.data) => {}
+                    Ok(packet) => {
+                        return Some(Ok(SourcePacket {
+                            timestamp: packet.timestamp,
+                            orig_len: packet.orig_len,
+                            data: packet.data.into_owned(),
+                            user_index: None,
+                        }));
+                    }
+                    Err(e) => return Some(Err(pcap_file_error(e))),
+                }
+            },
+            CaptureReader::PcapNg {
+                reader,
+                datalink,
+                pending,
+            } => loop {
+                if let Some(packet) = pending.take() {
+                    return Some(Ok(packet));
+                }
+                match reader.next_block()? {
+                    Ok(Block::EnhancedPacket(packet)) => {
+                        return Some(Ok(SourcePacket {
+                            timestamp: packet.timestamp,
+                            orig_len: packet.original_len,
+                            data: packet.data.into_owned(),
+                            user_index: None,
+                        }));
+                    }
+                    Ok(Block::InterfaceDescription(interface)) => *datalink = interface.linktype,
+                    Ok(_) => {}
+                    Err(e) => return Some(Err(pcap_file_error(e))),
+                }
+            },
+        }
+    }
+}
+
+/// pcap文件中的心跳记录：抓取长度和原始长度都为0，真实的链路不会产生这样的数据包。
+/// pcapng的心跳是接口统计块，本来就不会作为数据包读出
+pub(crate) fn is_heartbeat(orig_len: u32, data: &[u8]) -> bool {
+    orig_len == 0 && data.is_empty()
+}
+
+impl<R: BufRead> PacketStream for CaptureReader<R> {
+    fn datalink(&self) -> DataLink {
+        CaptureReader::datalink(self)
+    }
+
+    fn next_packet(&mut self, _pool: &mut BufferPool) -> Result<NextPacket, SavePcapError> {
+        match self.read_packet() {
+            Some(packet) => Ok(NextPacket::Packet(packet?)),
+            None => Ok(NextPacket::End),
+        }
+    }
+}
+
+// 在用户态按BPF过滤器筛选数据来源的数据包，用于不经过libpcap读取的pcap/pcapng流
+pub(crate) struct FilteredStream<'a> {
+    inner: &'a mut dyn PacketStream,
+    filter: BpfProgram,
+}
+
+impl<'a> FilteredStream<'a> {
+    pub fn new(inner: &'a mut dyn PacketStream, filter: BpfProgram) -> Self {
+        Self { inner, filter }
+    }
+}
+
+impl PacketStream for FilteredStream<'_> {
+    fn datalink(&self) -> DataLink {
+        self.inner.datalink()
+    }
+
+    fn next_packet(&mut self, pool: &mut BufferPool) -> Result<NextPacket, SavePcapError> {
+        loop {
+            match self.inner.next_packet(pool)? {
+                NextPacket::Packet(packet) if !self.filter.filter(&packet.data) => {
+                    pool.give(packet.data);
+                }
+                next => return Ok(next),
+            }
+        }
+    }
+}
+
+pub(crate) fn pcap_file_error(e: pcap_file::PcapError) -> SavePcapError {
+    SavePcapError::PcapFileError(e.to_string())
+}
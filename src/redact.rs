@@ -0,0 +1,150 @@
+use crate::parse;
+use pcap_file::DataLink;
+
+/// 需要遮盖的内容
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RedactionPattern {
+    /// 固定的字节序列，整段遮盖，例如已知的密码或内部主机名
+    Bytes(Vec<u8>),
+    /// 紧跟在该前缀之后的令牌：遮盖前缀后连续的字母、数字和`-`、`_`、`.`、`~`、`+`、`/`、`=`，
+    /// 前缀本身保留，例如`Authorization: Bearer `或`sk_live_`
+    TokenAfter(Vec<u8>),
+    /// 13到19位、通过Luhn校验的银行卡号，数字之间可以有单个空格或`-`
+    CardNumber,
+}
+
+/// 写入文件前遮盖数据包中的敏感内容，见`PcapCaptureOptions::redaction`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedactionOptions {
+    pub patterns: Vec<RedactionPattern>,
+    /// 替换匹配内容的字节，默认为`*`
+    pub mask: u8,
+}
+
+impl RedactionOptions {
+    pub fn new(patterns: Vec<RedactionPattern>) -> Self {
+        Self {
+            patterns,
+            mask: b'*',
+        }
+    }
+
+    /// 遮盖TCP和UDP载荷中匹配的内容（其他数据包在整个数据包中查找），返回遮盖后的副本和遮盖的处数。
+    /// 按原长度替换，数据包长度不变；没有匹配时返回None，不复制数据
+    pub(crate) fn redact(&self, datalink: DataLink, data: &[u8]) -> Option<(Vec<u8>, u64)> {
+        let range = match parse::transport(datalink, data) {
+            Some(transport) => {
+                let start = transport.payload.as_ptr() as usize - data.as_ptr() as usize;
+                start..start + transport.payload.len()
+            }
+            None => 0..data.len(),
+        };
+        // 第一处匹配时才复制，之后的模式在已遮盖的副本中查找
+        let mut redacted: Option<Vec<u8>> = None;
+        let mut count = 0;
+        for pattern in &self.patterns {
+            let mut position = 0;
+            loop {
+                let region = &redacted.as_deref().unwrap_or(data)[range.clone()];
+                let Some((start, end)) = find(pattern, region, position) else {
+                    break;
+                };
+                let copy = redacted.get_or_insert_with(|| data.to_vec());
+                copy[range.start + start..range.start + end].fill(self.mask);
+                count += 1;
+                position = end;
+            }
+        }
+        redacted.map(|redacted| (redacted, count))
+    }
+}
+
+// 从from开始查找下一处需要遮盖的范围
+fn find(pattern: &RedactionPattern, data: &[u8], from: usize) -> Option<(usize, usize)> {
+    match pattern {
+        RedactionPattern::Bytes(bytes) => {
+            let start = find_bytes(data, bytes, from)?;
+            Some((start, start + bytes.len()))
+        }
+        RedactionPattern::TokenAfter(prefix) => {
+            let mut from = from;
+            loop {
+                let start = find_bytes(data, prefix, from)? + prefix.len();
+                let len = data[start..]
+                    .iter()
+                    .take_while(|&&byte| is_token_byte(byte))
+                    .count();
+                if len > 0 {
+                    return Some((start, start + len));
+                }
+                from = start;
+            }
+        }
+        RedactionPattern::CardNumber => find_card_number(data, from),
+    }
+}
+
+fn find_bytes(data: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    if needle.is_empty() || from >= data.len() {
+        return None;
+    }
+    data[from..]
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|position| from + position)
+}
+
+fn is_token_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"-_.~+/=".contains(&byte)
+}
+
+const CARD_MIN_DIGITS: usize = 13;
+const CARD_MAX_DIGITS: usize = 19;
+
+// 前后紧邻其他数字的序列不算卡号，避免遮盖更长的数字串中的一段
+fn find_card_number(data: &[u8], from: usize) -> Option<(usize, usize)> {
+    let mut start = from;
+    while start < data.len() {
+        if !data[start].is_ascii_digit() || (start > 0 && data[start - 1].is_ascii_digit()) {
+            start += 1;
+            continue;
+        }
+        // 收集数字，允许数字之间有单个分隔符
+        let mut digits = Vec::with_capacity(CARD_MAX_DIGITS);
+        let mut end = start;
+        let mut index = start;
+        while index < data.len() && digits.len() <= CARD_MAX_DIGITS {
+            match data[index] {
+                digit @ b'0'..=b'9' => {
+                    digits.push(digit - b'0');
+                    index += 1;
+                    end = index;
+                }
+                b' ' | b'-' if data.get(index + 1).is_some_and(u8::is_ascii_digit) => index += 1,
+                _ => break,
+            }
+        }
+        if (CARD_MIN_DIGITS..=CARD_MAX_DIGITS).contains(&digits.len()) && luhn(&digits) {
+            return Some((start, end));
+        }
+        start = end.max(start + 1);
+    }
+    None
+}
+
+fn luhn(digits: &[u8]) -> bool {
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(index, &digit)| {
+            let digit = digit as u32;
+            match index % 2 {
+                0 => digit,
+                _ if digit * 2 > 9 => digit * 2 - 9,
+                _ => digit * 2,
+            }
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
@@ -0,0 +1,151 @@
+use crate::source::SourcePacket;
+use crate::stats::StatsCounters;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// 用户提供的数据包时间戳倒退时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimestampPolicy {
+    /// 按原样写入
+    #[default]
+    Accept,
+    /// 早于上一个数据包的时间戳改为上一个数据包的时间戳
+    Clamp,
+    /// 数据包在缓冲区中最多等待`window`（按数据包时间和实际时间计算，先到者为准），
+    /// 按时间戳排序后写入，适合多个线程各自带时间戳发送数据包的情况。
+    /// 缓冲区最多保存`max_packets`个数据包，已满或缓存的数据包超过`memory_limit_bytes`时
+    /// 提前写出最早的数据包；
+    /// 超出窗口或缓冲区仍然迟到的数据包按`Clamp`处理
+    Reorder {
+        window: Duration,
+        max_packets: usize,
+    },
+}
+
+// 缓冲区中的数据包，时间戳相同时按到达顺序
+struct Pending {
+    packet: SourcePacket,
+    arrival: u64,
+    received_at: Instant,
+}
+
+impl Pending {
+    fn key(&self) -> (Duration, u64) {
+        (self.packet.timestamp, self.arrival)
+    }
+}
+
+impl PartialEq for Pending {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Pending {}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Pending {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+// 按策略调整时间戳，保证写出的时间戳不倒退（Accept除外）
+pub(crate) struct TimestampOrder {
+    policy: TimestampPolicy,
+    heap: BinaryHeap<Reverse<Pending>>,
+    arrivals: u64,
+    // 已收到的最新时间戳和已写出的最新时间戳
+    newest: Duration,
+    last_released: Option<Duration>,
+    memory_limit: Option<u64>,
+    stats: Arc<StatsCounters>,
+}
+
+impl TimestampOrder {
+    pub fn new(
+        policy: TimestampPolicy,
+        memory_limit: Option<u64>,
+        stats: Arc<StatsCounters>,
+    ) -> Self {
+        Self {
+            policy,
+            heap: BinaryHeap::new(),
+            arrivals: 0,
+            newest: Duration::ZERO,
+            last_released: None,
+            memory_limit,
+            stats,
+        }
+    }
+
+    /// 缓冲区中还有数据包时，等待新数据包的时间不应超过窗口
+    pub fn max_wait(&self) -> Option<Duration> {
+        match self.policy {
+            TimestampPolicy::Reorder { window, .. } if !self.heap.is_empty() => Some(window),
+            _ => None,
+        }
+    }
+
+    /// 接收一个数据包，返回可以立即写出的数据包（Reorder策略下可能需要继续等待）
+    pub fn push(&mut self, packet: SourcePacket) -> Option<SourcePacket> {
+        let TimestampPolicy::Reorder { max_packets, .. } = self.policy else {
+            return Some(self.release(packet));
+        };
+        self.newest = self.newest.max(packet.timestamp);
+        self.stats.reserve_memory(packet.memory_size(), None);
+        self.heap.push(Reverse(Pending {
+            packet,
+            arrival: self.arrivals,
+            received_at: Instant::now(),
+        }));
+        self.arrivals += 1;
+        let over_memory = self
+            .memory_limit
+            .is_some_and(|limit| self.stats.memory_used() > limit);
+        if self.heap.len() > max_packets || over_memory {
+            return self.pop_any();
+        }
+        self.pop_ready()
+    }
+
+    /// 已在窗口外、可以写出的最早的数据包
+    pub fn pop_ready(&mut self) -> Option<SourcePacket> {
+        let TimestampPolicy::Reorder { window, .. } = self.policy else {
+            return None;
+        };
+        let Reverse(oldest) = self.heap.peek()?;
+        let ready = oldest.packet.timestamp + window <= self.newest
+            || oldest.received_at.elapsed() >= window;
+        if !ready {
+            return None;
+        }
+        self.pop_any()
+    }
+
+    /// 不论窗口，取出缓冲区中最早的数据包，用于数据来源结束或停止捕获时
+    pub fn pop_any(&mut self) -> Option<SourcePacket> {
+        let Reverse(oldest) = self.heap.pop()?;
+        self.stats.release_memory(oldest.packet.memory_size());
+        Some(self.release(oldest.packet))
+    }
+
+    fn release(&mut self, mut packet: SourcePacket) -> SourcePacket {
+        if self.policy != TimestampPolicy::Accept
+            && let Some(last) = self.last_released
+            && packet.timestamp < last
+        {
+            packet.timestamp = last;
+            self.stats.record_late();
+        }
+        self.last_released = Some(packet.timestamp);
+        packet
+    }
+}
@@ -0,0 +1,277 @@
+use crate::SavePcapError;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const PCAP_HEADER_LEN: u64 = 24;
+const PCAP_RECORD_HEADER_LEN: u64 = 16;
+const PCAP_SNAPLEN_OFFSET: u64 = 16;
+// 文件头不完整时补写的默认值：pcap 2.4版本，快照长度65535，以太网链路层
+const DEFAULT_PCAP_SNAPLEN: u32 = 65535;
+const DEFAULT_PCAP_LINKTYPE: u32 = 1;
+
+const PCAPNG_SHB_TYPE: u32 = 0x0A0D0D0A;
+const PCAPNG_MIN_SHB_LEN: u64 = 28;
+const PCAPNG_SECTION_LENGTH_OFFSET: u64 = 16;
+// 数据包块：已废弃的Packet Block、Simple Packet Block、Enhanced Packet Block
+const PCAPNG_PACKET_BLOCK_TYPES: [u32; 3] = [0x2, 0x3, 0x6];
+
+/// 修复结果
+#[derive(Debug, Clone, Default)]
+pub struct RepairReport {
+    /// 修复前的文件长度
+    pub original_len: u64,
+    /// 修复后的文件长度
+    pub repaired_len: u64,
+    /// 修复后文件中完整的数据包数量
+    pub packets: u64,
+    /// 是否改写了文件头（补全不完整的文件头，或修正与内容不一致的字段）
+    pub header_rewritten: bool,
+}
+
+impl RepairReport {
+    pub fn is_modified(&self) -> bool {
+        self.repaired_len != self.original_len || self.header_rewritten
+    }
+}
+
+/// 修复异常中断（进程崩溃、断电、磁盘已满）时留下的pcap或pcapng文件：
+/// 截断末尾不完整的数据包或块，并在需要时改写文件头，使文件可以正常打开。
+///
+/// 完好的文件不会被修改。文件过短、无法判断格式时截断为空文件。
+pub fn repair<P: AsRef<Path>>(path: P) -> Result<RepairReport, SavePcapError> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path.as_ref())?;
+    let original_len = file.metadata()?.len();
+
+    let mut magic = [0u8; 4];
+    let mut report = if original_len < magic.len() as u64 {
+        RepairReport::default()
+    } else {
+        file.read_exact(&mut magic)?;
+        match magic {
+            [0xA1, 0xB2, 0xC3, 0xD4] | [0xA1, 0xB2, 0x3C, 0x4D] => {
+                repair_pcap(&mut file, original_len, Endian::Big)?
+            }
+            [0xD4, 0xC3, 0xB2, 0xA1] | [0x4D, 0x3C, 0xB2, 0xA1] => {
+                repair_pcap(&mut file, original_len, Endian::Little)?
+            }
+            [0x0A, 0x0D, 0x0D, 0x0A] => repair_pcapng(&mut file, original_len)?,
+            _ => {
+                return Err(SavePcapError::PcapFileError(format!(
+                    "{} is not a pcap or pcapng file",
+                    path.as_ref().display()
+                )));
+            }
+        }
+    };
+    report.original_len = original_len;
+
+    if report.repaired_len < original_len {
+        file.set_len(report.repaired_len)?;
+    }
+
+    Ok(report)
+}
+
+#[derive(Clone, Copy)]
+pub(crate) enum Endian {
+    Big,
+    Little,
+}
+
+impl Endian {
+    pub(crate) fn u32(self, bytes: [u8; 4]) -> u32 {
+        match self {
+            Endian::Big => u32::from_be_bytes(bytes),
+            Endian::Little => u32::from_le_bytes(bytes),
+        }
+    }
+
+    pub(crate) fn u16(self, bytes: [u8; 2]) -> u16 {
+        match self {
+            Endian::Big => u16::from_be_bytes(bytes),
+            Endian::Little => u16::from_le_bytes(bytes),
+        }
+    }
+
+    fn u32_bytes(self, value: u32) -> [u8; 4] {
+        match self {
+            Endian::Big => value.to_be_bytes(),
+            Endian::Little => value.to_le_bytes(),
+        }
+    }
+
+    pub(crate) fn i64(self, bytes: [u8; 8]) -> i64 {
+        match self {
+            Endian::Big => i64::from_be_bytes(bytes),
+            Endian::Little => i64::from_le_bytes(bytes),
+        }
+    }
+
+    fn i64_bytes(self, value: i64) -> [u8; 8] {
+        match self {
+            Endian::Big => value.to_be_bytes(),
+            Endian::Little => value.to_le_bytes(),
+        }
+    }
+}
+
+fn repair_pcap(file: &mut File, file_len: u64, endian: Endian) -> io::Result<RepairReport> {
+    let mut report = RepairReport::default();
+
+    // 文件头写到一半：没有任何数据包，补写完整的默认文件头（保留原有的字节序和时间精度）
+    if file_len < PCAP_HEADER_LEN {
+        let version = match endian {
+            Endian::Big => [0x00, 0x02, 0x00, 0x04],
+            Endian::Little => [0x02, 0x00, 0x04, 0x00],
+        };
+        file.seek(SeekFrom::Start(4))?;
+        file.write_all(&version)?;
+        file.write_all(&[0u8; 8])?;
+        file.write_all(&endian.u32_bytes(DEFAULT_PCAP_SNAPLEN))?;
+        file.write_all(&endian.u32_bytes(DEFAULT_PCAP_LINKTYPE))?;
+        report.repaired_len = PCAP_HEADER_LEN;
+        report.header_rewritten = true;
+        return Ok(report);
+    }
+
+    let mut reader = BufReader::new(&*file);
+    reader.seek(SeekFrom::Start(PCAP_SNAPLEN_OFFSET))?;
+    let mut snaplen = [0u8; 4];
+    reader.read_exact(&mut snaplen)?;
+    let snaplen = endian.u32(snaplen);
+
+    let mut offset = PCAP_HEADER_LEN;
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut record_header = [0u8; PCAP_RECORD_HEADER_LEN as usize];
+    let mut max_incl_len = 0;
+    loop {
+        if offset + PCAP_RECORD_HEADER_LEN > file_len {
+            break;
+        }
+        reader.read_exact(&mut record_header)?;
+
+        let incl_len = endian.u32([
+            record_header[8],
+            record_header[9],
+            record_header[10],
+            record_header[11],
+        ]);
+        let record_end = offset + PCAP_RECORD_HEADER_LEN + incl_len as u64;
+        if record_end > file_len {
+            break;
+        }
+        // 相对跳过数据包内容，保留BufReader中已读取的数据
+        reader.seek_relative(incl_len as i64)?;
+
+        offset = record_end;
+        report.packets += 1;
+        max_incl_len = max_incl_len.max(incl_len);
+    }
+    drop(reader);
+    report.repaired_len = offset;
+
+    // 快照长度小于实际数据包长度时，部分工具会拒绝读取
+    if max_incl_len > snaplen {
+        file.seek(SeekFrom::Start(PCAP_SNAPLEN_OFFSET))?;
+        file.write_all(&endian.u32_bytes(max_incl_len))?;
+        report.header_rewritten = true;
+    }
+
+    Ok(report)
+}
+
+fn repair_pcapng(file: &mut File, file_len: u64) -> io::Result<RepairReport> {
+    let mut report = RepairReport::default();
+    if file_len < PCAPNG_MIN_SHB_LEN {
+        return Ok(report);
+    }
+
+    let mut reader = BufReader::new(&*file);
+    // 每个节头块的位置、字节序、记录的节长度；节长度为-1表示未指定
+    let mut sections: Vec<(u64, Endian, i64)> = Vec::new();
+    let mut endian = Endian::Little;
+    let mut offset = 0;
+    reader.seek(SeekFrom::Start(0))?;
+
+    while offset + 12 <= file_len {
+        let mut head = [0u8; 8];
+        reader.read_exact(&mut head)?;
+        let raw_type = [head[0], head[1], head[2], head[3]];
+        let raw_len = [head[4], head[5], head[6], head[7]];
+
+        let mut section = None;
+        if u32::from_le_bytes(raw_type) == PCAPNG_SHB_TYPE {
+            // 节头块中的字节序标识决定本节内所有字段的字节序
+            let mut shb_fields = [0u8; 16];
+            if offset + 8 + 16 > file_len {
+                break;
+            }
+            reader.read_exact(&mut shb_fields)?;
+            endian = match shb_fields[..4] {
+                [0x1A, 0x2B, 0x3C, 0x4D] => Endian::Big,
+                [0x4D, 0x3C, 0x2B, 0x1A] => Endian::Little,
+                _ => break,
+            };
+            let section_length = endian.i64(shb_fields[8..16].try_into().unwrap());
+            section = Some((offset, endian, section_length));
+            reader.seek_relative(-16)?;
+        } else if sections.is_empty() {
+            break;
+        }
+
+        let block_len = endian.u32(raw_len) as u64;
+        let block_end = offset + block_len;
+        if block_len < 12 || !block_len.is_multiple_of(4) || block_end > file_len {
+            break;
+        }
+
+        // 块末尾重复的长度字段与开头一致才算完整写入
+        reader.seek_relative(block_len as i64 - 12)?;
+        let mut trailing = [0u8; 4];
+        reader.read_exact(&mut trailing)?;
+        if endian.u32(trailing) as u64 != block_len {
+            break;
+        }
+
+        if let Some(section) = section {
+            sections.push(section);
+        } else if PCAPNG_PACKET_BLOCK_TYPES.contains(&endian.u32(raw_type)) {
+            report.packets += 1;
+        }
+        offset = block_end;
+    }
+    drop(reader);
+
+    // 没有完整的节头块，文件无法使用
+    if sections.is_empty() {
+        return Ok(report);
+    }
+    report.repaired_len = offset;
+
+    // 截断后记录的节长度不再准确，改为未指定
+    for (index, (shb_offset, endian, section_length)) in sections.iter().enumerate() {
+        let section_end = sections
+            .get(index + 1)
+            .map(|(next_offset, _, _)| *next_offset)
+            .unwrap_or(report.repaired_len);
+        let shb_len = {
+            let mut raw_len = [0u8; 4];
+            file.seek(SeekFrom::Start(shb_offset + 4))?;
+            file.read_exact(&mut raw_len)?;
+            endian.u32(raw_len) as u64
+        };
+        let actual = section_end.saturating_sub(shb_offset + shb_len) as i64;
+
+        if *section_length != -1 && *section_length != actual {
+            file.seek(SeekFrom::Start(shb_offset + PCAPNG_SECTION_LENGTH_OFFSET))?;
+            file.write_all(&endian.i64_bytes(-1))?;
+            report.header_rewritten = true;
+        }
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,155 @@
+use crate::SavePcapError;
+use crate::diagnose;
+use crate::reader::CaptureReader;
+use log::{debug, info};
+use pcap::{Capture, Device};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const MIN_SPEED: f64 = 0.1;
+const MAX_SPEED: f64 = 100.0;
+
+// 精确模式下先休眠到发送时间之前这么久，剩余的时间忙等，避开操作系统定时器的误差
+const SPIN_THRESHOLD: Duration = Duration::from_millis(2);
+
+/// 重放时数据包之间的间隔
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReplayTiming {
+    /// 不等待，尽快发送所有数据包
+    AsFastAsPossible,
+    /// 按原始时间戳的间隔发送，通过休眠等待，间隔的误差取决于操作系统定时器（通常在毫秒级）
+    #[default]
+    Original,
+    /// 按原始时间戳的间隔发送，最后2毫秒忙等以还原亚毫秒级的间隔，等待期间占满一个CPU核心
+    Accurate,
+}
+
+/// 重放选项
+#[derive(Debug, Clone)]
+pub struct ReplayOptions {
+    pub timing: ReplayTiming,
+    /// 速度倍数（0.1到100），例如2.0表示间隔缩短一半；对`AsFastAsPossible`无效
+    pub speed: f64,
+    /// 重放整个文件的次数，0表示一直重放，直到发送出错。两次之间不等待
+    pub loop_count: u32,
+}
+
+impl Default for ReplayOptions {
+    fn default() -> Self {
+        Self {
+            timing: ReplayTiming::default(),
+            speed: 1.0,
+            loop_count: 1,
+        }
+    }
+}
+
+/// 重放结果
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplayReport {
+    /// 完整重放文件的次数
+    pub loops: u32,
+    pub packets: u64,
+    pub bytes: u64,
+    /// 从发送第一个数据包到发送最后一个数据包的时间
+    pub elapsed: Duration,
+    /// 实际发送时间比按（调整速度后的）原始间隔计算的时间晚得最多的一次，
+    /// 用于判断重放是否跟得上目标速率
+    pub max_lag: Duration,
+}
+
+/// 从网卡发送捕获文件中的数据包（pcap或pcapng），数据包之间的间隔由`options.timing`决定。
+/// 需要发送原始帧的权限（Linux下为root或CAP_NET_RAW）
+pub fn replay<P: AsRef<Path>>(
+    input: P,
+    device_name: &str,
+    options: &ReplayOptions,
+) -> Result<ReplayReport, SavePcapError> {
+    let devices = Device::list().map_err(|e| diagnose::device_error(device_name, e))?;
+    if !devices.iter().any(|d| d.name == device_name) {
+        return Err(diagnose::missing_device_error(device_name));
+    }
+    let mut capture = Capture::from_device(device_name)
+        .and_then(|capture| capture.open())
+        .map_err(|e| diagnose::device_error(device_name, e))?;
+    let report = replay_with(input, options, |data| Ok(capture.sendpacket(data)?))?;
+    info!(
+        "Replayed {} packets ({} bytes) on {} in {:?}",
+        report.packets, report.bytes, device_name, report.elapsed
+    );
+    Ok(report)
+}
+
+/// 与`replay`相同，但由send发送每个数据包，例如通过原始套接字或写入另一个进程
+pub fn replay_with<P, F>(
+    input: P,
+    options: &ReplayOptions,
+    mut send: F,
+) -> Result<ReplayReport, SavePcapError>
+where
+    P: AsRef<Path>,
+    F: FnMut(&[u8]) -> Result<(), SavePcapError>,
+{
+    if !(MIN_SPEED..=MAX_SPEED).contains(&options.speed) {
+        return Err(SavePcapError::InvalidReplayOptions(format!(
+            "speed {} is outside {}..={}",
+            options.speed, MIN_SPEED, MAX_SPEED
+        )));
+    }
+
+    let mut report = ReplayReport::default();
+    let mut replay_started = None;
+    while options.loop_count == 0 || report.loops < options.loop_count {
+        let mut reader = CaptureReader::open(input.as_ref())?;
+        // 本轮第一个数据包的时间戳和发送时间，之后的数据包按与它的时间差计算发送时间，误差不会累积
+        let mut origin: Option<(Duration, Instant)> = None;
+
+        while let Some(packet) = reader.read_packet() {
+            let packet = packet?;
+            let now = Instant::now();
+            let (first_timestamp, started) = *origin.get_or_insert((packet.timestamp, now));
+            let replay_started = *replay_started.get_or_insert(now);
+
+            if options.timing != ReplayTiming::AsFastAsPossible {
+                // 时间戳倒退的数据包立即发送
+                let gap = packet.timestamp.saturating_sub(first_timestamp);
+                let due = started + gap.div_f64(options.speed);
+                wait_until(due, options.timing);
+                report.max_lag = report
+                    .max_lag
+                    .max(Instant::now().saturating_duration_since(due));
+            }
+
+            send(&packet.data)?;
+            report.packets += 1;
+            report.bytes += packet.data.len() as u64;
+            report.elapsed = replay_started.elapsed();
+        }
+        report.loops += 1;
+        // 没有数据包的文件不会无限循环
+        if report.packets == 0 {
+            break;
+        }
+    }
+
+    debug!(
+        "Replay finished: {} packets, max lag {:?}",
+        report.packets, report.max_lag
+    );
+    Ok(report)
+}
+
+fn wait_until(due: Instant, timing: ReplayTiming) {
+    let spin = match timing {
+        ReplayTiming::Accurate => SPIN_THRESHOLD,
+        _ => Duration::ZERO,
+    };
+    let remaining = due.saturating_duration_since(Instant::now());
+    if remaining > spin {
+        thread::sleep(remaining - spin);
+    }
+    while Instant::now() < due {
+        std::hint::spin_loop();
+    }
+}
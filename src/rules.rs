@@ -0,0 +1,251 @@
+use crate::SavePcapError;
+use crate::parse::{IPPROTO_TCP, IPPROTO_UDP, ip_addresses, ip_protocol, transport, vlan_id};
+use pcap_file::DataLink;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+
+const IPPROTO_ICMP: u8 = 1;
+const IPPROTO_ICMPV6: u8 = 58;
+
+/// 分类规则的一个匹配条件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuleMatch {
+    /// 源端口或目的端口在该范围内（仅TCP和UDP）
+    Port { low: u16, high: u16 },
+    /// 源地址或目的地址在该网段内
+    Network { address: IpAddr, prefix_len: u8 },
+    /// 最外层VLAN标签的VLAN ID
+    Vlan(u16),
+    /// IP协议号，例如6为TCP
+    Protocol(u8),
+}
+
+/// 一条分类规则：所有条件都满足时，数据包写入名为output的输出；没有条件的规则匹配所有数据包
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub matches: Vec<RuleMatch>,
+    pub output: String,
+}
+
+/// 按顺序检查的分类规则，每个数据包写入第一条匹配规则的输出，都不匹配时不写入任何规则输出。
+/// 每个输出名对应一组文件，文件前缀为输出名，其余设置沿用主输出。
+///
+/// 可以从文本解析，每行一条规则，条件之间用`and`连接，`#`开始注释：
+///
+/// ```text
+/// vlan 100 -> voice
+/// udp and port 5060-5061 -> sip
+/// net 10.0.0.0/8 and tcp -> internal
+/// any -> other
+/// ```
+///
+/// 条件为`port N`或`port N-M`、`net ADDR/LEN`（也可只写地址）、`vlan N`、`proto NAME|N`，
+/// 以及`tcp`、`udp`、`icmp`、`icmp6`等协议名的简写
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ClassificationRules {
+    pub rules: Vec<Rule>,
+}
+
+impl ClassificationRules {
+    /// 从规则文件读取
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SavePcapError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| {
+            SavePcapError::InvalidRules(format!("failed to read {}: {}", path.display(), e))
+        })?;
+        text.parse()
+    }
+
+    // 所有输出名，按第一次出现的顺序
+    pub(crate) fn outputs(&self) -> Vec<&str> {
+        let mut outputs: Vec<&str> = Vec::new();
+        for rule in &self.rules {
+            if !outputs.contains(&rule.output.as_str()) {
+                outputs.push(&rule.output);
+            }
+        }
+        outputs
+    }
+
+    // 每条规则对应的输出在`outputs()`中的序号
+    pub(crate) fn routes(&self) -> Vec<usize> {
+        let outputs = self.outputs();
+        self.rules
+            .iter()
+            .map(|rule| {
+                outputs
+                    .iter()
+                    .position(|output| *output == rule.output)
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+
+    // 返回第一条匹配规则的序号
+    pub(crate) fn classify(&self, datalink: DataLink, data: &[u8]) -> Option<usize> {
+        let packet = Fields::parse(datalink, data);
+        self.rules
+            .iter()
+            .position(|rule| rule.matches.iter().all(|m| packet.matches(m)))
+    }
+}
+
+impl FromStr for ClassificationRules {
+    type Err = SavePcapError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut rules = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let rule = parse_rule(line).map_err(|reason| {
+                SavePcapError::InvalidRules(format!("line {}: {}", number + 1, reason))
+            })?;
+            rules.push(rule);
+        }
+        Ok(Self { rules })
+    }
+}
+
+pub(crate) fn parse_rule(line: &str) -> Result<Rule, String> {
+    let (conditions, output) = line
+        .split_once("->")
+        .ok_or_else(|| format!("expected \"<conditions> -> <output>\" in {:?}", line))?;
+    let output = output.trim();
+    if output.is_empty() || output.contains(['/', '\\']) || output.contains(char::is_whitespace) {
+        return Err(format!("invalid output name {:?}", output));
+    }
+
+    let mut matches = Vec::new();
+    let words: Vec<&str> = conditions.split_whitespace().collect();
+    if words != ["any"] && words != ["*"] {
+        for condition in words.split(|word| *word == "and") {
+            matches.push(parse_condition(condition)?);
+        }
+    }
+    Ok(Rule {
+        matches,
+        output: output.to_string(),
+    })
+}
+
+fn parse_condition(words: &[&str]) -> Result<RuleMatch, String> {
+    match words {
+        ["port", ports] => {
+            let (low, high) = ports.split_once('-').unwrap_or((ports, ports));
+            let port = |text: &str| {
+                text.parse::<u16>()
+                    .map_err(|_| format!("invalid port {:?}", text))
+            };
+            let (low, high) = (port(low)?, port(high)?);
+            if low > high {
+                return Err(format!("invalid port range {}", ports));
+            }
+            Ok(RuleMatch::Port { low, high })
+        }
+        ["net", network] => {
+            let (address, prefix_len) = match network.split_once('/') {
+                Some((address, prefix_len)) => (address, Some(prefix_len)),
+                None => (*network, None),
+            };
+            let address: IpAddr = address
+                .parse()
+                .map_err(|_| format!("invalid address {:?}", address))?;
+            let max_len = if address.is_ipv4() { 32 } else { 128 };
+            let prefix_len = match prefix_len {
+                Some(len) => len
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|len| *len <= max_len)
+                    .ok_or_else(|| format!("invalid prefix length {:?}", len))?,
+                None => max_len,
+            };
+            Ok(RuleMatch::Network {
+                address,
+                prefix_len,
+            })
+        }
+        ["vlan", id] => id
+            .parse::<u16>()
+            .ok()
+            .filter(|id| *id < 4096)
+            .map(RuleMatch::Vlan)
+            .ok_or_else(|| format!("invalid VLAN ID {:?}", id)),
+        ["proto", protocol] => protocol_number(protocol)
+            .or_else(|| protocol.parse().ok())
+            .map(RuleMatch::Protocol)
+            .ok_or_else(|| format!("unknown protocol {:?}", protocol)),
+        [protocol] => protocol_number(protocol)
+            .map(RuleMatch::Protocol)
+            .ok_or_else(|| format!("unknown condition {:?}", protocol)),
+        _ => Err(format!("unknown condition {:?}", words.join(" "))),
+    }
+}
+
+fn protocol_number(name: &str) -> Option<u8> {
+    match name {
+        "icmp" => Some(IPPROTO_ICMP),
+        "tcp" => Some(IPPROTO_TCP),
+        "udp" => Some(IPPROTO_UDP),
+        "gre" => Some(47),
+        "esp" => Some(50),
+        "icmp6" => Some(IPPROTO_ICMPV6),
+        "sctp" => Some(132),
+        _ => None,
+    }
+}
+
+// 规则用到的字段，每个数据包只解析一次
+struct Fields {
+    vlan: Option<u16>,
+    protocol: Option<u8>,
+    addresses: Option<(IpAddr, IpAddr)>,
+    ports: Option<(u16, u16)>,
+}
+
+impl Fields {
+    fn parse(datalink: DataLink, data: &[u8]) -> Self {
+        Self {
+            vlan: vlan_id(datalink, data),
+            protocol: ip_protocol(datalink, data),
+            addresses: ip_addresses(datalink, data),
+            ports: transport(datalink, data)
+                .map(|transport| (transport.source_port, transport.destination_port)),
+        }
+    }
+
+    fn matches(&self, condition: &RuleMatch) -> bool {
+        match condition {
+            RuleMatch::Port { low, high } => self.ports.is_some_and(|(source, destination)| {
+                (low..=high).contains(&&source) || (low..=high).contains(&&destination)
+            }),
+            RuleMatch::Network {
+                address,
+                prefix_len,
+            } => self.addresses.is_some_and(|(source, destination)| {
+                in_network(source, *address, *prefix_len)
+                    || in_network(destination, *address, *prefix_len)
+            }),
+            RuleMatch::Vlan(id) => self.vlan == Some(*id),
+            RuleMatch::Protocol(protocol) => self.protocol == Some(*protocol),
+        }
+    }
+}
+
+fn in_network(address: IpAddr, network: IpAddr, prefix_len: u8) -> bool {
+    match (address, network) {
+        (IpAddr::V4(address), IpAddr::V4(network)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+            u32::from(address) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(address), IpAddr::V6(network)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0);
+            u128::from(address) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,118 @@
+use crate::parse::{self, ip_addresses, ip_protocol};
+use crate::{PcapCaptureOptions, SavePcapError};
+use pcap_file::DataLink;
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// 检查抽样设置，在创建文件之前调用
+pub(crate) fn validate(options: &PcapCaptureOptions) -> Result<(), SavePcapError> {
+    if let Some(probability) = options.sample_probability
+        && !(0.0..=1.0).contains(&probability)
+    {
+        return Err(SavePcapError::InvalidSampling(format!(
+            "sample_probability must be between 0 and 1, got {}",
+            probability
+        )));
+    }
+    if options.flow_sample_every == Some(0) {
+        return Err(SavePcapError::InvalidSampling(
+            "flow_sample_every must be at least 1".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+// 按概率独立地决定每个数据包是否保留，相同的种子和数据包序列得到相同的结果
+pub(crate) struct Sampler {
+    probability: f64,
+    state: u64,
+}
+
+impl Sampler {
+    /// seed为None时使用当前时间，每次捕获的抽样结果不同
+    pub fn new(probability: f64, seed: Option<u64>) -> Self {
+        let seed = seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos() as u64
+                ^ u64::from(std::process::id())
+        });
+        Self {
+            probability,
+            state: seed,
+        }
+    }
+
+    pub fn keep(&mut self) -> bool {
+        // 取高53位得到[0, 1)内均匀分布的浮点数
+        let random = (self.next_random() >> 11) as f64 / (1u64 << 53) as f64;
+        random < self.probability
+    }
+
+    // splitmix64：任意种子（包括0）都能得到分布均匀的序列，不需要密码学强度
+    fn next_random(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        mix(self.state)
+    }
+}
+
+// 按流抽样：同一条流（不分方向）的所有数据包一起保留或丢弃
+pub(crate) struct FlowSampler {
+    every: u64,
+    seed: u64,
+}
+
+impl FlowSampler {
+    pub fn new(every: u64, seed: u64) -> Self {
+        Self { every, seed }
+    }
+
+    /// TCP和UDP按五元组、其他IP协议按地址和协议划分流，非IP数据包全部保留
+    pub fn keep(&self, datalink: DataLink, data: &[u8]) -> bool {
+        let (mut a, mut b, protocol) = match parse::transport(datalink, data) {
+            Some(transport) => (
+                (transport.source, transport.source_port),
+                (transport.destination, transport.destination_port),
+                transport.protocol,
+            ),
+            None => match (ip_addresses(datalink, data), ip_protocol(datalink, data)) {
+                (Some((source, destination)), Some(protocol)) => {
+                    ((source, 0), (destination, 0), protocol)
+                }
+                _ => return true,
+            },
+        };
+        // 两个方向的数据包得到相同的哈希
+        if b < a {
+            std::mem::swap(&mut a, &mut b);
+        }
+        let mut hash = FNV_OFFSET_BASIS;
+        for endpoint in [a, b] {
+            match endpoint.0 {
+                IpAddr::V4(address) => fnv1a(&mut hash, &address.octets()),
+                IpAddr::V6(address) => fnv1a(&mut hash, &address.octets()),
+            }
+            fnv1a(&mut hash, &endpoint.1.to_be_bytes());
+        }
+        fnv1a(&mut hash, &[protocol]);
+        mix(hash ^ self.seed).is_multiple_of(self.every)
+    }
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xCBF2_9CE4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01B3;
+
+fn fnv1a(hash: &mut u64, bytes: &[u8]) {
+    for byte in bytes {
+        *hash ^= u64::from(*byte);
+        *hash = hash.wrapping_mul(FNV_PRIME);
+    }
+}
+
+// splitmix64的输出函数，把相近的输入打散
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
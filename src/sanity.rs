@@ -0,0 +1,128 @@
+use crate::parse::{ETHERNET_HEADER_LEN, ETHERTYPE_IPV4, ETHERTYPE_IPV6, network_layer};
+use crate::source::SourcePacket;
+use pcap_file::DataLink;
+
+const IPV4_MIN_HEADER_LEN: usize = 20;
+const IPV6_HEADER_LEN: usize = 40;
+
+/// 写入前对每个数据包做的合法性检查，用于排查网卡或驱动问题
+#[derive(Debug, Clone)]
+pub struct SanityCheck {
+    /// 小于该长度的帧视为异常
+    pub min_frame_len: usize,
+    /// 校验IPv4头部校验和。本机发出的数据包在启用校验和卸载时校验和尚未填写，会被误判
+    pub verify_ip_checksum: bool,
+    /// 发现异常数据包后的处理方式
+    pub action: InvalidPacketAction,
+}
+
+impl Default for SanityCheck {
+    fn default() -> Self {
+        Self {
+            min_frame_len: ETHERNET_HEADER_LEN,
+            verify_ip_checksum: false,
+            action: InvalidPacketAction::Divert,
+        }
+    }
+}
+
+/// 异常数据包的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidPacketAction {
+    /// 丢弃，不写入任何文件
+    Drop,
+    /// 照常写入，并在数据包上附加说明原因的注释（需要pcapng格式）
+    Tag,
+    /// 写入单独的`{prefix}_errors_*`文件，不进入正常的捕获文件
+    Divert,
+}
+
+impl SanityCheck {
+    /// 返回数据包异常的原因，正常时返回None
+    pub(crate) fn check(&self, datalink: DataLink, packet: &SourcePacket) -> Option<String> {
+        let data = &packet.data;
+        if data.len() < self.min_frame_len {
+            return Some(format!(
+                "frame is {} bytes, shorter than the minimum of {}",
+                data.len(),
+                self.min_frame_len
+            ));
+        }
+        if data.len() > packet.orig_len as usize {
+            return Some(format!(
+                "captured length {} exceeds original length {}",
+                data.len(),
+                packet.orig_len
+            ));
+        }
+
+        // 被快照长度截断的数据包无法判断IP长度字段是否与帧一致
+        let truncated = data.len() < packet.orig_len as usize;
+        match network_layer(datalink, data)? {
+            (ETHERTYPE_IPV4, ip) => self.check_ipv4(ip, truncated),
+            (ETHERTYPE_IPV6, ip) => check_ipv6(ip, truncated),
+            _ => None,
+        }
+    }
+
+    fn check_ipv4(&self, ip: &[u8], truncated: bool) -> Option<String> {
+        if ip.len() < IPV4_MIN_HEADER_LEN {
+            return (!truncated).then(|| format!("IPv4 header truncated to {} bytes", ip.len()));
+        }
+        if ip[0] >> 4 != 4 {
+            return Some(format!("IP version {} in an IPv4 frame", ip[0] >> 4));
+        }
+
+        let header_len = (ip[0] & 0x0F) as usize * 4;
+        let total_len = u16::from_be_bytes([ip[2], ip[3]]) as usize;
+        if header_len < IPV4_MIN_HEADER_LEN || total_len < header_len {
+            return Some(format!(
+                "IPv4 header length {} and total length {} are inconsistent",
+                header_len, total_len
+            ));
+        }
+        if !truncated && total_len > ip.len() {
+            return Some(format!(
+                "IPv4 total length {} exceeds the {} bytes in the frame",
+                total_len,
+                ip.len()
+            ));
+        }
+
+        if self.verify_ip_checksum && ip.len() >= header_len && checksum(&ip[..header_len]) != 0 {
+            return Some("bad IPv4 header checksum".to_string());
+        }
+        None
+    }
+}
+
+fn check_ipv6(ip: &[u8], truncated: bool) -> Option<String> {
+    if ip.len() < IPV6_HEADER_LEN {
+        return (!truncated).then(|| format!("IPv6 header truncated to {} bytes", ip.len()));
+    }
+    if ip[0] >> 4 != 6 {
+        return Some(format!("IP version {} in an IPv6 frame", ip[0] >> 4));
+    }
+
+    let total_len = IPV6_HEADER_LEN + u16::from_be_bytes([ip[4], ip[5]]) as usize;
+    if !truncated && total_len > ip.len() {
+        return Some(format!(
+            "IPv6 payload length {} exceeds the {} bytes in the frame",
+            total_len - IPV6_HEADER_LEN,
+            ip.len() - IPV6_HEADER_LEN
+        ));
+    }
+    None
+}
+
+// 反码求和，头部校验和正确时结果为0
+fn checksum(header: &[u8]) -> u16 {
+    let mut sum: u32 = header
+        .chunks(2)
+        .map(|word| u16::from_be_bytes([word[0], *word.get(1).unwrap_or(&0)]) as u32)
+        .sum();
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
@@ -0,0 +1,99 @@
+use crate::{FileFormat, HistogramOptions, PcapCaptureOptions, SavePcapError};
+use std::fmt;
+use std::str::FromStr;
+
+/// 常见排障场景的预设，一次设置好过滤表达式、快照长度、文件滚动和需要提取的元数据。
+/// 可以通过名称从配置文件或命令行选择，例如`"voip-quality".parse::<Scenario>()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scenario {
+    /// Web应用响应慢：HTTP/HTTPS和DNS流量，建立TLS SNI/HTTP会话索引和DNS查询日志
+    SlowWebApp,
+    /// VoIP通话质量：SIP信令和RTP媒体流，统计到达间隔分布以观察抖动
+    VoipQuality,
+    /// DHCP问题：DHCPv4/DHCPv6和ARP，按小时滚动以便长时间等待偶发故障
+    DhcpIssues,
+}
+
+impl Scenario {
+    pub const ALL: [Scenario; 3] = [
+        Scenario::SlowWebApp,
+        Scenario::VoipQuality,
+        Scenario::DhcpIssues,
+    ];
+
+    /// 配置文件和命令行中使用的名称
+    pub fn name(self) -> &'static str {
+        match self {
+            Scenario::SlowWebApp => "slow-web-app",
+            Scenario::VoipQuality => "voip-quality",
+            Scenario::DhcpIssues => "dhcp-issues",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Scenario::SlowWebApp => "HTTP/HTTPS and DNS with session index and DNS log",
+            Scenario::VoipQuality => "SIP and RTP with inter-arrival histograms for jitter",
+            Scenario::DhcpIssues => "DHCP, DHCPv6 and ARP, rotated hourly",
+        }
+    }
+
+    /// 覆盖`options`中与场景相关的设置（文件前缀、过滤表达式、快照长度、滚动和元数据提取），
+    /// 数据来源和输出目录等其他设置保持不变
+    pub fn apply(self, options: &mut PcapCaptureOptions) {
+        options.file_prefix = self.name().replace('-', "_");
+        options.snaplen = 65535;
+        options.continuous_capture = true;
+        match self {
+            Scenario::SlowWebApp => {
+                options.filter = Some(
+                    "tcp port 80 or tcp port 443 or tcp port 8080 or tcp port 8443 or port 53"
+                        .to_string(),
+                );
+                options.file_format = FileFormat::PcapNg;
+                options.rollover_file_size_mb = Some(100);
+                options.tls_sni_index = true;
+                options.http_request_index = true;
+                options.dns_log = true;
+                options.dns_name_resolution = true;
+                options.top_talkers = Some(20);
+            }
+            Scenario::VoipQuality => {
+                // 常见的RTP端口范围，SIP的TCP/TLS端口同时包含在内
+                options.filter = Some(
+                    "port 5060 or port 5061 or (udp portrange 10000-20000) or (udp portrange 16384-32767)"
+                        .to_string(),
+                );
+                options.rollover_time_seconds = Some(300);
+                options.histograms = Some(HistogramOptions::default());
+                options.protocol_stats = true;
+            }
+            Scenario::DhcpIssues => {
+                options.filter = Some(
+                    "udp port 67 or udp port 68 or udp port 546 or udp port 547 or arp".to_string(),
+                );
+                options.rollover_time_seconds = Some(3600);
+                options.top_talkers = Some(20);
+            }
+        }
+    }
+}
+
+impl fmt::Display for Scenario {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Scenario {
+    type Err = SavePcapError;
+
+    /// 名称不区分大小写，`-`和`_`等价
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let normalized = s.trim().to_ascii_lowercase().replace('_', "-");
+        Scenario::ALL
+            .into_iter()
+            .find(|scenario| scenario.name() == normalized)
+            .ok_or_else(|| SavePcapError::UnknownScenario(s.to_string()))
+    }
+}
@@ -0,0 +1,65 @@
+use crate::stats::SenderCounters;
+use crate::{CaptureHandle, SavePcapError, UserPacket};
+use std::sync::Arc;
+use std::sync::mpsc::{RecvTimeoutError, SendError, Sender, channel};
+use std::time::Duration;
+
+// 等待刷新确认时检查捕获是否已结束的间隔
+const FLUSH_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// 带ID的用户数据包发送端，发送的数据包按ID计入`CaptureReport::senders`。
+/// 每个发送线程应使用各自ID的发送端；克隆的发送端与原发送端共用统计
+#[derive(Debug, Clone)]
+pub struct PacketSender {
+    id: String,
+    sender: Sender<UserPacket>,
+    flushes: Sender<Sender<()>>,
+    handle: CaptureHandle,
+    counters: Arc<SenderCounters>,
+}
+
+impl PacketSender {
+    pub(crate) fn new(
+        id: &str,
+        sender: Sender<UserPacket>,
+        flushes: Sender<Sender<()>>,
+        handle: CaptureHandle,
+    ) -> Self {
+        Self {
+            id: id.to_string(),
+            sender,
+            flushes,
+            counters: handle.stats.register_sender(id),
+            handle,
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// 与`Sender::send`相同，捕获已结束时返回未发送的数据包
+    pub fn send(&self, packet: UserPacket) -> Result<(), SendError<UserPacket>> {
+        let bytes = packet.data.len() as u64;
+        self.sender.send(packet)?;
+        self.counters.record_sent(bytes);
+        Ok(())
+    }
+
+    /// 阻塞直到之前发送的数据包（包括其他发送端已发送的）都已写入所有输出文件并同步到磁盘，
+    /// 之后即可直接读取文件检查内容。捕获尚未开始时等待捕获开始；
+    /// 捕获在确认前结束或写入出错时返回`CaptureInterrupted`
+    pub fn flush(&self) -> Result<(), SavePcapError> {
+        let (ack, done) = channel();
+        self.flushes
+            .send(ack)
+            .map_err(|_| SavePcapError::CaptureInterrupted)?;
+        loop {
+            match done.recv_timeout(FLUSH_POLL_INTERVAL) {
+                Ok(()) => return Ok(()),
+                Err(RecvTimeoutError::Timeout) if !self.handle.state().is_finished() => {}
+                Err(_) => return Err(SavePcapError::CaptureInterrupted),
+            }
+        }
+    }
+}
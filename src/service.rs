@@ -0,0 +1,137 @@
+//! 将捕获会话作为Windows服务运行：服务控制管理器的停止/暂停/继续请求
+//! 会映射到捕获控制句柄上。
+
+use crate::{PcapCapturer, SavePcapError};
+use log::{error, info};
+use std::ffi::OsString;
+use std::sync::OnceLock;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+use windows_service::service::{
+    ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+use windows_service::{define_windows_service, service_dispatcher};
+
+type CapturerFactory = Box<dyn Fn() -> Result<PcapCapturer, SavePcapError> + Send + Sync>;
+
+// 服务入口由系统回调，只能通过静态变量传递服务名和捕获器的创建方式
+static SERVICE_NAME: OnceLock<String> = OnceLock::new();
+static CAPTURER_FACTORY: OnceLock<CapturerFactory> = OnceLock::new();
+
+define_windows_service!(ffi_service_main, service_main);
+
+/// 以Windows服务方式运行捕获，阻塞直到服务停止。
+///
+/// 必须由服务控制管理器启动的进程调用（例如通过`sc create`注册的服务）。
+/// `factory`在服务启动时被调用以创建捕获器，每个进程只能调用一次。
+pub fn run_as_service<F>(service_name: &str, factory: F) -> Result<(), SavePcapError>
+where
+    F: Fn() -> Result<PcapCapturer, SavePcapError> + Send + Sync + 'static,
+{
+    if SERVICE_NAME.set(service_name.to_string()).is_err()
+        || CAPTURER_FACTORY.set(Box::new(factory)).is_err()
+    {
+        return Err(SavePcapError::AlreadyRunning(format!(
+            "service {} is already running in this process",
+            service_name
+        )));
+    }
+
+    service_dispatcher::start(service_name, ffi_service_main)
+        .map_err(|e| SavePcapError::UnsupportedSource(format!("Service dispatcher error: {}", e)))
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(e) = run_service() {
+        error!("Windows service failed: {}", e);
+    }
+}
+
+fn run_service() -> windows_service::Result<()> {
+    let service_name = SERVICE_NAME.get().map(String::as_str).unwrap_or_default();
+
+    let (control_sender, control_receiver) = mpsc::channel();
+    let status_handle =
+        service_control_handler::register(service_name, move |control| match control {
+            ServiceControl::Stop
+            | ServiceControl::Shutdown
+            | ServiceControl::Pause
+            | ServiceControl::Continue => {
+                let _ = control_sender.send(control);
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        })?;
+
+    let set_state = |state: ServiceState, exit_code: ServiceExitCode| {
+        let controls_accepted = match state {
+            ServiceState::Running | ServiceState::Paused => {
+                ServiceControlAccept::STOP
+                    | ServiceControlAccept::SHUTDOWN
+                    | ServiceControlAccept::PAUSE_CONTINUE
+            }
+            _ => ServiceControlAccept::empty(),
+        };
+
+        status_handle.set_service_status(ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state: state,
+            controls_accepted,
+            exit_code,
+            checkpoint: 0,
+            wait_hint: Duration::from_secs(5),
+            process_id: None,
+        })
+    };
+
+    let capturer = match CAPTURER_FACTORY.get().map(|factory| factory()) {
+        Some(Ok(capturer)) => capturer,
+        Some(Err(e)) => {
+            error!("Failed to create capturer: {}", e);
+            return set_state(ServiceState::Stopped, ServiceExitCode::ServiceSpecific(1));
+        }
+        None => return set_state(ServiceState::Stopped, ServiceExitCode::ServiceSpecific(1)),
+    };
+
+    let handle = capturer.handle();
+    let worker = thread::spawn(move || capturer.capture());
+    set_state(ServiceState::Running, ServiceExitCode::NO_ERROR)?;
+    info!("Windows service {} started", service_name);
+
+    // 捕获在工作线程中运行，这里负责把控制请求转发给控制句柄并上报服务状态
+    loop {
+        match control_receiver.recv_timeout(Duration::from_secs(1)) {
+            Ok(ServiceControl::Stop) | Ok(ServiceControl::Shutdown) => {
+                set_state(ServiceState::StopPending, ServiceExitCode::NO_ERROR)?;
+                handle.stop();
+                break;
+            }
+            Ok(ServiceControl::Pause) => {
+                handle.pause();
+                set_state(ServiceState::Paused, ServiceExitCode::NO_ERROR)?;
+            }
+            Ok(ServiceControl::Continue) => {
+                handle.resume();
+                set_state(ServiceState::Running, ServiceExitCode::NO_ERROR)?;
+            }
+            Ok(_) => {}
+            Err(RecvTimeoutError::Timeout) if !worker.is_finished() => {}
+            Err(_) => break,
+        }
+    }
+
+    let exit_code = match worker.join() {
+        Ok(Ok(())) => ServiceExitCode::NO_ERROR,
+        Ok(Err(e)) => {
+            error!("Capture failed: {}", e);
+            ServiceExitCode::ServiceSpecific(1)
+        }
+        Err(_) => ServiceExitCode::ServiceSpecific(2),
+    };
+    info!("Windows service {} stopped", service_name);
+
+    set_state(ServiceState::Stopped, exit_code)
+}
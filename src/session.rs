@@ -0,0 +1,212 @@
+use crate::observer::{ClosedFile, Observer, Observers};
+use crate::{CaptureStats, WriteErrorPolicy};
+use log::debug;
+use std::path::Path;
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// 捕获会话的生命周期状态，通过`CaptureHandle::state()`获取
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SessionState {
+    /// 已创建，尚未开始捕获
+    #[default]
+    Created,
+    Running,
+    /// 暂停期间仍读取数据包但不写入文件
+    Paused,
+    /// 正在关闭当前文件并创建下一个文件，完成后回到Running或Paused
+    Rotating,
+    /// 捕获正常结束（停止请求、数据包上限或数据来源结束）
+    Stopped,
+    /// 捕获因错误结束
+    Failed,
+}
+
+impl SessionState {
+    /// Stopped和Failed之后不会再有状态变化
+    pub fn is_finished(self) -> bool {
+        matches!(self, SessionState::Stopped | SessionState::Failed)
+    }
+}
+
+/// 一次状态变化，通过`CaptureHandle::subscribe()`接收
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateChange {
+    pub from: SessionState,
+    pub to: SessionState,
+    pub time: SystemTime,
+    /// 进入Failed状态时的错误信息
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    // 最近一次通知的状态
+    state: SessionState,
+    started: bool,
+    paused: bool,
+    rotating: bool,
+    finished: Option<SessionState>,
+    // 进入Failed状态时的错误信息
+    error: Option<String>,
+    subscribers: Vec<Sender<StateChange>>,
+}
+
+impl Inner {
+    fn current(&self) -> SessionState {
+        match self.finished {
+            Some(finished) => finished,
+            None if !self.started => SessionState::Created,
+            None if self.rotating => SessionState::Rotating,
+            None if self.paused => SessionState::Paused,
+            None => SessionState::Running,
+        }
+    }
+}
+
+// 由控制句柄、捕获循环和写入线程共享的会话状态。各方只更新自己负责的部分，
+// 对外的状态由这些标志推导，例如滚动期间恢复捕获时，滚动结束后直接回到Running
+#[derive(Debug, Default)]
+pub(crate) struct SessionStatus {
+    inner: Mutex<Inner>,
+    observers: Observers,
+}
+
+impl SessionStatus {
+    pub fn state(&self) -> SessionState {
+        self.inner
+            .lock()
+            .map(|inner| inner.state)
+            .unwrap_or_default()
+    }
+
+    pub fn error(&self) -> Option<String> {
+        self.inner.lock().ok().and_then(|inner| inner.error.clone())
+    }
+
+    pub fn subscribe(&self) -> Receiver<StateChange> {
+        let (sender, receiver) = channel();
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.subscribers.push(sender);
+        }
+        receiver
+    }
+
+    pub fn add_observer(&self, observer: Arc<dyn Observer>) {
+        self.observers.add(observer);
+    }
+
+    pub fn start(&self) {
+        let started = self.update(None, |inner| !std::mem::replace(&mut inner.started, true));
+        if started {
+            self.observers.notify(|observer| observer.on_start());
+        }
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        // 只通知捕获期间实际发生的暂停和恢复
+        let changed = self.update(None, |inner| {
+            let was_paused = std::mem::replace(&mut inner.paused, paused);
+            was_paused != paused && inner.started && inner.finished.is_none()
+        });
+        if changed {
+            self.observers.notify(|observer| match paused {
+                true => observer.on_pause(),
+                false => observer.on_resume(),
+            });
+        }
+    }
+
+    /// 文件滚动完成后由写入方调用
+    pub fn rotated(&self, closed: &ClosedFile, opened: &Path) {
+        self.observers
+            .notify(|observer| observer.on_rotate(closed, opened));
+    }
+
+    /// 写入失败但按`write_error_policy`继续捕获时由写入方调用
+    pub fn write_failed(&self, error: &str, policy: WriteErrorPolicy) {
+        self.observers
+            .notify(|observer| observer.on_write_error(error, policy));
+    }
+
+    /// 输出位置不可用、开始在内存中缓存数据包时由写入方调用
+    pub fn output_unavailable(&self, error: &str) {
+        self.observers
+            .notify(|observer| observer.on_output_unavailable(error));
+    }
+
+    /// 输出位置恢复、缓存的数据包写出后由写入方调用
+    pub fn output_restored(&self, backlog: u64) {
+        self.observers
+            .notify(|observer| observer.on_output_restored(backlog));
+    }
+
+    /// 进入Rotating状态，返回的守卫被丢弃时结束
+    pub fn rotating(&self) -> RotatingGuard<'_> {
+        self.update(None, |inner| inner.rotating = true);
+        RotatingGuard { status: self }
+    }
+
+    pub fn finish(&self, error: Option<String>, stats: &CaptureStats) {
+        let finished = match error {
+            Some(_) => SessionState::Failed,
+            None => SessionState::Stopped,
+        };
+        let first = self.update(error.clone(), |inner| {
+            let first = inner.finished.is_none();
+            if first {
+                inner.finished = Some(finished);
+                inner.error = error.clone();
+            }
+            first
+        });
+        if !first {
+            return;
+        }
+        if let Some(error) = &error {
+            self.observers.notify(|observer| observer.on_error(error));
+        }
+        self.observers.notify(|observer| observer.on_stop(stats));
+    }
+
+    // 在锁内通知订阅者，保证所有订阅者收到的顺序与实际变化一致；观察者在锁外由调用方通知
+    fn update<R>(&self, error: Option<String>, change: impl FnOnce(&mut Inner) -> R) -> R
+    where
+        R: Default,
+    {
+        let Ok(mut inner) = self.inner.lock() else {
+            return R::default();
+        };
+        let result = change(&mut inner);
+        let from = inner.state;
+        let to = inner.current();
+        if from == to {
+            return result;
+        }
+        inner.state = to;
+        debug!("Capture session state {:?} -> {:?}", from, to);
+
+        let change = StateChange {
+            from,
+            to,
+            time: SystemTime::now(),
+            error,
+        };
+        // 接收端已丢弃的订阅者不再通知
+        inner
+            .subscribers
+            .retain(|subscriber| subscriber.send(change.clone()).is_ok());
+        result
+    }
+}
+
+pub(crate) struct RotatingGuard<'a> {
+    status: &'a SessionStatus,
+}
+
+impl Drop for RotatingGuard<'_> {
+    fn drop(&mut self) {
+        self.status.update(None, |inner| inner.rotating = false);
+    }
+}
@@ -0,0 +1,120 @@
+use crate::PART_SUFFIX;
+use crate::metadata;
+use log::{info, warn};
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+/// 输出目录位于网络共享时，遇到共享的临时错误（连接中断、服务器忙、NFS句柄失效等）的重试设置，
+/// 见`PcapCaptureOptions::share_retry`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShareRetry {
+    /// 第一次失败后最多再尝试的次数
+    pub attempts: u32,
+    /// 第一次重试前等待的毫秒数，之后每次加倍
+    pub delay_ms: u64,
+}
+
+impl Default for ShareRetry {
+    fn default() -> Self {
+        Self {
+            attempts: 5,
+            delay_ms: 500,
+        }
+    }
+}
+
+/// 是否为网络共享上可以重试的临时错误
+pub(crate) fn is_transient(e: &io::Error) -> bool {
+    use io::ErrorKind::*;
+    if matches!(
+        e.kind(),
+        TimedOut
+            | Interrupted
+            | ConnectionReset
+            | ConnectionAborted
+            | NotConnected
+            | HostUnreachable
+            | NetworkUnreachable
+            | NetworkDown
+            | StaleNetworkFileHandle
+            | ResourceBusy
+    ) {
+        return true;
+    }
+    // SMB常见的Windows错误码：ERROR_SHARING_VIOLATION、ERROR_LOCK_VIOLATION、ERROR_BAD_NETPATH、
+    // ERROR_UNEXP_NET_ERR、ERROR_NETNAME_DELETED、ERROR_BAD_NET_NAME、ERROR_SEM_TIMEOUT、
+    // ERROR_NETWORK_UNREACHABLE
+    cfg!(windows)
+        && matches!(
+            e.raw_os_error(),
+            Some(32 | 33 | 53 | 59 | 64 | 67 | 121 | 1231)
+        )
+}
+
+/// 执行操作，遇到临时错误时按`retry`等待后重试；retry为None或错误不是临时错误时直接返回
+pub(crate) fn retry<T>(
+    retry: Option<ShareRetry>,
+    what: &str,
+    mut operation: impl FnMut() -> io::Result<T>,
+) -> io::Result<T> {
+    let Some(retry) = retry else {
+        return operation();
+    };
+    let mut delay = Duration::from_millis(retry.delay_ms);
+    let mut attempt = 0;
+    loop {
+        match operation() {
+            Err(e) if attempt < retry.attempts && is_transient(&e) => {
+                attempt += 1;
+                warn!(
+                    "{} failed: {}, retrying in {:?} ({}/{})",
+                    what, e, delay, attempt, retry.attempts
+                );
+                thread::sleep(delay);
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// 把暂存目录中写完的文件移动到destination，返回文件最终的路径；失败时文件留在暂存目录。
+/// 同一文件系统内直接重命名，跨文件系统时先复制为`.part`文件并落盘，再重命名为最终的文件名，
+/// 读取输出目录的程序不会看到写了一半的文件
+pub(crate) fn move_staged(
+    staged: &Path,
+    destination: &Path,
+    share_retry: Option<ShareRetry>,
+) -> PathBuf {
+    let what = format!("Moving {:?} to {:?}", staged, destination);
+    match retry(share_retry, &what, || move_file(staged, destination)) {
+        Ok(()) => {
+            info!("Moved {:?} to {:?}", staged, destination);
+            metadata::remove_sidecar(staged);
+            destination.to_path_buf()
+        }
+        Err(e) => {
+            warn!("{} failed: {}, keeping the staged file", what, e);
+            staged.to_path_buf()
+        }
+    }
+}
+
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {}
+        result => return result,
+    }
+    let part = metadata::sidecar_path_with(to, PART_SUFFIX);
+    let copied = fs::copy(from, &part)
+        .and_then(|_| OpenOptions::new().write(true).open(&part)?.sync_all())
+        .and_then(|_| fs::rename(&part, to));
+    if copied.is_err() {
+        let _ = fs::remove_file(&part);
+    }
+    copied?;
+    fs::remove_file(from)
+}
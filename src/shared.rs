@@ -0,0 +1,227 @@
+use crate::devices::resolve_device;
+use crate::diagnose;
+use crate::pool::BufferPool;
+use crate::query::compile;
+use crate::source::{NextPacket, PacketStream, SourcePacket};
+use crate::{PcapCaptureOptions, SavePcapError};
+use log::{error, info};
+use pcap::{BpfProgram, Capture, Error as PcapError};
+use pcap_file::DataLink;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, SyncSender, TrySendError, sync_channel};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// 每个捕获等待写入的数据包上限，写入跟不上时丢弃并计入该捕获的丢包数，不影响其他捕获
+const CONSUMER_QUEUE_CAPACITY: usize = 4096;
+// 读取线程检查是否已没有捕获使用该网卡的间隔
+const READ_TIMEOUT_MS: i32 = 100;
+
+// 进程内已打开的共享网卡，按网卡名查找；最后一个捕获结束时网卡关闭，这里只保留弱引用
+static DEVICES: Mutex<Vec<(String, Weak<SharedDevice>)>> = Mutex::new(Vec::new());
+
+// 使用共享网卡的一个捕获，过滤器在读取线程中对每个数据包求值
+struct Consumer {
+    id: u64,
+    filter: Option<BpfProgram>,
+    sender: SyncSender<SourcePacket>,
+    dropped: Arc<AtomicU64>,
+}
+
+#[derive(Default)]
+struct Shared {
+    consumers: Mutex<Vec<Consumer>>,
+    next_id: AtomicU64,
+    stop: AtomicBool,
+    kernel_dropped: AtomicU64,
+}
+
+// 一个打开的网卡和读取它的线程，由所有使用它的捕获共同持有
+pub(crate) struct SharedDevice {
+    name: String,
+    datalink: DataLink,
+    shared: Arc<Shared>,
+    reader: Option<JoinHandle<()>>,
+}
+
+impl SharedDevice {
+    fn open(name: &str, options: &PcapCaptureOptions) -> Result<Self, SavePcapError> {
+        let device = resolve_device(name)?;
+        let mut capture = Capture::from_device(device.as_str())
+            .and_then(|capture| {
+                capture
+                    .snaplen(options.snaplen)
+                    .promisc(true)
+                    .rfmon(options.rfmon)
+                    .timeout(READ_TIMEOUT_MS)
+                    .open()
+            })
+            .map_err(|e| diagnose::device_error(&device, e))?;
+        let datalink = DataLink::from(capture.get_datalink().0 as u32);
+        info!("Opened shared capture device: {}", device);
+
+        let shared = Arc::new(Shared::default());
+        let reader = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name(format!("shared-capture-{}", device))
+                .spawn(move || read(&mut capture, &shared))?
+        };
+        Ok(Self {
+            name: name.to_string(),
+            datalink,
+            shared,
+            reader: Some(reader),
+        })
+    }
+}
+
+impl Drop for SharedDevice {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Relaxed);
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.join();
+        }
+        info!("Closed shared capture device: {}", self.name);
+    }
+}
+
+// 读取线程：把每个数据包复制给过滤器匹配的捕获，出错时断开所有捕获
+fn read(capture: &mut Capture<pcap::Active>, shared: &Shared) {
+    while !shared.stop.load(Ordering::Relaxed) {
+        let packet = match capture.next_packet() {
+            Ok(packet) => packet,
+            Err(PcapError::TimeoutExpired) => {
+                if let Ok(stats) = capture.stats() {
+                    shared.kernel_dropped.store(
+                        stats.dropped as u64 + stats.if_dropped as u64,
+                        Ordering::Relaxed,
+                    );
+                }
+                continue;
+            }
+            Err(e) => {
+                error!("Shared capture error: {}", e);
+                break;
+            }
+        };
+        let timestamp = Duration::new(
+            packet.header.ts.tv_sec as u64,
+            packet.header.ts.tv_usec as u32 * 1_000,
+        );
+        let Ok(consumers) = shared.consumers.lock() else {
+            break;
+        };
+        for consumer in consumers.iter() {
+            if let Some(filter) = &consumer.filter
+                && !filter.filter(packet.data)
+            {
+                continue;
+            }
+            let copy = SourcePacket {
+                timestamp,
+                orig_len: packet.header.len,
+                data: packet.data.to_vec(),
+                user_index: None,
+            };
+            if let Err(TrySendError::Full(_)) = consumer.sender.try_send(copy) {
+                consumer.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+    // 丢弃发送端，正在读取的捕获随之结束
+    if let Ok(mut consumers) = shared.consumers.lock() {
+        consumers.clear();
+    }
+}
+
+// 共享网卡上的一个捕获的数据来源
+pub(crate) struct SharedStream {
+    device: Arc<SharedDevice>,
+    id: u64,
+    receiver: Receiver<SourcePacket>,
+    dropped: Arc<AtomicU64>,
+    poll_timeout: Duration,
+}
+
+impl SharedStream {
+    /// 使用进程内已打开的网卡，没有时按options打开；options.filter在用户态求值
+    pub fn attach(name: &str, options: &PcapCaptureOptions) -> Result<Self, SavePcapError> {
+        let device = {
+            let mut devices = DEVICES
+                .lock()
+                .map_err(|_| SavePcapError::InvalidDevice(name.to_string()))?;
+            devices.retain(|(_, device)| device.strong_count() > 0);
+            match devices
+                .iter()
+                .find(|(device_name, _)| device_name == name)
+                .and_then(|(_, device)| device.upgrade())
+            {
+                Some(device) => device,
+                None => {
+                    let device = Arc::new(SharedDevice::open(name, options)?);
+                    devices.push((name.to_string(), Arc::downgrade(&device)));
+                    device
+                }
+            }
+        };
+
+        let filter = match &options.filter {
+            Some(filter) => Some(compile(filter, device.datalink)?),
+            None => None,
+        };
+        let (sender, receiver) = sync_channel(CONSUMER_QUEUE_CAPACITY);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let id = device.shared.next_id.fetch_add(1, Ordering::Relaxed);
+        device
+            .shared
+            .consumers
+            .lock()
+            .map_err(|_| SavePcapError::InvalidDevice(name.to_string()))?
+            .push(Consumer {
+                id,
+                filter,
+                sender,
+                dropped: dropped.clone(),
+            });
+        Ok(Self {
+            device,
+            id,
+            receiver,
+            dropped,
+            poll_timeout: Duration::from_millis(options.timeout_ms.max(1) as u64),
+        })
+    }
+}
+
+impl Drop for SharedStream {
+    fn drop(&mut self) {
+        if let Ok(mut consumers) = self.device.shared.consumers.lock() {
+            consumers.retain(|consumer| consumer.id != self.id);
+        }
+    }
+}
+
+impl PacketStream for SharedStream {
+    fn datalink(&self) -> DataLink {
+        self.device.datalink
+    }
+
+    // 读取线程已为每个捕获复制了数据包，直接沿用
+    fn next_packet(&mut self, _pool: &mut BufferPool) -> Result<NextPacket, SavePcapError> {
+        match self.receiver.recv_timeout(self.poll_timeout) {
+            Ok(packet) => Ok(NextPacket::Packet(packet)),
+            Err(RecvTimeoutError::Timeout) => Ok(NextPacket::Idle),
+            Err(RecvTimeoutError::Disconnected) => Ok(NextPacket::End),
+        }
+    }
+
+    // 网卡丢弃的数据包由所有捕获共同承担，再加上该捕获因写入跟不上而丢弃的数据包
+    fn dropped(&mut self) -> Option<u64> {
+        Some(
+            self.device.shared.kernel_dropped.load(Ordering::Relaxed)
+                + self.dropped.load(Ordering::Relaxed),
+        )
+    }
+}
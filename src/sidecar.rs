@@ -0,0 +1,130 @@
+use crate::annotation::AnnotationLog;
+use crate::dns::DnsLog;
+#[cfg(feature = "geoip")]
+use crate::geoip::GeoIpEnricher;
+use crate::index::SessionIndex;
+use crate::packet_index::PacketIndexWriter;
+use crate::source::SourcePacket;
+use crate::talkers::TopTalkers;
+use crate::{PcapCaptureOptions, SavePcapError};
+use log::warn;
+use pcap_file::DataLink;
+use std::path::Path;
+
+// 由写入的数据包生成、随捕获文件保存的附加文件。写入时逐包记录，
+// 文件关闭（并按需重命名）后以文件的最终路径写出
+pub(crate) struct Sidecars {
+    #[cfg(feature = "geoip")]
+    geoip: Option<GeoIpEnricher>,
+    index: Option<SessionIndex>,
+    dns_log: Option<DnsLog>,
+    talkers: Option<TopTalkers>,
+    packet_index: Option<PacketIndexWriter>,
+    annotations: Option<AnnotationLog>,
+}
+
+impl Sidecars {
+    pub fn new(options: &PcapCaptureOptions) -> Result<Self, SavePcapError> {
+        Ok(Self {
+            #[cfg(feature = "geoip")]
+            geoip: options
+                .geoip
+                .as_ref()
+                .map(GeoIpEnricher::open)
+                .transpose()?,
+            index: SessionIndex::new(options),
+            dns_log: options.dns_log.then(DnsLog::new),
+            talkers: options.top_talkers.map(TopTalkers::new),
+            packet_index: options.packet_index.then(PacketIndexWriter::new),
+            annotations: options.annotator.is_some().then(AnnotationLog::new),
+        })
+    }
+
+    /// offset为数据包记录在捕获文件中的起始偏移
+    pub fn record(&mut self, datalink: DataLink, packet: &SourcePacket, offset: u64) {
+        #[cfg(feature = "geoip")]
+        if let Some(geoip) = &mut self.geoip {
+            geoip.record(datalink, &packet.data, packet.orig_len);
+        }
+        if let Some(index) = &mut self.index {
+            index.record(datalink, packet, offset);
+        }
+        if let Some(dns_log) = &mut self.dns_log {
+            dns_log.record(datalink, packet);
+        }
+        if let Some(talkers) = &mut self.talkers {
+            talkers.record(datalink, packet);
+        }
+        if let Some(packet_index) = &mut self.packet_index {
+            packet_index.record(packet, offset);
+        }
+    }
+
+    pub fn record_annotations(
+        &mut self,
+        packet: &SourcePacket,
+        offset: u64,
+        annotations: &[(String, String)],
+    ) {
+        if let Some(log) = &mut self.annotations {
+            log.record(packet, offset, annotations);
+        }
+    }
+
+    pub fn file_closed(&mut self, path: &Path) {
+        #[cfg(feature = "geoip")]
+        if let Some(geoip) = &mut self.geoip
+            && let Err(e) = geoip.write_sidecar(path)
+        {
+            warn!("Failed to write GeoIP metadata for {:?}: {}", path, e);
+        }
+        if let Some(index) = &mut self.index
+            && let Err(e) = index.write_sidecar(path)
+        {
+            warn!("Failed to write session index for {:?}: {}", path, e);
+        }
+        if let Some(dns_log) = &mut self.dns_log
+            && let Err(e) = dns_log.write_sidecar(path)
+        {
+            warn!("Failed to write DNS log for {:?}: {}", path, e);
+        }
+        if let Some(talkers) = &mut self.talkers
+            && let Err(e) = talkers.write_sidecar(path)
+        {
+            warn!("Failed to write top talkers for {:?}: {}", path, e);
+        }
+        if let Some(packet_index) = &mut self.packet_index
+            && let Err(e) = packet_index.write_sidecar(path)
+        {
+            warn!("Failed to write packet index for {:?}: {}", path, e);
+        }
+        if let Some(annotations) = &mut self.annotations
+            && let Err(e) = annotations.write_sidecar(path)
+        {
+            warn!("Failed to write annotations for {:?}: {}", path, e);
+        }
+    }
+
+    // 文件被截断时已记录的数据包不一定都保留在文件中，不写出
+    pub fn discard(&mut self) {
+        #[cfg(feature = "geoip")]
+        if let Some(geoip) = &mut self.geoip {
+            geoip.discard();
+        }
+        if let Some(index) = &mut self.index {
+            index.discard();
+        }
+        if let Some(dns_log) = &mut self.dns_log {
+            dns_log.discard();
+        }
+        if let Some(talkers) = &mut self.talkers {
+            talkers.discard();
+        }
+        if let Some(packet_index) = &mut self.packet_index {
+            packet_index.discard();
+        }
+        if let Some(annotations) = &mut self.annotations {
+            annotations.discard();
+        }
+    }
+}
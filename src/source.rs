@@ -0,0 +1,245 @@
+use crate::parse::ETHERNET_HEADER_LEN;
+use crate::pool::BufferPool;
+use crate::reorder::TimestampOrder;
+use crate::stats::{PcapStats, StatsCounters};
+use crate::{PcapCaptureOptions, SavePcapError, UserPacket};
+use log::{error, info};
+use pcap::{Activated, Capture, Error as PcapError};
+use pcap_file::DataLink;
+use std::sync::Arc;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+
+// 等待用户数据包时至少按这个间隔检查刷新请求
+const FLUSH_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+// 各种数据来源统一转换后的数据包
+pub(crate) struct SourcePacket {
+    pub timestamp: Duration,
+    pub orig_len: u32,
+    pub data: Vec<u8>,
+    // 用户提供的数据包在发送顺序中的序号，用于报告被拒绝的数据包；其他来源为None
+    pub user_index: Option<u64>,
+}
+
+impl SourcePacket {
+    /// 缓存该数据包占用的内存，按缓冲区容量计算，计入`memory_limit_bytes`
+    pub fn memory_size(&self) -> u64 {
+        self.data.capacity() as u64
+    }
+}
+
+pub(crate) enum NextPacket {
+    Packet(SourcePacket),
+    // 暂时没有数据（例如读超时），调用方应继续等待
+    Idle,
+    // 数据来源已结束
+    End,
+    // 之前的数据包都已交出，写出并同步到磁盘后通过该发送端确认
+    Flush(Sender<()>),
+}
+
+// 捕获循环只依赖这个接口，网卡、离线文件和用户数据包都实现它
+pub(crate) trait PacketStream {
+    fn datalink(&self) -> DataLink;
+    // 数据包内容应复制到从pool中取出的缓冲区，避免逐包分配内存
+    fn next_packet(&mut self, pool: &mut BufferPool) -> Result<NextPacket, SavePcapError>;
+    // 内核和网卡累计丢弃的数据包数量，数据来源无法提供时返回None
+    fn dropped(&mut self) -> Option<u64> {
+        None
+    }
+    // libpcap的累计统计，不是通过libpcap打开的数据来源返回None
+    fn pcap_stats(&mut self) -> Option<PcapStats> {
+        None
+    }
+    // 停止捕获时逐个取出数据来源内部仍在缓存的数据包
+    fn drain(&mut self) -> Option<SourcePacket> {
+        None
+    }
+}
+
+impl<T: Activated + ?Sized> PacketStream for Capture<T> {
+    fn datalink(&self) -> DataLink {
+        // 文件头中的链路层类型需与实际捕获一致，否则radiotap等头部会被错误解析
+        DataLink::from(self.get_datalink().0 as u32)
+    }
+
+    fn next_packet(&mut self, pool: &mut BufferPool) -> Result<NextPacket, SavePcapError> {
+        match Capture::next_packet(self) {
+            Ok(packet) => {
+                let mut data = pool.take();
+                data.extend_from_slice(packet.data);
+
+                Ok(NextPacket::Packet(SourcePacket {
+                    timestamp: Duration::new(
+                        packet.header.ts.tv_sec as u64,
+                        packet.header.ts.tv_usec as u32 * 1_000,
+                    ),
+                    orig_len: packet.header.len,
+                    data,
+                    user_index: None,
+                }))
+            }
+            Err(PcapError::TimeoutExpired) => Ok(NextPacket::Idle),
+            Err(PcapError::NoMorePackets) => Ok(NextPacket::End),
+            Err(e) => {
+                error!("Capture error: {}", e);
+                Ok(NextPacket::End)
+            }
+        }
+    }
+
+    fn dropped(&mut self) -> Option<u64> {
+        let stats = self.pcap_stats()?;
+        Some(stats.dropped + stats.if_dropped)
+    }
+
+    fn pcap_stats(&mut self) -> Option<PcapStats> {
+        let stats = self.stats().ok()?;
+        Some(PcapStats {
+            received: stats.received as u64,
+            dropped: stats.dropped as u64,
+            if_dropped: stats.if_dropped as u64,
+        })
+    }
+}
+
+pub(crate) struct UserPacketStream<'a> {
+    receiver: &'a Receiver<UserPacket>,
+    // `PacketSender::flush`的刷新请求，与数据包通道分开，不影响`Sender<UserPacket>`接口
+    flushes: &'a Receiver<Sender<()>>,
+    pending_flush: Option<Sender<()>>,
+    datalink: DataLink,
+    // 等待数据包的最长时间，超时后返回Idle以便捕获循环检查停止请求
+    poll_timeout: Duration,
+    options: &'a PcapCaptureOptions,
+    received: u64,
+    order: TimestampOrder,
+}
+
+impl<'a> UserPacketStream<'a> {
+    pub fn new(
+        receiver: &'a Receiver<UserPacket>,
+        flushes: &'a Receiver<Sender<()>>,
+        datalink: DataLink,
+        poll_timeout: Duration,
+        options: &'a PcapCaptureOptions,
+        stats: Arc<StatsCounters>,
+    ) -> Self {
+        Self {
+            receiver,
+            flushes,
+            pending_flush: None,
+            datalink,
+            poll_timeout,
+            options,
+            received: 0,
+            order: TimestampOrder::new(options.timestamp_policy, options.memory_limit_bytes, stats),
+        }
+    }
+
+    // 检查收到的数据包并交给排序缓冲区，被拒绝或需要等待排序时返回Idle
+    fn accept(&mut self, user_packet: UserPacket) -> NextPacket {
+        let index = self.received;
+        self.received += 1;
+        let timestamp = user_packet.timestamp.unwrap_or_else(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+        });
+
+        let packet = SourcePacket {
+            timestamp,
+            orig_len: user_packet.data.len() as u32,
+            data: user_packet.data,
+            user_index: Some(index),
+        };
+        if let Some(reason) = self.check(&packet.data) {
+            self.options.report_rejected(&packet, &reason);
+            // 返回Idle以便捕获循环照常检查停止请求
+            return NextPacket::Idle;
+        }
+        match self.order.push(packet) {
+            Some(packet) => NextPacket::Packet(packet),
+            None => NextPacket::Idle,
+        }
+    }
+
+    // 写入文件头的快照长度和链路层类型决定了合法的数据包，不符合的数据包无法被正确读取
+    fn check(&self, data: &[u8]) -> Option<String> {
+        let snaplen = self.options.snaplen.max(0) as usize;
+        if snaplen > 0 && data.len() > snaplen {
+            return Some(format!(
+                "packet of {} bytes exceeds the snaplen of {}",
+                data.len(),
+                snaplen
+            ));
+        }
+        match self.datalink {
+            _ if data.is_empty() => Some("empty packet".to_string()),
+            DataLink::ETHERNET if data.len() < ETHERNET_HEADER_LEN => Some(format!(
+                "{} byte frame is shorter than an Ethernet header",
+                data.len()
+            )),
+            DataLink::RAW if !matches!(data[0] >> 4, 4 | 6) => {
+                Some(format!("IP version {} in a raw IP packet", data[0] >> 4))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl PacketStream for UserPacketStream<'_> {
+    fn datalink(&self) -> DataLink {
+        self.datalink
+    }
+
+    // 用户提供的数据包本身就拥有缓冲区，直接沿用
+    fn next_packet(&mut self, _pool: &mut BufferPool) -> Result<NextPacket, SavePcapError> {
+        if self.pending_flush.is_none() {
+            self.pending_flush = self.flushes.try_recv().ok();
+        }
+        if self.pending_flush.is_some() {
+            // 标记之前发送的数据包（包括排序缓冲区中的）全部交出后再确认
+            if let Ok(user_packet) = self.receiver.try_recv() {
+                return Ok(self.accept(user_packet));
+            }
+            if let Some(packet) = self.order.pop_any() {
+                return Ok(NextPacket::Packet(packet));
+            }
+            return Ok(self
+                .pending_flush
+                .take()
+                .map_or(NextPacket::Idle, NextPacket::Flush));
+        }
+
+        if let Some(packet) = self.order.pop_ready() {
+            return Ok(NextPacket::Packet(packet));
+        }
+        // 有数据包在等待排序时，按窗口及时检查是否已可以写出
+        let timeout = self
+            .order
+            .max_wait()
+            .map_or(self.poll_timeout, |window| window.min(self.poll_timeout))
+            .min(FLUSH_CHECK_INTERVAL);
+
+        match self.receiver.recv_timeout(timeout) {
+            Ok(user_packet) => Ok(self.accept(user_packet)),
+            Err(RecvTimeoutError::Timeout) => Ok(match self.order.pop_ready() {
+                Some(packet) => NextPacket::Packet(packet),
+                None => NextPacket::Idle,
+            }),
+            Err(RecvTimeoutError::Disconnected) => {
+                if let Some(packet) = self.order.pop_any() {
+                    return Ok(NextPacket::Packet(packet));
+                }
+                info!("Sender disconnected, stopping user packet processing");
+                Ok(NextPacket::End)
+            }
+        }
+    }
+
+    fn drain(&mut self) -> Option<SourcePacket> {
+        self.order.pop_any()
+    }
+}
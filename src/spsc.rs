@@ -0,0 +1,152 @@
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+// 单生产者单消费者的有界无锁环形队列，槽位在创建时一次性分配。
+// 捕获线程只负责入队，写入线程只负责出队，热路径上没有锁和内存分配。
+
+// 读写位置分别独占一个缓存行，避免生产者和消费者之间的伪共享
+#[repr(align(64))]
+struct CachePadded<T>(T);
+
+struct Ring<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    // 下一个读取位置，只由消费者修改；位置单调递增，取模后得到槽位
+    head: CachePadded<AtomicUsize>,
+    // 下一个写入位置，只由生产者修改
+    tail: CachePadded<AtomicUsize>,
+    // 任意一端被释放后置位，另一端据此结束
+    closed: AtomicBool,
+}
+
+// 每个槽位同一时刻只被一端访问，由head/tail的Acquire/Release保证
+unsafe impl<T: Send> Sync for Ring<T> {}
+unsafe impl<T: Send> Send for Ring<T> {}
+
+impl<T> Ring<T> {
+    fn slot(&self, position: usize) -> *mut MaybeUninit<T> {
+        self.slots[position % self.slots.len()].get()
+    }
+
+    fn len(&self) -> usize {
+        let head = self.head.0.load(Ordering::Acquire);
+        let tail = self.tail.0.load(Ordering::Acquire);
+        tail.wrapping_sub(head)
+    }
+}
+
+impl<T> Drop for Ring<T> {
+    fn drop(&mut self) {
+        let head = *self.head.0.get_mut();
+        let tail = *self.tail.0.get_mut();
+        let mut position = head;
+        while position != tail {
+            unsafe { (*self.slot(position)).assume_init_drop() };
+            position = position.wrapping_add(1);
+        }
+    }
+}
+
+pub(crate) struct Producer<T> {
+    ring: Arc<Ring<T>>,
+    // 缓存的消费者位置，只有看起来已满时才重新读取，减少跨核访问
+    cached_head: usize,
+}
+
+pub(crate) struct Consumer<T> {
+    ring: Arc<Ring<T>>,
+    cached_tail: usize,
+}
+
+pub(crate) fn channel<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    let capacity = capacity.max(1);
+    let slots = (0..capacity)
+        .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+        .collect();
+    let ring = Arc::new(Ring {
+        slots,
+        head: CachePadded(AtomicUsize::new(0)),
+        tail: CachePadded(AtomicUsize::new(0)),
+        closed: AtomicBool::new(false),
+    });
+
+    (
+        Producer {
+            ring: ring.clone(),
+            cached_head: 0,
+        },
+        Consumer {
+            ring,
+            cached_tail: 0,
+        },
+    )
+}
+
+impl<T> Producer<T> {
+    /// 队列已满时原样返回数据，由调用方决定等待还是丢弃
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        let tail = self.ring.tail.0.load(Ordering::Relaxed);
+        if tail.wrapping_sub(self.cached_head) == self.ring.slots.len() {
+            self.cached_head = self.ring.head.0.load(Ordering::Acquire);
+            if tail.wrapping_sub(self.cached_head) == self.ring.slots.len() {
+                return Err(value);
+            }
+        }
+
+        unsafe { (*self.ring.slot(tail)).write(value) };
+        self.ring
+            .tail
+            .0
+            .store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.ring.slots.len()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.ring.closed.load(Ordering::Acquire)
+    }
+}
+
+impl<T> Drop for Producer<T> {
+    fn drop(&mut self) {
+        self.ring.closed.store(true, Ordering::Release);
+    }
+}
+
+impl<T> Consumer<T> {
+    pub fn pop(&mut self) -> Option<T> {
+        let head = self.ring.head.0.load(Ordering::Relaxed);
+        if head == self.cached_tail {
+            self.cached_tail = self.ring.tail.0.load(Ordering::Acquire);
+            if head == self.cached_tail {
+                return None;
+            }
+        }
+
+        let value = unsafe { (*self.ring.slot(head)).assume_init_read() };
+        self.ring
+            .head
+            .0
+            .store(head.wrapping_add(1), Ordering::Release);
+        Some(value)
+    }
+
+    /// 生产者已释放且队列中没有剩余数据
+    pub fn is_finished(&self) -> bool {
+        self.ring.closed.load(Ordering::Acquire) && self.ring.len() == 0
+    }
+}
+
+impl<T> Drop for Consumer<T> {
+    fn drop(&mut self) {
+        self.ring.closed.store(true, Ordering::Release);
+    }
+}
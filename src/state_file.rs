@@ -0,0 +1,335 @@
+use crate::layout;
+use crate::{FileFormat, OutputLayout, PacketSource, PcapCaptureOptions, SavePcapError};
+use log::warn;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+// CaptureManager的会话状态文件，每个会话一节：
+//
+//     [uplink]
+//     source = device:eth0
+//     file_path = /data/uplink
+//     next_file_sequence = 42
+//
+// 只保存可以用文本表示的常用选项，其余选项恢复时使用默认值
+
+/// 保存的会话配置，不含`file_sequence`
+pub(crate) fn encode_options(options: &PcapCaptureOptions) -> String {
+    let mut config = String::new();
+    let source = match &options.packet_source {
+        PacketSource::NetworkDevice(name) => format!("device:{}", name),
+        PacketSource::UserProvided => "user".to_string(),
+        PacketSource::BluetoothHci(name) => format!("bluetooth:{}", name),
+        PacketSource::CanInterface(name) => format!("can:{}", name),
+        PacketSource::Nflog(group) => format!("nflog:{}", group),
+        PacketSource::Pktmon => "pktmon".to_string(),
+        PacketSource::File(path) => format!("file:{}", path),
+        PacketSource::Stdin => "stdin".to_string(),
+        PacketSource::SharedDevice(name) => format!("shared:{}", name),
+    };
+    let format = match options.file_format {
+        FileFormat::Pcap => "pcap",
+        FileFormat::PcapNg => "pcapng",
+    };
+
+    let mut field = |key: &str, value: Option<String>| {
+        if let Some(value) = value {
+            let _ = writeln!(config, "{} = {}", key, value);
+        }
+    };
+    field("source", Some(source));
+    field("file_prefix", Some(options.file_prefix.clone()));
+    field("file_path", Some(options.file_path.clone()));
+    field("base_dir", options.base_dir.clone());
+    field("staging_dir", options.staging_dir.clone());
+    if let Some(layout) = &options.output_layout {
+        field("layout_template", Some(layout.template.clone()));
+        field("layout_tenant", Some(layout.tenant.clone()));
+        field("layout_session", Some(layout.session.clone()));
+        // 权限按八进制保存，例如`750`
+        field(
+            "layout_dir_mode",
+            layout.dir_mode.map(|v| format!("{:o}", v)),
+        );
+        field(
+            "layout_file_mode",
+            layout.file_mode.map(|v| format!("{:o}", v)),
+        );
+    }
+    field("file_format", Some(format.to_string()));
+    field("packet_limit", options.packet_limit.map(|v| v.to_string()));
+    field("byte_limit", options.byte_limit.map(|v| v.to_string()));
+    field(
+        "stop_after_quiet_seconds",
+        options.stop_after_quiet_seconds.map(|v| v.to_string()),
+    );
+    field(
+        "discard_first_seconds",
+        options.discard_first_seconds.map(|v| v.to_string()),
+    );
+    field(
+        "discard_first_packets",
+        options.discard_first_packets.map(|v| v.to_string()),
+    );
+    field(
+        "sample_probability",
+        options.sample_probability.map(|v| v.to_string()),
+    );
+    field(
+        "flow_sample_every",
+        options.flow_sample_every.map(|v| v.to_string()),
+    );
+    field("sample_seed", options.sample_seed.map(|v| v.to_string()));
+    field("snaplen", Some(options.snaplen.to_string()));
+    field("slice_bytes", options.slice_bytes.map(|v| v.to_string()));
+    field("timeout_ms", Some(options.timeout_ms.to_string()));
+    field(
+        "continuous_capture",
+        Some(options.continuous_capture.to_string()),
+    );
+    field(
+        "rollover_time_seconds",
+        options.rollover_time_seconds.map(|v| v.to_string()),
+    );
+    field(
+        "rollover_packet_count",
+        options.rollover_packet_count.map(|v| v.to_string()),
+    );
+    field(
+        "rollover_file_size_mb",
+        options.rollover_file_size_mb.map(|v| v.to_string()),
+    );
+    field(
+        "strict_size_rollover",
+        Some(options.strict_size_rollover.to_string()),
+    );
+    field(
+        "rollover_idle_seconds",
+        options.rollover_idle_seconds.map(|v| v.to_string()),
+    );
+    field(
+        "heartbeat_interval_seconds",
+        options.heartbeat_interval_seconds.map(|v| v.to_string()),
+    );
+    field("rfmon", Some(options.rfmon.to_string()));
+    field("linktype", options.linktype.map(|v| v.to_string()));
+    field("worker_id", options.worker_id.map(|v| v.to_string()));
+    field(
+        "device_in_file_name",
+        Some(options.device_in_file_name.to_string()),
+    );
+    field(
+        "writer_queue_capacity",
+        options.writer_queue_capacity.map(|v| v.to_string()),
+    );
+    field(
+        "memory_limit_bytes",
+        options.memory_limit_bytes.map(|v| v.to_string()),
+    );
+    field(
+        "memory_fallback_bytes",
+        options.memory_fallback_bytes.map(|v| v.to_string()),
+    );
+    field("filter", options.filter.clone());
+    field(
+        "metadata_sidecar",
+        Some(options.metadata_sidecar.to_string()),
+    );
+    field(
+        "time_range_file_names",
+        Some(options.time_range_file_names.to_string()),
+    );
+    field("part_files", Some(options.part_files.to_string()));
+    field(
+        "labels_in_file_name",
+        Some(options.labels_in_file_name.to_string()),
+    );
+    // 每个标签一行，值中可以再有`=`
+    for (key, value) in &options.labels {
+        field("label", Some(format!("{}={}", key, value)));
+    }
+    config
+}
+
+/// 覆盖写入状态文件，sessions为会话名称、`encode_options`的结果和下一个文件序号
+pub(crate) fn write(path: &Path, sessions: &[(&str, &str, Option<u64>)]) -> io::Result<()> {
+    let mut contents = String::from("# save_pcap capture sessions\n");
+    for (name, config, next_sequence) in sessions {
+        let _ = writeln!(contents, "\n[{}]", name);
+        contents.push_str(config);
+        if let Some(next_sequence) = next_sequence {
+            let _ = writeln!(contents, "next_file_sequence = {}", next_sequence);
+        }
+    }
+
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    fs::write(&temp_path, contents)?;
+    fs::rename(&temp_path, path)
+}
+
+/// 读取状态文件中的会话，`file_sequence`设置为保存的下一个文件序号。文件不存在时返回空列表
+pub(crate) fn read(path: &Path) -> Result<Vec<(String, PcapCaptureOptions)>, SavePcapError> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut sessions: Vec<(String, PcapCaptureOptions)> = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = |reason: &str| {
+            SavePcapError::InvalidStateFile(format!(
+                "{}:{}: {}",
+                path.display(),
+                number + 1,
+                reason
+            ))
+        };
+
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            sessions.push((name.to_string(), PcapCaptureOptions::default()));
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(invalid("expected `key = value`"));
+        };
+        let Some((_, options)) = sessions.last_mut() else {
+            return Err(invalid("option outside of a [session] section"));
+        };
+        apply(options, key.trim(), value.trim()).map_err(|reason| invalid(&reason))?;
+    }
+    Ok(sessions)
+}
+
+fn apply(options: &mut PcapCaptureOptions, key: &str, value: &str) -> Result<(), String> {
+    fn parse<T: FromStr>(value: &str) -> Result<T, String> {
+        value
+            .parse()
+            .map_err(|_| format!("invalid value `{}`", value))
+    }
+
+    match key {
+        "source" => {
+            let (kind, argument) = value.split_once(':').unwrap_or((value, ""));
+            options.packet_source = match kind {
+                "device" => PacketSource::NetworkDevice(argument.to_string()),
+                "user" => PacketSource::UserProvided,
+                "bluetooth" => PacketSource::BluetoothHci(argument.to_string()),
+                "can" => PacketSource::CanInterface(argument.to_string()),
+                "nflog" => PacketSource::Nflog(parse(argument)?),
+                "pktmon" => PacketSource::Pktmon,
+                "file" => PacketSource::File(argument.to_string()),
+                "stdin" => PacketSource::Stdin,
+                "shared" => PacketSource::SharedDevice(argument.to_string()),
+                _ => return Err(format!("unknown packet source `{}`", value)),
+            };
+        }
+        "file_prefix" => options.file_prefix = value.to_string(),
+        "file_path" => options.file_path = value.to_string(),
+        "base_dir" => options.base_dir = Some(value.to_string()),
+        "staging_dir" => options.staging_dir = Some(value.to_string()),
+        "layout_template" | "layout_tenant" | "layout_session" | "layout_dir_mode"
+        | "layout_file_mode" => {
+            // 没有保存的权限表示按umask
+            let layout = options.output_layout.get_or_insert_with(|| OutputLayout {
+                dir_mode: None,
+                file_mode: None,
+                ..OutputLayout::new("", "")
+            });
+            let mode =
+                || u32::from_str_radix(value, 8).map_err(|_| format!("invalid mode `{}`", value));
+            match key {
+                "layout_template" => layout.template = value.to_string(),
+                "layout_tenant" => layout.tenant = value.to_string(),
+                "layout_session" => layout.session = value.to_string(),
+                "layout_dir_mode" => layout.dir_mode = Some(mode()?),
+                _ => layout.file_mode = Some(mode()?),
+            }
+        }
+        "file_format" => {
+            options.file_format = match value {
+                "pcap" => FileFormat::Pcap,
+                "pcapng" => FileFormat::PcapNg,
+                _ => return Err(format!("unknown file format `{}`", value)),
+            }
+        }
+        "packet_limit" => options.packet_limit = Some(parse(value)?),
+        "byte_limit" => options.byte_limit = Some(parse(value)?),
+        "stop_after_quiet_seconds" => options.stop_after_quiet_seconds = Some(parse(value)?),
+        "discard_first_seconds" => options.discard_first_seconds = Some(parse(value)?),
+        "discard_first_packets" => options.discard_first_packets = Some(parse(value)?),
+        "sample_probability" => options.sample_probability = Some(parse(value)?),
+        "flow_sample_every" => options.flow_sample_every = Some(parse(value)?),
+        "sample_seed" => options.sample_seed = Some(parse(value)?),
+        "snaplen" => options.snaplen = parse(value)?,
+        "slice_bytes" => options.slice_bytes = Some(parse(value)?),
+        "timeout_ms" => options.timeout_ms = parse(value)?,
+        "continuous_capture" => options.continuous_capture = parse(value)?,
+        "rollover_time_seconds" => options.rollover_time_seconds = Some(parse(value)?),
+        "rollover_packet_count" => options.rollover_packet_count = Some(parse(value)?),
+        "rollover_file_size_mb" => options.rollover_file_size_mb = Some(parse(value)?),
+        "strict_size_rollover" => options.strict_size_rollover = parse(value)?,
+        "rollover_idle_seconds" => options.rollover_idle_seconds = Some(parse(value)?),
+        "heartbeat_interval_seconds" => options.heartbeat_interval_seconds = Some(parse(value)?),
+        "rfmon" => options.rfmon = parse(value)?,
+        "linktype" => options.linktype = Some(parse(value)?),
+        "worker_id" => options.worker_id = Some(parse(value)?),
+        "device_in_file_name" => options.device_in_file_name = parse(value)?,
+        "labels_in_file_name" => options.labels_in_file_name = parse(value)?,
+        "label" => {
+            let (key, value) = value
+                .split_once('=')
+                .ok_or_else(|| format!("expected `label = key=value`, got `{}`", value))?;
+            options
+                .labels
+                .push((key.trim().to_string(), value.trim().to_string()));
+        }
+        "writer_queue_capacity" => options.writer_queue_capacity = Some(parse(value)?),
+        "memory_limit_bytes" => options.memory_limit_bytes = Some(parse(value)?),
+        "memory_fallback_bytes" => options.memory_fallback_bytes = Some(parse(value)?),
+        "filter" => options.filter = Some(value.to_string()),
+        "metadata_sidecar" => options.metadata_sidecar = parse(value)?,
+        "time_range_file_names" => options.time_range_file_names = parse(value)?,
+        "part_files" => options.part_files = parse(value)?,
+        "next_file_sequence" => options.file_sequence = Some(parse(value)?),
+        // 新版本写入的选项不影响旧版本恢复其余配置
+        _ => warn!("Ignoring unknown session option `{}`", key),
+    }
+    Ok(())
+}
+
+/// 输出目录（包括按`output_layout`生成的子目录）中已有的带序号捕获文件之后的下一个序号
+pub(crate) fn next_sequence_in_dir(options: &PcapCaptureOptions) -> Option<u64> {
+    let prefix = format!("{}_", options.file_prefix);
+    layout::capture_dirs(
+        options.output_layout.as_ref(),
+        Path::new(&options.file_path),
+    )
+    .iter()
+    .filter_map(|dir| fs::read_dir(dir).ok())
+    .flatten()
+    .filter_map(|entry| {
+        let name = entry.ok()?.file_name().to_string_lossy().into_owned();
+        if !options.is_capture_file_name(&name) {
+            return None;
+        }
+        let rest = name.strip_prefix(&prefix)?;
+        let (sequence, time_part) = rest.split_once('_')?;
+        // 不带序号的文件名以日期开头，序号之后必须是日期（YYYYMMDD）和时间
+        let date = time_part.get(..8)?;
+        let separator = time_part.get(8..9)?;
+        (date.bytes().all(|b| b.is_ascii_digit()) && matches!(separator, "_" | "T"))
+            .then(|| sequence.parse::<u64>().ok())
+            .flatten()
+    })
+    .max()
+    .map(|sequence| sequence + 1)
+}
@@ -0,0 +1,574 @@
+use crate::histogram::{Histogram, PacketHistograms};
+use crate::metadata::{self, json_string};
+use crate::parse::{self, ETHERTYPE_IPV4, ETHERTYPE_IPV6};
+use pcap_file::DataLink;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// 1024以上单独统计的常见服务端口，其余高端口计入other_ports
+const COMMON_HIGH_PORTS: [u16; 10] = [1433, 1883, 3306, 3389, 5353, 5432, 6379, 8080, 8443, 27017];
+
+/// 捕获过程的统计快照，通过`CaptureHandle::stats()`获取
+#[derive(Debug, Clone, Default)]
+pub struct CaptureStats {
+    /// 已写入文件的数据包数量
+    pub packets_written: u64,
+    /// 已写入文件的字节数（包括每个数据包的记录头，不含文件头）
+    pub bytes_written: u64,
+    /// 文件滚动次数
+    pub rotations: u64,
+    /// 所有文件滚动（刷新旧文件、创建新文件）累计耗时
+    pub rotation_time_total: Duration,
+    /// 单次文件滚动的最长耗时
+    pub rotation_time_max: Duration,
+    /// 未通过合法性检查的数据包数量（无论被丢弃、标记还是写入错误文件）
+    pub invalid_packets: u64,
+    /// 时间戳早于已写出的数据包、被改为上一个时间戳的用户数据包数量（见`timestamp_policy`）
+    pub late_packets: u64,
+    /// 写入失败后按`write_error_policy`继续捕获的次数，失败的数据包不写入文件
+    pub write_errors: u64,
+    /// 写入文件前遮盖的内容处数（见`redaction`）
+    pub redactions: u64,
+    /// 静默期间写入的心跳记录数量（见`heartbeat_interval_seconds`），不计入`packets_written`
+    pub heartbeats: u64,
+    /// 内核缓冲区已满或网卡丢弃的数据包数量（仅libpcap数据来源，每秒更新）
+    pub kernel_dropped: u64,
+    /// 写入队列中等待写入的数据包数量（仅在启用独立写入线程时有效）
+    pub writer_queue_len: usize,
+    /// 写入队列容量，0表示未启用独立写入线程
+    pub writer_queue_capacity: usize,
+    /// 写入队列出现过的最大占用量
+    pub writer_queue_high_watermark: usize,
+    /// 因写入队列已满而需要等待的数据包数量
+    pub writer_queue_full_count: u64,
+    /// 写入队列和排序缓冲区中的数据包当前占用的内存（按缓冲区容量计算）
+    pub memory_used: u64,
+    /// `memory_used`出现过的最大值
+    pub memory_peak: u64,
+    /// 超过`memory_limit_bytes`时按`MemoryLimitPolicy::Drop`丢弃的数据包数量
+    pub memory_dropped: u64,
+    /// 输出位置不可用期间缓存在内存中、尚未写出的数据包数量，见`memory_fallback_bytes`
+    pub fallback_buffered: u64,
+    /// 输出位置不可用期间因超过`memory_fallback_bytes`丢弃的数据包数量
+    pub fallback_dropped: u64,
+    /// 按协议分类的已写入流量，未开启`protocol_stats`时为空
+    pub protocols: ProtocolStats,
+}
+
+/// 捕获的汇总报告，通过`CaptureHandle::report()`获取；捕获过程中获取时为截至目前的结果
+#[derive(Debug, Clone, Default)]
+pub struct CaptureReport {
+    pub stats: CaptureStats,
+    /// 相邻数据包时间戳之差的分布（纳秒），未配置`histograms`时为None
+    pub inter_arrival: Option<Histogram>,
+    /// 数据包原始长度的分布（字节），未配置`histograms`时为None
+    pub packet_sizes: Option<Histogram>,
+    /// 按发送端ID统计的用户数据包，只包括通过`PcapCapturer::get_packet_sender_with_id`
+    /// 获取的发送端
+    pub senders: BTreeMap<String, SenderStats>,
+}
+
+/// 一个用户数据包发送端的统计，用于排查哪个来源发送过少或过多
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SenderStats {
+    /// 成功放入数据包通道的数据包数量（之后仍可能被拒绝）
+    pub packets: u64,
+    pub bytes: u64,
+    /// 最后一次发送的时间，从未发送时为None
+    pub last_sent: Option<SystemTime>,
+}
+
+// 发送端自己更新的计数器，同一ID的发送端（包括克隆）共用
+#[derive(Debug, Default)]
+pub(crate) struct SenderCounters {
+    packets: AtomicU64,
+    bytes: AtomicU64,
+    // 自UNIX纪元起的微秒数，0表示从未发送
+    last_sent_micros: AtomicU64,
+}
+
+impl SenderCounters {
+    pub fn record_sent(&self, bytes: u64) {
+        self.packets.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        self.last_sent_micros
+            .fetch_max(now.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> SenderStats {
+        let last_sent_micros = self.last_sent_micros.load(Ordering::Relaxed);
+        SenderStats {
+            packets: self.packets.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            last_sent: (last_sent_micros > 0)
+                .then(|| UNIX_EPOCH + Duration::from_micros(last_sent_micros)),
+        }
+    }
+}
+
+/// 用于实时状态显示（类似`dumpcap`）的统计，通过`CaptureHandle::live_stats()`轮询，每秒更新
+#[derive(Debug, Clone, Default)]
+pub struct LiveStats {
+    /// 最近一秒写入的数据包速率
+    pub packets_per_second: f64,
+    /// 最近一秒写入的流量（按数据包原始长度）
+    pub bits_per_second: f64,
+    pub packets_written: u64,
+    pub bytes_written: u64,
+    /// 内核缓冲区已满或网卡丢弃的数据包数量
+    pub kernel_dropped: u64,
+    /// 写入队列中等待写入的数据包数量（仅在启用独立写入线程时有效）
+    pub writer_queue_len: usize,
+    /// 正在写入的文件，捕获开始前为None
+    pub current_file: Option<PathBuf>,
+    /// 正在写入的文件的序号，未开启`file_sequence`时为None
+    pub current_file_sequence: Option<u64>,
+    /// 正在写入的文件已写入的字节数
+    pub current_file_size: u64,
+}
+
+/// libpcap报告的累计统计（`pcap_stats`），只有通过libpcap打开的数据来源提供
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PcapStats {
+    /// ps_recv：libpcap收到的数据包数。Linux上只计入通过BPF过滤的数据包，
+    /// 其他平台（例如BSD和macOS）还包括未通过过滤的数据包
+    pub received: u64,
+    /// ps_drop：内核缓冲区已满而丢弃的数据包数
+    pub dropped: u64,
+    /// ps_ifdrop：网卡或驱动丢弃的数据包数
+    pub if_dropped: u64,
+}
+
+impl PcapStats {
+    // 自base以来的增量
+    pub(crate) fn since(self, base: PcapStats) -> PcapStats {
+        PcapStats {
+            received: self.received.saturating_sub(base.received),
+            dropped: self.dropped.saturating_sub(base.dropped),
+            if_dropped: self.if_dropped.saturating_sub(base.if_dropped),
+        }
+    }
+}
+
+/// 一类流量的数据包数和字节数（按数据包原始长度）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TrafficCounter {
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+impl TrafficCounter {
+    fn add(&mut self, bytes: u64) {
+        self.packets += 1;
+        self.bytes += bytes;
+    }
+}
+
+/// 按协议分类的流量统计
+#[derive(Debug, Clone, Default)]
+pub struct ProtocolStats {
+    /// 按以太网类型（VLAN标签之后的类型）分类，原始IP链路层按IP版本计入0x0800或0x86DD
+    pub ethertypes: BTreeMap<u16, TrafficCounter>,
+    /// 按IP协议号分类
+    pub ip_protocols: BTreeMap<u8, TrafficCounter>,
+    /// 按TCP/UDP服务端口（连接两端中较小的端口）分类，只统计1024以下和常见的高端口
+    pub ports: BTreeMap<u16, TrafficCounter>,
+    /// 服务端口不在`ports`统计范围内的TCP/UDP流量
+    pub other_ports: TrafficCounter,
+}
+
+impl ProtocolStats {
+    fn record(&mut self, datalink: DataLink, data: &[u8], bytes: u64) {
+        let Some((ethertype, ip)) = parse::network_layer(datalink, data) else {
+            return;
+        };
+        self.ethertypes.entry(ethertype).or_default().add(bytes);
+
+        let protocol = match ethertype {
+            ETHERTYPE_IPV4 => ip.get(9),
+            ETHERTYPE_IPV6 => ip.get(6),
+            _ => None,
+        };
+        if let Some(&protocol) = protocol {
+            self.ip_protocols.entry(protocol).or_default().add(bytes);
+        }
+
+        if let Some(transport) = parse::transport(datalink, data) {
+            let port = transport.source_port.min(transport.destination_port);
+            if port < 1024 || COMMON_HIGH_PORTS.contains(&port) {
+                self.ports.entry(port).or_default().add(bytes);
+            } else {
+                self.other_ports.add(bytes);
+            }
+        }
+    }
+}
+
+// 捕获线程和写入线程更新的计数器，读取方只获取快照
+#[derive(Debug, Default)]
+pub(crate) struct StatsCounters {
+    packets_written: AtomicU64,
+    bytes_written: AtomicU64,
+    rotations: AtomicU64,
+    rotation_nanos_total: AtomicU64,
+    rotation_nanos_max: AtomicU64,
+    invalid_packets: AtomicU64,
+    late_packets: AtomicU64,
+    write_errors: AtomicU64,
+    redactions: AtomicU64,
+    heartbeats: AtomicU64,
+    kernel_dropped: AtomicU64,
+    // 捕获线程每秒更新一次，数据来源不提供时为None
+    pcap_stats: Mutex<Option<PcapStats>>,
+    // f64的位表示
+    packets_per_second: AtomicU64,
+    bits_per_second: AtomicU64,
+    current_file: Mutex<Option<(PathBuf, Option<u64>)>>,
+    current_file_size: AtomicU64,
+    writer_queue_len: AtomicUsize,
+    writer_queue_capacity: AtomicUsize,
+    writer_queue_high_watermark: AtomicUsize,
+    writer_queue_full_count: AtomicU64,
+    memory_used: AtomicU64,
+    memory_peak: AtomicU64,
+    memory_dropped: AtomicU64,
+    fallback_buffered: AtomicU64,
+    fallback_dropped: AtomicU64,
+    // 只由写入线程更新，读取方获取快照时短暂加锁
+    protocols: Mutex<ProtocolStats>,
+    // 写入线程每秒和捕获结束时发布一次
+    histograms: Mutex<Option<(Histogram, Histogram)>>,
+    senders: Mutex<BTreeMap<String, Arc<SenderCounters>>>,
+    // 最近一次写入数据包（或开始捕获）的时间，自UNIX纪元起的微秒数，按秒更新
+    last_active_micros: AtomicU64,
+}
+
+impl StatsCounters {
+    pub fn record_written(&self, bytes: u64) {
+        self.packets_written.fetch_add(1, Ordering::Relaxed);
+        self.bytes_written.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn record_kernel_dropped(&self, dropped: u64) {
+        self.kernel_dropped.store(dropped, Ordering::Relaxed);
+    }
+
+    pub fn kernel_dropped(&self) -> u64 {
+        self.kernel_dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn record_pcap_stats(&self, stats: PcapStats) {
+        if let Ok(mut pcap_stats) = self.pcap_stats.lock() {
+            *pcap_stats = Some(stats);
+        }
+    }
+
+    pub fn pcap_stats(&self) -> Option<PcapStats> {
+        self.pcap_stats.lock().ok().and_then(|stats| *stats)
+    }
+
+    pub fn record_rates(&self, packets_per_second: f64, bits_per_second: f64) {
+        self.packets_per_second
+            .store(packets_per_second.to_bits(), Ordering::Relaxed);
+        self.bits_per_second
+            .store(bits_per_second.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn record_current_file(&self, path: &Path, sequence: Option<u64>) {
+        if let Ok(mut current_file) = self.current_file.lock() {
+            *current_file = Some((path.to_path_buf(), sequence));
+        }
+        self.current_file_size.store(0, Ordering::Relaxed);
+    }
+
+    pub fn record_current_file_size(&self, size: u64) {
+        self.current_file_size.store(size, Ordering::Relaxed);
+    }
+
+    pub fn record_histograms(&self, histograms: &PacketHistograms) {
+        if let Ok(mut published) = self.histograms.lock() {
+            *published = Some((histograms.inter_arrival.clone(), histograms.sizes.clone()));
+        }
+    }
+
+    pub fn report(&self) -> CaptureReport {
+        let histograms = self
+            .histograms
+            .lock()
+            .ok()
+            .and_then(|histograms| histograms.clone());
+        let (inter_arrival, packet_sizes) = histograms.unzip();
+        let senders = self
+            .senders
+            .lock()
+            .map(|senders| {
+                senders
+                    .iter()
+                    .map(|(id, counters)| (id.clone(), counters.snapshot()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        CaptureReport {
+            stats: self.snapshot(),
+            inter_arrival,
+            packet_sizes,
+            senders,
+        }
+    }
+
+    // 相同的ID返回相同的计数器，重复获取发送端时统计会合并
+    pub fn register_sender(&self, id: &str) -> Arc<SenderCounters> {
+        match self.senders.lock() {
+            Ok(mut senders) => senders.entry(id.to_string()).or_default().clone(),
+            Err(_) => Arc::default(),
+        }
+    }
+
+    pub fn live_snapshot(&self) -> LiveStats {
+        let (current_file, current_file_sequence) = self
+            .current_file
+            .lock()
+            .ok()
+            .and_then(|current_file| current_file.clone())
+            .unzip();
+        LiveStats {
+            packets_per_second: f64::from_bits(self.packets_per_second.load(Ordering::Relaxed)),
+            bits_per_second: f64::from_bits(self.bits_per_second.load(Ordering::Relaxed)),
+            packets_written: self.packets_written.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            kernel_dropped: self.kernel_dropped.load(Ordering::Relaxed),
+            writer_queue_len: self.writer_queue_len.load(Ordering::Relaxed),
+            current_file,
+            current_file_sequence: current_file_sequence.flatten(),
+            current_file_size: self.current_file_size.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn record_protocols(&self, datalink: DataLink, data: &[u8], orig_len: u32) {
+        if let Ok(mut protocols) = self.protocols.lock() {
+            protocols.record(datalink, data, orig_len as u64);
+        }
+    }
+
+    pub fn record_rotation(&self, elapsed: Duration) {
+        let nanos = elapsed.as_nanos() as u64;
+        self.rotations.fetch_add(1, Ordering::Relaxed);
+        self.rotation_nanos_total
+            .fetch_add(nanos, Ordering::Relaxed);
+        self.rotation_nanos_max.fetch_max(nanos, Ordering::Relaxed);
+    }
+
+    pub fn record_invalid(&self) {
+        self.invalid_packets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn mark_active(&self) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        self.last_active_micros
+            .store(now.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// 距上次写入数据包（或开始捕获）的时间
+    pub fn idle(&self) -> Duration {
+        let last_active =
+            UNIX_EPOCH + Duration::from_micros(self.last_active_micros.load(Ordering::Relaxed));
+        SystemTime::now()
+            .duration_since(last_active)
+            .unwrap_or_default()
+    }
+
+    pub fn record_late(&self) {
+        self.late_packets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_write_error(&self) {
+        self.write_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_redactions(&self, count: u64) {
+        self.redactions.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn record_heartbeat(&self) {
+        self.heartbeats.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_writer_queue_capacity(&self, capacity: usize) {
+        self.writer_queue_capacity
+            .store(capacity, Ordering::Relaxed);
+    }
+
+    pub fn record_writer_queue_len(&self, len: usize) {
+        self.writer_queue_len.store(len, Ordering::Relaxed);
+        self.writer_queue_high_watermark
+            .fetch_max(len, Ordering::Relaxed);
+    }
+
+    pub fn record_writer_queue_full(&self) {
+        self.writer_queue_full_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 为缓存的数据包预留内存，预留后超过limit时不预留并返回false
+    pub fn reserve_memory(&self, bytes: u64, limit: Option<u64>) -> bool {
+        let reserved =
+            self.memory_used
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                    let used = used + bytes;
+                    limit.is_none_or(|limit| used <= limit).then_some(used)
+                });
+        match reserved {
+            Ok(used) => {
+                self.memory_peak.fetch_max(used + bytes, Ordering::Relaxed);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    pub fn release_memory(&self, bytes: u64) {
+        self.memory_used.fetch_sub(bytes, Ordering::Relaxed);
+    }
+
+    pub fn memory_used(&self) -> u64 {
+        self.memory_used.load(Ordering::Relaxed)
+    }
+
+    pub fn record_memory_dropped(&self) {
+        self.memory_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_fallback(&self, buffered: u64, dropped: u64) {
+        self.fallback_buffered.store(buffered, Ordering::Relaxed);
+        self.fallback_dropped.fetch_add(dropped, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> CaptureStats {
+        CaptureStats {
+            packets_written: self.packets_written.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            rotations: self.rotations.load(Ordering::Relaxed),
+            rotation_time_total: Duration::from_nanos(
+                self.rotation_nanos_total.load(Ordering::Relaxed),
+            ),
+            rotation_time_max: Duration::from_nanos(
+                self.rotation_nanos_max.load(Ordering::Relaxed),
+            ),
+            invalid_packets: self.invalid_packets.load(Ordering::Relaxed),
+            late_packets: self.late_packets.load(Ordering::Relaxed),
+            write_errors: self.write_errors.load(Ordering::Relaxed),
+            redactions: self.redactions.load(Ordering::Relaxed),
+            heartbeats: self.heartbeats.load(Ordering::Relaxed),
+            kernel_dropped: self.kernel_dropped.load(Ordering::Relaxed),
+            writer_queue_len: self.writer_queue_len.load(Ordering::Relaxed),
+            writer_queue_capacity: self.writer_queue_capacity.load(Ordering::Relaxed),
+            writer_queue_high_watermark: self.writer_queue_high_watermark.load(Ordering::Relaxed),
+            writer_queue_full_count: self.writer_queue_full_count.load(Ordering::Relaxed),
+            memory_used: self.memory_used.load(Ordering::Relaxed),
+            memory_peak: self.memory_peak.load(Ordering::Relaxed),
+            memory_dropped: self.memory_dropped.load(Ordering::Relaxed),
+            fallback_buffered: self.fallback_buffered.load(Ordering::Relaxed),
+            fallback_dropped: self.fallback_dropped.load(Ordering::Relaxed),
+            protocols: self
+                .protocols
+                .lock()
+                .map(|protocols| protocols.clone())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// 以JSON格式覆盖写入统计文件。先写临时文件再重命名，读取方不会读到写了一半的文件
+pub(crate) fn write_stats_file(path: &Path, stats: &CaptureStats) -> io::Result<()> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let counter = |counter: &TrafficCounter| {
+        format!(
+            "{{\"packets\": {}, \"bytes\": {}}}",
+            counter.packets, counter.bytes
+        )
+    };
+    let map = |entries: Vec<(String, &TrafficCounter)>| {
+        let entries: Vec<_> = entries
+            .into_iter()
+            .map(|(key, value)| format!("{}: {}", json_string(&key), counter(value)))
+            .collect();
+        format!("{{{}}}", entries.join(", "))
+    };
+    let protocols = &stats.protocols;
+
+    let mut json = String::from("{\n");
+    let _ = writeln!(
+        json,
+        "  \"updated\": {},",
+        json_string(&metadata::rfc3339(now))
+    );
+    let _ = writeln!(json, "  \"packets_written\": {},", stats.packets_written);
+    let _ = writeln!(json, "  \"bytes_written\": {},", stats.bytes_written);
+    let _ = writeln!(json, "  \"rotations\": {},", stats.rotations);
+    let _ = writeln!(json, "  \"invalid_packets\": {},", stats.invalid_packets);
+    let _ = writeln!(json, "  \"late_packets\": {},", stats.late_packets);
+    let _ = writeln!(json, "  \"write_errors\": {},", stats.write_errors);
+    let _ = writeln!(json, "  \"redactions\": {},", stats.redactions);
+    let _ = writeln!(json, "  \"heartbeats\": {},", stats.heartbeats);
+    let _ = writeln!(json, "  \"kernel_dropped\": {},", stats.kernel_dropped);
+    let _ = writeln!(json, "  \"writer_queue_len\": {},", stats.writer_queue_len);
+    let _ = writeln!(json, "  \"memory_used\": {},", stats.memory_used);
+    let _ = writeln!(json, "  \"memory_peak\": {},", stats.memory_peak);
+    let _ = writeln!(json, "  \"memory_dropped\": {},", stats.memory_dropped);
+    let _ = writeln!(
+        json,
+        "  \"fallback_buffered\": {},",
+        stats.fallback_buffered
+    );
+    let _ = writeln!(json, "  \"fallback_dropped\": {},", stats.fallback_dropped);
+    let _ = writeln!(
+        json,
+        "  \"ethertypes\": {},",
+        map(protocols
+            .ethertypes
+            .iter()
+            .map(|(ethertype, value)| (format!("0x{:04x}", ethertype), value))
+            .collect())
+    );
+    let _ = writeln!(
+        json,
+        "  \"ip_protocols\": {},",
+        map(protocols
+            .ip_protocols
+            .iter()
+            .map(|(protocol, value)| (protocol.to_string(), value))
+            .collect())
+    );
+    let _ = writeln!(
+        json,
+        "  \"ports\": {},",
+        map(protocols
+            .ports
+            .iter()
+            .map(|(port, value)| (port.to_string(), value))
+            .collect())
+    );
+    let _ = writeln!(
+        json,
+        "  \"other_ports\": {}",
+        counter(&protocols.other_ports)
+    );
+    json.push_str("}\n");
+
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    fs::write(&temp_path, json)?;
+    fs::rename(&temp_path, path)
+}
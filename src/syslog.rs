@@ -0,0 +1,153 @@
+use crate::observer::{ClosedFile, Observer};
+use crate::{CaptureStats, WriteErrorPolicy};
+use std::path::Path;
+
+/// syslog设施
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SyslogFacility {
+    User,
+    #[default]
+    Daemon,
+    Local0,
+    Local1,
+    Local2,
+    Local3,
+    Local4,
+    Local5,
+    Local6,
+    Local7,
+}
+
+/// 把开始、滚动、结束和错误事件发送到本机syslog（需要启用`syslog` feature，仅Unix）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyslogOptions {
+    pub facility: SyslogFacility,
+    /// 加在每条消息开头，例如会话名称，用于区分同一主机上的多个捕获
+    pub tag: String,
+}
+
+impl Default for SyslogOptions {
+    fn default() -> Self {
+        Self {
+            facility: SyslogFacility::default(),
+            tag: "save_pcap".to_string(),
+        }
+    }
+}
+
+// 与syslog.h中的定义一致，所有Unix平台相同
+#[derive(Clone, Copy)]
+enum Severity {
+    Error = 3,
+    Warning = 4,
+    Notice = 5,
+    Info = 6,
+}
+
+impl SyslogFacility {
+    fn code(self) -> i32 {
+        let code = match self {
+            SyslogFacility::User => 1,
+            SyslogFacility::Daemon => 3,
+            SyslogFacility::Local0 => 16,
+            SyslogFacility::Local1 => 17,
+            SyslogFacility::Local2 => 18,
+            SyslogFacility::Local3 => 19,
+            SyslogFacility::Local4 => 20,
+            SyslogFacility::Local5 => 21,
+            SyslogFacility::Local6 => 22,
+            SyslogFacility::Local7 => 23,
+        };
+        code << 3
+    }
+}
+
+// 作为观察者注册到会话状态
+pub(crate) struct SyslogNotifier {
+    options: SyslogOptions,
+}
+
+impl SyslogNotifier {
+    pub fn new(options: &SyslogOptions) -> Self {
+        Self {
+            options: options.clone(),
+        }
+    }
+
+    fn send(&self, severity: Severity, message: &str) {
+        let message = format!("{}: {}", self.options.tag, message);
+        send(self.options.facility.code() | severity as i32, &message);
+    }
+}
+
+impl Observer for SyslogNotifier {
+    fn on_start(&self) {
+        self.send(Severity::Info, "capture started");
+    }
+
+    fn on_rotate(&self, closed: &ClosedFile, opened: &Path) {
+        self.send(
+            Severity::Notice,
+            &format!(
+                "rotated capture file {} ({} packets, {} bytes, {} dropped) to {}",
+                closed.path.display(),
+                closed.packets,
+                closed.bytes,
+                closed.dropped,
+                opened.display()
+            ),
+        );
+    }
+
+    fn on_write_error(&self, error: &str, policy: WriteErrorPolicy) {
+        self.send(
+            Severity::Warning,
+            &format!("write failed, continuing ({:?}): {}", policy, error),
+        );
+    }
+
+    fn on_output_unavailable(&self, error: &str) {
+        self.send(
+            Severity::Warning,
+            &format!("output unavailable, buffering packets in memory: {}", error),
+        );
+    }
+
+    fn on_output_restored(&self, backlog: u64) {
+        self.send(
+            Severity::Notice,
+            &format!("output restored, wrote {} buffered packets", backlog),
+        );
+    }
+
+    fn on_error(&self, error: &str) {
+        self.send(Severity::Error, &format!("capture failed: {}", error));
+    }
+
+    fn on_stop(&self, stats: &CaptureStats) {
+        self.send(
+            Severity::Info,
+            &format!(
+                "capture stopped after {} packets ({} bytes)",
+                stats.packets_written, stats.bytes_written
+            ),
+        );
+    }
+}
+
+#[cfg(unix)]
+fn send(priority: i32, message: &str) {
+    use std::ffi::CString;
+
+    // 消息中的NUL无法传给syslog，替换掉
+    let Ok(message) = CString::new(message.replace('\0', " ")) else {
+        return;
+    };
+    // 消息作为参数传入，不作为格式串，避免其中的%被解释
+    unsafe { libc::syslog(priority, c"%s".as_ptr(), message.as_ptr()) };
+}
+
+#[cfg(not(unix))]
+fn send(_priority: i32, message: &str) {
+    log::warn!("syslog is not available on this platform: {}", message);
+}
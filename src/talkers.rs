@@ -0,0 +1,142 @@
+use crate::metadata::{self, json_string};
+use crate::parse::{self, IPPROTO_TCP, IPPROTO_UDP};
+use crate::source::SourcePacket;
+use pcap_file::DataLink;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::hash::Hash;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+
+#[derive(Default, Clone, Copy)]
+struct Counters {
+    packets: u64,
+    bytes: u64,
+}
+
+impl Counters {
+    fn add(&mut self, bytes: u64) {
+        self.packets += 1;
+        self.bytes += bytes;
+    }
+}
+
+// 每个文件关闭时写一个`.talkers.json`摘要，列出流量最大的源地址、目的地址和服务端口
+pub(crate) struct TopTalkers {
+    limit: usize,
+    total: Counters,
+    sources: HashMap<IpAddr, Counters>,
+    destinations: HashMap<IpAddr, Counters>,
+    // 以连接两端中较小的端口作为服务端口，请求和响应计入同一个端口
+    ports: HashMap<(u8, u16), Counters>,
+}
+
+impl TopTalkers {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            total: Counters::default(),
+            sources: HashMap::new(),
+            destinations: HashMap::new(),
+            ports: HashMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, datalink: DataLink, packet: &SourcePacket) {
+        let bytes = packet.orig_len as u64;
+        self.total.add(bytes);
+
+        if let Some(transport) = parse::transport(datalink, &packet.data) {
+            self.sources.entry(transport.source).or_default().add(bytes);
+            self.destinations
+                .entry(transport.destination)
+                .or_default()
+                .add(bytes);
+            let port = transport.source_port.min(transport.destination_port);
+            self.ports
+                .entry((transport.protocol, port))
+                .or_default()
+                .add(bytes);
+        } else if let Some((source, destination)) = parse::ip_addresses(datalink, &packet.data) {
+            self.sources.entry(source).or_default().add(bytes);
+            self.destinations.entry(destination).or_default().add(bytes);
+        }
+    }
+
+    pub fn discard(&mut self) {
+        self.total = Counters::default();
+        self.sources.clear();
+        self.destinations.clear();
+        self.ports.clear();
+    }
+
+    /// 没有数据包的文件不生成摘要
+    pub fn write_sidecar(&mut self, capture_path: &Path) -> io::Result<()> {
+        if self.total.packets == 0 {
+            return Ok(());
+        }
+
+        let file_name = capture_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut json = String::from("{\n");
+        let _ = writeln!(json, "  \"file\": {},", json_string(&file_name));
+        let _ = writeln!(json, "  \"packets\": {},", self.total.packets);
+        let _ = writeln!(json, "  \"bytes\": {},", self.total.bytes);
+
+        let address_entry =
+            |address: &IpAddr| format!("\"ip\": {}", json_string(&address.to_string()));
+        let port_entry = |(protocol, port): &(u8, u16)| {
+            let protocol = match *protocol {
+                IPPROTO_TCP => "tcp",
+                IPPROTO_UDP => "udp",
+                _ => "other",
+            };
+            format!("\"protocol\": \"{}\", \"port\": {}", protocol, port)
+        };
+        let sources = top_entries(&self.sources, self.limit, address_entry);
+        let destinations = top_entries(&self.destinations, self.limit, address_entry);
+        let ports = top_entries(&self.ports, self.limit, port_entry);
+        let _ = writeln!(json, "  \"sources\": [{}],", sources);
+        let _ = writeln!(json, "  \"destinations\": [{}],", destinations);
+        let _ = writeln!(json, "  \"ports\": [{}]", ports);
+        json.push_str("}\n");
+
+        self.discard();
+        fs::write(
+            metadata::sidecar_path_with(capture_path, metadata::TALKERS_SUFFIX),
+            json,
+        )
+    }
+}
+
+// 按字节数从大到小取前limit项，每项一行
+fn top_entries<K: Eq + Hash>(
+    counters: &HashMap<K, Counters>,
+    limit: usize,
+    key: impl Fn(&K) -> String,
+) -> String {
+    let mut entries: Vec<_> = counters.iter().collect();
+    entries.sort_by_key(|(_, counters)| Reverse(counters.bytes));
+    let lines: Vec<_> = entries
+        .into_iter()
+        .take(limit)
+        .map(|(k, counters)| {
+            format!(
+                "\n    {{{}, \"packets\": {}, \"bytes\": {}}}",
+                key(k),
+                counters.packets,
+                counters.bytes
+            )
+        })
+        .collect();
+    if lines.is_empty() {
+        String::new()
+    } else {
+        format!("{}\n  ", lines.join(","))
+    }
+}
@@ -0,0 +1,327 @@
+//! 合成流量生成：按指定的包长分布、速率、协议和流数量生成有效的以太网/IPv4数据包，
+//! 可以发送到`UserProvided`模式的数据包通道，也可以直接交给写入流程，
+//! 用于在没有真实网络的环境中做集成测试和性能测试。
+
+use crate::packet_builder::EthernetFrame;
+use crate::pool::BufferPool;
+use crate::source::{NextPacket, PacketStream, SourcePacket};
+use crate::{CaptureStats, PcapCaptureOptions, PcapCapturer, SavePcapError, UserPacket};
+use log::info;
+use pcap_file::DataLink;
+use std::fs;
+use std::net::Ipv4Addr;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// 帧长上限：IPv4总长度字段的最大值加以太网头部
+const MAX_FRAME_SIZE: usize = 14 + u16::MAX as usize;
+// 经典IMIX的帧长和比例（7:4:1）
+const IMIX: [(usize, u32); 3] = [(64, 7), (594, 4), (1518, 1)];
+
+// 生成的数据包都发往同一个目的主机，源地址和源端口按流区分
+const DESTINATION_MAC: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0x00, 0x01];
+const DESTINATION_IP: Ipv4Addr = Ipv4Addr::new(192, 168, 0, 1);
+const UDP_DESTINATION_PORT: u16 = 5001;
+const TCP_DESTINATION_PORT: u16 = 80;
+const FIRST_SOURCE_PORT: u16 = 1024;
+// 直接写入时每次等待的最长时间
+const MAX_IDLE_SLEEP: Duration = Duration::from_millis(10);
+
+/// 生成的传输层协议
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrafficProtocol {
+    Udp,
+    /// 已建立连接上的数据段（ACK|PSH），序号按流递增
+    Tcp,
+    /// ICMP回显请求，标识符区分各条流
+    Icmp,
+}
+
+/// 帧长（含以太网头部，不含FCS）分布。小于协议头部总长的帧长按头部总长生成
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketSizes {
+    Fixed(usize),
+    /// 在`[min, max]`内均匀分布
+    Uniform {
+        min: usize,
+        max: usize,
+    },
+    /// 经典IMIX：64、594和1518字节按7:4:1混合
+    Imix,
+}
+
+/// 合成流量参数
+#[derive(Debug, Clone)]
+pub struct TrafficOptions {
+    pub packet_sizes: PacketSizes,
+    /// 速率（包/秒），决定数据包时间戳的间隔，发送和写入时按该速率实际限速；
+    /// None表示尽可能快地生成，时间戳取生成时的当前时间
+    pub packet_rate: Option<u64>,
+    /// 各条流依次使用的协议，第N条流使用`protocols[N % len]`
+    pub protocols: Vec<TrafficProtocol>,
+    /// 流的数量，数据包按轮询的方式分配到各条流
+    pub flows: u32,
+    /// 生成的数据包总数
+    pub packet_count: u64,
+    /// 随机包长的种子，相同的参数和种子生成完全相同的数据包
+    pub seed: u64,
+}
+
+impl Default for TrafficOptions {
+    fn default() -> Self {
+        Self {
+            packet_sizes: PacketSizes::Fixed(512),
+            packet_rate: None,
+            protocols: vec![TrafficProtocol::Udp],
+            flows: 1,
+            packet_count: 1000,
+            seed: 1,
+        }
+    }
+}
+
+// 一条流的地址和协议状态
+struct Flow {
+    protocol: TrafficProtocol,
+    source_mac: [u8; 6],
+    source_ip: Ipv4Addr,
+    source_port: u16,
+    // TCP序号或ICMP序号
+    sequence: u32,
+}
+
+impl Flow {
+    fn new(number: u32, protocol: TrafficProtocol) -> Self {
+        let [_, b, c, d] = number.to_be_bytes();
+        Self {
+            protocol,
+            source_mac: [0x02, 0x00, 0x01, b, c, d],
+            // 10.0.0.0/8中从10.0.0.1开始依次分配
+            source_ip: Ipv4Addr::from(u32::from(Ipv4Addr::new(10, 0, 0, 1)) + number),
+            source_port: FIRST_SOURCE_PORT
+                + (number % (u16::MAX - FIRST_SOURCE_PORT) as u32) as u16,
+            sequence: 0,
+        }
+    }
+
+    // 在data后追加一个帧长为frame_size的帧，负载填充数据包序号
+    fn write_frame(&mut self, frame_size: usize, packet_number: u64, data: &mut Vec<u8>) {
+        let frame = EthernetFrame::new()
+            .source_mac(self.source_mac)
+            .destination_mac(DESTINATION_MAC)
+            .ipv4(self.source_ip, DESTINATION_IP)
+            .ip_identification(packet_number as u16);
+        let frame = match self.protocol {
+            TrafficProtocol::Udp => frame.udp(self.source_port, UDP_DESTINATION_PORT),
+            TrafficProtocol::Tcp => frame
+                .tcp(self.source_port, TCP_DESTINATION_PORT)
+                .tcp_sequence(self.sequence, 1),
+            TrafficProtocol::Icmp => {
+                let [_, _, c, d] = self.source_ip.octets();
+                frame.icmp_echo(u16::from_be_bytes([c, d]), self.sequence as u16)
+            }
+        };
+
+        let headers_len = frame.frame_len();
+        let payload_len = frame_size.clamp(headers_len, MAX_FRAME_SIZE) - headers_len;
+        self.sequence = match self.protocol {
+            TrafficProtocol::Tcp => self.sequence.wrapping_add(payload_len as u32),
+            _ => self.sequence.wrapping_add(1),
+        };
+        let number = packet_number.to_be_bytes();
+        let payload: Vec<u8> = (0..payload_len).map(|i| number[i % number.len()]).collect();
+        // 帧长已限制在IPv4允许的范围内，不会失败
+        frame
+            .payload(payload)
+            .write_to(data)
+            .expect("generated frame within IPv4 length limits");
+    }
+}
+
+/// 合成流量生成器，作为迭代器逐个返回数据包（不限速）
+pub struct TrafficGenerator {
+    options: TrafficOptions,
+    flows: Vec<Flow>,
+    generated: u64,
+    // 第一个数据包的时间戳
+    started: Duration,
+    rng: u64,
+}
+
+impl TrafficGenerator {
+    pub fn new(options: TrafficOptions) -> Result<Self, SavePcapError> {
+        let invalid = |reason: &str| Err(SavePcapError::InvalidTrafficOptions(reason.to_string()));
+        if options.protocols.is_empty() {
+            return invalid("at least one protocol is required");
+        }
+        if options.flows == 0 {
+            return invalid("flows must be at least 1");
+        }
+        if options.packet_rate == Some(0) {
+            return invalid("packet_rate must be greater than 0");
+        }
+        if let PacketSizes::Uniform { min, max } = options.packet_sizes
+            && min > max
+        {
+            return invalid("packet size range min is greater than max");
+        }
+
+        let flows = (0..options.flows)
+            .map(|number| {
+                let protocol = options.protocols[number as usize % options.protocols.len()];
+                Flow::new(number, protocol)
+            })
+            .collect();
+        Ok(Self {
+            // xorshift的状态不能为0
+            rng: options.seed.max(1),
+            options,
+            flows,
+            generated: 0,
+            started: now(),
+        })
+    }
+
+    /// 已生成的数据包数量
+    pub fn generated(&self) -> u64 {
+        self.generated
+    }
+
+    /// 把数据包发送到`UserProvided`模式的通道，设置了速率时按速率限速。
+    /// 返回发送的数据包数量，接收端关闭时提前结束
+    pub fn send_to(mut self, sender: &Sender<UserPacket>) -> Result<u64, SavePcapError> {
+        let started = Instant::now();
+        loop {
+            if let Some(due) = self.due(started) {
+                thread::sleep(due.saturating_duration_since(Instant::now()));
+            }
+            let Some(packet) = self.next() else {
+                break;
+            };
+            if sender.send(packet).is_err() {
+                info!("Packet receiver closed, stopping traffic generation");
+                break;
+            }
+        }
+        Ok(self.generated)
+    }
+
+    /// 不经过数据包通道，直接走与真实捕获相同的写入和滚动流程，
+    /// 写入配置中的数据包来源不起作用。返回写入统计
+    pub fn write(self, capture_options: PcapCaptureOptions) -> Result<CaptureStats, SavePcapError> {
+        fs::create_dir_all(&capture_options.file_path)?;
+        let capturer = PcapCapturer::new(capture_options);
+        let mut stream = GeneratedStream {
+            generator: self,
+            started: Instant::now(),
+        };
+        capturer.run_capture(&mut stream)?;
+        Ok(capturer.handle().stats())
+    }
+
+    // 生成下一个数据包，数据追加到data之后，返回时间戳
+    fn generate_into(&mut self, data: &mut Vec<u8>) -> Option<Duration> {
+        if self.generated >= self.options.packet_count {
+            return None;
+        }
+        let frame_size = self.frame_size();
+        let number = self.generated;
+        let flow_count = self.flows.len() as u64;
+        self.flows[(number % flow_count) as usize].write_frame(frame_size, number, data);
+        self.generated += 1;
+
+        Some(match self.options.packet_rate {
+            Some(rate) => self.started + Duration::from_secs_f64(number as f64 / rate as f64),
+            None => now(),
+        })
+    }
+
+    // 限速时下一个数据包的发送时间，started为开始发送的时间
+    fn due(&self, started: Instant) -> Option<Instant> {
+        let rate = self.options.packet_rate?;
+        Some(started + Duration::from_secs_f64(self.generated as f64 / rate as f64))
+    }
+
+    fn frame_size(&mut self) -> usize {
+        match self.options.packet_sizes {
+            PacketSizes::Fixed(size) => size,
+            PacketSizes::Uniform { min, max } => {
+                min + (self.next_random() % (max - min + 1) as u64) as usize
+            }
+            PacketSizes::Imix => {
+                let total: u32 = IMIX.iter().map(|(_, weight)| weight).sum();
+                let mut pick = (self.next_random() % total as u64) as u32;
+                for (size, weight) in IMIX {
+                    if pick < weight {
+                        return size;
+                    }
+                    pick -= weight;
+                }
+                unreachable!()
+            }
+        }
+    }
+
+    // xorshift64，只用于包长，不需要密码学强度
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+}
+
+impl Iterator for TrafficGenerator {
+    type Item = UserPacket;
+
+    fn next(&mut self) -> Option<UserPacket> {
+        let mut data = Vec::new();
+        let timestamp = self.generate_into(&mut data)?;
+        Some(UserPacket {
+            data,
+            timestamp: Some(timestamp),
+        })
+    }
+}
+
+fn now() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+// 直接交给写入流程的数据包流，数据使用缓冲池中的缓冲区
+struct GeneratedStream {
+    generator: TrafficGenerator,
+    started: Instant,
+}
+
+impl PacketStream for GeneratedStream {
+    fn datalink(&self) -> DataLink {
+        DataLink::ETHERNET
+    }
+
+    fn next_packet(&mut self, pool: &mut BufferPool) -> Result<NextPacket, SavePcapError> {
+        // 分段等待，以便捕获循环及时响应停止请求
+        if let Some(due) = self.generator.due(self.started) {
+            let remaining = due.saturating_duration_since(Instant::now());
+            if !remaining.is_zero() {
+                thread::sleep(remaining.min(MAX_IDLE_SLEEP));
+                return Ok(NextPacket::Idle);
+            }
+        }
+
+        let mut data = pool.take();
+        let Some(timestamp) = self.generator.generate_into(&mut data) else {
+            pool.give(data);
+            return Ok(NextPacket::End);
+        };
+        Ok(NextPacket::Packet(SourcePacket {
+            timestamp,
+            orig_len: data.len() as u32,
+            data,
+            user_index: None,
+        }))
+    }
+}
@@ -0,0 +1,53 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// 改写所有数据包时间戳的方式，见`PcapCaptureOptions::time_shift`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeShift {
+    /// 所有时间戳推后固定的时间
+    Forward(Duration),
+    /// 所有时间戳提前固定的时间，早于UNIX纪元的结果为0
+    Backward(Duration),
+    /// 第一个数据包的时间戳改为该时间（自UNIX纪元起），其余数据包保持与第一个数据包的间隔。
+    /// `StartAt(Duration::ZERO)`使捕获从t=0开始
+    StartAt(Duration),
+    /// 同`StartAt`，第一个数据包对齐到读取它时的当前时间
+    StartNow,
+}
+
+// 在第一个数据包时确定偏移量，之后对每个数据包使用同一个偏移量，保持数据包之间的间隔不变
+pub(crate) struct TimeShifter {
+    shift: TimeShift,
+    // 是否推后，以及偏移量
+    offset: Option<(bool, Duration)>,
+}
+
+impl TimeShifter {
+    pub fn new(shift: TimeShift) -> Self {
+        Self {
+            shift,
+            offset: None,
+        }
+    }
+
+    pub fn shift(&mut self, timestamp: Duration) -> Duration {
+        let shift = self.shift;
+        let (forward, offset) = *self.offset.get_or_insert_with(|| {
+            let start = match shift {
+                TimeShift::Forward(offset) => return (true, offset),
+                TimeShift::Backward(offset) => return (false, offset),
+                TimeShift::StartAt(start) => start,
+                TimeShift::StartNow => SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default(),
+            };
+            match start.checked_sub(timestamp) {
+                Some(offset) => (true, offset),
+                None => (false, timestamp - start),
+            }
+        });
+        match forward {
+            true => timestamp.saturating_add(offset),
+            false => timestamp.saturating_sub(offset),
+        }
+    }
+}
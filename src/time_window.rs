@@ -0,0 +1,52 @@
+use crate::SavePcapError;
+use chrono::{DateTime, Local, NaiveTime};
+use std::str::FromStr;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// 每天的一个时间段（本地时间），包含开始时间，不包含结束时间。
+/// 结束时间早于开始时间表示跨越午夜，例如22:00-06:00
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl TimeWindow {
+    pub fn new(start: NaiveTime, end: NaiveTime) -> Self {
+        Self { start, end }
+    }
+
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+/// 解析"HH:MM-HH:MM"或"HH:MM:SS-HH:MM:SS"格式，便于从命令行或配置文件读取
+impl FromStr for TimeWindow {
+    type Err = SavePcapError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || SavePcapError::InvalidTimeWindow(s.to_string());
+        let (start, end) = s.split_once('-').ok_or_else(invalid)?;
+        let parse = |value: &str| {
+            let value = value.trim();
+            NaiveTime::parse_from_str(value, "%H:%M:%S")
+                .or_else(|_| NaiveTime::parse_from_str(value, "%H:%M"))
+                .map_err(|_| invalid())
+        };
+        Ok(Self::new(parse(start)?, parse(end)?))
+    }
+}
+
+/// 时间戳（本地时间）是否落在任一时间段内；没有配置时间段时不做限制
+pub(crate) fn in_windows(windows: &[TimeWindow], timestamp: Duration) -> bool {
+    if windows.is_empty() {
+        return true;
+    }
+    let time = DateTime::<Local>::from(UNIX_EPOCH + timestamp).time();
+    windows.iter().any(|window| window.contains(time))
+}
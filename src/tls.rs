@@ -0,0 +1,74 @@
+// TLS记录和握手消息的类型
+const CONTENT_TYPE_HANDSHAKE: u8 = 0x16;
+const HANDSHAKE_CLIENT_HELLO: u8 = 0x01;
+const EXTENSION_SERVER_NAME: u16 = 0x0000;
+const SERVER_NAME_HOST_NAME: u8 = 0x00;
+// 记录头5字节，握手消息头4字节，随后是客户端版本2字节和随机数32字节
+const SESSION_ID_OFFSET: usize = 5 + 4 + 2 + 32;
+
+/// 从TCP负载中解析ClientHello的SNI。只处理ClientHello位于单个数据包开头的情况，
+/// 跨多个TCP段的ClientHello（例如带有较大的后量子密钥共享）不做重组
+pub(crate) fn client_hello_sni(payload: &[u8]) -> Option<&str> {
+    if *payload.first()? != CONTENT_TYPE_HANDSHAKE || *payload.get(5)? != HANDSHAKE_CLIENT_HELLO {
+        return None;
+    }
+
+    let mut reader = Reader {
+        data: payload,
+        offset: SESSION_ID_OFFSET,
+    };
+    let session_id_len = reader.u8()? as usize;
+    reader.skip(session_id_len)?;
+    let cipher_suites_len = reader.u16()? as usize;
+    reader.skip(cipher_suites_len)?;
+    let compression_methods_len = reader.u8()? as usize;
+    reader.skip(compression_methods_len)?;
+
+    let extensions_end = (reader.u16()? as usize + reader.offset).min(payload.len());
+    while reader.offset + 4 <= extensions_end {
+        let extension_type = reader.u16()?;
+        let extension_len = reader.u16()? as usize;
+        if extension_type != EXTENSION_SERVER_NAME {
+            reader.skip(extension_len)?;
+            continue;
+        }
+
+        // server_name_list只取第一个host_name
+        reader.skip(2)?;
+        if reader.u8()? != SERVER_NAME_HOST_NAME {
+            return None;
+        }
+        let name_len = reader.u16()? as usize;
+        let name = reader.bytes(name_len)?;
+        return std::str::from_utf8(name)
+            .ok()
+            .filter(|name| !name.is_empty() && name.is_ascii());
+    }
+    None
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.offset..self.offset.checked_add(len)?)?;
+        self.offset += len;
+        Some(bytes)
+    }
+
+    fn skip(&mut self, len: usize) -> Option<()> {
+        self.bytes(len).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|bytes| bytes[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.bytes(2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+}
@@ -0,0 +1,358 @@
+//! 用户提供数据包的协议校验（需要启用`validate` feature）：用pnet解析以太网、IP和
+//! TCP/UDP/ICMP头部，检查长度字段和校验和，按配置拒绝、修正或标记异常的数据包。
+
+use crate::parse::{ETHERTYPE_IPV4, ETHERTYPE_IPV6, network_layer};
+use pcap_file::DataLink;
+use pnet_packet::icmp::{self, IcmpPacket, MutableIcmpPacket};
+use pnet_packet::icmpv6::{self, Icmpv6Packet, MutableIcmpv6Packet};
+use pnet_packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
+use pnet_packet::ipv4::{self, Ipv4Packet, MutableIpv4Packet};
+use pnet_packet::ipv6::{Ipv6Packet, MutableIpv6Packet};
+use pnet_packet::tcp::{self, MutableTcpPacket, TcpPacket};
+use pnet_packet::udp::{self, MutableUdpPacket, UdpPacket};
+use std::net::IpAddr;
+
+const IPV4_MIN_HEADER_LEN: usize = 20;
+const IPV6_HEADER_LEN: usize = 40;
+const UDP_HEADER_LEN: usize = 8;
+const TCP_MIN_HEADER_LEN: usize = 20;
+const ICMP_HEADER_LEN: usize = 4;
+
+/// 用户提供数据包的校验配置
+#[derive(Debug, Clone, Default)]
+pub struct PacketValidation {
+    /// 发现异常数据包后的处理方式
+    pub action: ValidationAction,
+}
+
+/// 未通过校验的数据包的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ValidationAction {
+    /// 丢弃，不写入文件
+    #[default]
+    Reject,
+    /// 按帧中实际的数据重写IP/UDP长度字段并重新计算校验和后写入；
+    /// 头部本身不完整等无法修正的数据包丢弃
+    Fix,
+    /// 照常写入，并在数据包上附加说明原因的注释（需要pcapng格式）
+    Tag,
+}
+
+impl PacketValidation {
+    /// 返回数据包异常的原因，正常或无法解析的协议返回None
+    pub(crate) fn check(&self, datalink: DataLink, data: &[u8]) -> Option<String> {
+        let (ethertype, ip) = network_layer(datalink, data)?;
+        match ethertype {
+            ETHERTYPE_IPV4 => check_ipv4(ip),
+            ETHERTYPE_IPV6 => check_ipv6(ip),
+            _ => None,
+        }
+    }
+
+    /// 修正长度字段和校验和，修正后仍不合法时返回原因
+    pub(crate) fn fix(&self, datalink: DataLink, data: &mut [u8]) -> Result<(), String> {
+        let Some((ethertype, ip)) = network_layer(datalink, data) else {
+            return Ok(());
+        };
+        let offset = data.len() - ip.len();
+        let ip = &mut data[offset..];
+        match ethertype {
+            ETHERTYPE_IPV4 => fix_ipv4(ip),
+            ETHERTYPE_IPV6 => fix_ipv6(ip),
+            _ => {}
+        }
+        match self.check(datalink, data) {
+            Some(reason) => Err(reason),
+            None => Ok(()),
+        }
+    }
+}
+
+// IPv4头部长度和总长度，头部本身不完整时返回原因
+fn ipv4_lengths(ip: &[u8]) -> Result<(usize, usize), String> {
+    let packet = Ipv4Packet::new(ip)
+        .ok_or_else(|| format!("IPv4 header truncated to {} bytes", ip.len()))?;
+    if packet.get_version() != 4 {
+        return Err(format!(
+            "IP version {} in an IPv4 frame",
+            packet.get_version()
+        ));
+    }
+    let header_len = packet.get_header_length() as usize * 4;
+    if header_len < IPV4_MIN_HEADER_LEN || header_len > ip.len() {
+        return Err(format!("invalid IPv4 header length {}", header_len));
+    }
+    Ok((header_len, packet.get_total_length() as usize))
+}
+
+fn check_ipv4(ip: &[u8]) -> Option<String> {
+    let (header_len, total_len) = match ipv4_lengths(ip) {
+        Ok(lengths) => lengths,
+        Err(reason) => return Some(reason),
+    };
+    if total_len < header_len || total_len > ip.len() {
+        return Some(format!(
+            "IPv4 total length {} does not match the {} bytes in the frame",
+            total_len,
+            ip.len()
+        ));
+    }
+    let packet = Ipv4Packet::new(&ip[..total_len])?;
+    if ipv4::checksum(&packet) != packet.get_checksum() {
+        return Some("bad IPv4 header checksum".to_string());
+    }
+    // 分片的传输层数据不完整，不校验
+    if packet.get_fragment_offset() != 0 || packet.get_flags() & 0x1 != 0 {
+        return None;
+    }
+    check_transport(
+        packet.get_next_level_protocol(),
+        &ip[header_len..total_len],
+        IpAddr::V4(packet.get_source()),
+        IpAddr::V4(packet.get_destination()),
+    )
+}
+
+fn check_ipv6(ip: &[u8]) -> Option<String> {
+    let Some(packet) = Ipv6Packet::new(ip) else {
+        return Some(format!("IPv6 header truncated to {} bytes", ip.len()));
+    };
+    if packet.get_version() != 6 {
+        return Some(format!(
+            "IP version {} in an IPv6 frame",
+            packet.get_version()
+        ));
+    }
+    let total_len = IPV6_HEADER_LEN + packet.get_payload_length() as usize;
+    if total_len > ip.len() {
+        return Some(format!(
+            "IPv6 payload length {} exceeds the {} bytes in the frame",
+            total_len - IPV6_HEADER_LEN,
+            ip.len() - IPV6_HEADER_LEN
+        ));
+    }
+    // 只校验紧跟在固定头部之后的传输层，带扩展头部的数据包不校验
+    check_transport(
+        packet.get_next_header(),
+        &ip[IPV6_HEADER_LEN..total_len],
+        IpAddr::V6(packet.get_source()),
+        IpAddr::V6(packet.get_destination()),
+    )
+}
+
+fn check_transport(
+    protocol: IpNextHeaderProtocol,
+    segment: &[u8],
+    source: IpAddr,
+    destination: IpAddr,
+) -> Option<String> {
+    match transport_checksum(protocol, segment, source, destination) {
+        Err(reason) => Some(reason),
+        Ok(Some((stored, computed))) if stored != computed => Some(format!(
+            "bad {} checksum 0x{:04x}, expected 0x{:04x}",
+            protocol_name(protocol),
+            stored,
+            computed
+        )),
+        Ok(_) => None,
+    }
+}
+
+// 传输层中记录的校验和与按内容计算的校验和，不认识的协议返回None
+fn transport_checksum(
+    protocol: IpNextHeaderProtocol,
+    segment: &[u8],
+    source: IpAddr,
+    destination: IpAddr,
+) -> Result<Option<(u16, u16)>, String> {
+    let truncated = |name: &str| format!("{} header truncated", name);
+    match (protocol, source, destination) {
+        (IpNextHeaderProtocols::Udp, source, destination) => {
+            let packet = UdpPacket::new(segment).ok_or_else(|| truncated("UDP"))?;
+            if packet.get_length() as usize != segment.len() {
+                return Err(format!(
+                    "UDP length {} does not match the {} byte IP payload",
+                    packet.get_length(),
+                    segment.len()
+                ));
+            }
+            let stored = packet.get_checksum();
+            let computed = match (source, destination) {
+                // IPv4下校验和为0表示发送方未计算
+                (IpAddr::V4(_), _) if stored == 0 => 0,
+                (IpAddr::V4(source), IpAddr::V4(destination)) => {
+                    udp_checksum(udp::ipv4_checksum(&packet, &source, &destination))
+                }
+                (IpAddr::V6(source), IpAddr::V6(destination)) => {
+                    udp_checksum(udp::ipv6_checksum(&packet, &source, &destination))
+                }
+                _ => return Ok(None),
+            };
+            Ok(Some((stored, computed)))
+        }
+        (IpNextHeaderProtocols::Tcp, source, destination) => {
+            let packet = TcpPacket::new(segment).ok_or_else(|| truncated("TCP"))?;
+            let header_len = packet.get_data_offset() as usize * 4;
+            if header_len < TCP_MIN_HEADER_LEN || header_len > segment.len() {
+                return Err(format!("invalid TCP header length {}", header_len));
+            }
+            let computed = match (source, destination) {
+                (IpAddr::V4(source), IpAddr::V4(destination)) => {
+                    tcp::ipv4_checksum(&packet, &source, &destination)
+                }
+                (IpAddr::V6(source), IpAddr::V6(destination)) => {
+                    tcp::ipv6_checksum(&packet, &source, &destination)
+                }
+                _ => return Ok(None),
+            };
+            Ok(Some((packet.get_checksum(), computed)))
+        }
+        (IpNextHeaderProtocols::Icmp, IpAddr::V4(_), _) => {
+            let packet = IcmpPacket::new(segment).ok_or_else(|| truncated("ICMP"))?;
+            Ok(Some((packet.get_checksum(), icmp::checksum(&packet))))
+        }
+        (IpNextHeaderProtocols::Icmpv6, IpAddr::V6(source), IpAddr::V6(destination)) => {
+            let packet = Icmpv6Packet::new(segment).ok_or_else(|| truncated("ICMPv6"))?;
+            Ok(Some((
+                packet.get_checksum(),
+                icmpv6::checksum(&packet, &source, &destination),
+            )))
+        }
+        _ => Ok(None),
+    }
+}
+
+// UDP中计算结果为0的校验和以全1发送，全0表示未计算
+fn udp_checksum(computed: u16) -> u16 {
+    if computed == 0 { 0xFFFF } else { computed }
+}
+
+fn protocol_name(protocol: IpNextHeaderProtocol) -> &'static str {
+    match protocol {
+        IpNextHeaderProtocols::Udp => "UDP",
+        IpNextHeaderProtocols::Tcp => "TCP",
+        IpNextHeaderProtocols::Icmp => "ICMP",
+        IpNextHeaderProtocols::Icmpv6 => "ICMPv6",
+        _ => "transport",
+    }
+}
+
+fn fix_ipv4(ip: &mut [u8]) {
+    let Ok((header_len, total_len)) = ipv4_lengths(ip) else {
+        return;
+    };
+    // 总长度超出帧中的数据或小于头部时按实际数据计算；较短的总长度可能是以太网填充，保留
+    let total_len = if total_len < header_len || total_len > ip.len() {
+        ip.len().min(u16::MAX as usize)
+    } else {
+        total_len
+    };
+    let ip = &mut ip[..total_len];
+    let (protocol, source, destination) = {
+        let Some(mut packet) = MutableIpv4Packet::new(ip) else {
+            return;
+        };
+        packet.set_total_length(total_len as u16);
+        packet.set_checksum(0);
+        let header_checksum = ipv4::checksum(&packet.to_immutable());
+        packet.set_checksum(header_checksum);
+        if packet.get_fragment_offset() != 0 || packet.get_flags() & 0x1 != 0 {
+            return;
+        }
+        (
+            packet.get_next_level_protocol(),
+            IpAddr::V4(packet.get_source()),
+            IpAddr::V4(packet.get_destination()),
+        )
+    };
+    fix_transport(protocol, &mut ip[header_len..], source, destination);
+}
+
+fn fix_ipv6(ip: &mut [u8]) {
+    if ip.len() < IPV6_HEADER_LEN {
+        return;
+    }
+    let payload_len = (ip.len() - IPV6_HEADER_LEN).min(u16::MAX as usize);
+    let (protocol, source, destination) = {
+        let Some(mut packet) = MutableIpv6Packet::new(ip) else {
+            return;
+        };
+        let declared = packet.get_payload_length() as usize;
+        // 声明的长度较短时可能是以太网填充，保留
+        if declared > payload_len {
+            packet.set_payload_length(payload_len as u16);
+        }
+        (
+            packet.get_next_header(),
+            IpAddr::V6(packet.get_source()),
+            IpAddr::V6(packet.get_destination()),
+        )
+    };
+    let total_len = IPV6_HEADER_LEN + u16::from_be_bytes([ip[4], ip[5]]) as usize;
+    fix_transport(
+        protocol,
+        &mut ip[IPV6_HEADER_LEN..total_len],
+        source,
+        destination,
+    );
+}
+
+fn fix_transport(
+    protocol: IpNextHeaderProtocol,
+    segment: &mut [u8],
+    source: IpAddr,
+    destination: IpAddr,
+) {
+    let segment_len = segment.len();
+    match (protocol, source, destination) {
+        (IpNextHeaderProtocols::Udp, source, destination) if segment_len >= UDP_HEADER_LEN => {
+            let Some(mut packet) = MutableUdpPacket::new(segment) else {
+                return;
+            };
+            packet.set_length(segment_len as u16);
+            packet.set_checksum(0);
+            let computed = match (source, destination) {
+                (IpAddr::V4(source), IpAddr::V4(destination)) => {
+                    udp::ipv4_checksum(&packet.to_immutable(), &source, &destination)
+                }
+                (IpAddr::V6(source), IpAddr::V6(destination)) => {
+                    udp::ipv6_checksum(&packet.to_immutable(), &source, &destination)
+                }
+                _ => return,
+            };
+            packet.set_checksum(udp_checksum(computed));
+        }
+        (IpNextHeaderProtocols::Tcp, source, destination) if segment_len >= TCP_MIN_HEADER_LEN => {
+            let Some(mut packet) = MutableTcpPacket::new(segment) else {
+                return;
+            };
+            let computed = match (source, destination) {
+                (IpAddr::V4(source), IpAddr::V4(destination)) => {
+                    tcp::ipv4_checksum(&packet.to_immutable(), &source, &destination)
+                }
+                (IpAddr::V6(source), IpAddr::V6(destination)) => {
+                    tcp::ipv6_checksum(&packet.to_immutable(), &source, &destination)
+                }
+                _ => return,
+            };
+            packet.set_checksum(computed);
+        }
+        (IpNextHeaderProtocols::Icmp, IpAddr::V4(_), _) if segment_len >= ICMP_HEADER_LEN => {
+            let Some(mut packet) = MutableIcmpPacket::new(segment) else {
+                return;
+            };
+            let computed = icmp::checksum(&packet.to_immutable());
+            packet.set_checksum(computed);
+        }
+        (IpNextHeaderProtocols::Icmpv6, IpAddr::V6(source), IpAddr::V6(destination))
+            if segment_len >= ICMP_HEADER_LEN =>
+        {
+            let Some(mut packet) = MutableIcmpv6Packet::new(segment) else {
+                return;
+            };
+            let computed = icmpv6::checksum(&packet.to_immutable(), &source, &destination);
+            packet.set_checksum(computed);
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,415 @@
+use crate::repair::Endian;
+use crate::{FileFormat, SavePcapError};
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::Duration;
+
+const PCAP_HEADER_LEN: u64 = 24;
+const PCAP_RECORD_HEADER_LEN: u64 = 16;
+const PCAPNG_SHB_TYPE: u32 = 0x0A0D0D0A;
+const PCAPNG_IDB_TYPE: u32 = 0x1;
+const PCAPNG_PB_TYPE: u32 = 0x2;
+const PCAPNG_SPB_TYPE: u32 = 0x3;
+const PCAPNG_EPB_TYPE: u32 = 0x6;
+const PCAPNG_IF_TSRESOL: u16 = 9;
+// 只记录前若干个问题，严重损坏的文件不会得到一个巨大的报告
+const MAX_ISSUES: usize = 100;
+
+/// 检查结果。`issues`为空表示文件完好
+#[derive(Debug, Clone)]
+pub struct VerificationReport {
+    pub format: FileFormat,
+    pub file_len: u64,
+    /// 最后一个完整的数据包或块结束的位置，完好的文件等于`file_len`
+    pub valid_len: u64,
+    /// 完整的数据包数量
+    pub packets: u64,
+    /// 第一个和最后一个数据包的时间戳（自UNIX纪元起），没有带时间戳的数据包时为None
+    pub first_packet: Option<Duration>,
+    pub last_packet: Option<Duration>,
+    /// 发现的问题，最多记录前100个
+    pub issues: Vec<VerificationIssue>,
+    /// 发现的问题总数，可能大于`issues.len()`
+    pub issue_count: u64,
+}
+
+impl VerificationReport {
+    pub fn is_ok(&self) -> bool {
+        self.issue_count == 0
+    }
+
+    fn add(&mut self, issue: VerificationIssue) {
+        self.issue_count += 1;
+        if self.issues.len() < MAX_ISSUES {
+            self.issues.push(issue);
+        }
+    }
+}
+
+/// 文件中发现的问题。packet为从1开始的数据包序号，offset为记录或块在文件中的位置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerificationIssue {
+    /// 文件头（pcap文件头或pcapng节头块、接口描述块）不完整或字段无效
+    InvalidHeader(String),
+    /// 记录的长度字段互相矛盾：捕获长度大于原始长度或快照长度，或块长度容不下数据包
+    LengthMismatch {
+        packet: u64,
+        offset: u64,
+        detail: String,
+    },
+    /// 时间戳早于前一个数据包
+    TimestampOutOfOrder { packet: u64, offset: u64 },
+    /// 文件末尾的记录或块不完整，通常是写入被中断，可以用`repair()`截断
+    Truncated { offset: u64 },
+    /// 块的结构损坏（例如首尾长度字段不一致），之后的内容无法解析
+    Corrupt { offset: u64, detail: String },
+}
+
+/// 只读地检查pcap或pcapng文件：文件头是否有效、每个数据包的长度字段是否一致、
+/// 时间戳是否单调不减、文件末尾是否被截断。不修改文件，适合在CI或归档前校验。
+///
+/// 文件不是pcap或pcapng格式时返回错误，其他问题记录在报告中
+pub fn verify<P: AsRef<Path>>(path: P) -> Result<VerificationReport, SavePcapError> {
+    let path = path.as_ref();
+    let file = File::open(path)?;
+    let file_len = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+
+    let mut magic = [0u8; 4];
+    if file_len < magic.len() as u64 {
+        return Err(SavePcapError::PcapFileError(format!(
+            "{} is too short to be a capture file",
+            path.display()
+        )));
+    }
+    reader.read_exact(&mut magic)?;
+    // pcap的魔数同时决定字节序和时间戳精度
+    let pcap = match magic {
+        [0xA1, 0xB2, 0xC3, 0xD4] => Some((Endian::Big, 1_000)),
+        [0xA1, 0xB2, 0x3C, 0x4D] => Some((Endian::Big, 1)),
+        [0xD4, 0xC3, 0xB2, 0xA1] => Some((Endian::Little, 1_000)),
+        [0x4D, 0x3C, 0xB2, 0xA1] => Some((Endian::Little, 1)),
+        [0x0A, 0x0D, 0x0D, 0x0A] => None,
+        _ => {
+            return Err(SavePcapError::PcapFileError(format!(
+                "{} is not a pcap or pcapng file",
+                path.display()
+            )));
+        }
+    };
+
+    let mut verifier = Verifier {
+        report: VerificationReport {
+            format: match pcap {
+                Some(_) => FileFormat::Pcap,
+                None => FileFormat::PcapNg,
+            },
+            file_len,
+            valid_len: 0,
+            packets: 0,
+            first_packet: None,
+            last_packet: None,
+            issues: Vec::new(),
+            issue_count: 0,
+        },
+    };
+    match pcap {
+        Some((endian, nanos_per_unit)) => {
+            verifier.verify_pcap(&mut reader, endian, nanos_per_unit)?
+        }
+        None => verifier.verify_pcapng(&mut reader)?,
+    }
+    Ok(verifier.report)
+}
+
+struct Verifier {
+    report: VerificationReport,
+}
+
+impl Verifier {
+    fn verify_pcap(
+        &mut self,
+        reader: &mut BufReader<File>,
+        endian: Endian,
+        nanos_per_unit: u64,
+    ) -> Result<(), SavePcapError> {
+        let file_len = self.report.file_len;
+        if file_len < PCAP_HEADER_LEN {
+            self.report.add(VerificationIssue::InvalidHeader(format!(
+                "file header is {} bytes, expected {}",
+                file_len, PCAP_HEADER_LEN
+            )));
+            self.report.add(VerificationIssue::Truncated { offset: 0 });
+            return Ok(());
+        }
+
+        let mut header = [0u8; PCAP_HEADER_LEN as usize - 4];
+        reader.read_exact(&mut header)?;
+        let major = endian.u16([header[0], header[1]]);
+        let minor = endian.u16([header[2], header[3]]);
+        if (major, minor) != (2, 4) {
+            self.report.add(VerificationIssue::InvalidHeader(format!(
+                "unsupported version {}.{}",
+                major, minor
+            )));
+        }
+        let snaplen = endian.u32(header[12..16].try_into().unwrap());
+        if snaplen == 0 {
+            self.report.add(VerificationIssue::InvalidHeader(
+                "snapshot length is 0".to_string(),
+            ));
+        }
+
+        let mut offset = PCAP_HEADER_LEN;
+        let mut record_header = [0u8; PCAP_RECORD_HEADER_LEN as usize];
+        while offset < file_len {
+            if offset + PCAP_RECORD_HEADER_LEN > file_len {
+                self.report.add(VerificationIssue::Truncated { offset });
+                break;
+            }
+            reader.read_exact(&mut record_header)?;
+            let field =
+                |index: usize| endian.u32(record_header[index..index + 4].try_into().unwrap());
+            let (seconds, fraction, incl_len, orig_len) = (field(0), field(4), field(8), field(12));
+            let record_end = offset + PCAP_RECORD_HEADER_LEN + incl_len as u64;
+            if record_end > file_len {
+                self.report.add(VerificationIssue::Truncated { offset });
+                break;
+            }
+            reader.seek_relative(incl_len as i64)?;
+
+            let packet = self.report.packets + 1;
+            if incl_len > orig_len {
+                self.length_mismatch(packet, offset, "captured length", incl_len, orig_len);
+            }
+            if snaplen > 0 && incl_len > snaplen {
+                self.length_mismatch(packet, offset, "snapshot length", incl_len, snaplen);
+            }
+            let timestamp = Duration::from_secs(seconds as u64)
+                + Duration::from_nanos(fraction as u64 * nanos_per_unit);
+            self.record_packet(offset, Some(timestamp));
+            offset = record_end;
+        }
+        self.report.valid_len = offset.min(file_len);
+        Ok(())
+    }
+
+    fn verify_pcapng(&mut self, reader: &mut BufReader<File>) -> Result<(), SavePcapError> {
+        let file_len = self.report.file_len;
+        reader.seek(SeekFrom::Start(0))?;
+        let mut endian = Endian::Little;
+        // 当前节中每个接口的快照长度和时间戳单位（纳秒数的分子和分母）
+        let mut interfaces: Vec<(u32, (u128, u128))> = Vec::new();
+        let mut offset = 0;
+
+        while offset < file_len {
+            if offset + 12 > file_len {
+                self.report.add(VerificationIssue::Truncated { offset });
+                break;
+            }
+            let mut head = [0u8; 8];
+            reader.read_exact(&mut head)?;
+            let raw_type: [u8; 4] = head[..4].try_into().unwrap();
+            let raw_len: [u8; 4] = head[4..].try_into().unwrap();
+
+            if u32::from_le_bytes(raw_type) == PCAPNG_SHB_TYPE {
+                // 字节序标识紧跟在块长度之后，决定本节内所有字段的字节序
+                let mut byte_order = [0u8; 4];
+                reader.read_exact(&mut byte_order)?;
+                reader.seek_relative(-4)?;
+                endian = match byte_order {
+                    [0x1A, 0x2B, 0x3C, 0x4D] => Endian::Big,
+                    [0x4D, 0x3C, 0x2B, 0x1A] => Endian::Little,
+                    _ => {
+                        self.report.add(VerificationIssue::InvalidHeader(format!(
+                            "invalid byte-order magic in section header at offset {}",
+                            offset
+                        )));
+                        break;
+                    }
+                };
+                interfaces.clear();
+            } else if offset == 0 {
+                self.report.add(VerificationIssue::InvalidHeader(
+                    "file does not start with a section header block".to_string(),
+                ));
+                break;
+            }
+
+            let block_type = endian.u32(raw_type);
+            let block_len = endian.u32(raw_len) as u64;
+            if block_len < 12 || !block_len.is_multiple_of(4) {
+                self.report.add(VerificationIssue::Corrupt {
+                    offset,
+                    detail: format!("invalid block length {}", block_len),
+                });
+                break;
+            }
+            if offset + block_len > file_len {
+                self.report.add(VerificationIssue::Truncated { offset });
+                break;
+            }
+
+            let mut body = vec![0u8; block_len as usize - 8];
+            reader.read_exact(&mut body)?;
+            let (body, trailing) = body.split_at(body.len() - 4);
+            if endian.u32(trailing.try_into().unwrap()) as u64 != block_len {
+                self.report.add(VerificationIssue::Corrupt {
+                    offset,
+                    detail: "leading and trailing block lengths differ".to_string(),
+                });
+                break;
+            }
+
+            match block_type {
+                PCAPNG_SHB_TYPE => {
+                    let major = (body.len() >= 8).then(|| endian.u16([body[4], body[5]]));
+                    if major != Some(1) {
+                        self.report.add(VerificationIssue::InvalidHeader(format!(
+                            "unsupported section header at offset {}",
+                            offset
+                        )));
+                    }
+                }
+                PCAPNG_IDB_TYPE => match parse_interface(endian, body) {
+                    Some(interface) => interfaces.push(interface),
+                    None => {
+                        self.report.add(VerificationIssue::InvalidHeader(format!(
+                            "invalid interface description block at offset {}",
+                            offset
+                        )));
+                        // 占位，保持后续接口的编号不变
+                        interfaces.push((0, (1_000, 1)));
+                    }
+                },
+                PCAPNG_EPB_TYPE | PCAPNG_PB_TYPE => {
+                    self.verify_packet_block(endian, &interfaces, block_type, body, offset)
+                }
+                PCAPNG_SPB_TYPE => {
+                    if body.len() < 4 {
+                        let packet = self.report.packets + 1;
+                        self.report.add(VerificationIssue::LengthMismatch {
+                            packet,
+                            offset,
+                            detail: "simple packet block is too short".to_string(),
+                        });
+                    }
+                    self.record_packet(offset, None);
+                }
+                _ => {}
+            }
+            offset += block_len;
+        }
+        self.report.valid_len = offset.min(file_len);
+        Ok(())
+    }
+
+    fn verify_packet_block(
+        &mut self,
+        endian: Endian,
+        interfaces: &[(u32, (u128, u128))],
+        block_type: u32,
+        body: &[u8],
+        offset: u64,
+    ) {
+        let packet = self.report.packets + 1;
+        if body.len() < 20 {
+            self.report.add(VerificationIssue::LengthMismatch {
+                packet,
+                offset,
+                detail: "packet block is too short".to_string(),
+            });
+            self.record_packet(offset, None);
+            return;
+        }
+        let field = |index: usize| endian.u32(body[index..index + 4].try_into().unwrap());
+        // 已废弃的Packet Block接口编号只有2个字节，其后为丢包计数
+        let interface_id = match block_type {
+            PCAPNG_PB_TYPE => endian.u16([body[0], body[1]]) as u32,
+            _ => field(0),
+        };
+        let (high, low, captured_len, orig_len) = (field(4), field(8), field(12), field(16));
+
+        if 20 + (captured_len as u64).next_multiple_of(4) > body.len() as u64 {
+            self.length_mismatch(
+                packet,
+                offset,
+                "captured length",
+                captured_len,
+                body.len() as u32 - 20,
+            );
+        }
+        if captured_len > orig_len {
+            self.length_mismatch(packet, offset, "captured length", captured_len, orig_len);
+        }
+        let Some(&(snaplen, (numerator, denominator))) = interfaces.get(interface_id as usize)
+        else {
+            self.report.add(VerificationIssue::LengthMismatch {
+                packet,
+                offset,
+                detail: format!("unknown interface {}", interface_id),
+            });
+            self.record_packet(offset, None);
+            return;
+        };
+        if snaplen > 0 && captured_len > snaplen {
+            self.length_mismatch(packet, offset, "snapshot length", captured_len, snaplen);
+        }
+        let units = ((high as u128) << 32) | low as u128;
+        let nanos = units * numerator / denominator;
+        let timestamp = Duration::from_nanos(nanos.min(u64::MAX as u128) as u64);
+        self.record_packet(offset, Some(timestamp));
+    }
+
+    fn length_mismatch(&mut self, packet: u64, offset: u64, what: &str, len: u32, limit: u32) {
+        self.report.add(VerificationIssue::LengthMismatch {
+            packet,
+            offset,
+            detail: format!("{} {} exceeds {}", what, len, limit),
+        });
+    }
+
+    fn record_packet(&mut self, offset: u64, timestamp: Option<Duration>) {
+        self.report.packets += 1;
+        let Some(timestamp) = timestamp else {
+            return;
+        };
+        if let Some(last) = self.report.last_packet
+            && timestamp < last
+        {
+            self.report.add(VerificationIssue::TimestampOutOfOrder {
+                packet: self.report.packets,
+                offset,
+            });
+        }
+        self.report.first_packet.get_or_insert(timestamp);
+        self.report.last_packet = Some(timestamp);
+    }
+}
+
+// 接口描述块：快照长度和if_tsresol选项给出的时间戳单位，默认微秒
+fn parse_interface(endian: Endian, body: &[u8]) -> Option<(u32, (u128, u128))> {
+    let snaplen = endian.u32(body.get(4..8)?.try_into().ok()?);
+    let mut resolution = (1_000, 1);
+    let mut options = body.get(8..)?;
+    while options.len() >= 4 {
+        let code = endian.u16([options[0], options[1]]);
+        let len = endian.u16([options[2], options[3]]) as usize;
+        let value = options.get(4..4 + len)?;
+        if code == PCAPNG_IF_TSRESOL && len == 1 {
+            // 最高位为0表示10的负幂，为1表示2的负幂
+            let exponent = (value[0] & 0x7F) as u32;
+            resolution = match value[0] & 0x80 {
+                0 if exponent <= 9 => (10u128.pow(9 - exponent), 1),
+                0 => (1, 10u128.checked_pow(exponent - 9)?),
+                _ => (1_000_000_000, 2u128.checked_pow(exponent)?),
+            };
+        }
+        if code == 0 {
+            break;
+        }
+        options = options.get(4 + len.next_multiple_of(4)..)?;
+    }
+    Some((snaplen, resolution))
+}
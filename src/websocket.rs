@@ -0,0 +1,417 @@
+use crate::metadata::{self, json_string};
+use crate::parse::{ip_addresses, ip_protocol, transport};
+use crate::source::SourcePacket;
+use crate::{PcapCaptureOptions, SavePcapError};
+use log::{debug, info, warn};
+use pcap_file::DataLink;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, SyncSender, TrySendError, sync_channel};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_HANDSHAKE_LEN: usize = 8192;
+// RFC 6455中计算Sec-WebSocket-Accept时追加的固定GUID
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+
+/// 通过WebSocket向浏览器等客户端实时推送写入的数据包（需要启用`websocket` feature）。
+/// 客户端按连接路径选择格式：`/pcapng`以二进制消息推送pcapng块（第一条消息为节头块和接口描述块，
+/// 之后每个数据包一个增强数据包块），`/json`以文本消息推送每个数据包的时间戳、长度、地址和端口
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiveStreamOptions {
+    /// 监听地址，例如"0.0.0.0:9001"
+    pub listen: String,
+    /// 每个客户端等待发送的消息上限，客户端跟不上时丢弃新的消息，不影响捕获和其他客户端
+    pub queue_len: usize,
+}
+
+impl Default for LiveStreamOptions {
+    fn default() -> Self {
+        Self {
+            listen: "127.0.0.1:9001".to_string(),
+            queue_len: 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Pcapng,
+    Json,
+}
+
+struct Client {
+    format: Format,
+    sender: SyncSender<Arc<Vec<u8>>>,
+}
+
+// 已连接的客户端，捕获线程向其推送，接受连接的线程向其添加
+struct Clients {
+    clients: Mutex<Vec<Client>>,
+    datalink: DataLink,
+    snaplen: u32,
+    queue_len: usize,
+}
+
+// 一次捕获的实时推送，随捕获结束关闭所有连接
+pub(crate) struct LiveStream {
+    clients: Arc<Clients>,
+    stop: Arc<AtomicBool>,
+    acceptor: Option<JoinHandle<()>>,
+}
+
+impl LiveStream {
+    pub fn start(
+        options: &PcapCaptureOptions,
+        live: &LiveStreamOptions,
+        datalink: DataLink,
+    ) -> Result<Self, SavePcapError> {
+        let listener = TcpListener::bind(&live.listen)?;
+        listener.set_nonblocking(true)?;
+        info!(
+            "Streaming packets over WebSocket on ws://{}",
+            listener.local_addr()?
+        );
+        let clients = Arc::new(Clients {
+            clients: Mutex::new(Vec::new()),
+            datalink,
+            snaplen: options.snaplen.max(0) as u32,
+            queue_len: live.queue_len.max(1),
+        });
+        let stop = Arc::new(AtomicBool::new(false));
+        let acceptor = {
+            let (clients, stop) = (clients.clone(), stop.clone());
+            thread::Builder::new()
+                .name("save-pcap-websocket".to_string())
+                .spawn(move || accept(&listener, &clients, &stop))?
+        };
+        Ok(Self {
+            clients,
+            stop,
+            acceptor: Some(acceptor),
+        })
+    }
+
+    // 每种格式的消息只在有对应客户端时编码一次
+    pub fn publish(&self, packet: &SourcePacket) {
+        let Ok(mut clients) = self.clients.clients.lock() else {
+            return;
+        };
+        if clients.is_empty() {
+            return;
+        }
+        let mut pcapng = None;
+        let mut json = None;
+        clients.retain(|client| {
+            let message = match client.format {
+                Format::Pcapng => pcapng.get_or_insert_with(|| Arc::new(enhanced_packet(packet))),
+                Format::Json => json.get_or_insert_with(|| {
+                    Arc::new(packet_json(self.clients.datalink, packet).into_bytes())
+                }),
+            };
+            !matches!(
+                client.sender.try_send(message.clone()),
+                Err(TrySendError::Disconnected(_))
+            )
+        });
+    }
+}
+
+impl Drop for LiveStream {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(acceptor) = self.acceptor.take() {
+            let _ = acceptor.join();
+        }
+        // 丢弃发送端，客户端线程发送关闭帧后结束
+        if let Ok(mut clients) = self.clients.clients.lock() {
+            clients.clear();
+        }
+    }
+}
+
+fn accept(listener: &TcpListener, clients: &Arc<Clients>, stop: &AtomicBool) {
+    while !stop.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, peer)) => {
+                let clients = clients.clone();
+                let spawned = thread::Builder::new()
+                    .name("save-pcap-websocket-client".to_string())
+                    .spawn(move || {
+                        if let Err(e) = serve(stream, &clients) {
+                            debug!("WebSocket client {} disconnected: {}", peer, e);
+                        }
+                    });
+                if let Err(e) = spawned {
+                    warn!("Failed to serve WebSocket client {}: {}", peer, e);
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(ACCEPT_POLL_INTERVAL);
+            }
+            Err(e) => {
+                warn!("WebSocket accept failed: {}", e);
+                thread::sleep(ACCEPT_POLL_INTERVAL);
+            }
+        }
+    }
+}
+
+fn serve(mut stream: TcpStream, clients: &Clients) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let (path, key) = read_handshake(&mut BufReader::new(stream.try_clone()?))?;
+    let format = match path.as_str() {
+        "/pcapng" => Format::Pcapng,
+        "/json" => Format::Json,
+        _ => {
+            stream.write_all(
+                b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            )?;
+            return Ok(());
+        }
+    };
+    let Some(key) = key else {
+        stream.write_all(
+            b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        )?;
+        return Ok(());
+    };
+
+    // 先登记客户端再完成握手，握手完成后写入的数据包都会推送给该客户端
+    let (sender, receiver) = sync_channel(clients.queue_len);
+    if let Ok(mut list) = clients.clients.lock() {
+        list.push(Client { format, sender });
+    }
+    write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(&key)
+    )?;
+    if format == Format::Pcapng {
+        let mut header = section_header();
+        header.extend(interface_description(clients.datalink, clients.snaplen));
+        write_frame(&mut stream, OPCODE_BINARY, &header)?;
+    }
+    forward(&mut stream, format, &receiver)
+}
+
+fn forward(
+    stream: &mut TcpStream,
+    format: Format,
+    receiver: &Receiver<Arc<Vec<u8>>>,
+) -> io::Result<()> {
+    let opcode = match format {
+        Format::Pcapng => OPCODE_BINARY,
+        Format::Json => OPCODE_TEXT,
+    };
+    while let Ok(message) = receiver.recv() {
+        write_frame(stream, opcode, &message)?;
+    }
+    // 捕获已结束，正常关闭连接（状态码1000）
+    write_frame(stream, OPCODE_CLOSE, &1000u16.to_be_bytes())
+}
+
+// 返回请求的路径和Sec-WebSocket-Key
+fn read_handshake(reader: &mut impl BufRead) -> io::Result<(String, Option<String>)> {
+    let mut path = None;
+    let mut key = None;
+    let mut total = 0;
+    loop {
+        let mut line = String::new();
+        let read = reader.read_line(&mut line)?;
+        total += read;
+        if total > MAX_HANDSHAKE_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "handshake too long",
+            ));
+        }
+        let line = line.trim_end();
+        if read == 0 || line.is_empty() {
+            break;
+        }
+        if path.is_none() {
+            path = line.split_whitespace().nth(1).map(str::to_string);
+        } else if let Some((name, value)) = line.split_once(':')
+            && name.trim().eq_ignore_ascii_case("sec-websocket-key")
+        {
+            key = Some(value.trim().to_string());
+        }
+    }
+    Ok((path.unwrap_or_default(), key))
+}
+
+fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{}{}", key, WEBSOCKET_GUID).as_bytes()))
+}
+
+// 服务器发出的帧不加掩码
+fn write_frame(stream: &mut impl Write, opcode: u8, payload: &[u8]) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(0x80 | opcode);
+    match payload.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xFFFF => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    stream.write_all(&frame)
+}
+
+fn packet_json(datalink: DataLink, packet: &SourcePacket) -> String {
+    let mut json = format!(
+        "{{\"timestamp\": {}, \"length\": {}, \"orig_len\": {}",
+        json_string(&metadata::rfc3339(packet.timestamp)),
+        packet.data.len(),
+        packet.orig_len
+    );
+    if let Some(protocol) = ip_protocol(datalink, &packet.data) {
+        let _ = write!(json, ", \"protocol\": {}", protocol);
+    }
+    if let Some((source, destination)) = ip_addresses(datalink, &packet.data) {
+        let _ = write!(
+            json,
+            ", \"source\": {}, \"destination\": {}",
+            json_string(&source.to_string()),
+            json_string(&destination.to_string())
+        );
+    }
+    if let Some(transport) = transport(datalink, &packet.data) {
+        let _ = write!(
+            json,
+            ", \"source_port\": {}, \"destination_port\": {}",
+            transport.source_port, transport.destination_port
+        );
+    }
+    json.push('}');
+    json
+}
+
+// pcapng块：类型、总长度、内容（按4字节对齐）、总长度
+fn block(block_type: u32, body: &[u8]) -> Vec<u8> {
+    let padded = body.len().div_ceil(4) * 4;
+    let total = (12 + padded) as u32;
+    let mut block = Vec::with_capacity(total as usize);
+    block.extend_from_slice(&block_type.to_le_bytes());
+    block.extend_from_slice(&total.to_le_bytes());
+    block.extend_from_slice(body);
+    block.resize(8 + padded, 0);
+    block.extend_from_slice(&total.to_le_bytes());
+    block
+}
+
+fn section_header() -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&0x1A2B_3C4Du32.to_le_bytes());
+    body.extend_from_slice(&1u16.to_le_bytes());
+    body.extend_from_slice(&0u16.to_le_bytes());
+    // 节长度未知
+    body.extend_from_slice(&(-1i64).to_le_bytes());
+    block(0x0A0D_0D0A, &body)
+}
+
+fn interface_description(datalink: DataLink, snaplen: u32) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&(u32::from(datalink) as u16).to_le_bytes());
+    body.extend_from_slice(&0u16.to_le_bytes());
+    body.extend_from_slice(&snaplen.to_le_bytes());
+    block(1, &body)
+}
+
+// 时间戳使用默认的微秒精度
+fn enhanced_packet(packet: &SourcePacket) -> Vec<u8> {
+    let micros = packet.timestamp.as_micros() as u64;
+    let mut body = Vec::with_capacity(20 + packet.data.len());
+    body.extend_from_slice(&0u32.to_le_bytes());
+    body.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+    body.extend_from_slice(&(micros as u32).to_le_bytes());
+    body.extend_from_slice(&(packet.data.len() as u32).to_le_bytes());
+    body.extend_from_slice(&packet.orig_len.to_le_bytes());
+    body.extend_from_slice(&packet.data);
+    block(6, &body)
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for chunk in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in chunk.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (state, value) in h.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0u8; 20];
+    for (bytes, word) in digest.chunks_mut(4).zip(h) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 0x3F] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
@@ -0,0 +1,1676 @@
+use crate::alert::AlertMonitor;
+use crate::annotation;
+use crate::bandwidth::BandwidthLog;
+#[cfg(all(target_os = "linux", feature = "direct-io"))]
+use crate::direct::DirectWriter;
+use crate::dns::NameResolution;
+use crate::fallback::{BufferedPacket, MemoryRing};
+use crate::fcs;
+use crate::histogram::PacketHistograms;
+use crate::layout;
+use crate::metadata::{self, FileMetadata};
+use crate::observer::ClosedFile;
+use crate::paths;
+use crate::repair;
+use crate::session::SessionStatus;
+use crate::share;
+use crate::sidecar::Sidecars;
+use crate::source::SourcePacket;
+use crate::stats::{self, PcapStats, StatsCounters};
+#[cfg(feature = "validate")]
+use crate::validate::ValidationAction;
+use crate::{
+    ConflictPolicy, DiskFullPolicy, FileFormat, InvalidPacketAction, LIVE_STATS_INTERVAL,
+    LatePacketPolicy, PART_SUFFIX, PcapCaptureOptions, SavePcapError, WriteErrorPolicy,
+};
+use log::{error, info, warn};
+use pcap_file::pcap::PcapHeader;
+use pcap_file::pcapng::PcapNgWriter;
+use pcap_file::pcapng::blocks::enhanced_packet::{EnhancedPacketBlock, EnhancedPacketOption};
+use pcap_file::pcapng::blocks::interface_description::{
+    InterfaceDescriptionBlock, InterfaceDescriptionOption,
+};
+use pcap_file::pcapng::blocks::interface_statistics::{
+    InterfaceStatisticsBlock, InterfaceStatisticsOption,
+};
+use pcap_file::pcapng::blocks::name_resolution::{
+    Ipv4Record, Ipv6Record, NameResolutionBlock, Record,
+};
+use pcap_file::pcapng::blocks::section_header::{SectionHeaderBlock, SectionHeaderOption};
+use pcap_file::{DataLink, Endianness, PcapError, TsResolution};
+use std::borrow::Cow;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::mem;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// epb_flags中第5~8位为FCS长度
+const EPB_FLAGS_FCS_LEN_SHIFT: u32 = 5;
+// 超过该时间没有数据包时才按系统时间结束`time_buckets`时间段
+const BUCKET_END_IDLE: Duration = Duration::from_secs(1);
+// pcapng心跳记录的注释，便于与真正的统计块区分
+const HEARTBEAT_COMMENT: &str = "save_pcap heartbeat";
+// 内存回退期间检查输出位置是否恢复的间隔
+const FALLBACK_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// 负责写入当前文件，并在持续捕获模式下按时间、数据包数量或文件大小滚动文件
+pub(crate) struct RotatingWriter<'a> {
+    options: &'a PcapCaptureOptions,
+    stats: &'a StatsCounters,
+    status: &'a SessionStatus,
+    datalink: DataLink,
+    metadata: FileMetadata,
+    sidecars: Sidecars,
+    // 当前文件中已写入名称解析块的DNS结果，未启用`dns_name_resolution`时为None
+    name_resolution: Option<NameResolution>,
+    file_writer: FormatWriter,
+    current_file_name: String,
+    current_full_path: PathBuf,
+    // 当前文件的序号，未开启`file_sequence`时为None
+    current_file_sequence: Option<u64>,
+    current_file_packet_count: usize,
+    // 当前文件已写入的字节数（含文件头），即下一条数据包记录在文件中的偏移
+    current_file_offset: u64,
+    file_creation_time: SystemTime,
+    // 当前文件中第一个和最后一个数据包的时间戳
+    packet_time_range: Option<(Duration, Duration)>,
+    // 打开当前文件时内核已丢弃的数据包数和libpcap统计
+    file_dropped_base: u64,
+    file_pcap_base: PcapStats,
+    // 当前文件中被遮盖的内容处数
+    current_file_redactions: u64,
+    // 合法性检查不通过的数据包单独写入的文件，第一次出现异常数据包时创建
+    errors_file: Option<(FormatWriter, PathBuf)>,
+    // 按时间段记录带宽的CSV，整个捕获期间只有一个，不随捕获文件滚动
+    bandwidth_log: Option<BandwidthLog>,
+    // 计算实时速率的当前时间窗口：开始时间、窗口内的数据包数和字节数（按原始长度）
+    rate_window: (Instant, u64, u64),
+    histograms: Option<PacketHistograms>,
+    alerts: Option<AlertMonitor>,
+    // 上次写统计文件的时间
+    stats_file_written: Instant,
+    // 磁盘已满且无法腾出空间，当前文件只能截断到最后一个完整的数据包
+    disk_full: bool,
+    // 当前文件的句柄已关闭，写入前需要重新打开
+    suspended: bool,
+    // 最后一次写入数据包的时间，用于`rollover_idle_seconds`
+    last_packet: Instant,
+    // 最后一次写入心跳记录的时间，与last_packet一起决定下一次心跳
+    last_heartbeat: Instant,
+    // 空闲时已结束的文件，下一个数据包到达时才打开新文件
+    idle_closed: Option<ClosedFile>,
+    // 当前文件对应的`time_buckets`时间段的开始时间
+    current_bucket: Option<Duration>,
+    // 输出位置不可用期间缓存数据包的内存环，见`memory_fallback_bytes`
+    fallback: Option<MemoryRing>,
+}
+
+impl<'a> RotatingWriter<'a> {
+    pub fn new(
+        options: &'a PcapCaptureOptions,
+        stats: &'a StatsCounters,
+        status: &'a SessionStatus,
+        datalink: DataLink,
+    ) -> Result<Self, SavePcapError> {
+        // 第一个数据包到达前按系统时间确定时间段
+        let current_bucket = options.time_buckets.map(|bucket| {
+            bucket.start(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default(),
+            )
+        });
+        let (current_file_name, current_full_path) = create_file(
+            options,
+            current_bucket,
+            options.file_sequence,
+            options.conflict_policy,
+        )?;
+        if options.continuous_capture {
+            info!(
+                "Starting continuous capture, first file: {:?}",
+                current_full_path
+            );
+        } else {
+            info!("Saving to file: {:?}", current_full_path);
+        }
+
+        if let Some(sanity_check) = &options.sanity_check
+            && sanity_check.action == InvalidPacketAction::Tag
+            && matches!(options.file_format, FileFormat::Pcap)
+        {
+            return Err(SavePcapError::UnsupportedSource(
+                "Tagging invalid packets requires the pcapng format".to_string(),
+            ));
+        }
+        #[cfg(feature = "validate")]
+        if let Some(validation) = &options.packet_validation
+            && validation.action == ValidationAction::Tag
+            && matches!(options.file_format, FileFormat::Pcap)
+        {
+            return Err(SavePcapError::UnsupportedSource(
+                "Tagging packets that fail validation requires the pcapng format".to_string(),
+            ));
+        }
+        if options.dns_name_resolution && matches!(options.file_format, FileFormat::Pcap) {
+            return Err(SavePcapError::UnsupportedSource(
+                "Name resolution blocks require the pcapng format".to_string(),
+            ));
+        }
+
+        let bandwidth_log = match options.bandwidth_interval_seconds {
+            Some(interval) => {
+                let path = Path::new(&options.file_path).join(options.bandwidth_file_name());
+                info!("Logging bandwidth to {:?}", path);
+                Some(BandwidthLog::create(&path, interval)?)
+            }
+            None => None,
+        };
+
+        stats.record_current_file(&current_full_path, options.file_sequence);
+        stats.mark_active();
+        let metadata = FileMetadata::collect(options);
+        let sidecars = Sidecars::new(options)?;
+        let mut file_writer = open_file_writer(options, &metadata, &current_full_path, datalink)?;
+        let current_file_offset = file_writer.get_mut().position()?;
+
+        Ok(Self {
+            options,
+            stats,
+            status,
+            datalink,
+            metadata,
+            sidecars,
+            name_resolution: options.dns_name_resolution.then(NameResolution::new),
+            file_writer,
+            current_file_name,
+            current_full_path,
+            current_file_sequence: options.file_sequence,
+            current_file_packet_count: 0,
+            current_file_offset,
+            file_creation_time: SystemTime::now(),
+            packet_time_range: None,
+            file_dropped_base: stats.kernel_dropped(),
+            file_pcap_base: stats.pcap_stats().unwrap_or_default(),
+            current_file_redactions: 0,
+            errors_file: None,
+            bandwidth_log,
+            rate_window: (Instant::now(), 0, 0),
+            histograms: options.histograms.as_ref().map(PacketHistograms::new),
+            alerts: options.alerts.as_ref().map(AlertMonitor::new),
+            stats_file_written: Instant::now(),
+            disk_full: false,
+            suspended: false,
+            last_packet: Instant::now(),
+            last_heartbeat: Instant::now(),
+            idle_closed: None,
+            current_bucket,
+            fallback: None,
+        })
+    }
+
+    /// 没有数据包时也需要调用，使按时间滚动在空闲期间同样生效
+    pub fn tick(&mut self) -> Result<(), SavePcapError> {
+        if self.fallback.is_some() {
+            self.check_restore(false)?;
+        } else if let Err(e) = self.tick_files() {
+            self.fall_back(e, None)?;
+        }
+        self.tick_stats();
+        Ok(())
+    }
+
+    fn tick_files(&mut self) -> Result<(), SavePcapError> {
+        if self.options.continuous_capture && self.idle_closed.is_none() {
+            if self.check_idle() || self.check_bucket_ended() {
+                self.close_idle()?;
+            } else if self.check_needs_rollover() {
+                self.rollover()?;
+            }
+        }
+        if self.check_heartbeat() {
+            self.write_heartbeat()?;
+        }
+        Ok(())
+    }
+
+    fn tick_stats(&mut self) {
+        if self.rate_window.0.elapsed() >= LIVE_STATS_INTERVAL {
+            self.update_live_stats();
+        }
+        if self.stats_file_written.elapsed().as_secs() >= self.options.stats_interval_seconds {
+            self.write_stats_file();
+        }
+    }
+
+    /// 返回数据包是否写入了捕获文件（没有因时间段、写入错误被跳过，也没有因输出位置不可用而缓存）。
+    /// tag和annotations作为数据包注释写入（仅pcapng格式），annotations同时记录到附加文件
+    pub fn write(
+        &mut self,
+        packet: &SourcePacket,
+        tag: Option<String>,
+        annotations: &[(String, String)],
+    ) -> Result<bool, SavePcapError> {
+        self.check_restore(false)?;
+        if self.fallback.is_none() {
+            match self.write_to_file(packet, tag.clone(), annotations) {
+                Err(e) => self.fall_back(e, None)?,
+                result => return result,
+            }
+        }
+        if let Some(ring) = &mut self.fallback {
+            let dropped = ring.push(BufferedPacket::new(packet, tag, annotations));
+            self.stats.record_fallback(ring.len() as u64, dropped);
+        }
+        Ok(false)
+    }
+
+    fn write_to_file(
+        &mut self,
+        packet: &SourcePacket,
+        tag: Option<String>,
+        annotations: &[(String, String)],
+    ) -> Result<bool, SavePcapError> {
+        self.resume()?;
+        let bucket = self
+            .options
+            .time_buckets
+            .map(|bucket| bucket.start(packet.timestamp));
+        if self.idle_closed.is_some() && bucket.is_some() {
+            self.current_bucket = bucket;
+        }
+        self.reopen_after_idle()?;
+        if let Some(bucket) = bucket
+            && !self.enter_bucket(packet, bucket)?
+        {
+            return Ok(false);
+        }
+        self.tick_files()?;
+        self.tick_stats();
+
+        let comment = match (tag, annotation::comment(annotations)) {
+            (Some(tag), Some(annotations)) => Some(format!("{}; {}", annotations, tag)),
+            (tag, annotations) => annotations.or(tag),
+        };
+
+        if self.would_exceed_size_limit(packet, comment.as_deref()) {
+            self.rollover()?;
+        }
+
+        let written = loop {
+            let data = self.options.stored_data(&packet.data);
+            match self
+                .file_writer
+                .write_packet(packet, data, comment.as_deref())
+            {
+                Ok(written) => break written,
+                // 按磁盘已满策略处理后在新文件中重试
+                Err(PcapError::IoError(e)) if is_disk_full(&e) => self.recover_from_disk_full()?,
+                // 输出位置不可用时由调用方转为内存回退
+                Err(PcapError::IoError(e)) if self.output_lost() => return Err(e.into()),
+                Err(e) => {
+                    self.recover_from_write_error(e)?;
+                    return Ok(false);
+                }
+            }
+        };
+
+        self.current_file_packet_count += 1;
+        self.last_packet = Instant::now();
+        self.packet_time_range = match self.packet_time_range {
+            Some((first, _)) => Some((first, packet.timestamp)),
+            None => Some((packet.timestamp, packet.timestamp)),
+        };
+        self.sidecars
+            .record(self.datalink, packet, self.current_file_offset);
+        self.sidecars
+            .record_annotations(packet, self.current_file_offset, annotations);
+        if let Some(bandwidth_log) = &mut self.bandwidth_log
+            && let Err(e) = bandwidth_log.record(packet.timestamp, packet.orig_len as u64)
+        {
+            warn!("Failed to write bandwidth log, disabling it: {}", e);
+            self.bandwidth_log = None;
+        }
+        self.rate_window.1 += 1;
+        self.rate_window.2 += packet.orig_len as u64;
+        if let Some(histograms) = &mut self.histograms {
+            histograms.record(packet.timestamp, packet.orig_len);
+        }
+        if self.options.protocol_stats {
+            self.stats
+                .record_protocols(self.datalink, &packet.data, packet.orig_len);
+        }
+        self.current_file_offset += written as u64;
+        self.write_name_resolution(packet)?;
+        self.stats.record_written(written as u64);
+
+        Ok(true)
+    }
+
+    /// 记录刚写入的数据包中被遮盖的内容处数，计入当前文件和总计
+    pub fn record_redactions(&mut self, count: u64) {
+        self.current_file_redactions += count;
+        self.stats.record_redactions(count);
+    }
+
+    /// 写出当前文件缓冲区中的数据并同步到磁盘，不关闭文件。
+    /// O_DIRECT写入时末尾不足一块的数据要到关闭文件时才写出
+    pub fn flush(&mut self) -> Result<(), SavePcapError> {
+        if self.idle_closed.is_some() || self.fallback.is_some() {
+            return Ok(());
+        }
+        if self.suspended {
+            // 暂停时已写出缓冲区，只需同步到磁盘
+            OpenOptions::new()
+                .write(true)
+                .open(&self.current_full_path)?
+                .sync_data()?;
+            return Ok(());
+        }
+        let file = self.file_writer.get_mut();
+        file.flush()?;
+        file.file()?.sync_data()?;
+        Ok(())
+    }
+
+    /// 结束当前文件并开始新文件。空闲时已结束文件的，下一个数据包到达时本来就会开始新文件
+    pub fn rotate_now(&mut self) -> Result<(), SavePcapError> {
+        if self.idle_closed.is_some() || self.fallback.is_some() {
+            return Ok(());
+        }
+        self.rollover()
+    }
+
+    /// 写出缓冲区并关闭当前文件的句柄，但不结束文件：之后的写入和滚动会重新打开文件并在
+    /// 末尾继续写入。用于限制同时打开的文件数，不支持O_DIRECT写入
+    pub fn suspend(&mut self) -> Result<(), SavePcapError> {
+        if self.suspended || self.idle_closed.is_some() || self.fallback.is_some() {
+            return Ok(());
+        }
+        let file = self.file_writer.get_mut();
+        file.flush()?;
+        *file = OutputFile::Suspended;
+        self.suspended = true;
+        Ok(())
+    }
+
+    // 空闲时结束了文件的写入器同样不占用文件句柄
+    pub fn is_suspended(&self) -> bool {
+        self.suspended || self.idle_closed.is_some()
+    }
+
+    /// 重新打开暂停的文件，从上次写到的位置继续（预分配的空间不计入文件长度，不能直接追加到末尾）
+    pub fn resume(&mut self) -> Result<(), SavePcapError> {
+        if !self.suspended {
+            return Ok(());
+        }
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(&self.current_full_path)?;
+        file.seek(SeekFrom::Start(self.current_file_offset))?;
+        *self.file_writer.get_mut() = OutputFile::Buffered(BufWriter::new(file));
+        self.suspended = false;
+        Ok(())
+    }
+
+    /// 关闭当前文件。磁盘已满时文件截断到最后一个完整的数据包，并返回`DiskFull`错误
+    pub fn finish(mut self) -> Result<(), SavePcapError> {
+        // 结束前最后尝试一次写出内存中缓存的数据包
+        self.check_restore(true)?;
+        self.resume()?;
+        // 捕获已结束，实时速率归零
+        self.stats.record_rates(0.0, 0.0);
+        self.stats
+            .record_current_file_size(self.current_file_offset);
+        if let Some(histograms) = &self.histograms {
+            self.stats.record_histograms(histograms);
+        }
+        self.write_stats_file();
+        if let Some(bandwidth_log) = self.bandwidth_log.take()
+            && let Err(e) = bandwidth_log.finish()
+        {
+            warn!("Failed to write bandwidth log: {}", e);
+        }
+        if let Some((errors_writer, errors_path)) = self.errors_file.take() {
+            if let Err(e) = errors_writer.into_writer().close(self.options) {
+                error!("Failed to close file: {:?}, error: {}", errors_path, e);
+            }
+            info!("Invalid packets saved to: {}", errors_path.display());
+        }
+        if let Some(ring) = &self.fallback {
+            return Err(SavePcapError::OutputUnavailable(format!(
+                "{}, {} buffered packets were not written",
+                output_dir(self.options).display(),
+                ring.len()
+            )));
+        }
+        if let Some(closed) = &self.idle_closed {
+            info!(
+                "Capture completed. Packets saved to: {}",
+                closed.path.display()
+            );
+            return Ok(());
+        }
+
+        if !self.disk_full {
+            match self.end_current_file() {
+                Ok(()) => {}
+                Err(e) if is_disk_full(&e) => {
+                    error!("Disk full while flushing {}", self.current_file_name);
+                    self.disk_full = true;
+                }
+                Err(e) => error!(
+                    "Failed to flush file: {}, error: {}",
+                    self.current_file_name, e
+                ),
+            }
+        }
+
+        if self.disk_full {
+            self.file_writer.into_writer().discard();
+            self.sidecars.discard();
+            finalize_truncated(self.options, &self.current_full_path);
+            return Err(SavePcapError::DiskFull(
+                self.current_full_path.display().to_string(),
+            ));
+        }
+
+        let closed = self.closed_file();
+        if let Err(e) = self.file_writer.into_writer().close(self.options) {
+            error!(
+                "Failed to close file: {}, error: {}",
+                self.current_file_name, e
+            );
+        }
+        let final_path = finalize_file(
+            self.options,
+            &self.metadata,
+            &closed,
+            self.current_file_sequence,
+        );
+        self.sidecars.file_closed(&final_path);
+
+        info!(
+            "Capture completed. Packets saved to: {}",
+            final_path.display()
+        );
+        Ok(())
+    }
+
+    fn rollover(&mut self) -> Result<(), SavePcapError> {
+        // 暂停中的文件按时间滚动时临时打开，换到新文件后仍保持暂停
+        let suspended = self.suspended;
+        self.resume()?;
+        self.rollover_open()?;
+        if suspended {
+            self.suspend()?;
+        }
+        Ok(())
+    }
+
+    fn rollover_open(&mut self) -> Result<(), SavePcapError> {
+        let status = self.status;
+        let _rotating = status.rotating();
+        let started = Instant::now();
+        if let Err(e) = self.end_current_file() {
+            if is_disk_full(&e) {
+                return self.recover_from_disk_full();
+            }
+            error!(
+                "Failed to flush file: {}, error: {}",
+                self.current_file_name, e
+            );
+        }
+
+        info!(
+            "Rolling over to new file after {} packets in {}",
+            self.current_file_packet_count, self.current_file_name
+        );
+
+        let mut closed = self.closed_file();
+        let (old_writer, old_file_name, _, old_sequence) = self.open_next_file()?;
+        if let Err(e) = old_writer.into_writer().close(self.options) {
+            error!("Failed to close file: {}, error: {}", old_file_name, e);
+        }
+        let final_path = finalize_file(self.options, &self.metadata, &closed, old_sequence);
+        self.sidecars.file_closed(&final_path);
+        self.stats.record_rotation(started.elapsed());
+        closed.path = final_path;
+        status.rotated(&closed, &self.current_full_path);
+
+        Ok(())
+    }
+
+    // 超过`rollover_idle_seconds`没有数据包时在最后一个数据包处结束当前文件，
+    // 下一个文件在流量恢复时才创建，文件名中的时间即为这段流量开始的时间
+    fn close_idle(&mut self) -> Result<(), SavePcapError> {
+        self.resume()?;
+        if let Err(e) = self.end_current_file() {
+            if is_disk_full(&e) {
+                return self.recover_from_disk_full();
+            }
+            error!(
+                "Failed to flush file: {}, error: {}",
+                self.current_file_name, e
+            );
+        }
+        info!(
+            "No packets for {} seconds, closing {} until traffic resumes",
+            self.last_packet.elapsed().as_secs(),
+            self.current_file_name
+        );
+
+        let mut closed = self.closed_file();
+        let writer = mem::replace(
+            &mut self.file_writer,
+            FormatWriter::Closed(OutputFile::Suspended),
+        );
+        if let Err(e) = writer.into_writer().close(self.options) {
+            error!(
+                "Failed to close file: {}, error: {}",
+                self.current_file_name, e
+            );
+        }
+        closed.path = finalize_file(
+            self.options,
+            &self.metadata,
+            &closed,
+            self.current_file_sequence,
+        );
+        self.sidecars.file_closed(&closed.path);
+        self.idle_closed = Some(closed);
+        Ok(())
+    }
+
+    // 启用`memory_fallback_bytes`且输出位置不可写时放弃当前文件，之后的数据包缓存在内存中；
+    // 否则返回原来的错误。ring为写出缓存时再次失败、尚未写出的数据包
+    fn fall_back(
+        &mut self,
+        e: SavePcapError,
+        ring: Option<MemoryRing>,
+    ) -> Result<(), SavePcapError> {
+        let Some(max_bytes) = self.options.memory_fallback_bytes else {
+            return Err(e);
+        };
+        let dir = output_dir(self.options);
+        if paths::is_writable(dir) {
+            return Err(e);
+        }
+        error!(
+            "Output {:?} is unavailable, buffering packets in memory: {}",
+            dir, e
+        );
+        let writer = mem::replace(
+            &mut self.file_writer,
+            FormatWriter::Closed(OutputFile::Suspended),
+        );
+        writer.into_writer().discard();
+        self.sidecars.discard();
+        self.suspended = false;
+
+        let first = ring.is_none();
+        let mut ring = ring.unwrap_or_else(|| MemoryRing::new(max_bytes));
+        // 空闲时已结束的文件不需要截断
+        ring.abandoned = match self.idle_closed.take() {
+            Some(_) => None,
+            None => Some(self.current_full_path.clone()),
+        };
+        self.stats.record_fallback(ring.len() as u64, 0);
+        self.fallback = Some(ring);
+        if first {
+            self.status.output_unavailable(&e.to_string());
+        }
+        Ok(())
+    }
+
+    // 回退期间每秒检查一次输出位置，恢复可写后截断放弃的文件，在新文件中写出缓存的数据包。
+    // force时不等待检查间隔
+    fn check_restore(&mut self, force: bool) -> Result<(), SavePcapError> {
+        let Some(ring) = &mut self.fallback else {
+            return Ok(());
+        };
+        if !force && ring.checked.elapsed() < FALLBACK_CHECK_INTERVAL {
+            return Ok(());
+        }
+        ring.checked = Instant::now();
+        if !paths::is_writable(output_dir(self.options)) {
+            return Ok(());
+        }
+
+        let Some(mut ring) = self.fallback.take() else {
+            return Ok(());
+        };
+        if let Err(e) = self.open_next_file() {
+            warn!("Failed to reopen output, still buffering in memory: {}", e);
+            self.fallback = Some(ring);
+            return Ok(());
+        }
+        if let Some(abandoned) = ring.abandoned.take()
+            && abandoned.exists()
+        {
+            finalize_truncated(self.options, &abandoned);
+        }
+        let backlog = ring.len() as u64;
+        info!(
+            "Output is writable again, writing {} buffered packets to {}",
+            backlog, self.current_file_name
+        );
+        while let Some(buffered) = ring.pop() {
+            let written = self.write_to_file(
+                &buffered.packet,
+                buffered.tag.clone(),
+                &buffered.annotations,
+            );
+            if let Err(e) = written {
+                ring.push_front(buffered);
+                return self.fall_back(e, Some(ring));
+            }
+        }
+        self.stats.record_fallback(0, 0);
+        self.status.output_restored(backlog);
+        Ok(())
+    }
+
+    // 输出位置不可用，写入错误交给内存回退处理
+    fn output_lost(&self) -> bool {
+        self.options.memory_fallback_bytes.is_some()
+            && !paths::is_writable(output_dir(self.options))
+    }
+
+    // 空闲后第一个数据包到达，打开下一个文件
+    fn reopen_after_idle(&mut self) -> Result<(), SavePcapError> {
+        let Some(closed) = self.idle_closed.take() else {
+            return Ok(());
+        };
+        let started = Instant::now();
+        if let Err(e) = self.open_next_file() {
+            self.idle_closed = Some(closed);
+            return Err(e);
+        }
+        self.stats.record_rotation(started.elapsed());
+        self.status.rotated(&closed, &self.current_full_path);
+        Ok(())
+    }
+
+    // `strict_size_rollover`：写入该数据包是否会使非空的当前文件超过大小上限
+    fn would_exceed_size_limit(&self, packet: &SourcePacket, comment: Option<&str>) -> bool {
+        if !self.options.continuous_capture
+            || !self.options.strict_size_rollover
+            || self.current_file_packet_count == 0
+        {
+            return false;
+        }
+        let Some(max_size_bytes) = size_limit(self.options) else {
+            return false;
+        };
+        let data_len = self.options.stored_data(&packet.data).len();
+        self.current_file_offset + self.file_writer.record_len(data_len, comment) > max_size_bytes
+    }
+
+    // 数据包属于之后的时间段时滚动到该时间段；属于之前的时间段时按`late_packet_policy`处理。
+    // 返回是否写入该数据包
+    fn enter_bucket(
+        &mut self,
+        packet: &SourcePacket,
+        bucket: Duration,
+    ) -> Result<bool, SavePcapError> {
+        let Some(current) = self.current_bucket else {
+            return Ok(true);
+        };
+        if bucket == current {
+            return Ok(true);
+        }
+        // 当前文件还没有数据包时直接换到数据包的时间段，例如重新保存以前的捕获文件
+        if bucket > current || self.current_file_packet_count == 0 {
+            if self.options.continuous_capture {
+                self.current_bucket = Some(bucket);
+                self.rollover()?;
+            }
+            return Ok(true);
+        }
+        match self.options.late_packet_policy {
+            LatePacketPolicy::Current => Ok(true),
+            LatePacketPolicy::Drop => {
+                self.options
+                    .report_rejected(packet, "late packet from an earlier time bucket");
+                Ok(false)
+            }
+        }
+    }
+
+    // 没有数据包时按系统时间判断当前时间段是否已结束。数据包仍在到达时由数据包的时间戳决定，
+    // 重新保存以前的捕获文件时不受系统时间影响
+    fn check_bucket_ended(&self) -> bool {
+        let (Some(time_buckets), Some(current)) = (self.options.time_buckets, self.current_bucket)
+        else {
+            return false;
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        self.current_file_packet_count > 0
+            && self.last_packet.elapsed() >= BUCKET_END_IDLE
+            && time_buckets.start(now) > current
+    }
+
+    // 空闲关闭或暂时关闭了文件时没有可写入的文件，不写心跳
+    fn check_heartbeat(&self) -> bool {
+        self.options
+            .heartbeat_interval_seconds
+            .is_some_and(|interval| {
+                self.idle_closed.is_none()
+                    && !self.suspended
+                    && self.last_packet.elapsed().as_secs() >= interval
+                    && self.last_heartbeat.elapsed().as_secs() >= interval
+            })
+    }
+
+    // 写入后立即写出缓冲区，读取文件的程序能及时看到心跳；写入失败只记录警告，
+    // 真正的写入错误会在下一个数据包时按`write_error_policy`处理
+    fn write_heartbeat(&mut self) -> Result<(), SavePcapError> {
+        self.last_heartbeat = Instant::now();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let result = self.file_writer.write_heartbeat(now).and_then(|written| {
+            self.current_file_offset += written as u64;
+            self.file_writer
+                .get_mut()
+                .flush()
+                .map_err(PcapError::IoError)
+        });
+        match result {
+            Ok(()) => self.stats.record_heartbeat(),
+            Err(e) => warn!(
+                "Failed to write heartbeat to {}: {}",
+                self.current_file_name, e
+            ),
+        }
+        Ok(())
+    }
+
+    fn check_idle(&self) -> bool {
+        self.options.rollover_idle_seconds.is_some_and(|idle| {
+            self.current_file_packet_count > 0 && self.last_packet.elapsed().as_secs() >= idle
+        })
+    }
+
+    // 写入时磁盘已满：缓冲区中可能只有半个数据包，不能再刷新。按策略腾出空间后
+    // 把当前文件截断到最后一个完整的数据包并换到新文件；无法腾出空间时返回`DiskFull`
+    fn recover_from_disk_full(&mut self) -> Result<(), SavePcapError> {
+        error!("Disk full while writing {}", self.current_file_name);
+
+        let freed = match self.options.disk_full_policy {
+            DiskFullPolicy::Stop => false,
+            DiskFullPolicy::DeleteOldest => self.delete_oldest_file()?,
+        };
+        if !freed {
+            self.disk_full = true;
+            return Err(SavePcapError::DiskFull(
+                self.current_full_path.display().to_string(),
+            ));
+        }
+
+        let (old_writer, _, old_full_path, _) = self.open_next_file()?;
+        old_writer.into_writer().discard();
+        self.sidecars.discard();
+        finalize_truncated(self.options, &old_full_path);
+        info!("Continuing capture in {}", self.current_file_name);
+
+        Ok(())
+    }
+
+    // 其他写入错误：按`write_error_policy`结束捕获，或者放弃这次写入后继续。
+    // `Rotate`与磁盘已满时相同，缓冲区中可能只有半个数据包，丢弃后截断当前文件
+    fn recover_from_write_error(&mut self, e: PcapError) -> Result<(), SavePcapError> {
+        let policy = self.options.write_error_policy;
+        if policy == WriteErrorPolicy::Abort {
+            return Err(SavePcapError::PcapFileError(e.to_string()));
+        }
+        error!(
+            "Failed to write {}, error: {}, continuing ({:?})",
+            self.current_file_name, e, policy
+        );
+
+        if policy == WriteErrorPolicy::Rotate {
+            let (old_writer, _, old_full_path, _) = self.open_next_file()?;
+            old_writer.into_writer().discard();
+            self.sidecars.discard();
+            finalize_truncated(self.options, &old_full_path);
+            info!("Continuing capture in {}", self.current_file_name);
+        }
+        self.stats.record_write_error();
+        self.status.write_failed(&e.to_string(), policy);
+        Ok(())
+    }
+
+    // DNS响应之后紧跟一个名称解析块，记录当前文件中尚未记录过的地址和名称
+    fn write_name_resolution(&mut self, packet: &SourcePacket) -> Result<(), SavePcapError> {
+        let Some(name_resolution) = &mut self.name_resolution else {
+            return Ok(());
+        };
+        let records = name_resolution.new_records(self.datalink, &packet.data);
+        if records.is_empty() {
+            return Ok(());
+        }
+
+        match self.file_writer.write_name_resolution(&records) {
+            Ok(written) => self.current_file_offset += written as u64,
+            // 数据包已完整写入，换到新文件后不再补写这些记录
+            Err(PcapError::IoError(e)) if is_disk_full(&e) => self.recover_from_disk_full()?,
+            Err(e) => self.recover_from_write_error(e)?,
+        }
+        Ok(())
+    }
+
+    /// 异常数据包写入单独的文件，不参与滚动；pcapng格式下以注释记录异常原因
+    pub fn divert(&mut self, packet: &SourcePacket, reason: &str) -> Result<(), SavePcapError> {
+        let (errors_writer, errors_path) = match &mut self.errors_file {
+            Some(errors_file) => errors_file,
+            None => {
+                let errors_path =
+                    Path::new(&self.options.file_path).join(self.options.errors_file_name());
+                info!("Writing invalid packets to {:?}", errors_path);
+                let errors_writer =
+                    open_file_writer(self.options, &self.metadata, &errors_path, self.datalink)?;
+                self.errors_file.insert((errors_writer, errors_path))
+            }
+        };
+
+        let data = self.options.stored_data(&packet.data);
+        match errors_writer.write_packet(packet, data, Some(reason)) {
+            Ok(_) => Ok(()),
+            Err(PcapError::IoError(e)) if is_disk_full(&e) => {
+                Err(SavePcapError::DiskFull(errors_path.display().to_string()))
+            }
+            Err(e) => Err(SavePcapError::PcapFileError(e.to_string())),
+        }
+    }
+
+    fn update_live_stats(&mut self) {
+        let (started, packets, bytes) = self.rate_window;
+        if packets > 0 {
+            self.stats.mark_active();
+        }
+        let seconds = started.elapsed().as_secs_f64();
+        self.stats
+            .record_rates(packets as f64 / seconds, bytes as f64 * 8.0 / seconds);
+        self.stats
+            .record_current_file_size(self.current_file_offset);
+        if let Some(histograms) = &self.histograms {
+            self.stats.record_histograms(histograms);
+        }
+        if let Some(alerts) = &mut self.alerts {
+            alerts.check(&self.stats.live_snapshot());
+        }
+        self.rate_window = (Instant::now(), 0, 0);
+    }
+
+    fn write_stats_file(&mut self) {
+        self.stats_file_written = Instant::now();
+        if let Some(path) = &self.options.stats_file
+            && let Err(e) = stats::write_stats_file(Path::new(path), &self.stats.snapshot())
+        {
+            warn!("Failed to write stats file {}: {}", path, e);
+        }
+    }
+
+    // 在文件末尾记录首尾数据包时间并写出缓冲区中的数据
+    fn end_current_file(&mut self) -> io::Result<()> {
+        let pcap_stats = self.file_pcap_stats();
+        let packets = self.current_file_packet_count as u64;
+        self.file_writer
+            .write_statistics(self.packet_time_range, pcap_stats, packets)
+            .map_err(|e| match e {
+                PcapError::IoError(e) => e,
+                e => io::Error::other(e.to_string()),
+            })?;
+        self.file_writer.get_mut().flush()
+    }
+
+    // 删除最旧的捕获文件（不包括当前文件），返回是否删除了文件
+    fn delete_oldest_file(&self) -> Result<bool, SavePcapError> {
+        match oldest_capture_file(self.options, &self.current_full_path)? {
+            Some(path) => {
+                fs::remove_file(&path)?;
+                metadata::remove_sidecar(&path);
+                warn!("Disk full, deleted oldest capture file {:?}", path);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    // 创建新文件并替换当前写入器，返回旧文件的写入器、文件名和路径
+    fn open_next_file(
+        &mut self,
+    ) -> Result<(FormatWriter, String, PathBuf, Option<u64>), SavePcapError> {
+        let sequence = self.current_file_sequence.map(|sequence| sequence + 1);
+        // 滚动时不覆盖已有文件，也不因文件名冲突中断捕获
+        let (file_name, full_path) = create_file(
+            self.options,
+            self.current_bucket,
+            sequence,
+            ConflictPolicy::AppendSuffix,
+        )?;
+        let mut new_writer =
+            open_file_writer(self.options, &self.metadata, &full_path, self.datalink)?;
+        let offset = new_writer.get_mut().position()?;
+
+        let old_writer = mem::replace(&mut self.file_writer, new_writer);
+        let old_file_name = mem::replace(&mut self.current_file_name, file_name);
+        let old_full_path = mem::replace(&mut self.current_full_path, full_path);
+        let old_sequence = mem::replace(&mut self.current_file_sequence, sequence);
+        self.stats
+            .record_current_file(&self.current_full_path, sequence);
+
+        self.current_file_packet_count = 0;
+        self.current_file_offset = offset;
+        if let Some(name_resolution) = &mut self.name_resolution {
+            name_resolution.reset();
+        }
+        self.file_creation_time = SystemTime::now();
+        self.packet_time_range = None;
+        self.file_dropped_base = self.stats.kernel_dropped();
+        self.file_pcap_base = self.stats.pcap_stats().unwrap_or_default();
+        self.current_file_redactions = 0;
+
+        Ok((old_writer, old_file_name, old_full_path, old_sequence))
+    }
+
+    // 当前文件的统计，path为文件结束前的路径，调用方在文件结束后换成最终路径
+    fn closed_file(&self) -> ClosedFile {
+        ClosedFile {
+            path: self.current_full_path.clone(),
+            first_packet: self.packet_time_range.map(|(first, _)| first),
+            last_packet: self.packet_time_range.map(|(_, last)| last),
+            packets: self.current_file_packet_count as u64,
+            bytes: self.current_file_offset,
+            dropped: self
+                .stats
+                .kernel_dropped()
+                .saturating_sub(self.file_dropped_base),
+            pcap_stats: self.file_pcap_stats(),
+            redactions: self.current_file_redactions,
+        }
+    }
+
+    fn file_pcap_stats(&self) -> Option<PcapStats> {
+        self.stats
+            .pcap_stats()
+            .map(|stats| stats.since(self.file_pcap_base))
+    }
+
+    fn check_needs_rollover(&self) -> bool {
+        if let Some(rollover_seconds) = self.options.rollover_time_seconds {
+            if let Ok(elapsed) = self.file_creation_time.elapsed() {
+                if elapsed.as_secs() >= rollover_seconds {
+                    return true;
+                }
+            }
+        }
+
+        if let Some(max_packets) = self.options.rollover_packet_count {
+            if self.current_file_packet_count >= max_packets {
+                return true;
+            }
+        }
+
+        // 按实际写入的字节数计算，包括文件头和每条记录的头部
+        if size_limit(self.options)
+            .is_some_and(|max_size_bytes| self.current_file_offset >= max_size_bytes)
+        {
+            return true;
+        }
+
+        false
+    }
+}
+
+// 按文件格式写入数据包
+enum FormatWriter {
+    // 文件头写入后，数据包记录按header中的字节序、时间精度和快照长度直接写入文件
+    Pcap {
+        file: OutputFile,
+        header: PcapHeader,
+    },
+    PcapNg {
+        writer: PcapNgWriter<OutputFile>,
+        // 每个增强数据包块的epb_flags，None表示不写该选项
+        epb_flags: Option<u32>,
+    },
+    // 空闲时已关闭当前文件、尚未打开下一个文件，其中为`OutputFile::Suspended`
+    Closed(OutputFile),
+}
+
+impl FormatWriter {
+    // data为实际写入的数据（可能已截断），原始长度取自packet。
+    // 经典pcap无法记录注释，comment只对pcapng生效
+    fn write_packet(
+        &mut self,
+        packet: &SourcePacket,
+        data: &[u8],
+        comment: Option<&str>,
+    ) -> Result<usize, PcapError> {
+        match self {
+            FormatWriter::Pcap { file, header } => {
+                write_pcap_record(file, header, packet.timestamp, packet.orig_len, data)
+            }
+            FormatWriter::PcapNg { writer, epb_flags } => {
+                let mut options = Vec::new();
+                if let Some(flags) = epb_flags {
+                    options.push(EnhancedPacketOption::Flags(*flags));
+                }
+                if let Some(comment) = comment {
+                    options.push(EnhancedPacketOption::Comment(Cow::Borrowed(comment)));
+                }
+                let mut block = EnhancedPacketBlock::default();
+                block.timestamp = packet.timestamp;
+                block.original_len = packet.orig_len;
+                block.data = Cow::Borrowed(data);
+                block.options = options;
+                writer.write_pcapng_block(block)
+            }
+            FormatWriter::Closed(_) => Err(PcapError::IoError(suspended_error())),
+        }
+    }
+
+    // pcapng为带心跳注释的接口统计块；pcap没有可以标记记录的字段，写一条长度为0的数据包记录，
+    // 真实的链路上不会出现0字节的帧
+    fn write_heartbeat(&mut self, now: Duration) -> Result<usize, PcapError> {
+        match self {
+            FormatWriter::Pcap { file, header } => write_pcap_record(file, header, now, 0, &[]),
+            FormatWriter::PcapNg { writer, .. } => {
+                writer.write_pcapng_block(InterfaceStatisticsBlock {
+                    interface_id: 0,
+                    timestamp: now.as_nanos() as u64,
+                    options: vec![InterfaceStatisticsOption::Comment(Cow::Borrowed(
+                        HEARTBEAT_COMMENT,
+                    ))],
+                })
+            }
+            FormatWriter::Closed(_) => Err(PcapError::IoError(suspended_error())),
+        }
+    }
+
+    // 数据包记录在文件中占用的字节数：pcap为16字节记录头加数据；pcapng为增强数据包块，
+    // 数据按4字节对齐，另有epb_flags和注释选项
+    fn record_len(&self, data_len: usize, comment: Option<&str>) -> u64 {
+        let padded = |len: usize| len.div_ceil(4) * 4;
+        let len = match self {
+            FormatWriter::Pcap { .. } => 16 + data_len,
+            FormatWriter::PcapNg { epb_flags, .. } => {
+                let mut options = 0;
+                if epb_flags.is_some() {
+                    options += 8;
+                }
+                if let Some(comment) = comment {
+                    options += 4 + padded(comment.len());
+                }
+                if options > 0 {
+                    // 选项结束标记
+                    options += 4;
+                }
+                32 + padded(data_len) + options
+            }
+            FormatWriter::Closed(_) => 0,
+        };
+        len as u64
+    }
+
+    fn get_mut(&mut self) -> &mut OutputFile {
+        match self {
+            FormatWriter::Pcap { file, .. } => file,
+            FormatWriter::PcapNg { writer, .. } => writer.get_mut(),
+            FormatWriter::Closed(file) => file,
+        }
+    }
+
+    fn into_writer(self) -> OutputFile {
+        match self {
+            FormatWriter::Pcap { file, .. } => file,
+            FormatWriter::PcapNg { writer, .. } => writer.into_inner(),
+            FormatWriter::Closed(file) => file,
+        }
+    }
+
+    // pcap没有名称解析块，只对pcapng生效
+    fn write_name_resolution(&mut self, records: &[(IpAddr, String)]) -> Result<usize, PcapError> {
+        match self {
+            FormatWriter::Pcap { .. } | FormatWriter::Closed(_) => Ok(0),
+            FormatWriter::PcapNg { writer, .. } => {
+                let records = records
+                    .iter()
+                    .map(|(address, name)| {
+                        let names = vec![Cow::Borrowed(name.as_str())];
+                        match address {
+                            IpAddr::V4(address) => Record::Ipv4(Ipv4Record {
+                                ip_addr: Cow::Owned(address.octets().to_vec()),
+                                names,
+                            }),
+                            IpAddr::V6(address) => Record::Ipv6(Ipv6Record {
+                                ip_addr: Cow::Owned(address.octets().to_vec()),
+                                names,
+                            }),
+                        }
+                    })
+                    .collect();
+                writer.write_pcapng_block(NameResolutionBlock {
+                    records,
+                    options: Vec::new(),
+                })
+            }
+        }
+    }
+
+    // pcapng在文件末尾写一个接口统计块，记录首尾数据包时间和该文件期间的libpcap统计；
+    // pcap没有对应的结构，记录在元数据文件中
+    fn write_statistics(
+        &mut self,
+        time_range: Option<(Duration, Duration)>,
+        pcap_stats: Option<PcapStats>,
+        packets: u64,
+    ) -> Result<(), PcapError> {
+        match self {
+            FormatWriter::Pcap { .. } | FormatWriter::Closed(_) => Ok(()),
+            FormatWriter::PcapNg { writer, .. } => {
+                let mut options = Vec::new();
+                if let Some((first, last)) = time_range {
+                    options.push(InterfaceStatisticsOption::IsbStartTime(
+                        first.as_nanos() as u64
+                    ));
+                    options.push(InterfaceStatisticsOption::IsbEndTime(last.as_nanos() as u64));
+                }
+                if let Some(stats) = pcap_stats {
+                    options.extend([
+                        InterfaceStatisticsOption::IsbIfRecv(stats.received),
+                        InterfaceStatisticsOption::IsbIfDrop(stats.if_dropped),
+                        InterfaceStatisticsOption::IsbOsDrop(stats.dropped),
+                        InterfaceStatisticsOption::IsbUsrDeliv(packets),
+                    ]);
+                }
+                if options.is_empty() {
+                    return Ok(());
+                }
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                writer.write_pcapng_block(InterfaceStatisticsBlock {
+                    interface_id: 0,
+                    timestamp: now.as_nanos() as u64,
+                    options,
+                })?;
+                Ok(())
+            }
+        }
+    }
+}
+
+// 当前输出文件，普通模式经过页缓存写入，direct_io模式绕过页缓存
+enum OutputFile {
+    Buffered(BufWriter<File>),
+    #[cfg(all(target_os = "linux", feature = "direct-io"))]
+    Direct(DirectWriter),
+    // 为限制打开的文件数暂时关闭了句柄，由`RotatingWriter::resume`重新打开
+    Suspended,
+}
+
+impl OutputFile {
+    fn create(options: &PcapCaptureOptions, path: &Path) -> Result<Self, SavePcapError> {
+        let what = format!("Creating {:?}", path);
+        if options.direct_io {
+            #[cfg(all(target_os = "linux", feature = "direct-io"))]
+            {
+                let writer =
+                    share::retry(options.share_retry, &what, || DirectWriter::create(path))?;
+                if let Some(layout) = &options.output_layout {
+                    layout.set_file_mode(path)?;
+                }
+                return Ok(OutputFile::Direct(writer));
+            }
+
+            #[cfg(not(all(target_os = "linux", feature = "direct-io")))]
+            return Err(SavePcapError::UnsupportedSource(
+                "O_DIRECT writing requires Linux and the `direct-io` feature".to_string(),
+            ));
+        }
+
+        let file = share::retry(options.share_retry, &what, || File::create(path))?;
+        if let Some(layout) = &options.output_layout {
+            layout.set_file_mode(path)?;
+        }
+        Ok(OutputFile::Buffered(BufWriter::new(file)))
+    }
+
+    fn file(&self) -> io::Result<&File> {
+        match self {
+            OutputFile::Buffered(writer) => Ok(writer.get_ref()),
+            #[cfg(all(target_os = "linux", feature = "direct-io"))]
+            OutputFile::Direct(writer) => Ok(writer.file()),
+            OutputFile::Suspended => Err(suspended_error()),
+        }
+    }
+
+    // 当前写入位置，包括缓冲区中尚未写出的数据
+    fn position(&self) -> io::Result<u64> {
+        match self {
+            OutputFile::Buffered(writer) => {
+                let mut file = writer.get_ref();
+                Ok(file.stream_position()? + writer.buffer().len() as u64)
+            }
+            #[cfg(all(target_os = "linux", feature = "direct-io"))]
+            OutputFile::Direct(writer) => writer.position(),
+            OutputFile::Suspended => Err(suspended_error()),
+        }
+    }
+
+    // 按文件大小滚动时预先分配整个文件的空间，减少碎片和元数据更新。
+    // 使用FALLOC_FL_KEEP_SIZE，文件长度仍为实际写入的长度
+    fn preallocate(&self, options: &PcapCaptureOptions) {
+        let Some(size) = preallocation_size(options) else {
+            return;
+        };
+
+        #[cfg(target_os = "linux")]
+        if let Ok(file) = self.file() {
+            use std::os::unix::io::AsRawFd;
+
+            let result = unsafe {
+                libc::fallocate(
+                    file.as_raw_fd(),
+                    libc::FALLOC_FL_KEEP_SIZE,
+                    0,
+                    size as libc::off_t,
+                )
+            };
+            if result != 0 {
+                warn!(
+                    "Failed to preallocate {} bytes: {}",
+                    size,
+                    io::Error::last_os_error()
+                );
+            }
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        let _ = size;
+    }
+
+    // 丢弃尚未写出的数据并关闭文件，用于写入出错后缓冲区中可能只有半个数据包的情况
+    fn discard(self) {
+        match self {
+            OutputFile::Buffered(writer) => drop(writer.into_parts()),
+            #[cfg(all(target_os = "linux", feature = "direct-io"))]
+            OutputFile::Direct(mut writer) => writer.discard(),
+            OutputFile::Suspended => {}
+        }
+    }
+
+    // 写出剩余数据；预分配过的文件截断到实际长度，释放未使用的空间
+    fn close(mut self, options: &PcapCaptureOptions) -> io::Result<()> {
+        match &mut self {
+            OutputFile::Buffered(writer) => writer.flush()?,
+            #[cfg(all(target_os = "linux", feature = "direct-io"))]
+            OutputFile::Direct(writer) => writer.close()?,
+            OutputFile::Suspended => return Err(suspended_error()),
+        }
+
+        if preallocation_size(options).is_some() {
+            let file = self.file()?;
+            file.set_len(file.metadata()?.len())?;
+        }
+        // 重命名后文件即可被读取，之前必须确保内容已落盘
+        if options.part_files {
+            self.file()?.sync_data()?;
+        }
+        Ok(())
+    }
+}
+
+fn preallocation_size(options: &PcapCaptureOptions) -> Option<u64> {
+    if !options.continuous_capture || !options.preallocate {
+        return None;
+    }
+    size_limit(options)
+}
+
+fn size_limit(options: &PcapCaptureOptions) -> Option<u64> {
+    options
+        .rollover_file_size_mb
+        .map(|max_size_mb| max_size_mb * 1024 * 1024)
+}
+
+impl Write for OutputFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            OutputFile::Buffered(writer) => writer.write(buf),
+            #[cfg(all(target_os = "linux", feature = "direct-io"))]
+            OutputFile::Direct(writer) => writer.write(buf),
+            OutputFile::Suspended => Err(suspended_error()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            OutputFile::Buffered(writer) => writer.flush(),
+            #[cfg(all(target_os = "linux", feature = "direct-io"))]
+            OutputFile::Direct(writer) => writer.flush(),
+            OutputFile::Suspended => Err(suspended_error()),
+        }
+    }
+}
+
+fn suspended_error() -> io::Error {
+    io::Error::other("capture file is suspended")
+}
+
+fn is_disk_full(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::StorageFull
+}
+
+fn finalize_truncated(options: &PcapCaptureOptions, path: &Path) {
+    match repair::repair(path) {
+        // 一个完整的数据包都没有写入，不保留空文件
+        Ok(report) if report.packets == 0 => {
+            if let Err(e) = fs::remove_file(path) {
+                error!("Failed to remove empty file {:?}: {}", path, e);
+            }
+            metadata::remove_sidecar(path);
+        }
+        Ok(report) => {
+            info!(
+                "Finalized {:?} at the last complete packet ({} bytes)",
+                path, report.repaired_len
+            );
+            let path = publish_part_file(options, path);
+            move_staged(options, &path);
+        }
+        Err(e) => error!("Failed to repair {:?}: {}", path, e),
+    }
+}
+
+// 文件关闭后更新元数据文件，并按需把首尾数据包时间加入文件名，返回文件最终的路径
+fn finalize_file(
+    options: &PcapCaptureOptions,
+    metadata: &FileMetadata,
+    closed: &ClosedFile,
+    sequence: Option<u64>,
+) -> PathBuf {
+    let path = closed.path.as_path();
+    let time_range = closed.first_packet.zip(closed.last_packet);
+    let mut final_path = path.to_path_buf();
+    if options.time_range_file_names
+        && let Some((first, last)) = time_range
+    {
+        let renamed = path.with_file_name(options.time_range_file_name(first, last, sequence));
+        if renamed.exists() {
+            warn!("Not renaming {:?}: {:?} already exists", path, renamed);
+        } else if let Err(e) = rename(options, path, &renamed) {
+            warn!("Failed to rename {:?} to {:?}: {}", path, renamed, e);
+        } else {
+            info!("Renamed {:?} to {:?}", path, renamed);
+            metadata::remove_sidecar(path);
+            final_path = renamed;
+        }
+    }
+    // 没有按时间范围重命名时去掉`.part`后缀
+    if final_path == path {
+        final_path = publish_part_file(options, path);
+    }
+    final_path = move_staged(options, &final_path);
+
+    if options.metadata_sidecar
+        && matches!(options.file_format, FileFormat::Pcap)
+        && let Err(e) = metadata.write_sidecar(&final_path, Some(closed))
+    {
+        warn!("Failed to write metadata for {:?}: {}", final_path, e);
+    }
+
+    final_path
+}
+
+// 启用`part_files`时把写入完成的文件重命名为最终的文件名，返回文件最终的路径
+fn publish_part_file(options: &PcapCaptureOptions, path: &Path) -> PathBuf {
+    let published = path
+        .to_str()
+        .and_then(|path| path.strip_suffix(PART_SUFFIX))
+        .map(PathBuf::from);
+    let Some(published) = published.filter(|_| options.part_files) else {
+        return path.to_path_buf();
+    };
+    match rename(options, path, &published) {
+        Ok(()) => {
+            metadata::remove_sidecar(path);
+            published
+        }
+        Err(e) => {
+            warn!("Failed to rename {:?} to {:?}: {}", path, published, e);
+            path.to_path_buf()
+        }
+    }
+}
+
+/// 写入目录（包括按`output_layout`生成的子目录）中最旧的捕获文件，不包括current。
+/// 设置`staging_dir`时磁盘已满的是暂存目录所在的磁盘，只在暂存目录中查找，
+/// 删除输出目录中已移走的文件腾不出空间
+pub(crate) fn oldest_capture_file(
+    options: &PcapCaptureOptions,
+    current: &Path,
+) -> io::Result<Option<PathBuf>> {
+    let mut oldest: Option<(SystemTime, PathBuf)> = None;
+
+    let dirs = layout::capture_dirs(options.output_layout.as_ref(), output_dir(options));
+    for entry in dirs
+        .iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flatten()
+    {
+        let entry = entry?;
+        let path = entry.path();
+        let is_capture_file = options.is_capture_file_name(&entry.file_name().to_string_lossy());
+        if !is_capture_file || path == current {
+            continue;
+        }
+
+        let modified = entry.metadata()?.modified()?;
+        if oldest.as_ref().is_none_or(|(time, _)| modified < *time) {
+            oldest = Some((modified, path));
+        }
+    }
+    Ok(oldest.map(|(_, path)| path))
+}
+
+// 实际写入捕获文件的目录
+fn output_dir(options: &PcapCaptureOptions) -> &Path {
+    Path::new(options.staging_dir.as_ref().unwrap_or(&options.file_path))
+}
+
+fn rename(options: &PcapCaptureOptions, from: &Path, to: &Path) -> io::Result<()> {
+    let what = format!("Renaming {:?} to {:?}", from, to);
+    share::retry(options.share_retry, &what, || fs::rename(from, to))
+}
+
+// 设置`staging_dir`时把暂存目录中写完的文件移动到输出目录，返回文件最终的路径。
+// 按`output_layout`生成的子目录在输出目录中保持不变
+pub(crate) fn move_staged(options: &PcapCaptureOptions, path: &Path) -> PathBuf {
+    let Some(staging_dir) = &options.staging_dir else {
+        return path.to_path_buf();
+    };
+    let relative = path
+        .strip_prefix(staging_dir)
+        .unwrap_or_else(|_| Path::new(path.file_name().unwrap_or_default()));
+    if let (Some(layout), Some(parent)) = (&options.output_layout, relative.parent())
+        && let Err(e) = layout.create_dirs(Path::new(&options.file_path), parent)
+    {
+        warn!(
+            "Failed to create {:?}: {}, keeping the staged file",
+            parent, e
+        );
+        return path.to_path_buf();
+    }
+    let destination = Path::new(&options.file_path).join(relative);
+    share::move_staged(path, &destination, options.share_retry)
+}
+
+// 设置`time_buckets`时以时间段命名，否则以当前时间命名
+fn create_file(
+    options: &PcapCaptureOptions,
+    bucket: Option<Duration>,
+    sequence: Option<u64>,
+    policy: ConflictPolicy,
+) -> Result<(String, PathBuf), SavePcapError> {
+    match (options.time_buckets, bucket) {
+        (Some(time_buckets), Some(start)) => {
+            options.create_file_at(&time_buckets.label(start), sequence, policy)
+        }
+        _ => options.create_new_file(sequence, policy),
+    }
+}
+
+fn open_file_writer(
+    options: &PcapCaptureOptions,
+    metadata: &FileMetadata,
+    path: &Path,
+    datalink: DataLink,
+) -> Result<FormatWriter, SavePcapError> {
+    let file = OutputFile::create(options, path)?;
+    file.preallocate(options);
+
+    match options.file_format {
+        FileFormat::Pcap => {
+            let writer = new_pcap_writer(options, file, datalink)?;
+            if options.metadata_sidecar
+                && let Err(e) = metadata.write_sidecar(path, None)
+            {
+                warn!("Failed to write metadata for {:?}: {}", path, e);
+            }
+            Ok(writer)
+        }
+        FileFormat::PcapNg => {
+            let fcs_len = fcs_len(options, datalink);
+            Ok(FormatWriter::PcapNg {
+                writer: new_pcapng_writer(options, metadata, file, datalink, fcs_len)?,
+                epb_flags: fcs_len.map(|len| (len as u32) << EPB_FLAGS_FCS_LEN_SHIFT),
+            })
+        }
+    }
+}
+
+// 保留FCS时以太网帧末尾带有校验和，需要在文件中注明长度，否则会被当作数据解析
+fn fcs_len(options: &PcapCaptureOptions, datalink: DataLink) -> Option<u8> {
+    (options.preserve_fcs && datalink == DataLink::ETHERNET).then_some(fcs::ETHERNET_FCS_LEN)
+}
+
+fn new_pcap_writer(
+    options: &PcapCaptureOptions,
+    mut file: OutputFile,
+    datalink: DataLink,
+) -> Result<FormatWriter, SavePcapError> {
+    let header = PcapHeader {
+        snaplen: options.file_snaplen(),
+        datalink,
+        endianness: Endianness::native(),
+        ..Default::default()
+    };
+
+    header
+        .write_to(&mut file)
+        .map_err(|e| SavePcapError::PcapFileError(e.to_string()))?;
+    Ok(FormatWriter::Pcap { file, header })
+}
+
+// 写入一条pcap数据包记录：16字节记录头（秒、秒以下部分、保存长度、原始长度）加数据，
+// 与`PcapWriter::write_packet`的编码和检查相同
+fn write_pcap_record(
+    file: &mut OutputFile,
+    header: &PcapHeader,
+    timestamp: Duration,
+    orig_len: u32,
+    data: &[u8],
+) -> Result<usize, PcapError> {
+    let seconds = u32::try_from(timestamp.as_secs())
+        .map_err(|_| PcapError::InvalidField("PcapPacket: timestamp_secs > u32::MAX"))?;
+    let fraction = match header.ts_resolution {
+        TsResolution::MicroSecond => timestamp.subsec_micros(),
+        TsResolution::NanoSecond => timestamp.subsec_nanos(),
+    };
+    let incl_len = u32::try_from(data.len())
+        .map_err(|_| PcapError::InvalidField("PcapPacket: incl_len > u32::MAX"))?;
+    if incl_len > header.snaplen {
+        return Err(PcapError::InvalidField("PcapPacket: incl_len > snap_len"));
+    }
+    if incl_len > orig_len {
+        return Err(PcapError::InvalidField("PcapPacket: incl_len > orig_len"));
+    }
+
+    let mut record = [0u8; 16];
+    for (bytes, value) in record
+        .chunks_exact_mut(4)
+        .zip([seconds, fraction, incl_len, orig_len])
+    {
+        bytes.copy_from_slice(&match header.endianness {
+            Endianness::Big => value.to_be_bytes(),
+            Endianness::Little => value.to_le_bytes(),
+        });
+    }
+    file.write_all(&record).map_err(PcapError::IoError)?;
+    file.write_all(data).map_err(PcapError::IoError)?;
+    Ok(record.len() + data.len())
+}
+
+// 主机名、操作系统和写入程序记录在节头块中，接口名和过滤表达式记录在接口描述块中
+fn new_pcapng_writer<W: Write>(
+    options: &PcapCaptureOptions,
+    metadata: &FileMetadata,
+    writer: W,
+    datalink: DataLink,
+    fcs_len: Option<u8>,
+) -> Result<PcapNgWriter<W>, SavePcapError> {
+    let mut section_options = vec![
+        SectionHeaderOption::OS(Cow::Owned(metadata.os.clone())),
+        SectionHeaderOption::UserApplication(Cow::Owned(metadata.application.clone())),
+    ];
+    if let Some(comment) = metadata.hostname_comment() {
+        section_options.push(SectionHeaderOption::Comment(Cow::Owned(comment)));
+    }
+    section_options.extend(
+        metadata
+            .label_comments()
+            .map(|comment| SectionHeaderOption::Comment(Cow::Owned(comment))),
+    );
+    let section = SectionHeaderBlock {
+        options: section_options,
+        ..Default::default()
+    };
+
+    // 数据包时间戳统一按纳秒精度记录
+    let mut interface_options = vec![InterfaceDescriptionOption::IfTsResol(9)];
+    if let Some(interface) = &metadata.interface {
+        interface_options.push(InterfaceDescriptionOption::IfName(Cow::Owned(
+            interface.clone(),
+        )));
+    }
+    if let Some(filter) = metadata.if_filter() {
+        interface_options.push(InterfaceDescriptionOption::IfFilter(filter));
+    }
+    interface_options.push(InterfaceDescriptionOption::IfOs(Cow::Owned(
+        metadata.os.clone(),
+    )));
+    if let Some(fcs_len) = fcs_len {
+        interface_options.push(InterfaceDescriptionOption::IfFcsLen(fcs_len));
+    }
+    let interface = InterfaceDescriptionBlock {
+        linktype: datalink,
+        snaplen: options.file_snaplen(),
+        options: interface_options,
+    };
+
+    let mut writer = PcapNgWriter::with_section_header(writer, section)
+        .map_err(|e| SavePcapError::PcapFileError(e.to_string()))?;
+    writer
+        .write_pcapng_block(interface)
+        .map_err(|e| SavePcapError::PcapFileError(e.to_string()))?;
+    Ok(writer)
+}